@@ -1,4 +1,25 @@
+use std::env;
+
 fn main() -> anyhow::Result<()> {
-    tonic_prost_build::compile_protos("proto/chat.proto")?;
+    // The federation of servers goes through the client stubs as well.
+    let client = env::var_os("CARGO_FEATURE_CLIENT").is_some();
+    let server = env::var_os("CARGO_FEATURE_SERVER").is_some();
+    let mut builder = tonic_prost_build::configure()
+        .build_client(client || server)
+        .build_server(server);
+    if env::var_os("CARGO_FEATURE_SERDE").is_some() {
+        builder = builder
+            .type_attribute(".", "#[derive(serde::Serialize, serde::Deserialize)]")
+            // Like protobuf, which leaves out fields with default values.
+            .message_attribute(".", "#[serde(default)]");
+    }
+    builder.compile_protos(
+        &[
+            "proto/mlschat/version.proto",
+            "proto/mlschat/v1/chat.proto",
+            "proto/mlschat/v1/websocket.proto",
+        ],
+        &["proto"],
+    )?;
     Ok(())
 }
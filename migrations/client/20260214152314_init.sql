@@ -0,0 +1,5 @@
+CREATE TABLE IF NOT EXISTS client_user (
+  username TEXT NOT NULL PRIMARY KEY,
+  signature_private_key BLOB NOT NULL,
+  credential_with_key BLOB NOT NULL
+);
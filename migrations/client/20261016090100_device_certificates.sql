@@ -0,0 +1,7 @@
+ALTER TABLE client_user ADD COLUMN device_id TEXT NOT NULL DEFAULT '';
+
+CREATE TABLE IF NOT EXISTS client_group (
+  group_id BLOB NOT NULL PRIMARY KEY,
+  username TEXT NOT NULL,
+  created_at TEXT NOT NULL
+);
@@ -0,0 +1,5 @@
+CREATE TABLE IF NOT EXISTS client_key_log_root (
+  username TEXT NOT NULL PRIMARY KEY,
+  tree_size INTEGER NOT NULL,
+  root_hash BLOB NOT NULL
+);
@@ -0,0 +1,2 @@
+ALTER TABLE client_group ADD COLUMN creator TEXT NOT NULL DEFAULT '';
+ALTER TABLE client_group ADD COLUMN last_activity_at TEXT;
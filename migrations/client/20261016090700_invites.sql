@@ -0,0 +1,17 @@
+CREATE TABLE IF NOT EXISTS client_invite (
+  group_id BLOB NOT NULL,
+  username TEXT NOT NULL,
+  welcome BLOB NOT NULL,
+  inviter TEXT NOT NULL,
+  name TEXT NOT NULL,
+  members TEXT NOT NULL,
+  received_at TEXT NOT NULL,
+  PRIMARY KEY (group_id, username)
+);
+
+CREATE TABLE IF NOT EXISTS client_invite_message (
+  message_id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+  group_id BLOB NOT NULL,
+  username TEXT NOT NULL,
+  content BLOB NOT NULL
+);
@@ -0,0 +1,2 @@
+-- Users so far were all on 0x0003, the only ciphersuite supported before.
+ALTER TABLE client_user ADD COLUMN ciphersuite INTEGER NOT NULL DEFAULT 3;
@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS client_pending_message (
+  message_id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+  group_id BLOB NOT NULL,
+  username TEXT NOT NULL,
+  epoch INTEGER NOT NULL,
+  content BLOB NOT NULL,
+  received_at TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS client_pending_message_group
+  ON client_pending_message (group_id, username, epoch);
@@ -0,0 +1 @@
+ALTER TABLE client_group ADD COLUMN keys_updated_at TEXT;
@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS client_retired_key (
+  username TEXT NOT NULL,
+  signature_key BLOB NOT NULL,
+  signature_private_key BLOB NOT NULL,
+  retired_at TEXT NOT NULL,
+  PRIMARY KEY (username, signature_key)
+);
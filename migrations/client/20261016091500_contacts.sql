@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS client_contact (
+  username TEXT NOT NULL,
+  contact TEXT NOT NULL,
+  signature_key BLOB NOT NULL,
+  trusted INTEGER NOT NULL,
+  first_seen_at TEXT NOT NULL,
+  PRIMARY KEY (username, contact, signature_key)
+);
@@ -0,0 +1 @@
+ALTER TABLE client_contact ADD COLUMN verified_at TEXT;
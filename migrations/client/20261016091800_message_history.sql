@@ -0,0 +1,13 @@
+CREATE TABLE IF NOT EXISTS client_message (
+  message_id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+  group_id BLOB NOT NULL,
+  username TEXT NOT NULL,
+  sender TEXT NOT NULL,
+  epoch INTEGER NOT NULL,
+  direction TEXT NOT NULL,
+  body TEXT NOT NULL,
+  created_at TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS client_message_group
+  ON client_message (group_id, username, message_id);
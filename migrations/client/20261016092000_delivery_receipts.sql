@@ -0,0 +1,13 @@
+ALTER TABLE client_message ADD COLUMN message_uuid BLOB;
+-- Incoming messages whose delivery receipt wasn't sent yet.
+ALTER TABLE client_message ADD COLUMN receipt_pending INTEGER NOT NULL DEFAULT 0;
+
+CREATE INDEX IF NOT EXISTS client_message_uuid
+  ON client_message (message_uuid);
+
+CREATE TABLE IF NOT EXISTS client_receipt (
+  message_id INTEGER NOT NULL REFERENCES client_message (message_id) ON DELETE CASCADE,
+  member TEXT NOT NULL,
+  delivered_at TEXT NOT NULL,
+  PRIMARY KEY (message_id, member)
+);
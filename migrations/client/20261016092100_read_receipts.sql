@@ -0,0 +1,3 @@
+ALTER TABLE client_group ADD COLUMN read_receipts INTEGER NOT NULL DEFAULT TRUE;
+ALTER TABLE client_message ADD COLUMN read_at TEXT;
+ALTER TABLE client_receipt ADD COLUMN read_at TEXT;
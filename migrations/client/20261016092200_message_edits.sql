@@ -0,0 +1,12 @@
+ALTER TABLE client_message ADD COLUMN edited_at TEXT;
+
+-- Previous bodies of edited messages.
+CREATE TABLE IF NOT EXISTS client_message_edit (
+  edit_id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+  message_id INTEGER NOT NULL REFERENCES client_message (message_id) ON DELETE CASCADE,
+  body TEXT NOT NULL,
+  edited_at TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS client_message_edit_message
+  ON client_message_edit (message_id, edit_id);
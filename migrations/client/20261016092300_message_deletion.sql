@@ -0,0 +1,2 @@
+-- Messages deleted by their sender keep a row without body as tombstone.
+ALTER TABLE client_message ADD COLUMN deleted_at TEXT;
@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS client_direct (
+  username TEXT NOT NULL,
+  peer TEXT NOT NULL,
+  group_id BLOB NOT NULL,
+  PRIMARY KEY (username, peer)
+);
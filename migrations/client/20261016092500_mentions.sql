@@ -0,0 +1,2 @@
+-- JSON array of the identities mentioned in the message.
+ALTER TABLE client_message ADD COLUMN mentions TEXT NOT NULL DEFAULT '[]';
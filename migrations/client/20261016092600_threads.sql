@@ -0,0 +1,5 @@
+-- Envelope id of the message this one replies to.
+ALTER TABLE client_message ADD COLUMN reply_to BLOB;
+
+CREATE INDEX IF NOT EXISTS client_message_reply_to
+  ON client_message (group_id, username, reply_to);
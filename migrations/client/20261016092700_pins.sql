@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS client_pin (
+  username TEXT NOT NULL,
+  group_id BLOB NOT NULL,
+  -- Envelope id of the pinned message.
+  message_uuid BLOB NOT NULL,
+  pinned_by TEXT NOT NULL,
+  pinned_at TEXT NOT NULL,
+  PRIMARY KEY (username, group_id, message_uuid)
+);
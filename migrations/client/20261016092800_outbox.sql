@@ -0,0 +1,15 @@
+-- Application messages that couldn't be sent yet, retried in order per group.
+CREATE TABLE IF NOT EXISTS client_outbox (
+  outbox_id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+  username TEXT NOT NULL,
+  group_id BLOB NOT NULL,
+  -- JSON array of the recipient identities.
+  recipients TEXT NOT NULL,
+  content BLOB NOT NULL,
+  attempts INTEGER NOT NULL DEFAULT 0,
+  next_attempt_at TEXT NOT NULL,
+  created_at TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS client_outbox_group
+  ON client_outbox (username, group_id, outbox_id);
@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS client_group_alias (
+  username TEXT NOT NULL,
+  alias TEXT NOT NULL,
+  group_id BLOB NOT NULL,
+  PRIMARY KEY (username, alias)
+);
@@ -0,0 +1,10 @@
+-- Newest message of the group the user has read, below which nothing is
+-- counted as unread.
+ALTER TABLE client_group ADD COLUMN last_read_message_id INTEGER;
+
+UPDATE client_group SET last_read_message_id = (
+  SELECT MAX(message_id) FROM client_message
+  WHERE client_message.group_id = client_group.group_id
+    AND client_message.username = client_group.username
+    AND client_message.read_at IS NOT NULL
+);
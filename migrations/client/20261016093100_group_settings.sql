@@ -0,0 +1,4 @@
+-- Local settings: muted groups show no notifications, archived ones are left
+-- out of listings. Both still process their messages.
+ALTER TABLE client_group ADD COLUMN muted INTEGER NOT NULL DEFAULT FALSE;
+ALTER TABLE client_group ADD COLUMN archived INTEGER NOT NULL DEFAULT FALSE;
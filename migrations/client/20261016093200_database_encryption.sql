@@ -0,0 +1,7 @@
+-- Present once the signature private keys are encrypted: the salt of the key
+-- derivation and a value encrypted with the key to recognize a wrong secret.
+CREATE TABLE IF NOT EXISTS client_encryption (
+  id INTEGER NOT NULL PRIMARY KEY CHECK (id = 0),
+  salt BLOB NOT NULL,
+  check_value BLOB NOT NULL
+);
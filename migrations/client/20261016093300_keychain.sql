@@ -0,0 +1,3 @@
+-- Account of the keychain entry holding the signature private key, which is
+-- then left empty in this table.
+ALTER TABLE client_user ADD COLUMN keychain_account TEXT;
@@ -0,0 +1,5 @@
+-- Present once the OpenMLS state is encoded with another codec than JSON.
+CREATE TABLE IF NOT EXISTS client_storage_codec (
+  id INTEGER NOT NULL PRIMARY KEY CHECK (id = 0),
+  codec TEXT NOT NULL
+);
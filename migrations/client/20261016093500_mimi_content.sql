@@ -0,0 +1,10 @@
+-- Message id of messages in the MIMI content format, which replies, edits and
+-- reactions in that format refer to.
+ALTER TABLE client_message ADD COLUMN mimi_id BLOB;
+-- When the message is replaced with a tombstone, as requested by its sender.
+ALTER TABLE client_message ADD COLUMN expires_at TEXT;
+
+CREATE INDEX IF NOT EXISTS client_message_mimi_id
+  ON client_message (group_id, username, mimi_id);
+CREATE INDEX IF NOT EXISTS client_message_expires_at
+  ON client_message (username, expires_at);
@@ -0,0 +1,16 @@
+CREATE TABLE IF NOT EXISTS client_key_package (
+  key_package_ref BLOB NOT NULL PRIMARY KEY,
+  username TEXT NOT NULL,
+  ciphersuite INTEGER NOT NULL,
+  state TEXT NOT NULL CHECK (state IN ('created', 'uploaded', 'consumed', 'replaced', 'expired')),
+  created_at TEXT NOT NULL,
+  expires_at TEXT NOT NULL,
+  uploaded_at TEXT,
+  consumed_at TEXT,
+  consumed_by BLOB,
+  deleted_at TEXT
+);
+
+CREATE INDEX IF NOT EXISTS client_idx_key_package_username ON client_key_package (username, created_at);
+
+ALTER TABLE client_invite ADD COLUMN key_package_ref BLOB;
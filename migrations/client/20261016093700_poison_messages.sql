@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS client_poison_message (
+  message_id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+  username TEXT NOT NULL,
+  group_id BLOB,
+  epoch INTEGER,
+  content BLOB NOT NULL,
+  error TEXT NOT NULL,
+  received_at TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS client_idx_poison_message_username
+  ON client_poison_message (username);
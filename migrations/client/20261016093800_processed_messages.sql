@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS client_processed_message (
+  username TEXT NOT NULL,
+  message_key BLOB NOT NULL,
+  processed_at TEXT NOT NULL,
+  PRIMARY KEY (username, message_key)
+);
+
+CREATE INDEX IF NOT EXISTS client_idx_processed_message_processed_at
+  ON client_processed_message (processed_at);
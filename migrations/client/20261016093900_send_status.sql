@@ -0,0 +1,12 @@
+-- Whether the server accepted an outgoing message, and when by its clock.
+ALTER TABLE client_message ADD COLUMN send_status TEXT;
+ALTER TABLE client_message ADD COLUMN sent_at TEXT;
+-- History entry of a queued message.
+ALTER TABLE client_outbox ADD COLUMN message_id INTEGER;
+
+UPDATE client_message SET send_status = CASE
+    WHEN EXISTS (SELECT 1 FROM client_receipt r WHERE r.message_id = client_message.message_id)
+    THEN 'delivered'
+    ELSE 'sent'
+END
+WHERE direction = 'out';
@@ -0,0 +1,4 @@
+-- Member who shared the message from their history, see
+-- `Client::share_history`. The sender of such a message is only the sharer's
+-- claim. NULL for messages received from their sender.
+ALTER TABLE client_message ADD COLUMN shared_by TEXT;
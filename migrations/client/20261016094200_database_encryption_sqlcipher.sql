@@ -0,0 +1,3 @@
+-- The whole database is encrypted with SQLCipher instead of the signature
+-- private keys alone.
+DROP TABLE IF EXISTS client_encryption;
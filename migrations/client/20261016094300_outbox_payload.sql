@@ -0,0 +1,3 @@
+-- Unencrypted application message of a queued message, to encrypt it again
+-- if the group moved on to a newer epoch before it was sent.
+ALTER TABLE client_outbox ADD COLUMN payload BLOB;
@@ -16,9 +16,3 @@ CREATE TABLE IF NOT EXISTS server_message (
 );
 
 CREATE INDEX IF NOT EXISTS server_idx_message_recipient ON server_message (recipient, created_at);
-
-CREATE TABLE IF NOT EXISTS client_user (
-  username TEXT NOT NULL PRIMARY KEY,
-  signature_private_key BLOB NOT NULL,
-  credential_with_key BLOB NOT NULL
-);
@@ -0,0 +1,31 @@
+CREATE TABLE IF NOT EXISTS server_device (
+  client_id TEXT NOT NULL,
+  device_id TEXT NOT NULL,
+  created_at TEXT NOT NULL,
+  PRIMARY KEY (client_id, device_id)
+);
+
+ALTER TABLE server_key_package ADD COLUMN device_id TEXT NOT NULL DEFAULT '';
+
+DROP INDEX IF EXISTS server_idx_key_package_client_id;
+CREATE INDEX IF NOT EXISTS server_idx_key_package_device ON server_key_package (client_id, device_id);
+
+INSERT OR IGNORE INTO server_device (client_id, device_id, created_at)
+SELECT client_id, device_id, MIN(created_at) FROM server_key_package GROUP BY client_id, device_id;
+
+CREATE TABLE server_message_new (
+  message_id BLOB NOT NULL,
+  recipient TEXT NOT NULL,
+  device_id TEXT NOT NULL DEFAULT '',
+  content BLOB NOT NULL,
+  created_at TEXT NOT NULL,
+  PRIMARY KEY (message_id, recipient, device_id)
+);
+
+INSERT INTO server_message_new (message_id, recipient, device_id, content, created_at)
+SELECT message_id, recipient, '', content, created_at FROM server_message;
+
+DROP TABLE server_message;
+ALTER TABLE server_message_new RENAME TO server_message;
+
+CREATE INDEX IF NOT EXISTS server_idx_message_recipient ON server_message (recipient, device_id, created_at);
@@ -0,0 +1,10 @@
+ALTER TABLE server_device ADD COLUMN signature_key BLOB;
+
+CREATE TABLE IF NOT EXISTS server_device_certificate (
+  client_id TEXT NOT NULL,
+  device_id TEXT NOT NULL,
+  signature_key BLOB NOT NULL,
+  certificate BLOB NOT NULL,
+  created_at TEXT NOT NULL,
+  PRIMARY KEY (client_id, device_id)
+);
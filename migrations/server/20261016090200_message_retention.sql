@@ -0,0 +1,5 @@
+ALTER TABLE server_message ADD COLUMN delivered_at TEXT;
+
+DROP INDEX IF EXISTS server_idx_message_recipient;
+CREATE INDEX IF NOT EXISTS server_idx_message_pending ON server_message (recipient, device_id, delivered_at, created_at);
+CREATE INDEX IF NOT EXISTS server_idx_message_delivered_at ON server_message (delivered_at);
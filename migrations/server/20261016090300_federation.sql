@@ -0,0 +1,10 @@
+CREATE TABLE IF NOT EXISTS server_relay (
+  relay_id BLOB NOT NULL PRIMARY KEY,
+  domain TEXT NOT NULL,
+  request BLOB NOT NULL,
+  attempts INTEGER NOT NULL DEFAULT 0,
+  next_attempt_at TEXT NOT NULL,
+  created_at TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS server_idx_relay_next_attempt ON server_relay (next_attempt_at);
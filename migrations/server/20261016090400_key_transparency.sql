@@ -0,0 +1,10 @@
+CREATE TABLE IF NOT EXISTS server_key_log (
+  log_index INTEGER NOT NULL PRIMARY KEY,
+  client_id TEXT NOT NULL,
+  signature_key BLOB NOT NULL,
+  entry BLOB NOT NULL,
+  leaf_hash BLOB NOT NULL,
+  created_at TEXT NOT NULL
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS server_idx_key_log_binding ON server_key_log (client_id, signature_key);
@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS server_group_info (
+  group_id BLOB NOT NULL PRIMARY KEY,
+  epoch INTEGER NOT NULL,
+  group_info BLOB NOT NULL,
+  updated_at TEXT NOT NULL
+);
@@ -0,0 +1,3 @@
+-- Key packages so far were all on 0x0003, the only ciphersuite supported
+-- before.
+ALTER TABLE server_key_package ADD COLUMN ciphersuite INTEGER NOT NULL DEFAULT 3;
@@ -0,0 +1,5 @@
+CREATE TABLE IF NOT EXISTS server_group_epoch (
+  group_id BLOB NOT NULL PRIMARY KEY,
+  epoch INTEGER NOT NULL,
+  updated_at TEXT NOT NULL
+);
@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS server_blob (
+  blob_id TEXT NOT NULL PRIMARY KEY,
+  client_id TEXT NOT NULL,
+  content BLOB NOT NULL,
+  created_at TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS server_blob_created_at ON server_blob (created_at);
@@ -0,0 +1,2 @@
+-- The oldest kept message, which blobs are swept against.
+CREATE INDEX IF NOT EXISTS server_idx_message_created_at ON server_message (created_at);
@@ -0,0 +1,82 @@
+use std::{
+    fs,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+use uuid::Uuid;
+
+/// Settings of the bridge, e.g.
+///
+/// ```toml
+/// endpoint = "http://localhost:50051"
+/// db_path = "bridge.db"
+/// user = "ci"
+/// token = "secret"
+///
+/// [[group]]
+/// name = "builds"
+/// group_id = "6f1c..."
+/// webhook = "https://alerts.example.com/hooks/chat"
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub endpoint: String,
+    /// Client database, in which `user` must be registered.
+    pub db_path: PathBuf,
+    pub user: String,
+    /// Address of the HTTP server for incoming webhooks.
+    #[serde(default = "default_listen")]
+    pub listen: SocketAddr,
+    /// Bearer token that incoming webhooks must send, if set.
+    pub token: Option<String>,
+    /// PEM file of the CA certificate to trust for the endpoint instead of the
+    /// system roots.
+    pub ca_cert: Option<PathBuf>,
+    #[serde(default, rename = "group")]
+    pub groups: Vec<BridgedGroup>,
+}
+
+/// A group whose messages are bridged.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BridgedGroup {
+    /// Name of the group in the path of its incoming webhook,
+    /// `POST /groups/<name>`.
+    pub name: String,
+    pub group_id: Uuid,
+    /// URL that the messages of the group are posted to, if any.
+    pub webhook: Option<String>,
+    /// Whether to join the group with an external commit if it's open and
+    /// there is no invite, see `open-group` of the client.
+    #[serde(default)]
+    pub join: bool,
+}
+
+fn default_listen() -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], 8080))
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let content = fs::read_to_string(path)
+            .map_err(|error| format!("Failed to read {}: {error}", path.display()))?;
+        let config: Self = toml::from_str(&content)
+            .map_err(|error| format!("Invalid config {}: {error}", path.display()))?;
+        for (i, group) in config.groups.iter().enumerate() {
+            if config.groups[..i]
+                .iter()
+                .any(|other| other.name == group.name)
+            {
+                return Err(format!("Group name {} is used twice", group.name).into());
+            }
+        }
+        Ok(config)
+    }
+
+    pub fn group(&self, group_id: Uuid) -> Option<&BridgedGroup> {
+        self.groups.iter().find(|group| group.group_id == group_id)
+    }
+}
@@ -0,0 +1,237 @@
+//! Relays messages between groups and HTTP webhooks: a `POST
+//! /groups/<name>` sends its body to the group, and messages of the group are
+//! posted as JSON to its webhook.
+
+use std::{path::PathBuf, sync::Arc};
+
+use axum::{
+    Router,
+    body::Bytes,
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode, header},
+    routing::post,
+};
+use clap::Parser;
+use mls_chat::client::{Client, daemon::ClientHandle, events::ChatEvent};
+use serde::Deserialize;
+use serde_json::json;
+use tokio::{net::TcpListener, sync::broadcast::error::RecvError};
+use tonic::transport::{Certificate, ClientTlsConfig};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::{config::Config, webhook::Webhooks};
+
+mod config;
+mod webhook;
+
+/// Bridge between MLS chat groups and HTTP webhooks
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// Configuration file
+    #[arg(
+        short,
+        long,
+        env = "MLS_CHAT_BRIDGE_CONFIG",
+        default_value = "bridge.toml"
+    )]
+    config: PathBuf,
+}
+
+/// Body of an incoming webhook with a JSON content type. Other bodies are
+/// sent as they are.
+#[derive(Deserialize)]
+struct IncomingMessage {
+    text: String,
+}
+
+struct Bridge {
+    config: Config,
+    client: ClientHandle,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let filter = tracing_subscriber::EnvFilter::builder()
+        .with_default_directive(tracing::metadata::LevelFilter::INFO.into())
+        .from_env_lossy();
+    tracing_subscriber::fmt::fmt()
+        .with_writer(std::io::stderr)
+        .with_env_filter(filter)
+        .init();
+    let args = Args::parse();
+    let config = Config::load(&args.config)?;
+
+    let mut builder = Client::builder(&config.endpoint, &config.db_path);
+    if let Some(path) = &config.ca_cert {
+        let pem = std::fs::read(path)?;
+        builder =
+            builder.with_tls(ClientTlsConfig::new().ca_certificate(Certificate::from_pem(pem)));
+    }
+    let mut client = builder.build().await?;
+    join_groups(&mut client, &config).await?;
+
+    let (client, mut events) = client.spawn(config.user.clone());
+    let listener = TcpListener::bind(config.listen).await?;
+    info!(listen = %config.listen, "Serving incoming webhooks");
+    let bridge = Arc::new(Bridge { config, client });
+    let router = Router::new()
+        .route("/groups/{name}", post(incoming))
+        .with_state(bridge.clone());
+    let server = tokio::spawn(async move { axum::serve(listener, router).await });
+
+    let webhooks = Webhooks::new()?;
+    loop {
+        let event = match events.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(missed)) => {
+                warn!(missed, "Bridge fell behind and missed messages");
+                continue;
+            }
+            Err(RecvError::Closed) => break,
+        };
+        match event {
+            ChatEvent::WelcomeReceived {
+                group_id, inviter, ..
+            } if bridge.config.group(group_id).is_some() => {
+                let user = bridge.config.user.clone();
+                match bridge
+                    .client
+                    .call(move |client| Box::pin(client.accept_invite(user, group_id)))
+                    .await
+                {
+                    Ok(()) => info!(%group_id, inviter, "Joined bridged group"),
+                    Err(error) => warn!(%group_id, %error, "Failed to accept invite"),
+                }
+            }
+            ChatEvent::Message {
+                group_id,
+                message_id,
+                sender,
+                text,
+                mentioned,
+            } if sender != bridge.config.user => {
+                let Some(group) = bridge.config.group(group_id) else {
+                    continue;
+                };
+                let Some(url) = group.webhook.clone() else {
+                    continue;
+                };
+                let payload = json!({
+                    "group": group.name,
+                    "group_id": group_id.to_string(),
+                    "message_id": message_id,
+                    "sender": sender,
+                    "text": text,
+                    "mentioned": mentioned,
+                });
+                // A slow webhook shouldn't hold up the other groups.
+                let webhooks = webhooks.clone();
+                tokio::spawn(async move {
+                    if let Err(error) = webhooks.post(&url, &payload).await {
+                        warn!(%group_id, message_id, %error, "Failed to post message to webhook");
+                    }
+                });
+            }
+            _ => {}
+        }
+    }
+    server.abort();
+    Err("The client stopped".into())
+}
+
+/// Makes sure that the user is a member of the configured groups, accepting
+/// pending invites to them or joining open ones. Others are joined once an
+/// invite arrives.
+async fn join_groups(
+    client: &mut Client,
+    config: &Config,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let user = &config.user;
+    let member_of: Vec<Uuid> = client
+        .list_groups(user.clone())
+        .await?
+        .into_iter()
+        .map(|group| group.group_id)
+        .collect();
+    let invited: Vec<Uuid> = client
+        .invites(user.clone())
+        .await?
+        .into_iter()
+        .map(|invite| invite.group_id)
+        .collect();
+    for group in &config.groups {
+        let group_id = group.group_id;
+        if member_of.contains(&group_id) {
+            continue;
+        }
+        if invited.contains(&group_id) {
+            client.accept_invite(user.clone(), group_id).await?;
+            info!(%group_id, name = group.name, "Accepted invite to bridged group");
+        } else if group.join {
+            client.join_group_externally(user.clone(), group_id).await?;
+            info!(%group_id, name = group.name, "Joined bridged group");
+        } else {
+            warn!(%group_id, name = group.name, "Not a member of bridged group, waiting for an invite");
+        }
+    }
+    Ok(())
+}
+
+/// Sends the body of an incoming webhook to the group.
+async fn incoming(
+    State(bridge): State<Arc<Bridge>>,
+    Path(name): Path<String>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> (StatusCode, String) {
+    if let Some(token) = &bridge.config.token {
+        let authorization = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        if !authorization.is_some_and(|given| constant_time_eq(given.as_bytes(), token.as_bytes()))
+        {
+            return (StatusCode::UNAUTHORIZED, "Invalid token".to_string());
+        }
+    }
+    let Some(group) = bridge.config.groups.iter().find(|group| group.name == name) else {
+        return (StatusCode::NOT_FOUND, format!("Unknown group {name}"));
+    };
+    let json = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/json"));
+    let text = if json {
+        match serde_json::from_slice::<IncomingMessage>(&body) {
+            Ok(message) => message.text,
+            Err(error) => return (StatusCode::BAD_REQUEST, format!("Invalid message: {error}")),
+        }
+    } else {
+        match String::from_utf8(body.to_vec()) {
+            Ok(text) => text,
+            Err(_) => return (StatusCode::BAD_REQUEST, "Message is not UTF-8".to_string()),
+        }
+    };
+    if text.trim().is_empty() {
+        return (StatusCode::BAD_REQUEST, "Empty message".to_string());
+    }
+
+    let (user, group_id) = (bridge.config.user.clone(), group.group_id);
+    match bridge
+        .client
+        .call(move |client| Box::pin(client.send(user, group_id, text)))
+        .await
+    {
+        Ok(_) => (StatusCode::NO_CONTENT, String::new()),
+        Err(error) => {
+            warn!(%group_id, %error, "Failed to send message of webhook");
+            (StatusCode::BAD_GATEWAY, error.to_string())
+        }
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
@@ -0,0 +1,47 @@
+use std::time::Duration;
+
+use axum::body::Bytes;
+use http::{Request, header::CONTENT_TYPE};
+use http_body_util::Full;
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use hyper_util::{
+    client::legacy::{Client, connect::HttpConnector},
+    rt::TokioExecutor,
+};
+use serde_json::Value;
+
+/// How long to wait for a webhook to answer.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Posts JSON to outgoing webhooks, over HTTPS with the system roots or plain
+/// HTTP.
+#[derive(Debug, Clone)]
+pub struct Webhooks {
+    client: Client<HttpsConnector<HttpConnector>, Full<Bytes>>,
+}
+
+impl Webhooks {
+    pub fn new() -> std::io::Result<Self> {
+        let connector = HttpsConnectorBuilder::new()
+            .with_native_roots()?
+            .https_or_http()
+            .enable_http1()
+            .build();
+        Ok(Self {
+            client: Client::builder(TokioExecutor::new()).build(connector),
+        })
+    }
+
+    pub async fn post(&self, url: &str, payload: &Value) -> Result<(), Box<dyn std::error::Error>> {
+        let request = Request::post(url)
+            .header(CONTENT_TYPE, "application/json")
+            .body(Full::new(Bytes::from(serde_json::to_vec(payload)?)))?;
+        let response = tokio::time::timeout(TIMEOUT, self.client.request(request))
+            .await
+            .map_err(|_| "Webhook timed out")??;
+        if !response.status().is_success() {
+            return Err(format!("Webhook answered {}", response.status()).into());
+        }
+        Ok(())
+    }
+}
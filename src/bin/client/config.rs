@@ -0,0 +1,118 @@
+use std::{env, fs, io, path::PathBuf};
+
+use mls_chat::client::policy::AcceptPolicy;
+use serde::Deserialize;
+use tonic::transport::{Certificate, ClientTlsConfig};
+
+/// Settings of `config.toml` in the `mls-chat` configuration directory, or the
+/// file named by `MLS_CHAT_CONFIG`. Environment variables override the file,
+/// and flags override both.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Server to connect to, overridden by `--endpoint`.
+    pub endpoint: Option<String>,
+    /// Directory of the per-user databases, `MLS_CHAT_DB_DIR`.
+    pub db_dir: Option<PathBuf>,
+    /// User to act as without `--user`, `MLS_CHAT_USER`.
+    pub user: Option<String>,
+    /// Directory of received attachments, `MLS_CHAT_DOWNLOAD_DIR`.
+    pub download_dir: Option<PathBuf>,
+    pub tls: TlsConfig,
+    pub notifications: NotificationConfig,
+    pub policy: PolicyConfig,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsConfig {
+    /// PEM file of the CA certificate to trust instead of the system roots.
+    pub ca_cert: Option<PathBuf>,
+    /// Name to verify the server certificate against, if it differs from the
+    /// host of the endpoint.
+    pub domain: Option<String>,
+}
+
+/// Desktop notifications of `receive --follow`.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NotificationConfig {
+    pub enabled: bool,
+    pub show_content: bool,
+}
+
+impl Default for NotificationConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            show_content: true,
+        }
+    }
+}
+
+/// What the client accepts without asking, see `AcceptPolicy`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PolicyConfig {
+    /// Accept invites from contacts whose keys were verified right away.
+    pub auto_accept_verified: bool,
+    /// Reject commits that add members unless an admin added them.
+    pub adds_from_admins_only: bool,
+    /// Reject groups with more members.
+    pub max_members: Option<usize>,
+}
+
+impl From<&PolicyConfig> for AcceptPolicy {
+    fn from(policy: &PolicyConfig) -> Self {
+        Self {
+            auto_accept_verified: policy.auto_accept_verified,
+            adds_from_admins_only: policy.adds_from_admins_only,
+            max_members: policy.max_members,
+        }
+    }
+}
+
+impl Config {
+    pub fn load() -> Result<Self, Box<dyn std::error::Error>> {
+        let path = match env::var_os("MLS_CHAT_CONFIG") {
+            Some(path) => Some(PathBuf::from(path)),
+            None => dirs::config_dir().map(|dir| dir.join("mls-chat").join("config.toml")),
+        };
+        let mut config = match path {
+            Some(path) => match fs::read_to_string(&path) {
+                Ok(content) => toml::from_str(&content)
+                    .map_err(|error| format!("Invalid config {}: {error}", path.display()))?,
+                Err(error) if error.kind() == io::ErrorKind::NotFound => Self::default(),
+                Err(error) => return Err(error.into()),
+            },
+            None => Self::default(),
+        };
+
+        if let Some(db_dir) = env::var_os("MLS_CHAT_DB_DIR") {
+            config.db_dir = Some(db_dir.into());
+        }
+        if let Ok(user) = env::var("MLS_CHAT_USER") {
+            config.user = Some(user);
+        }
+        if let Some(download_dir) = env::var_os("MLS_CHAT_DOWNLOAD_DIR") {
+            config.download_dir = Some(download_dir.into());
+        }
+        Ok(config)
+    }
+
+    /// TLS settings of the connection, if any are configured.
+    pub fn client_tls(&self) -> io::Result<Option<ClientTlsConfig>> {
+        if self.tls.ca_cert.is_none() && self.tls.domain.is_none() {
+            return Ok(None);
+        }
+        let mut tls = ClientTlsConfig::new();
+        match &self.tls.ca_cert {
+            Some(path) => tls = tls.ca_certificate(Certificate::from_pem(fs::read(path)?)),
+            None => tls = tls.with_native_roots(),
+        }
+        if let Some(domain) = &self.tls.domain {
+            tls = tls.domain_name(domain);
+        }
+        Ok(Some(tls))
+    }
+}
@@ -0,0 +1,1933 @@
+use std::{path::PathBuf, pin::pin, sync::Arc, time::Duration};
+
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use clap_complete::Shell;
+use config::Config;
+use futures_util::{Stream, TryStreamExt};
+use mls_chat::{
+    client::{
+        Client, ClientError, GroupConfig,
+        context::GroupChange,
+        debug::TreeNode,
+        events::ChatEvent,
+        group::GroupSummary,
+        history::{Direction, HistoryFormat, HistoryMessage, SendStatus},
+        member::fingerprint,
+        notify::Notifications,
+        policy::RequiredCapabilities,
+        validator::Allowlist,
+    },
+    grpc::GroupMetadata,
+};
+use openmls::prelude::Ciphersuite;
+use qrcode::{QrCode, render::unicode::Dense1x2};
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+mod config;
+mod script;
+mod tui;
+
+/// Command line client of the MLS chat server
+#[derive(Parser)]
+#[command(name = env!("CARGO_BIN_NAME"), author, version, long_about = None)]
+struct Args {
+    /// User to act as, optional if configured or if the database has a single
+    /// profile
+    #[arg(short, long)]
+    user: Option<String>,
+    /// Server to connect to
+    #[arg(long, global = true, env = "MLS_CHAT_ENDPOINT")]
+    endpoint: Option<String>,
+    /// Database of the client, shared by several users if given
+    #[arg(long, alias = "db", global = true, env = "MLS_CHAT_DB_PATH")]
+    db_path: Option<PathBuf>,
+    /// Passphrase that the database is encrypted with. Encrypts it if it
+    /// isn't encrypted yet
+    #[arg(
+        long,
+        global = true,
+        env = "MLS_CHAT_DB_PASSPHRASE",
+        hide_env_values = true,
+        conflicts_with = "db_key_file"
+    )]
+    db_passphrase: Option<String>,
+    /// File whose content is used like the passphrase of the database
+    #[arg(long, global = true, env = "MLS_CHAT_DB_KEY_FILE")]
+    db_key_file: Option<PathBuf>,
+    /// Store the MLS state as CBOR, converting a database that uses JSON
+    #[arg(long, global = true, env = "MLS_CHAT_CBOR_STORAGE")]
+    cbor_storage: bool,
+    /// Keep the signature private keys in the keychain of the OS
+    #[cfg(feature = "keychain")]
+    #[arg(long, global = true, env = "MLS_CHAT_KEYCHAIN")]
+    keychain: bool,
+    /// Accept only members listed in this file, one identity or `*@domain`
+    /// per line
+    #[arg(long, global = true, env = "MLS_CHAT_ALLOWLIST")]
+    allowlist: Option<PathBuf>,
+    #[command(flatten)]
+    group_config: GroupConfigArgs,
+    /// Update own keys of groups on receive once they are older than this
+    #[arg(long, global = true)]
+    rotate_keys_after_days: Option<u64>,
+    /// Delete key material on receive once it is older than this, see the
+    /// maintenance command
+    #[arg(long, global = true)]
+    prune_after_days: Option<u64>,
+    /// Send text messages in the MIMI content format, for groups with other
+    /// MLS-based messengers
+    #[arg(long, global = true)]
+    mimi_content: bool,
+    /// Let own messages in the MIMI content format expire after this many
+    /// minutes
+    #[arg(long, global = true)]
+    message_expiry_minutes: Option<u64>,
+    /// Directory in which received attachments are saved
+    #[arg(long, global = true)]
+    download_dir: Option<PathBuf>,
+    /// Format of listed groups, messages and received events
+    #[arg(long, global = true, value_enum, default_value_t = Output::Text)]
+    output: Output,
+    #[command(subcommand)]
+    command: Commands,
+}
+
+/// Tolerance for delayed messages of groups created or joined by this command.
+#[derive(clap::Args)]
+struct GroupConfigArgs {
+    /// Skipped messages per sender that can still be decrypted later
+    #[arg(long, global = true, default_value_t = GroupConfig::default().out_of_order_tolerance)]
+    out_of_order_tolerance: u32,
+    /// Messages of a sender that may be skipped at once
+    #[arg(long, global = true, default_value_t = GroupConfig::default().maximum_forward_distance)]
+    maximum_forward_distance: u32,
+    /// Past epochs in which application messages can still be decrypted
+    #[arg(long, global = true, default_value_t = GroupConfig::default().max_past_epochs)]
+    max_past_epochs: usize,
+}
+
+impl From<GroupConfigArgs> for GroupConfig {
+    fn from(args: GroupConfigArgs) -> Self {
+        Self {
+            out_of_order_tolerance: args.out_of_order_tolerance,
+            maximum_forward_distance: args.maximum_forward_distance,
+            max_past_epochs: args.max_past_epochs,
+        }
+    }
+}
+
+// Settings of a group to change, see `Client::change_group`.
+#[derive(clap::Args)]
+struct GroupChangeArgs {
+    #[arg(short, long)]
+    group: String,
+    #[arg(short, long)]
+    name: Option<String>,
+    #[arg(short, long)]
+    topic: Option<String>,
+    /// Replace the admins of the group, repeated for each admin
+    #[arg(long = "admin")]
+    admins: Vec<String>,
+    /// Let messages sent to the group disappear after this many minutes, 0
+    /// for never
+    #[arg(long)]
+    message_timer_minutes: Option<u64>,
+}
+
+impl From<GroupChangeArgs> for GroupChange {
+    fn from(args: GroupChangeArgs) -> Self {
+        Self {
+            name: args.name,
+            topic: args.topic,
+            admins: (!args.admins.is_empty()).then_some(args.admins),
+            message_timer: args
+                .message_timer_minutes
+                .map(|minutes| Duration::from_secs(minutes * 60)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Output {
+    Text,
+    /// One JSON object per line
+    Json,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ExportFormat {
+    Json,
+    Md,
+}
+
+#[derive(Subcommand)]
+enum AliasCommands {
+    /// Name a group, replacing the group the alias named before
+    Set { alias: String, group: String },
+    /// Remove an alias
+    Remove { alias: String },
+    /// List the aliases
+    List,
+}
+
+#[derive(Subcommand)]
+enum PoisonCommands {
+    /// List the received messages that couldn't be processed
+    List,
+    /// Process a quarantined message again, e.g. after updating the client
+    Retry { message_id: i64 },
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// Print the completion script of a shell
+    Completions { shell: Shell },
+    /// Print the manual page, or write one per command to a directory
+    Man {
+        #[arg(long)]
+        dir: Option<PathBuf>,
+    },
+    /// List the users registered in the database
+    Profiles,
+    /// Write the database, with the keys and groups of all its users, to a
+    /// passphrase-encrypted file
+    Backup {
+        #[arg(long)]
+        out: PathBuf,
+        #[arg(long, env = "MLS_CHAT_PASSPHRASE", hide_env_values = true)]
+        passphrase: String,
+    },
+    /// Restore the database from a backup, e.g. on a new machine. The
+    /// database the backup was taken from must not be used anymore
+    Restore {
+        #[arg(long = "in")]
+        input: PathBuf,
+        #[arg(long, env = "MLS_CHAT_PASSPHRASE", hide_env_values = true)]
+        passphrase: String,
+    },
+    /// Move a group to another device of the user: write its state to a
+    /// passphrase-encrypted file and delete it here
+    ExportGroup {
+        #[arg(short, long)]
+        group: String,
+        #[arg(long)]
+        out: PathBuf,
+        #[arg(long, env = "MLS_CHAT_PASSPHRASE", hide_env_values = true)]
+        passphrase: String,
+    },
+    /// Import a group written by export-group on another device of the user
+    ImportGroup {
+        #[arg(long = "in")]
+        input: PathBuf,
+        #[arg(long, env = "MLS_CHAT_PASSPHRASE", hide_env_values = true)]
+        passphrase: String,
+    },
+    /// Register a new user
+    Register {
+        /// Default ciphersuite of new groups, as IANA code point
+        #[arg(long, value_parser = parse_ciphersuite, default_value = "0x0003")]
+        ciphersuite: Ciphersuite,
+    },
+    /// Create a new group
+    CreateGroup {
+        #[arg(short, long)]
+        name: Option<String>,
+        #[arg(short, long)]
+        topic: Option<String>,
+        /// Let anyone join with an external commit
+        #[arg(long)]
+        open: bool,
+        /// Only let admins send messages
+        #[arg(long)]
+        read_only: bool,
+        /// Ciphersuite as IANA code point; defaults to the one chosen at
+        /// registration
+        #[arg(long, value_parser = parse_ciphersuite)]
+        ciphersuite: Option<Ciphersuite>,
+        /// Extension type that members must support
+        #[arg(long, value_parser = parse_code)]
+        require_extension: Vec<u16>,
+        /// Credential type that members must support
+        #[arg(long, value_parser = parse_code)]
+        require_credential: Vec<u16>,
+        /// Additional ciphersuite that members must support, as IANA code point
+        #[arg(long, value_parser = parse_ciphersuite)]
+        require_ciphersuite: Vec<Ciphersuite>,
+    },
+    /// Change the name or topic of a group
+    SetGroupName {
+        #[arg(short, long)]
+        group: String,
+        #[arg(short, long)]
+        name: Option<String>,
+        #[arg(short, long)]
+        topic: Option<String>,
+    },
+    /// Change the name, topic, admins or message timer of a group with a
+    /// commit
+    ChangeGroup {
+        #[command(flatten)]
+        change: GroupChangeArgs,
+    },
+    /// Propose a change of the settings of a group, to be committed by an
+    /// admin
+    ProposeChange {
+        #[command(flatten)]
+        change: GroupChangeArgs,
+    },
+    /// Show the settings of a group
+    GroupSettings {
+        #[arg(short, long)]
+        group: String,
+    },
+    /// Let anyone who knows the group id join it
+    OpenGroup {
+        #[arg(short, long)]
+        group: String,
+    },
+    /// Stop accepting external joins to a group
+    CloseGroup {
+        #[arg(short, long)]
+        group: String,
+    },
+    /// Only let admins send messages to a group
+    SetReadOnly {
+        #[arg(short, long)]
+        group: String,
+        /// Let all members send messages again
+        #[arg(long)]
+        off: bool,
+    },
+    /// Mark the received messages of a group as read
+    MarkRead {
+        #[arg(short, long)]
+        group: String,
+    },
+    /// Send read receipts to a group, which is the default
+    ReadReceipts {
+        #[arg(short, long)]
+        group: String,
+        /// Stop telling the group which messages were read
+        #[arg(long)]
+        off: bool,
+    },
+    /// Suppress notifications of a group
+    Mute {
+        #[arg(short, long)]
+        group: String,
+        /// Show notifications again
+        #[arg(long)]
+        off: bool,
+    },
+    /// Hide a group from listings while staying a member
+    Archive {
+        #[arg(short, long)]
+        group: String,
+        /// List the group again
+        #[arg(long)]
+        off: bool,
+    },
+    /// Replace a group with a new one with the same members, e.g. to change
+    /// the ciphersuite
+    ReinitGroup {
+        #[arg(short, long)]
+        group: String,
+        /// Ciphersuite of the new group as IANA code point, e.g. 0x0003
+        #[arg(long, value_parser = parse_ciphersuite)]
+        ciphersuite: Option<Ciphersuite>,
+    },
+    /// Join an open group with an external commit
+    JoinGroup {
+        #[arg(short, long)]
+        group: String,
+    },
+    /// List the groups the user is a member of
+    ListGroups {
+        /// Include archived groups
+        #[arg(long)]
+        all: bool,
+    },
+    /// Show the stored messages of a group
+    History {
+        #[arg(short, long)]
+        group: String,
+        /// Maximum number of messages to show
+        #[arg(long, default_value_t = 50)]
+        limit: u32,
+        /// Show only messages older than this message id, to page back
+        #[arg(long, conflicts_with = "thread")]
+        before: Option<i64>,
+        /// Show this message and all replies to it
+        #[arg(long)]
+        thread: Option<i64>,
+    },
+    /// Write the stored messages of a group to a file
+    ExportHistory {
+        #[arg(short, long)]
+        group: String,
+        #[arg(long, value_enum, default_value_t = ExportFormat::Json)]
+        format: ExportFormat,
+        /// Output file; prints to stdout if not given
+        #[arg(long)]
+        out: Option<PathBuf>,
+    },
+    /// Update own key material in the group
+    UpdateGroup {
+        #[arg(short, long)]
+        group: String,
+    },
+    /// Update own key material in all groups where it is older than the
+    /// given age, and delete replaced key packages and the state of groups
+    /// left before it
+    Maintenance {
+        #[arg(long, default_value_t = 7)]
+        max_age_days: u64,
+    },
+    /// List the key packages of this device and where they are in their life
+    KeyPackages,
+    /// Add members to a group with a single commit
+    AddMember {
+        #[arg(short, long)]
+        group: String,
+        #[arg(short, long = "member", required = true)]
+        members: Vec<String>,
+        /// Share this many recent messages with the new members
+        #[arg(long)]
+        share_history: Option<u32>,
+    },
+    /// Propose adding a member, to be committed later
+    ProposeAdd {
+        #[arg(short, long)]
+        group: String,
+        #[arg(short, long)]
+        member: String,
+    },
+    /// Propose removing a member, to be committed later
+    ProposeRemove {
+        #[arg(short, long)]
+        group: String,
+        #[arg(short, long)]
+        member: String,
+    },
+    /// Commit all pending proposals of a group
+    CommitPending {
+        #[arg(short, long)]
+        group: String,
+    },
+    /// Leave a group
+    LeaveGroup {
+        #[arg(short, long)]
+        group: String,
+    },
+    /// List the members of a group
+    ListMembers {
+        #[arg(short, long)]
+        group: String,
+    },
+    /// Remove members from a group with a single commit
+    RemoveMember {
+        #[arg(short, long)]
+        group: String,
+        #[arg(short, long = "member", required = true)]
+        members: Vec<String>,
+    },
+    /// Make a member an admin of a group
+    Promote {
+        #[arg(short, long)]
+        group: String,
+        #[arg(short, long)]
+        member: String,
+    },
+    /// Revoke the admin role of a member
+    Demote {
+        #[arg(short, long)]
+        group: String,
+        #[arg(short, long)]
+        member: String,
+    },
+    /// Remove a member from a group and prevent it from being added again
+    Ban {
+        #[arg(short, long)]
+        group: String,
+        #[arg(short, long)]
+        member: String,
+    },
+    /// Allow a banned member to be added to a group again
+    Unban {
+        #[arg(short, long)]
+        group: String,
+        #[arg(short, long)]
+        member: String,
+    },
+    /// List the admins of a group
+    Admins {
+        #[arg(short, long)]
+        group: String,
+    },
+    /// Send a message to a group
+    Send {
+        #[arg(short, long)]
+        group: String,
+        /// Id of the message in the history to reply to
+        #[arg(long)]
+        reply_to: Option<i64>,
+        message: String,
+    },
+    /// Change the text of an own message for all members
+    Edit {
+        #[arg(short, long)]
+        group: String,
+        /// Id of the message in the history
+        #[arg(short, long)]
+        message: i64,
+        text: String,
+    },
+    /// Delete an own message for all members
+    DeleteMessage {
+        #[arg(short, long)]
+        group: String,
+        /// Id of the message in the history
+        #[arg(short, long)]
+        message: i64,
+    },
+    /// React to a message in the MIMI content format, e.g. with an emoji
+    React {
+        #[arg(short, long)]
+        group: String,
+        /// Id of the message in the history
+        #[arg(short, long)]
+        message: i64,
+        reaction: String,
+    },
+    /// Pin a message to a group for all members
+    Pin {
+        #[arg(short, long)]
+        group: String,
+        /// Id of the message in the history
+        #[arg(short, long)]
+        message: i64,
+        /// Unpin the message instead
+        #[arg(long)]
+        off: bool,
+    },
+    /// List the pinned messages of a group
+    Pins {
+        #[arg(short, long)]
+        group: String,
+    },
+    /// Show the earlier versions of an edited message
+    Edits {
+        /// Id of the message in the history
+        #[arg(short, long)]
+        message: i64,
+    },
+    /// Send a message to a one-on-one conversation, creating it if needed
+    Dm { peer: String, message: String },
+    /// Send a file as end-to-end encrypted attachment
+    SendFile {
+        #[arg(short, long)]
+        group: String,
+        path: PathBuf,
+    },
+    /// Receive messages
+    Receive {
+        /// Keep receiving and reconnect when the connection fails
+        #[arg(long)]
+        follow: bool,
+        /// Show desktop notifications of incoming messages
+        #[arg(long, requires = "follow")]
+        notify: bool,
+        /// Leave the text of messages out of notifications
+        #[arg(long, requires = "follow")]
+        hide_content: bool,
+    },
+    /// Full-screen chat with a list of groups
+    Chat,
+    /// Chat interactively with slash commands, printing incoming messages
+    Repl,
+    /// Run JSON commands from stdin, one per line, printing a JSON result per
+    /// command and the incoming messages over a single connection
+    Script,
+    /// Manage local names of groups, usable instead of group ids
+    Alias {
+        #[command(subcommand)]
+        command: AliasCommands,
+    },
+    /// List pending invites to groups
+    Invites {},
+    /// Join the group of a pending invite
+    AcceptInvite {
+        #[arg(short, long)]
+        group: String,
+    },
+    /// Discard a pending invite
+    DeclineInvite {
+        #[arg(short, long)]
+        group: String,
+    },
+    /// Show the number of messages pending on the server
+    QueueStatus {},
+    /// Write the messages and key packages spooled since the last export to a
+    /// file, to be imported by the other clients. Needs a file:// endpoint
+    ExportMessage {
+        #[arg(long)]
+        out: PathBuf,
+    },
+    /// Process the messages of a file written by export-message. Needs a
+    /// file:// endpoint
+    ImportMessage { path: PathBuf },
+    /// Inspect and retry received messages that couldn't be processed
+    Poison {
+        #[command(subcommand)]
+        command: PoisonCommands,
+    },
+    /// Show the identity and key material of the user
+    Whoami,
+    /// Link this client as a new device of an already registered user
+    LinkDevice {},
+    /// Approve a new device with the link code it printed
+    ApproveDevice { code: String },
+    /// List the devices of the user
+    ListDevices {},
+    /// Revoke a linked device and remove it from all groups
+    RevokeDevice {
+        #[arg(short, long)]
+        device: String,
+    },
+    /// Replace the signature key of this device and move all groups to it
+    RotateIdentityKey {},
+    /// Trust the changed signature keys of a contact after verifying them
+    Trust { contact: String },
+    /// Check the signature keys of a user against the key transparency log
+    VerifyKeys {
+        #[arg(short, long)]
+        member: String,
+    },
+    /// Show the safety number to compare with another user
+    Fingerprint {
+        #[arg(long = "user")]
+        contact: String,
+        /// Show the safety number as QR code for the other user to scan
+        #[arg(long)]
+        qr: bool,
+    },
+    /// Mark the keys of another user as verified after comparing safety
+    /// numbers, or by the payload of their scanned QR code
+    Verify {
+        #[arg(
+            long = "user",
+            required_unless_present = "qr",
+            conflicts_with = "qr",
+            requires = "code"
+        )]
+        contact: Option<String>,
+        /// Safety number shown by `fingerprint` and compared with the other user
+        #[arg(long, conflicts_with = "qr")]
+        code: Option<String>,
+        #[arg(long)]
+        qr: Option<String>,
+    },
+    /// Show the code of the current epoch to compare with the other members
+    VerifyGroup {
+        #[arg(short, long)]
+        group: String,
+    },
+    /// Print the epoch, tree hash, confirmed transcript hash and own leaf of a
+    /// group, to compare with a member that disagrees about its state
+    DebugGroup {
+        #[arg(short, long)]
+        group: String,
+        /// Also draw the ratchet tree, with a digest of each node
+        #[arg(long)]
+        tree: bool,
+    },
+    /// Print the epoch authenticator of a group in hex
+    EpochAuthenticator {
+        #[arg(short, long)]
+        group: String,
+    },
+    /// Derive a secret from the current epoch of a group and print it in hex
+    ExportSecret {
+        #[arg(short, long)]
+        group: String,
+        #[arg(long)]
+        label: String,
+        #[arg(long, default_value = "")]
+        context: String,
+        /// Length of the secret in bytes
+        #[arg(long, default_value_t = 32)]
+        length: usize,
+    },
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = init();
+    let output = args.output;
+    match &args.command {
+        Commands::Completions { shell } => {
+            let mut command = Args::command();
+            clap_complete::generate(
+                *shell,
+                &mut command,
+                env!("CARGO_BIN_NAME"),
+                &mut std::io::stdout(),
+            );
+            return Ok(());
+        }
+        Commands::Man { dir: Some(dir) } => {
+            std::fs::create_dir_all(dir)?;
+            clap_mangen::generate_to(Args::command(), dir)?;
+            return Ok(());
+        }
+        Commands::Man { dir: None } => {
+            clap_mangen::Man::new(Args::command()).render(&mut std::io::stdout())?;
+            return Ok(());
+        }
+        _ => {}
+    }
+
+    let config = Config::load()?;
+    let user = args.user.or_else(|| config.user.clone());
+    let db_path = match (&args.db_path, &user) {
+        (Some(db), _) => db.clone(),
+        (None, Some(user)) => config
+            .db_dir
+            .clone()
+            .unwrap_or_else(|| PathBuf::from("db"))
+            .join(format!("client-{user}.db")),
+        (None, None) => return Err("No user given, pass --user or configure one".into()),
+    };
+    let endpoint = args
+        .endpoint
+        .or_else(|| config.endpoint.clone())
+        .unwrap_or_else(|| "http://localhost:50051".to_string());
+    let download_dir = args
+        .download_dir
+        .or_else(|| config.download_dir.clone())
+        .unwrap_or_else(|| PathBuf::from("downloads"));
+
+    if let Commands::Restore { input, passphrase } = &args.command {
+        Client::restore_backup(input, passphrase, &db_path).await?;
+        println!("Restored {}", db_path.display());
+        return Ok(());
+    }
+
+    let mut builder = Client::builder(endpoint, db_path);
+    if let Some(tls) = config.client_tls()? {
+        builder = builder.with_tls(tls);
+    }
+    #[cfg(feature = "quic")]
+    if let Some(path) = &config.tls.ca_cert {
+        builder = builder.with_quic_ca_certificate(std::fs::read(path)?);
+    }
+    if let Some(passphrase) = args.db_passphrase {
+        builder = builder.with_passphrase(passphrase);
+    } else if let Some(path) = args.db_key_file {
+        builder = builder.with_key_file(path);
+    }
+    if args.cbor_storage {
+        builder = builder.with_cbor_storage();
+    }
+    #[cfg(feature = "keychain")]
+    if args.keychain {
+        builder = builder.with_keychain();
+    }
+    if let Some(path) = &args.allowlist {
+        builder = builder.with_credential_validator(Arc::new(Allowlist::load(path)?));
+    }
+    let mut client = builder
+        .build()
+        .await?
+        .with_group_config(args.group_config.into())
+        .with_download_dir(download_dir)
+        .with_accept_policy((&config.policy).into());
+    if let Some(days) = args.rotate_keys_after_days {
+        client = client.with_key_rotation(Duration::from_secs(days * 24 * 60 * 60));
+    }
+    if let Some(days) = args.prune_after_days {
+        client = client.with_pruning(Duration::from_secs(days * 24 * 60 * 60));
+    }
+    if args.mimi_content {
+        client = client.with_mimi_content();
+    }
+    if let Some(minutes) = args.message_expiry_minutes {
+        client = client.with_message_expiry(Duration::from_secs(minutes * 60));
+    }
+    match &args.command {
+        Commands::Profiles => {
+            for profile in client.profiles().await? {
+                println!("{profile}");
+            }
+            return Ok(());
+        }
+        Commands::Backup { out, passphrase } => {
+            client.backup(out, passphrase).await?;
+            return Ok(());
+        }
+        _ => {}
+    }
+    let user = match user {
+        Some(user) => user,
+        None => match client.profiles().await?.as_slice() {
+            [user] => user.clone(),
+            [] => {
+                return Err("No user registered in the database, register one with --user".into());
+            }
+            _ => {
+                return Err(
+                    "Several users registered in the database, choose one with --user".into(),
+                );
+            }
+        },
+    };
+    if !matches!(args.command, Commands::Register { .. }) {
+        client.switch_profile(&user).await?;
+    }
+    // Messages that couldn't be sent before are retried first, in order.
+    if let Err(error) = client.flush_outbox(&user).await {
+        warn!(%error, "Failed to send queued messages");
+    }
+
+    match args.command {
+        Commands::Completions { .. } | Commands::Man { .. } => {
+            unreachable!("printed before opening the database")
+        }
+        Commands::Restore { .. } => unreachable!("restored before opening the database"),
+        Commands::Profiles | Commands::Backup { .. } => {
+            unreachable!("run before choosing the user")
+        }
+        Commands::ExportGroup {
+            group,
+            out,
+            passphrase,
+        } => {
+            let group = client.resolve_group(&user, &group).await?;
+            info!(%group, "Exporting group");
+            client.export_group(user, group, &out, &passphrase).await?;
+        }
+        Commands::ImportGroup { input, passphrase } => {
+            let group = client.import_group(user, &input, &passphrase).await?;
+            println!("{group}");
+        }
+        Commands::Register { ciphersuite } => {
+            info!(user = user, "Registering user");
+            client.register(user, ciphersuite).await?;
+        }
+        Commands::CreateGroup {
+            name,
+            topic,
+            open,
+            read_only,
+            ciphersuite,
+            require_extension,
+            require_credential,
+            require_ciphersuite,
+        } => {
+            info!("Creating group");
+            let required = RequiredCapabilities {
+                extensions: require_extension.into_iter().map(Into::into).collect(),
+                credentials: require_credential.into_iter().map(Into::into).collect(),
+                ciphersuites: require_ciphersuite,
+            };
+            let metadata =
+                (name.is_some() || topic.is_some() || open || read_only).then(|| GroupMetadata {
+                    name: name.unwrap_or_default(),
+                    topic: topic.unwrap_or_default(),
+                    open,
+                    read_only,
+                    ..Default::default()
+                });
+            let group_id = client
+                .create_group(user, metadata, ciphersuite, required)
+                .await?;
+            println!("{group_id}");
+        }
+        Commands::SetGroupName { group, name, topic } => {
+            let group = client.resolve_group(&user, &group).await?;
+            info!(%group, "Changing group name");
+            client.set_group_metadata(user, group, name, topic).await?;
+        }
+        Commands::ChangeGroup { change } => {
+            let group = client.resolve_group(&user, &change.group).await?;
+            info!(%group, "Changing group settings");
+            client.change_group(user, group, change.into()).await?;
+        }
+        Commands::ProposeChange { change } => {
+            let group = client.resolve_group(&user, &change.group).await?;
+            info!(%group, "Proposing group settings change");
+            client
+                .propose_group_change(user, group, change.into())
+                .await?;
+        }
+        Commands::GroupSettings { group } => {
+            let group = client.resolve_group(&user, &group).await?;
+            let settings = client.group_settings(user, group).await?;
+            println!("name           {}", settings.name);
+            println!("topic          {}", settings.topic);
+            match settings.admins {
+                Some(admins) => println!("admins         {}", admins.join(", ")),
+                None => println!("admins         every member"),
+            }
+            match settings.message_timer {
+                Some(timer) => println!("message timer  {} minutes", timer.as_secs() / 60),
+                None => println!("message timer  off"),
+            }
+            println!("open           {}", settings.open);
+            println!("read-only      {}", settings.read_only);
+        }
+        Commands::OpenGroup { group } => {
+            let group = client.resolve_group(&user, &group).await?;
+            info!(%group, "Opening group");
+            client.set_group_open(user, group, true).await?;
+        }
+        Commands::CloseGroup { group } => {
+            let group = client.resolve_group(&user, &group).await?;
+            info!(%group, "Closing group");
+            client.set_group_open(user, group, false).await?;
+        }
+        Commands::SetReadOnly { group, off } => {
+            let group = client.resolve_group(&user, &group).await?;
+            info!(%group, read_only = !off, "Changing read-only mode");
+            client.set_group_read_only(user, group, !off).await?;
+        }
+        Commands::MarkRead { group } => {
+            let group = client.resolve_group(&user, &group).await?;
+            let read = client.mark_read(user, group).await?;
+            info!(%group, read, "Marked messages as read");
+        }
+        Commands::ReadReceipts { group, off } => {
+            let group = client.resolve_group(&user, &group).await?;
+            info!(%group, enabled = !off, "Changing read receipts");
+            client.set_read_receipts(user, group, !off).await?;
+        }
+        Commands::Mute { group, off } => {
+            let group = client.resolve_group(&user, &group).await?;
+            info!(%group, muted = !off, "Changing notifications");
+            client.set_muted(user, group, !off).await?;
+        }
+        Commands::Archive { group, off } => {
+            let group = client.resolve_group(&user, &group).await?;
+            info!(%group, archived = !off, "Changing archive state");
+            client.set_archived(user, group, !off).await?;
+        }
+        Commands::ReinitGroup { group, ciphersuite } => {
+            let group = client.resolve_group(&user, &group).await?;
+            info!(%group, "Re-initializing group");
+            let successor = client.reinit_group(user, group, ciphersuite).await?;
+            println!("{successor}");
+        }
+        Commands::JoinGroup { group } => {
+            let group = client.resolve_group(&user, &group).await?;
+            info!(%group, "Joining group");
+            client.join_group_externally(user, group).await?;
+        }
+        Commands::ListGroups { all } => {
+            let mut groups = client.list_groups(user).await?;
+            groups.retain(|group| all || !group.archived);
+            if output == Output::Json {
+                for group in &groups {
+                    println!("{}", group_json(group));
+                }
+            } else {
+                for group in groups {
+                    let last_activity = group
+                        .last_activity_at
+                        .map(|at| at.format("%Y-%m-%d %H:%M").to_string())
+                        .unwrap_or_else(|| "-".to_string());
+                    let flags = match (group.muted, group.archived) {
+                        (true, true) => "  (muted, archived)",
+                        (true, false) => "  (muted)",
+                        (false, true) => "  (archived)",
+                        (false, false) => "",
+                    };
+                    println!(
+                        "{}  {:<20}  {:<16}  {}  {}  {:>4}{flags}",
+                        group.group_id,
+                        group.name,
+                        group.creator,
+                        group.created_at.format("%Y-%m-%d %H:%M"),
+                        last_activity,
+                        group.unread,
+                    );
+                }
+            }
+        }
+        Commands::History {
+            group,
+            limit,
+            before,
+            thread,
+        } => {
+            let group = client.resolve_group(&user, &group).await?;
+            match thread {
+                Some(message_id) => {
+                    for (depth, message) in client.thread(user.clone(), group, message_id).await? {
+                        print_message(output, &user, depth, message);
+                    }
+                }
+                None => {
+                    for message in client.history(user.clone(), group, limit, before).await? {
+                        print_message(output, &user, 0, message);
+                    }
+                }
+            }
+        }
+        Commands::ExportHistory { group, format, out } => {
+            let group = client.resolve_group(&user, &group).await?;
+            let format = match format {
+                ExportFormat::Json => HistoryFormat::Json,
+                ExportFormat::Md => HistoryFormat::Markdown,
+            };
+            let export = client.export_history(user, group, format).await?;
+            match out {
+                Some(path) => std::fs::write(&path, export)?,
+                None => println!("{}", export.trim_end()),
+            }
+        }
+        Commands::UpdateGroup { group } => {
+            let group = client.resolve_group(&user, &group).await?;
+            info!(%group, "Updating group key material");
+            client.update_group(user, group).await?;
+        }
+        Commands::Send {
+            group,
+            reply_to,
+            message,
+        } => {
+            let group = client.resolve_group(&user, &group).await?;
+            info!(%group, "Sending message to group");
+            let sent = match reply_to {
+                Some(parent) => client.reply(user, group, parent, message).await?,
+                None => client.send(user, group, message).await?,
+            };
+            match sent.server_timestamp {
+                Some(timestamp) => info!(
+                    message_id = sent.message_id,
+                    server_id = ?sent.server_id,
+                    %timestamp,
+                    "Message sent"
+                ),
+                None => info!(message_id = sent.message_id, "Message queued"),
+            }
+        }
+        Commands::Edit {
+            group,
+            message,
+            text,
+        } => {
+            let group = client.resolve_group(&user, &group).await?;
+            info!(%group, message, "Editing message");
+            client.edit_message(user, group, message, text).await?;
+        }
+        Commands::DeleteMessage { group, message } => {
+            let group = client.resolve_group(&user, &group).await?;
+            info!(%group, message, "Deleting message");
+            client.delete_message(user, group, message).await?;
+        }
+        Commands::React {
+            group,
+            message,
+            reaction,
+        } => {
+            let group = client.resolve_group(&user, &group).await?;
+            info!(%group, message, "Reacting to message");
+            client.react(user, group, message, reaction).await?;
+        }
+        Commands::Pin {
+            group,
+            message,
+            off,
+        } => {
+            let group = client.resolve_group(&user, &group).await?;
+            info!(%group, message, pinned = !off, "Changing pin");
+            if off {
+                client.unpin_message(user, group, message).await?;
+            } else {
+                client.pin_message(user, group, message).await?;
+            }
+        }
+        Commands::Pins { group } => {
+            let group = client.resolve_group(&user, &group).await?;
+            for pin in client.pins(user, group).await? {
+                let message = match (pin.message_id, pin.sender, pin.body) {
+                    (Some(message_id), Some(sender), Some(body)) => {
+                        format!("{message_id:>6}  {sender}: {body}")
+                    }
+                    _ => format!("{:>6}  (message not available)", "-"),
+                };
+                println!(
+                    "{message}  (pinned by {} {})",
+                    pin.pinned_by,
+                    pin.pinned_at.format("%Y-%m-%d %H:%M")
+                );
+            }
+        }
+        Commands::Edits { message } => {
+            for edit in client.message_edits(user, message).await? {
+                println!("{}  {}", edit.edited_at.format("%Y-%m-%d %H:%M"), edit.body);
+            }
+        }
+        Commands::Dm { peer, message } => {
+            let group = client.dm(user, peer, message).await?;
+            info!(%group, "Sent direct message");
+        }
+        Commands::SendFile { group, path } => {
+            let group = client.resolve_group(&user, &group).await?;
+            info!(%group, path = %path.display(), "Sending file to group");
+            client.send_file(user, group, &path).await?;
+        }
+        Commands::Receive {
+            follow,
+            notify,
+            hide_content,
+        } => {
+            info!("Receiving messages");
+            if follow && (notify || config.notifications.enabled) {
+                client = client.with_notifications(Notifications {
+                    show_content: config.notifications.show_content && !hide_content,
+                });
+            }
+            if follow {
+                print_events(output, client.follow(user)).await?;
+            } else {
+                print_events(output, client.receive(user)).await?;
+            }
+        }
+        Commands::Chat => tui::chat(&mut client, user).await?,
+        Commands::Repl => repl(&mut client, user).await?,
+        Commands::Script => script::run(&mut client, user).await?,
+        Commands::Alias { command } => match command {
+            AliasCommands::Set { alias, group } => {
+                let group = client.resolve_group(&user, &group).await?;
+                client.set_alias(user, alias, group).await?;
+            }
+            AliasCommands::Remove { alias } => client.remove_alias(user, alias).await?,
+            AliasCommands::List => {
+                for alias in client.aliases(user).await? {
+                    println!("{:<20}  {}", alias.alias, alias.group_id);
+                }
+            }
+        },
+        Commands::Invites {} => {
+            for invite in client.invites(user).await? {
+                println!(
+                    "{}  {:<20}  from {:<16}  members: {}",
+                    invite.group_id,
+                    invite.name,
+                    invite.inviter,
+                    invite.members.join(", "),
+                );
+            }
+        }
+        Commands::AcceptInvite { group } => {
+            let group = client.resolve_group(&user, &group).await?;
+            info!(%group, "Accepting invite");
+            client.accept_invite(user, group).await?;
+        }
+        Commands::DeclineInvite { group } => {
+            let group = client.resolve_group(&user, &group).await?;
+            info!(%group, "Declining invite");
+            client.decline_invite(user, group).await?;
+        }
+        Commands::Maintenance { max_age_days } => {
+            let max_age = Duration::from_secs(max_age_days * 24 * 60 * 60);
+            let pruned = client.prune_key_material(user, max_age).await?;
+            for group in pruned.updated_groups {
+                println!("Updated keys in {group}");
+            }
+            println!(
+                "Deleted {} replaced key packages and the state of {} left groups",
+                pruned.key_packages, pruned.groups
+            );
+        }
+        Commands::KeyPackages => {
+            for key_package in client.key_packages(user).await? {
+                let state = match key_package.consumed_by {
+                    Some(group) => format!("{} by {group}", key_package.state.as_str()),
+                    None => key_package.state.as_str().to_string(),
+                };
+                println!(
+                    "{}  {:#06x}  {:<8}  created {}  expires {}  {}",
+                    &hex(&key_package.key_package_ref)[..16],
+                    key_package.ciphersuite,
+                    state,
+                    key_package.created_at.format("%Y-%m-%d %H:%M"),
+                    key_package.expires_at.format("%Y-%m-%d"),
+                    match key_package.deleted_at {
+                        Some(deleted_at) =>
+                            format!("keys deleted {}", deleted_at.format("%Y-%m-%d %H:%M")),
+                        None => "keys kept".to_string(),
+                    },
+                );
+            }
+        }
+        Commands::QueueStatus {} => {
+            let status = client.queue_status(user).await?;
+            println!(
+                "{} messages ({} bytes) pending",
+                status.pending_messages, status.pending_bytes
+            );
+        }
+        Commands::ExportMessage { out } => {
+            let messages = client.export_message_file(&out).await?;
+            info!(messages, "Exported messages");
+        }
+        Commands::ImportMessage { path } => {
+            let messages = client.import_message_file(user, &path).await?;
+            info!(messages, "Imported messages");
+        }
+        Commands::Poison { command } => match command {
+            PoisonCommands::List => {
+                for message in client.poison_messages(user).await? {
+                    let group_id = message
+                        .group_id
+                        .map_or("-".to_string(), |group_id| group_id.to_string());
+                    let epoch = message
+                        .epoch
+                        .map_or("-".to_string(), |epoch| epoch.to_string());
+                    println!(
+                        "{:>4}  {}  {group_id:<36}  epoch {epoch:<4}  {:>6} bytes  {}",
+                        message.message_id,
+                        message.received_at.format("%Y-%m-%d %H:%M"),
+                        message.size,
+                        message.error,
+                    );
+                }
+            }
+            PoisonCommands::Retry { message_id } => {
+                client.retry_poison_message(user, message_id).await?;
+                println!("Processed message {message_id}");
+            }
+        },
+        Commands::Whoami => {
+            let status = client.whoami(user).await?;
+            println!("identity:       {}", status.identity);
+            if !status.device_id.is_empty() {
+                println!("device:         {}", status.device_id);
+            }
+            println!("ciphersuite:    {:?}", status.ciphersuite);
+            println!("fingerprint:    {}", status.fingerprint);
+            println!("key packages:   {}", status.key_packages);
+            if status.key_packages == 0 {
+                eprintln!("Nobody can add this device to a group until it publishes a key package");
+            }
+            if status.retired_keys > 0 {
+                println!("retired keys:   {}", status.retired_keys);
+            }
+            println!("groups:         {}", status.groups);
+            println!("queued:         {}", status.queued_messages);
+        }
+        Commands::LinkDevice {} => match client.link_device(user).await? {
+            Some(code) => {
+                println!("{code}");
+                eprintln!("Approve this code on an existing device, then run link-device again");
+            }
+            None => info!("Device linked"),
+        },
+        Commands::ApproveDevice { code } => {
+            info!("Approving device");
+            client.approve_device(user, &code).await?;
+        }
+        Commands::ListDevices {} => {
+            for device_id in client.list_devices(user).await? {
+                if device_id.is_empty() {
+                    println!("(primary)");
+                } else {
+                    println!("{device_id}");
+                }
+            }
+        }
+        Commands::RevokeDevice { device } => {
+            info!(device, "Revoking device");
+            client.revoke_device(user, device).await?;
+        }
+        Commands::Trust { contact } => {
+            let trusted = client.trust(user, contact.clone()).await?;
+            println!("Trusted {trusted} new key(s) of {contact}");
+        }
+        Commands::RotateIdentityKey {} => {
+            for group in client.rotate_identity_key(user).await? {
+                println!("Still using the old key in {group}");
+            }
+        }
+        Commands::Fingerprint { contact, qr: true } => {
+            let payload = client.verification_payload(user, contact).await?;
+            let code = QrCode::new(payload)?;
+            println!(
+                "{}",
+                code.render::<Dense1x2>()
+                    .dark_color(Dense1x2::Light)
+                    .light_color(Dense1x2::Dark)
+                    .build()
+            );
+        }
+        Commands::Fingerprint { contact, qr: false } => {
+            let safety_number = client.safety_number(user, contact.clone()).await?;
+            println!("{}", safety_number.code);
+            for fingerprint in safety_number.fingerprints {
+                println!("{contact}: {fingerprint}");
+            }
+            if safety_number.verified {
+                println!("{contact} is verified");
+            }
+        }
+        Commands::Verify { contact, code, qr } => {
+            let contact = match (contact, code, qr) {
+                (_, _, Some(payload)) => client.verify_qr(user, &payload).await?,
+                (Some(contact), Some(code), None) => {
+                    client.verify_contact(user, contact.clone(), &code).await?;
+                    contact
+                }
+                _ => unreachable!("required by clap"),
+            };
+            println!("Verified {contact}");
+        }
+        Commands::DebugGroup { group, tree } => {
+            let group = client.resolve_group(&user, &group).await?;
+            let state = client.debug_group(user, group).await?;
+            println!("epoch                      {}", state.epoch);
+            println!("ciphersuite                {:#06x}", state.ciphersuite);
+            println!("tree hash                  {}", hex(&state.tree_hash));
+            println!(
+                "confirmed transcript hash  {}",
+                hex(&state.confirmed_transcript_hash)
+            );
+            println!(
+                "epoch authenticator        {}",
+                hex(&state.epoch_authenticator)
+            );
+            println!("own leaf index             {}", state.own_leaf_index);
+            println!("pending proposals          {}", state.pending_proposals);
+            println!("pending commit             {}", state.pending_commit);
+            if tree {
+                println!();
+                print_tree(&state.tree, state.own_leaf_index);
+            }
+        }
+        Commands::EpochAuthenticator { group } => {
+            let group = client.resolve_group(&user, &group).await?;
+            let authenticator = client.epoch_authenticator(user, group).await?;
+            println!("{}", hex(&authenticator));
+        }
+        Commands::ExportSecret {
+            group,
+            label,
+            context,
+            length,
+        } => {
+            let group = client.resolve_group(&user, &group).await?;
+            let secret = client
+                .export_secret(user, group, &label, context.as_bytes(), length)
+                .await?;
+            println!("{}", hex(&secret));
+        }
+        Commands::VerifyGroup { group } => {
+            let group = client.resolve_group(&user, &group).await?;
+            let (epoch, code) = client.group_code(user, group).await?;
+            println!("{code} (epoch {epoch})");
+        }
+        Commands::VerifyKeys { member } => {
+            for key in client.verify_keys(user, member).await? {
+                let device_id = if key.device_id.is_empty() {
+                    "(primary)"
+                } else {
+                    key.device_id.as_str()
+                };
+                println!("{device_id}: logged at index {}", key.leaf_index);
+            }
+        }
+        Commands::AddMember {
+            group,
+            members,
+            share_history,
+        } => {
+            let group = client.resolve_group(&user, &group).await?;
+            info!("Adding users {:?} to group: {}", members, group);
+            client
+                .add_members(user.clone(), group, members.clone())
+                .await?;
+            if let Some(limit) = share_history {
+                info!(%group, limit, "Sharing history with new members");
+                client.share_history(user, group, members, limit).await?;
+            }
+        }
+        Commands::ProposeAdd { group, member } => {
+            let group = client.resolve_group(&user, &group).await?;
+            info!(%group, member, "Proposing to add member");
+            client.propose_add_member(user, group, member).await?;
+        }
+        Commands::ProposeRemove { group, member } => {
+            let group = client.resolve_group(&user, &group).await?;
+            info!(%group, member, "Proposing to remove member");
+            client.propose_remove_member(user, group, member).await?;
+        }
+        Commands::CommitPending { group } => {
+            let group = client.resolve_group(&user, &group).await?;
+            info!(%group, "Committing pending proposals");
+            client.commit_pending(user, group).await?;
+        }
+        Commands::LeaveGroup { group } => {
+            let group = client.resolve_group(&user, &group).await?;
+            info!(%group, "Leaving group");
+            client.leave_group(user, group).await?;
+        }
+        Commands::ListMembers { group } => {
+            let group = client.resolve_group(&user, &group).await?;
+            for member in client.list_members(user, group).await? {
+                println!(
+                    "{:>3}{} {:<16}  {:?}  {}",
+                    member.leaf_index,
+                    if member.own { "*" } else { " " },
+                    member.identity,
+                    member.credential_type,
+                    fingerprint(&member.signature_key),
+                );
+            }
+        }
+        Commands::RemoveMember { group, members } => {
+            let group = client.resolve_group(&user, &group).await?;
+            info!("Removing users {:?} from group: {}", members, group);
+            client.remove_members(user, group, members).await?;
+        }
+        Commands::Promote { group, member } => {
+            let group = client.resolve_group(&user, &group).await?;
+            info!(%group, member, "Promoting member");
+            client.promote(user, group, member).await?;
+        }
+        Commands::Demote { group, member } => {
+            let group = client.resolve_group(&user, &group).await?;
+            info!(%group, member, "Demoting member");
+            client.demote(user, group, member).await?;
+        }
+        Commands::Ban { group, member } => {
+            let group = client.resolve_group(&user, &group).await?;
+            info!(%group, member, "Banning member");
+            client.ban(user, group, member).await?;
+        }
+        Commands::Unban { group, member } => {
+            let group = client.resolve_group(&user, &group).await?;
+            info!(%group, member, "Unbanning member");
+            client.unban(user, group, member).await?;
+        }
+        Commands::Admins { group } => {
+            let group = client.resolve_group(&user, &group).await?;
+            match client.admins(user, group).await? {
+                Some(admins) => {
+                    for admin in admins {
+                        println!("{admin}");
+                    }
+                }
+                None => println!("Group has no roles, every member is an admin"),
+            }
+        }
+    }
+
+    // Messages processed on the side, e.g. while catching up before sending.
+    for event in client.drain_events() {
+        print_event(output, event);
+    }
+
+    Ok(())
+}
+
+fn parse_ciphersuite(s: &str) -> Result<Ciphersuite, String> {
+    let value = parse_code(s)?;
+    Ciphersuite::try_from(value).map_err(|error| format!("{error:?}"))
+}
+
+/// Parses a decimal or `0x` prefixed hexadecimal code point.
+fn parse_code(s: &str) -> Result<u16, String> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+    .map_err(|error| error.to_string())
+}
+
+/// Draws a ratchet tree with the root on the left and the leaves on the
+/// right, one node per line in array order.
+fn print_tree(tree: &[TreeNode], own_leaf_index: u32) {
+    let root: usize = (1 << tree.len().max(1).ilog2()) - 1;
+    for (index, node) in tree.iter().enumerate() {
+        let level = index.trailing_ones() as usize;
+        let depth = root.trailing_ones() as usize - level;
+        let (label, digest) = match node {
+            TreeNode::Blank => ("_".to_string(), None),
+            TreeNode::Leaf { identity, digest } => {
+                let own = if index / 2 == own_leaf_index as usize {
+                    " (own)"
+                } else {
+                    ""
+                };
+                (format!("leaf {}: {identity}{own}", index / 2), Some(digest))
+            }
+            TreeNode::Parent { digest } => ("parent".to_string(), Some(digest)),
+        };
+        let root = if index == root { " (root)" } else { "" };
+        println!(
+            "{index:>4}  {}{label}{root}{}",
+            "    ".repeat(depth),
+            digest.map_or(String::new(), |digest| format!("  {}", &hex(digest)[..16])),
+        );
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn init() -> Args {
+    let filter = tracing_subscriber::EnvFilter::builder()
+        .with_default_directive(tracing::metadata::LevelFilter::INFO.into())
+        .from_env_lossy();
+    tracing_subscriber::fmt::fmt()
+        .with_writer(std::io::stderr)
+        .with_env_filter(filter)
+        .init();
+    Args::parse()
+}
+
+/// Prints a history entry, indented by `depth` in thread views.
+fn print_message(output: Output, user: &str, depth: usize, message: HistoryMessage) {
+    if output == Output::Json {
+        println!("{}", message_json(depth, &message));
+        return;
+    }
+    let reply = match message.reply_to {
+        Some(parent) if depth == 0 => format!("(reply to {parent}) "),
+        _ => String::new(),
+    };
+    let body = if message.deleted_at.is_some() {
+        "[deleted]".to_string()
+    } else if message.edited_at.is_some() {
+        format!("{} (edited)", message.body)
+    } else {
+        message.body
+    };
+    let mention = if message.mentions.iter().any(|mention| mention == user) {
+        "  (mentions you)"
+    } else {
+        ""
+    };
+    let shared = match &message.shared_by {
+        Some(member) => format!("  (shared by {member})"),
+        None => String::new(),
+    };
+    let delivered = if !message.read_by.is_empty() {
+        format!(
+            "  (delivered to {}; read by {})",
+            message.delivered_to.join(", "),
+            message.read_by.join(", ")
+        )
+    } else if !message.delivered_to.is_empty() {
+        format!("  (delivered to {})", message.delivered_to.join(", "))
+    } else if let Some(status @ (SendStatus::Queued | SendStatus::Failed)) = message.status {
+        format!("  ({})", status.as_str())
+    } else {
+        String::new()
+    };
+    println!(
+        "{:>6}  {}  {:indent$}{}: {reply}{body}{mention}{shared}{delivered}",
+        message.message_id,
+        message.created_at.format("%Y-%m-%d %H:%M"),
+        "",
+        message.sender,
+        indent = depth * 2,
+    );
+}
+
+const REPL_HELP: &str = "\
+/join GROUP     switch to a group, joining it if invited or open
+/send TEXT      send to the current group, same as TEXT alone
+/members        list the members of the current group
+/history [N]    show the last N messages of the current group
+/groups         list the groups
+/invites        list pending invites
+/quit           leave the REPL";
+
+/// Reads slash commands from stdin while printing incoming messages, over a
+/// single connection and receive stream.
+async fn repl(client: &mut Client, user: String) -> Result<(), Box<dyn std::error::Error>> {
+    let mut subscription = client.subscribe(user.clone()).await?;
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    let mut current = None;
+    println!("Type /help for commands");
+    // Events of the queued messages handled while subscribing.
+    for event in client.drain_events() {
+        print_event(Output::Text, event);
+    }
+    loop {
+        tokio::select! {
+            message = subscription.next() => match message {
+                Ok(Some(message)) => match client.process(&subscription, message).await {
+                    Ok(events) => events
+                        .into_iter()
+                        .for_each(|event| print_event(Output::Text, event)),
+                    Err(error) => eprintln!("Failed to process message: {error}"),
+                },
+                Ok(None) | Err(_) => {
+                    eprintln!("Disconnected, reconnecting");
+                    subscription = client.subscribe(user.clone()).await?;
+                    for event in client.drain_events() {
+                        print_event(Output::Text, event);
+                    }
+                }
+            },
+            line = lines.next_line() => {
+                let Some(line) = line? else {
+                    break;
+                };
+                match repl_command(client, &user, &mut current, line.trim()).await {
+                    Ok(true) => {}
+                    Ok(false) => break,
+                    Err(error) => eprintln!("{error}"),
+                }
+                for event in client.drain_events() {
+                    print_event(Output::Text, event);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Runs a line of the REPL. Returns `false` when the user quits.
+async fn repl_command(
+    client: &mut Client,
+    user: &str,
+    current: &mut Option<Uuid>,
+    line: &str,
+) -> Result<bool, ClientError> {
+    let (command, argument) = match line.strip_prefix('/') {
+        Some(command) => command
+            .split_once(char::is_whitespace)
+            .map(|(command, argument)| (command, argument.trim()))
+            .unwrap_or((command, "")),
+        None => ("send", line),
+    };
+    match command {
+        "" | "send" if argument.is_empty() => {}
+        "send" => {
+            let Some(group) = *current else {
+                eprintln!("Join a group first with /join");
+                return Ok(true);
+            };
+            client
+                .send(user.to_string(), group, argument.to_string())
+                .await?;
+        }
+        "join" => {
+            let group = client.resolve_group(user, argument).await?;
+            let invited = client
+                .invites(user.to_string())
+                .await?
+                .iter()
+                .any(|invite| invite.group_id == group);
+            let member = client
+                .list_groups(user.to_string())
+                .await?
+                .iter()
+                .any(|summary| summary.group_id == group);
+            if invited {
+                client.accept_invite(user.to_string(), group).await?;
+            } else if !member {
+                client
+                    .join_group_externally(user.to_string(), group)
+                    .await?;
+            }
+            *current = Some(group);
+            println!("Now in {group}");
+            client.mark_read(user.to_string(), group).await?;
+        }
+        "members" | "history" if current.is_none() => eprintln!("Join a group first with /join"),
+        "members" => {
+            for member in client
+                .list_members(user.to_string(), current.unwrap())
+                .await?
+            {
+                println!(
+                    "{}{}",
+                    member.identity,
+                    if member.own { " (you)" } else { "" }
+                );
+            }
+        }
+        "history" => {
+            let limit = argument.parse().unwrap_or(20);
+            let messages = client
+                .history(user.to_string(), current.unwrap(), limit, None)
+                .await?;
+            for message in messages {
+                print_message(Output::Text, user, 0, message);
+            }
+            client.mark_read(user.to_string(), current.unwrap()).await?;
+        }
+        "groups" => {
+            let groups = client.list_groups(user.to_string()).await?;
+            for group in groups.into_iter().filter(|group| !group.archived) {
+                match group.unread {
+                    0 => println!("{}  {}", group.group_id, group.name),
+                    unread => println!("{}  {}  ({unread} unread)", group.group_id, group.name),
+                }
+            }
+        }
+        "invites" => {
+            for invite in client.invites(user.to_string()).await? {
+                println!(
+                    "{}  {} from {}",
+                    invite.group_id, invite.name, invite.inviter
+                );
+            }
+        }
+        "help" => println!("{REPL_HELP}"),
+        "quit" | "exit" => return Ok(false),
+        _ => eprintln!("Unknown command /{command}, type /help for commands"),
+    }
+    Ok(true)
+}
+
+async fn print_events(
+    output: Output,
+    events: impl Stream<Item = Result<ChatEvent, ClientError>>,
+) -> Result<(), ClientError> {
+    let mut events = pin!(events);
+    while let Some(event) = events.try_next().await? {
+        print_event(output, event);
+    }
+    Ok(())
+}
+
+fn print_event(output: Output, event: ChatEvent) {
+    if output == Output::Json {
+        println!("{}", event_json(&event));
+        return;
+    }
+    match event {
+        ChatEvent::Message {
+            sender,
+            text,
+            mentioned,
+            ..
+        } => {
+            if mentioned {
+                println!("{sender} mentioned you: {text}");
+            } else {
+                println!("{sender}: {text}");
+            }
+        }
+        ChatEvent::Bounced { group_id, text } => {
+            println!("Not delivered to {group_id}, only admins can send: {text}");
+        }
+        ChatEvent::MessageEdited {
+            message_id,
+            sender,
+            text,
+            ..
+        } => println!("{sender} edited message {message_id}: {text}"),
+        ChatEvent::MessageDeleted {
+            message_id, sender, ..
+        } => println!("{sender} deleted message {message_id}"),
+        ChatEvent::Reaction {
+            message_id,
+            sender,
+            reaction,
+            ..
+        } => println!("{sender} reacted to message {message_id}: {reaction}"),
+        ChatEvent::HistoryShared {
+            sender, messages, ..
+        } => println!("{sender} shared {messages} earlier messages"),
+        ChatEvent::WelcomeReceived {
+            group_id,
+            name,
+            inviter,
+        } => println!("{inviter} invited you to {group_id} {name}"),
+        ChatEvent::MemberAdded {
+            group_id,
+            member,
+            added_by,
+        } => {
+            if member == added_by {
+                println!("{member} joined {group_id}");
+            } else {
+                println!("{added_by} added {member} to {group_id}");
+            }
+        }
+        ChatEvent::MemberRemoved {
+            group_id,
+            member,
+            removed_by,
+        } => {
+            if member == removed_by {
+                println!("{member} left {group_id}");
+            } else {
+                println!("{removed_by} removed {member} from {group_id}");
+            }
+        }
+        // Already logged.
+        ChatEvent::EpochChanged { .. } | ChatEvent::DecryptionFailed { .. } => {}
+    }
+}
+
+fn group_json(group: &GroupSummary) -> serde_json::Value {
+    serde_json::json!({
+        "group_id": group.group_id.to_string(),
+        "name": group.name,
+        "topic": group.topic,
+        "ciphersuite": group.ciphersuite.map(u16::from),
+        "creator": group.creator,
+        "inviter": group.inviter,
+        "created_at": group.created_at.to_rfc3339(),
+        "last_activity_at": group.last_activity_at.map(|at| at.to_rfc3339()),
+        "unread": group.unread,
+        "muted": group.muted,
+        "archived": group.archived,
+    })
+}
+
+/// A history entry, `depth` being its nesting in thread views.
+fn message_json(depth: usize, message: &HistoryMessage) -> serde_json::Value {
+    serde_json::json!({
+        "message_id": message.message_id,
+        "group_id": message.group_id.to_string(),
+        "uuid": message.uuid.map(|uuid| uuid.to_string()),
+        "sender": message.sender,
+        "epoch": message.epoch,
+        "direction": match message.direction {
+            Direction::Incoming => "in",
+            Direction::Outgoing => "out",
+        },
+        "body": message.body,
+        "reply_to": message.reply_to,
+        "depth": depth,
+        "mentions": message.mentions,
+        "created_at": message.created_at.to_rfc3339(),
+        "status": message.status.map(SendStatus::as_str),
+        "sent_at": message.sent_at.map(|at| at.to_rfc3339()),
+        "edited_at": message.edited_at.map(|at| at.to_rfc3339()),
+        "deleted_at": message.deleted_at.map(|at| at.to_rfc3339()),
+        "delivered_to": message.delivered_to,
+        "read_by": message.read_by,
+        "shared_by": message.shared_by,
+    })
+}
+
+/// An event as object with its kind in `event`, including the epoch changes
+/// and decryption failures that the text output leaves to the log.
+fn event_json(event: &ChatEvent) -> serde_json::Value {
+    use serde_json::json;
+    match event {
+        ChatEvent::Message {
+            group_id,
+            message_id,
+            sender,
+            text,
+            mentioned,
+        } => json!({
+            "event": "message",
+            "group_id": group_id.to_string(),
+            "message_id": message_id,
+            "sender": sender,
+            "text": text,
+            "mentioned": mentioned,
+        }),
+        ChatEvent::Bounced { group_id, text } => json!({
+            "event": "bounced",
+            "group_id": group_id.to_string(),
+            "text": text,
+        }),
+        ChatEvent::MessageEdited {
+            group_id,
+            message_id,
+            sender,
+            text,
+        } => json!({
+            "event": "message_edited",
+            "group_id": group_id.to_string(),
+            "message_id": message_id,
+            "sender": sender,
+            "text": text,
+        }),
+        ChatEvent::MessageDeleted {
+            group_id,
+            message_id,
+            sender,
+        } => json!({
+            "event": "message_deleted",
+            "group_id": group_id.to_string(),
+            "message_id": message_id,
+            "sender": sender,
+        }),
+        ChatEvent::Reaction {
+            group_id,
+            message_id,
+            sender,
+            reaction,
+        } => json!({
+            "event": "reaction",
+            "group_id": group_id.to_string(),
+            "message_id": message_id,
+            "sender": sender,
+            "reaction": reaction,
+        }),
+        ChatEvent::HistoryShared {
+            group_id,
+            sender,
+            messages,
+        } => json!({
+            "event": "history_shared",
+            "group_id": group_id.to_string(),
+            "sender": sender,
+            "messages": messages,
+        }),
+        ChatEvent::WelcomeReceived {
+            group_id,
+            name,
+            inviter,
+        } => json!({
+            "event": "welcome_received",
+            "group_id": group_id.to_string(),
+            "name": name,
+            "inviter": inviter,
+        }),
+        ChatEvent::MemberAdded {
+            group_id,
+            member,
+            added_by,
+        } => json!({
+            "event": "member_added",
+            "group_id": group_id.to_string(),
+            "member": member,
+            "added_by": added_by,
+        }),
+        ChatEvent::MemberRemoved {
+            group_id,
+            member,
+            removed_by,
+        } => json!({
+            "event": "member_removed",
+            "group_id": group_id.to_string(),
+            "member": member,
+            "removed_by": removed_by,
+        }),
+        ChatEvent::EpochChanged { group_id, epoch } => json!({
+            "event": "epoch_changed",
+            "group_id": group_id.to_string(),
+            "epoch": epoch,
+        }),
+        ChatEvent::DecryptionFailed {
+            group_id,
+            epoch,
+            error,
+            quarantined,
+        } => json!({
+            "event": "decryption_failed",
+            "group_id": group_id.map(|group_id| group_id.to_string()),
+            "epoch": epoch,
+            "error": error,
+            "quarantined": quarantined,
+        }),
+    }
+}
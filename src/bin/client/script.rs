@@ -0,0 +1,324 @@
+use mls_chat::{
+    client::{Client, ClientError, member::fingerprint, policy::RequiredCapabilities},
+    grpc::GroupMetadata,
+};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use tokio::io::{AsyncBufReadExt, BufReader};
+
+use crate::{event_json, group_json, message_json};
+
+/// A line of a script, e.g.
+/// `{"id": 1, "command": "send", "group": "team", "text": "hello"}`.
+#[derive(Deserialize)]
+struct Request {
+    /// Echoed in the result, to match results with requests.
+    #[serde(default)]
+    id: Value,
+    #[serde(flatten)]
+    command: ScriptCommand,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "command", rename_all = "kebab-case")]
+enum ScriptCommand {
+    CreateGroup {
+        name: Option<String>,
+        topic: Option<String>,
+        #[serde(default)]
+        open: bool,
+        #[serde(default)]
+        read_only: bool,
+    },
+    JoinGroup {
+        group: String,
+    },
+    LeaveGroup {
+        group: String,
+    },
+    UpdateGroup {
+        group: String,
+    },
+    ListGroups {
+        #[serde(default)]
+        all: bool,
+    },
+    AddMember {
+        group: String,
+        members: Vec<String>,
+    },
+    RemoveMember {
+        group: String,
+        members: Vec<String>,
+    },
+    ListMembers {
+        group: String,
+    },
+    Invites,
+    AcceptInvite {
+        group: String,
+    },
+    DeclineInvite {
+        group: String,
+    },
+    Send {
+        group: String,
+        text: String,
+        reply_to: Option<i64>,
+    },
+    Dm {
+        peer: String,
+        text: String,
+    },
+    Edit {
+        group: String,
+        message: i64,
+        text: String,
+    },
+    DeleteMessage {
+        group: String,
+        message: i64,
+    },
+    React {
+        group: String,
+        message: i64,
+        reaction: String,
+    },
+    History {
+        group: String,
+        #[serde(default = "default_limit")]
+        limit: u32,
+        before: Option<i64>,
+    },
+    MarkRead {
+        group: String,
+    },
+}
+
+fn default_limit() -> u32 {
+    20
+}
+
+/// Runs the JSON commands on stdin, one per line, over a single connection
+/// and receive stream. Prints a result object per command, with `ok` and
+/// either `result` or `error`, and the events of incoming messages in
+/// between, as `--output json` does. A failing command doesn't stop the
+/// script; it ends with stdin.
+pub async fn run(client: &mut Client, user: String) -> Result<(), Box<dyn std::error::Error>> {
+    let mut subscription = client.subscribe(user.clone()).await?;
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    print_events(client);
+    loop {
+        tokio::select! {
+            message = subscription.next() => match message {
+                Ok(Some(message)) => match client.process(&subscription, message).await {
+                    Ok(events) => events
+                        .iter()
+                        .for_each(|event| println!("{}", event_json(event))),
+                    Err(error) => eprintln!("Failed to process message: {error}"),
+                },
+                Ok(None) | Err(_) => {
+                    eprintln!("Disconnected, reconnecting");
+                    subscription = client.subscribe(user.clone()).await?;
+                    print_events(client);
+                }
+            },
+            line = lines.next_line() => {
+                let Some(line) = line? else {
+                    break;
+                };
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let response = match serde_json::from_str::<Request>(&line) {
+                    Ok(request) => match command(client, &user, request.command).await {
+                        Ok(result) => json!({ "id": request.id, "ok": true, "result": result }),
+                        Err(error) => {
+                            json!({ "id": request.id, "ok": false, "error": error.to_string() })
+                        }
+                    },
+                    Err(error) => json!({
+                        "id": Value::Null,
+                        "ok": false,
+                        "error": format!("Invalid command: {error}"),
+                    }),
+                };
+                // Events of messages processed while running the command, e.g.
+                // while catching up before sending, come before its result.
+                print_events(client);
+                println!("{response}");
+            }
+        }
+    }
+    Ok(())
+}
+
+fn print_events(client: &mut Client) {
+    for event in client.drain_events() {
+        println!("{}", event_json(&event));
+    }
+}
+
+/// Runs a command of the script, returning its result.
+async fn command(
+    client: &mut Client,
+    user: &str,
+    command: ScriptCommand,
+) -> Result<Value, ClientError> {
+    let user = user.to_string();
+    let result = match command {
+        ScriptCommand::CreateGroup {
+            name,
+            topic,
+            open,
+            read_only,
+        } => {
+            let metadata =
+                (name.is_some() || topic.is_some() || open || read_only).then(|| GroupMetadata {
+                    name: name.unwrap_or_default(),
+                    topic: topic.unwrap_or_default(),
+                    open,
+                    read_only,
+                    ..Default::default()
+                });
+            let group_id = client
+                .create_group(user, metadata, None, RequiredCapabilities::default())
+                .await?;
+            json!({ "group_id": group_id.to_string() })
+        }
+        ScriptCommand::JoinGroup { group } => {
+            let group = client.resolve_group(&user, &group).await?;
+            client.join_group_externally(user, group).await?;
+            Value::Null
+        }
+        ScriptCommand::LeaveGroup { group } => {
+            let group = client.resolve_group(&user, &group).await?;
+            client.leave_group(user, group).await?;
+            Value::Null
+        }
+        ScriptCommand::UpdateGroup { group } => {
+            let group = client.resolve_group(&user, &group).await?;
+            client.update_group(user, group).await?;
+            Value::Null
+        }
+        ScriptCommand::ListGroups { all } => {
+            let groups = client.list_groups(user).await?;
+            groups
+                .iter()
+                .filter(|group| all || !group.archived)
+                .map(group_json)
+                .collect()
+        }
+        ScriptCommand::AddMember { group, members } => {
+            let group = client.resolve_group(&user, &group).await?;
+            client.add_members(user, group, members).await?;
+            Value::Null
+        }
+        ScriptCommand::RemoveMember { group, members } => {
+            let group = client.resolve_group(&user, &group).await?;
+            client.remove_members(user, group, members).await?;
+            Value::Null
+        }
+        ScriptCommand::ListMembers { group } => {
+            let group = client.resolve_group(&user, &group).await?;
+            let members = client.list_members(user, group).await?;
+            members
+                .iter()
+                .map(|member| {
+                    json!({
+                        "leaf_index": member.leaf_index,
+                        "identity": member.identity,
+                        "fingerprint": fingerprint(&member.signature_key),
+                        "own": member.own,
+                    })
+                })
+                .collect()
+        }
+        ScriptCommand::Invites => {
+            let invites = client.invites(user).await?;
+            invites
+                .iter()
+                .map(|invite| {
+                    json!({
+                        "group_id": invite.group_id.to_string(),
+                        "name": invite.name,
+                        "inviter": invite.inviter,
+                        "members": invite.members,
+                        "received_at": invite.received_at.to_rfc3339(),
+                    })
+                })
+                .collect()
+        }
+        ScriptCommand::AcceptInvite { group } => {
+            let group = client.resolve_group(&user, &group).await?;
+            client.accept_invite(user, group).await?;
+            Value::Null
+        }
+        ScriptCommand::DeclineInvite { group } => {
+            let group = client.resolve_group(&user, &group).await?;
+            client.decline_invite(user, group).await?;
+            Value::Null
+        }
+        ScriptCommand::Send {
+            group,
+            text,
+            reply_to,
+        } => {
+            let group = client.resolve_group(&user, &group).await?;
+            let sent = match reply_to {
+                Some(parent) => client.reply(user, group, parent, text).await?,
+                None => client.send(user, group, text).await?,
+            };
+            json!({
+                "message_id": sent.message_id,
+                "server_id": sent.server_id.map(|id| id.to_string()),
+                "server_timestamp": sent.server_timestamp.map(|at| at.to_rfc3339()),
+            })
+        }
+        ScriptCommand::Dm { peer, text } => {
+            let group_id = client.dm(user, peer, text).await?;
+            json!({ "group_id": group_id.to_string() })
+        }
+        ScriptCommand::Edit {
+            group,
+            message,
+            text,
+        } => {
+            let group = client.resolve_group(&user, &group).await?;
+            client.edit_message(user, group, message, text).await?;
+            Value::Null
+        }
+        ScriptCommand::DeleteMessage { group, message } => {
+            let group = client.resolve_group(&user, &group).await?;
+            client.delete_message(user, group, message).await?;
+            Value::Null
+        }
+        ScriptCommand::React {
+            group,
+            message,
+            reaction,
+        } => {
+            let group = client.resolve_group(&user, &group).await?;
+            client.react(user, group, message, reaction).await?;
+            Value::Null
+        }
+        ScriptCommand::History {
+            group,
+            limit,
+            before,
+        } => {
+            let group = client.resolve_group(&user, &group).await?;
+            let messages = client.history(user, group, limit, before).await?;
+            messages
+                .iter()
+                .map(|message| message_json(0, message))
+                .collect()
+        }
+        ScriptCommand::MarkRead { group } => {
+            let group = client.resolve_group(&user, &group).await?;
+            let read = client.mark_read(user, group).await?;
+            json!({ "read": read })
+        }
+    };
+    Ok(result)
+}
@@ -0,0 +1,421 @@
+use std::collections::HashMap;
+
+use crossterm::event::{Event, EventStream, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use futures_util::StreamExt;
+use mls_chat::client::{Client, ClientError, events::ChatEvent, history::HistoryMessage};
+use ratatui::{
+    DefaultTerminal, Frame,
+    layout::{Constraint, Layout, Position},
+    style::{Style, Stylize},
+    text::Line,
+    widgets::{Block, List, ListItem, ListState, Paragraph},
+};
+use uuid::Uuid;
+
+/// Messages of a group loaded when it is first shown.
+const HISTORY_LIMIT: u32 = 200;
+
+/// Full-screen chat: groups and invites on the left, the messages of the
+/// selected group on the right and an input line below them.
+pub async fn chat(client: &mut Client, user: String) -> Result<(), Box<dyn std::error::Error>> {
+    let mut app = App::new(client, user).await?;
+    let mut terminal = ratatui::init();
+    let result = app.run(&mut terminal).await;
+    ratatui::restore();
+    result
+}
+
+struct App<'a> {
+    client: &'a mut Client,
+    user: String,
+    entries: Vec<Entry>,
+    selected: usize,
+    /// Lines of the groups shown so far.
+    lines: HashMap<Uuid, Vec<String>>,
+    /// Unread messages of the groups that aren't selected.
+    unread: HashMap<Uuid, u64>,
+    /// Lines scrolled back from the newest one.
+    scroll: usize,
+    input: String,
+    status: String,
+    quit: bool,
+}
+
+/// A group or an invite to one in the sidebar.
+struct Entry {
+    group_id: Uuid,
+    name: String,
+    invite: bool,
+}
+
+impl<'a> App<'a> {
+    async fn new(client: &'a mut Client, user: String) -> Result<Self, ClientError> {
+        let mut app = Self {
+            client,
+            user,
+            entries: Vec::new(),
+            selected: 0,
+            lines: HashMap::new(),
+            unread: HashMap::new(),
+            scroll: 0,
+            input: String::new(),
+            status: "Enter sends, Up/Down switch groups, Esc quits".to_string(),
+            quit: false,
+        };
+        app.load_entries().await?;
+        app.select(0).await?;
+        Ok(app)
+    }
+
+    async fn run(
+        &mut self,
+        terminal: &mut DefaultTerminal,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut subscription = self.client.subscribe(self.user.clone()).await?;
+        // Events of the queued messages handled while subscribing.
+        let events = self.client.drain_events();
+        self.handle_events(events).await?;
+        let mut terminal_events = EventStream::new();
+        while !self.quit {
+            terminal.draw(|frame| self.draw(frame))?;
+            tokio::select! {
+                message = subscription.next() => match message {
+                    Ok(Some(message)) => match self.client.process(&subscription, message).await {
+                        Ok(events) => self.handle_events(events).await?,
+                        Err(error) => self.status = format!("Failed to process message: {error}"),
+                    },
+                    Ok(None) | Err(_) => {
+                        self.status = "Disconnected, reconnecting".to_string();
+                        terminal.draw(|frame| self.draw(frame))?;
+                        subscription = self.client.subscribe(self.user.clone()).await?;
+                        self.status = "Reconnected".to_string();
+                        let events = self.client.drain_events();
+                        self.handle_events(events).await?;
+                    }
+                },
+                event = terminal_events.next() => match event {
+                    Some(Ok(Event::Key(key))) if key.kind == KeyEventKind::Press => {
+                        if let Err(error) = self.handle_key(key).await {
+                            self.status = error.to_string();
+                        }
+                        let events = self.client.drain_events();
+                        self.handle_events(events).await?;
+                    }
+                    Some(Ok(_)) => {}
+                    Some(Err(error)) => return Err(error.into()),
+                    None => break,
+                },
+            }
+        }
+        Ok(())
+    }
+
+    async fn handle_key(&mut self, key: KeyEvent) -> Result<(), ClientError> {
+        match key.code {
+            KeyCode::Esc => self.quit = true,
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => self.quit = true,
+            KeyCode::Up if self.selected > 0 => self.select(self.selected - 1).await?,
+            KeyCode::Down if self.selected + 1 < self.entries.len() => {
+                self.select(self.selected + 1).await?
+            }
+            KeyCode::PageUp => self.scroll += 10,
+            KeyCode::PageDown => self.scroll = self.scroll.saturating_sub(10),
+            KeyCode::Enter => self.submit().await?,
+            KeyCode::Backspace => {
+                self.input.pop();
+            }
+            KeyCode::Char(c) => self.input.push(c),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Accepts the selected invite, or sends the input to the selected group.
+    async fn submit(&mut self) -> Result<(), ClientError> {
+        let Some(entry) = self.entries.get(self.selected) else {
+            return Ok(());
+        };
+        let group_id = entry.group_id;
+        if entry.invite {
+            self.client
+                .accept_invite(self.user.clone(), group_id)
+                .await?;
+            self.load_entries().await?;
+            self.lines.remove(&group_id);
+            let selected = self.position(group_id).unwrap_or_default();
+            return self.select(selected).await;
+        }
+        if self.input.is_empty() {
+            return Ok(());
+        }
+        let text = std::mem::take(&mut self.input);
+        self.client
+            .send(self.user.clone(), group_id, text.clone())
+            .await?;
+        let line = format!("{}: {text}", self.user);
+        self.lines.entry(group_id).or_default().push(line);
+        self.scroll = 0;
+        Ok(())
+    }
+
+    async fn handle_events(&mut self, events: Vec<ChatEvent>) -> Result<(), ClientError> {
+        for event in events {
+            let message = matches!(event, ChatEvent::Message { .. });
+            let (group_id, line) = match event {
+                ChatEvent::Message {
+                    group_id,
+                    sender,
+                    text,
+                    ..
+                } => (group_id, format!("{sender}: {text}")),
+                ChatEvent::Bounced { group_id, text } => (
+                    group_id,
+                    format!("* Not delivered, only admins can send: {text}"),
+                ),
+                ChatEvent::MessageEdited {
+                    group_id,
+                    message_id,
+                    sender,
+                    text,
+                } => (
+                    group_id,
+                    format!("* {sender} edited message {message_id}: {text}"),
+                ),
+                ChatEvent::MessageDeleted {
+                    group_id,
+                    message_id,
+                    sender,
+                } => (group_id, format!("* {sender} deleted message {message_id}")),
+                ChatEvent::Reaction {
+                    group_id,
+                    message_id,
+                    sender,
+                    reaction,
+                } => (
+                    group_id,
+                    format!("* {sender} reacted to message {message_id}: {reaction}"),
+                ),
+                ChatEvent::HistoryShared {
+                    group_id,
+                    sender,
+                    messages,
+                } => {
+                    // Shown with the history when the group is selected again.
+                    self.lines.remove(&group_id);
+                    (
+                        group_id,
+                        format!("* {sender} shared {messages} earlier messages"),
+                    )
+                }
+                ChatEvent::WelcomeReceived { name, inviter, .. } => {
+                    self.status = format!("{inviter} invited you to {name}");
+                    self.load_entries().await?;
+                    continue;
+                }
+                ChatEvent::MemberAdded {
+                    group_id,
+                    member,
+                    added_by,
+                } if member == added_by => (group_id, format!("* {member} joined")),
+                ChatEvent::MemberAdded {
+                    group_id,
+                    member,
+                    added_by,
+                } => (group_id, format!("* {added_by} added {member}")),
+                ChatEvent::MemberRemoved {
+                    group_id,
+                    member,
+                    removed_by,
+                } if member == removed_by => (group_id, format!("* {member} left")),
+                ChatEvent::MemberRemoved {
+                    group_id,
+                    member,
+                    removed_by,
+                } => (group_id, format!("* {removed_by} removed {member}")),
+                ChatEvent::EpochChanged { .. } | ChatEvent::DecryptionFailed { .. } => continue,
+            };
+            // Loading the entries counts the message as unread already.
+            let known = self.position(group_id).is_some();
+            if !known {
+                self.load_entries().await?;
+            }
+            if self.selected_group() == Some(group_id) {
+                self.lines.entry(group_id).or_default().push(line);
+                self.client.mark_read(self.user.clone(), group_id).await?;
+            } else {
+                if let Some(lines) = self.lines.get_mut(&group_id) {
+                    lines.push(line);
+                }
+                if known && message {
+                    *self.unread.entry(group_id).or_default() += 1;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn load_entries(&mut self) -> Result<(), ClientError> {
+        let selected = self.selected_group();
+        let invites = self.client.invites(self.user.clone()).await?;
+        let mut groups = self.client.list_groups(self.user.clone()).await?;
+        groups.retain(|group| !group.archived);
+        self.unread = groups
+            .iter()
+            .filter(|group| group.unread > 0 && Some(group.group_id) != selected)
+            .map(|group| (group.group_id, group.unread))
+            .collect();
+        self.entries = invites
+            .into_iter()
+            .map(|invite| Entry {
+                group_id: invite.group_id,
+                name: invite.name,
+                invite: true,
+            })
+            .chain(groups.into_iter().map(|group| Entry {
+                group_id: group.group_id,
+                name: group.name,
+                invite: false,
+            }))
+            .collect();
+        self.selected = selected
+            .and_then(|group_id| self.position(group_id))
+            .unwrap_or_default();
+        Ok(())
+    }
+
+    async fn select(&mut self, index: usize) -> Result<(), ClientError> {
+        self.selected = index;
+        self.scroll = 0;
+        let Some(entry) = self.entries.get(index) else {
+            return Ok(());
+        };
+        if entry.invite {
+            return Ok(());
+        }
+        let group_id = entry.group_id;
+        self.unread.remove(&group_id);
+        if !self.lines.contains_key(&group_id) {
+            let history = self
+                .client
+                .history(self.user.clone(), group_id, HISTORY_LIMIT, None)
+                .await?;
+            let lines = history.into_iter().map(history_line).collect();
+            self.lines.insert(group_id, lines);
+        }
+        self.client.mark_read(self.user.clone(), group_id).await?;
+        Ok(())
+    }
+
+    fn position(&self, group_id: Uuid) -> Option<usize> {
+        self.entries
+            .iter()
+            .position(|entry| entry.group_id == group_id)
+    }
+
+    fn selected_group(&self) -> Option<Uuid> {
+        self.entries
+            .get(self.selected)
+            .filter(|entry| !entry.invite)
+            .map(|entry| entry.group_id)
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let [sidebar, main] =
+            Layout::horizontal([Constraint::Length(28), Constraint::Min(0)]).areas(frame.area());
+        let [messages, input, status] = Layout::vertical([
+            Constraint::Min(0),
+            Constraint::Length(3),
+            Constraint::Length(1),
+        ])
+        .areas(main);
+
+        let items: Vec<ListItem> = self
+            .entries
+            .iter()
+            .map(|entry| {
+                let name = if entry.name.is_empty() {
+                    entry.group_id.to_string()
+                } else {
+                    entry.name.clone()
+                };
+                match self.unread.get(&entry.group_id) {
+                    _ if entry.invite => ListItem::new(format!("+ {name}")).italic(),
+                    Some(unread) => ListItem::new(format!("{name} ({unread})")).bold(),
+                    None => ListItem::new(name),
+                }
+            })
+            .collect();
+        let mut state = ListState::default().with_selected(Some(self.selected));
+        frame.render_stateful_widget(
+            List::new(items)
+                .block(Block::bordered().title("Groups"))
+                .highlight_style(Style::new().reversed()),
+            sidebar,
+            &mut state,
+        );
+
+        let title = match self.entries.get(self.selected) {
+            Some(entry) if entry.invite => format!("Invite to {}", entry.name),
+            Some(entry) => entry.name.clone(),
+            None => "No groups".to_string(),
+        };
+        let block = Block::bordered().title(title);
+        let inner = block.inner(messages);
+        let width = usize::from(inner.width).max(1);
+        let height = usize::from(inner.height);
+        let wrapped: Vec<Line> = match self.entries.get(self.selected) {
+            Some(entry) if entry.invite => {
+                vec![Line::from("Press Enter to accept the invite")]
+            }
+            Some(entry) => self
+                .lines
+                .get(&entry.group_id)
+                .into_iter()
+                .flatten()
+                .flat_map(|line| wrap(line, width))
+                .map(Line::from)
+                .collect(),
+            None => Vec::new(),
+        };
+        let end = wrapped.len().saturating_sub(self.scroll);
+        let start = end.saturating_sub(height);
+        frame.render_widget(
+            Paragraph::new(wrapped[start..end].to_vec()).block(block),
+            messages,
+        );
+
+        frame.render_widget(
+            Paragraph::new(self.input.as_str()).block(Block::bordered().title(self.user.as_str())),
+            input,
+        );
+        frame.set_cursor_position(Position::new(
+            input.x + 1 + self.input.chars().count() as u16,
+            input.y + 1,
+        ));
+        frame.render_widget(Paragraph::new(self.status.as_str()).dim(), status);
+    }
+}
+
+fn history_line(message: HistoryMessage) -> String {
+    let sender = match &message.shared_by {
+        Some(member) => format!("{} (shared by {member})", message.sender),
+        None => message.sender,
+    };
+    if message.deleted_at.is_some() {
+        format!("{sender}: [deleted]")
+    } else {
+        format!("{sender}: {}", message.body)
+    }
+}
+
+/// Splits `line` into pieces of at most `width` characters.
+fn wrap(line: &str, width: usize) -> Vec<String> {
+    let chars: Vec<char> = line.chars().collect();
+    if chars.is_empty() {
+        return vec![String::new()];
+    }
+    chars
+        .chunks(width)
+        .map(|chunk| chunk.iter().collect())
+        .collect()
+}
@@ -1,14 +1,219 @@
-use std::{net::SocketAddr, time::Duration};
+use std::{net::SocketAddr, path::PathBuf, sync::Arc, time::Duration};
 
-use mls_chat::{grpc::chat_service_server::ChatServiceServer, server::ChatServiceImpl};
+use clap::{Parser, Subcommand};
+use mls_chat::{
+    grpc::{
+        chat_service_server::ChatServiceServer, federation_service_server::FederationServiceServer,
+        version_service_server::VersionServiceServer,
+    },
+    server::{
+        ChatServiceImpl, DEFAULT_CHECKPOINT_INTERVAL, DEFAULT_RETENTION,
+        federation::{Federation, Peer},
+        maintenance::{self, DatabaseOptions},
+    },
+};
 use tracing::{Span, info};
 
+#[derive(Parser)]
+#[command(author, version, about, long_about = None, args_conflicts_with_subcommands = true)]
+struct Args {
+    /// Path of the server database
+    #[arg(long, global = true, default_value = "db/server.db")]
+    db_path: PathBuf,
+    #[command(flatten)]
+    database: DatabaseArgs,
+    #[command(subcommand)]
+    command: Option<Commands>,
+    #[command(flatten)]
+    serve: ServeArgs,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// Run the server (default)
+    Serve(Box<ServeArgs>),
+    /// Apply pending database migrations
+    Migrate {},
+    /// Checkpoint the write-ahead log and compact the database
+    Vacuum {},
+    /// Write a consistent copy of the database, also while the server is running
+    Backup { path: PathBuf },
+    /// Show database size and row counts
+    Stats {},
+}
+
+#[derive(clap::Args)]
+struct DatabaseArgs {
+    /// How long to wait for a locked database, in milliseconds
+    #[arg(long, global = true, default_value_t = DatabaseOptions::default().busy_timeout.as_millis() as u64)]
+    busy_timeout_ms: u64,
+    /// Page cache size of each database connection, in KiB
+    #[arg(long, global = true, default_value_t = DatabaseOptions::default().cache_size_kib)]
+    cache_size_kib: u64,
+    /// Bytes of the database file to memory map; 0 disables mmap
+    #[arg(long, global = true, default_value_t = DatabaseOptions::default().mmap_size)]
+    mmap_size: u64,
+}
+
+impl From<DatabaseArgs> for DatabaseOptions {
+    fn from(args: DatabaseArgs) -> Self {
+        Self {
+            busy_timeout: Duration::from_millis(args.busy_timeout_ms),
+            cache_size_kib: args.cache_size_kib,
+            mmap_size: args.mmap_size,
+        }
+    }
+}
+
+#[derive(clap::Args)]
+struct ServeArgs {
+    /// Address to listen on
+    #[arg(long, default_value = "[::]:50051")]
+    listen: SocketAddr,
+    /// Address to also serve the WebSocket endpoint on, for clients that can't
+    /// use gRPC
+    #[cfg(feature = "websocket")]
+    #[arg(long)]
+    websocket_listen: Option<SocketAddr>,
+    /// Address to also serve gRPC-Web on, for clients in browsers
+    #[cfg(feature = "grpc-web")]
+    #[arg(long)]
+    grpc_web_listen: Option<SocketAddr>,
+    /// Address to also serve requests over QUIC on (experimental)
+    #[cfg(feature = "quic")]
+    #[arg(long, requires_all = ["quic_cert", "quic_key"])]
+    quic_listen: Option<SocketAddr>,
+    /// PEM file of the certificate chain of the QUIC endpoint
+    #[cfg(feature = "quic")]
+    #[arg(long)]
+    quic_cert: Option<PathBuf>,
+    /// PEM file of the private key of the QUIC endpoint
+    #[cfg(feature = "quic")]
+    #[arg(long)]
+    quic_key: Option<PathBuf>,
+    /// How long delivered messages are kept, in hours
+    #[arg(long, default_value_t = DEFAULT_RETENTION.as_secs() / 3600)]
+    retention_hours: u64,
+    /// Interval of WAL checkpoints, in seconds
+    #[arg(
+        long,
+        default_value_t = DEFAULT_CHECKPOINT_INTERVAL.as_secs(),
+        value_parser = clap::value_parser!(u64).range(1..),
+    )]
+    checkpoint_interval_secs: u64,
+    /// Domain of this server; enables federation for `user@domain` identities
+    #[arg(long)]
+    domain: Option<String>,
+    /// Federation peer as `<domain>,<endpoint>,<token>`
+    #[arg(long, value_parser = parse_peer)]
+    peer: Vec<(String, Peer)>,
+}
+
+fn parse_peer(s: &str) -> Result<(String, Peer), String> {
+    let mut parts = s.splitn(3, ',');
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(domain), Some(endpoint), Some(token)) => Ok((
+            domain.to_string(),
+            Peer {
+                endpoint: endpoint.to_string(),
+                token: token.to_string(),
+            },
+        )),
+        _ => Err("expected <domain>,<endpoint>,<token>".to_string()),
+    }
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     tracing_subscriber::fmt::fmt().init();
-    let listen: SocketAddr = "[::]:50051".parse()?;
+    let args = Args::parse();
+    let db_path = args.db_path;
+    let options = DatabaseOptions::from(args.database);
+
+    match args
+        .command
+        .unwrap_or(Commands::Serve(Box::new(args.serve)))
+    {
+        Commands::Serve(serve_args) => serve(db_path, &options, *serve_args).await?,
+        Commands::Migrate {} => {
+            let pool = maintenance::connect(&db_path, &options).await?;
+            let applied = maintenance::migrate(&pool).await?;
+            if applied.is_empty() {
+                println!("Database is up to date");
+            }
+            for migration in applied {
+                println!("Applied {migration}");
+            }
+        }
+        Commands::Vacuum {} => {
+            let pool = maintenance::connect(&db_path, &options).await?;
+            maintenance::vacuum(&pool).await?;
+        }
+        Commands::Backup { path } => {
+            let pool = maintenance::connect(&db_path, &options).await?;
+            maintenance::backup(&pool, &path).await?;
+            info!(path = %path.display(), "Backup written");
+        }
+        Commands::Stats {} => {
+            let pool = maintenance::connect(&db_path, &options).await?;
+            let stats = maintenance::stats(&pool).await?;
+            println!("size:               {} bytes", stats.size_bytes);
+            println!("free:               {} bytes", stats.free_bytes);
+            println!("devices:            {}", stats.devices);
+            println!("key packages:       {}", stats.key_packages);
+            println!("pending messages:   {}", stats.pending_messages);
+            println!("delivered messages: {}", stats.delivered_messages);
+            println!("pending relays:     {}", stats.pending_relays);
+            println!("key log entries:    {}", stats.key_log_entries);
+        }
+    }
+    Ok(())
+}
+
+async fn serve(db_path: PathBuf, options: &DatabaseOptions, args: ServeArgs) -> anyhow::Result<()> {
+    let listen = args.listen;
     info!(%listen, "Starting server");
-    let service = ChatServiceServer::new(ChatServiceImpl::new("db/server.db").await?);
+    let federation = Federation {
+        domain: args.domain,
+        peers: args.peer.into_iter().collect(),
+    };
+    let service = Arc::new(
+        ChatServiceImpl::new(&db_path, options)
+            .await?
+            .with_federation(federation),
+    );
+    service.spawn_retention_sweeper(Duration::from_secs(args.retention_hours * 3600));
+    service.spawn_wal_checkpointer(Duration::from_secs(args.checkpoint_interval_secs));
+    service.spawn_relay_worker();
+    #[cfg(feature = "websocket")]
+    if let Some(listen) = args.websocket_listen {
+        info!(%listen, "Serving WebSockets");
+        let listener = tokio::net::TcpListener::bind(listen).await?;
+        let service = service.clone();
+        tokio::spawn(async move {
+            if let Err(error) = mls_chat::server::websocket::serve(service, listener).await {
+                tracing::error!(%error, "WebSocket endpoint failed");
+            }
+        });
+    }
+    #[cfg(feature = "grpc-web")]
+    if let Some(listen) = args.grpc_web_listen {
+        info!(%listen, "Serving gRPC-Web");
+        let listener = tokio::net::TcpListener::bind(listen).await?;
+        let service = service.clone();
+        tokio::spawn(async move {
+            if let Err(error) = mls_chat::server::grpc_web::serve(service, listener).await {
+                tracing::error!(%error, "gRPC-Web endpoint failed");
+            }
+        });
+    }
+    #[cfg(feature = "quic")]
+    if let (Some(listen), Some(cert), Some(key)) = (args.quic_listen, args.quic_cert, args.quic_key)
+    {
+        info!(%listen, "Serving QUIC");
+        let endpoint = mls_chat::server::quic::endpoint(listen, &cert, &key)?;
+        tokio::spawn(mls_chat::server::quic::serve(service.clone(), endpoint));
+    }
     tonic::transport::Server::builder()
         .layer(
             tower_http::trace::TraceLayer::new_for_grpc()
@@ -29,7 +234,9 @@ async fn main() -> anyhow::Result<()> {
                     },
                 ),
         )
-        .add_service(service)
+        .add_service(ChatServiceServer::from_arc(service.clone()))
+        .add_service(FederationServiceServer::from_arc(service.clone()))
+        .add_service(VersionServiceServer::from_arc(service))
         .serve(listen)
         .await?;
     Ok(())
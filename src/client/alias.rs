@@ -0,0 +1,117 @@
+use std::str::FromStr;
+
+use sqlx::{query, query_as, query_scalar};
+use uuid::Uuid;
+
+use crate::client::{
+    Client, Result,
+    error::{ensure, not_found},
+};
+
+/// Local name of a group, usable instead of its id.
+#[derive(Debug)]
+pub struct GroupAlias {
+    pub alias: String,
+    pub group_id: Uuid,
+}
+
+impl Client {
+    /// Names the group `alias` for `user`, replacing the group the alias named
+    /// before.
+    pub async fn set_alias(&mut self, user: String, alias: String, group_uuid: Uuid) -> Result<()> {
+        ensure!(is_valid_alias(&alias), "Invalid alias {alias:?}");
+        query!(
+            "INSERT OR REPLACE INTO client_group_alias (username, alias, group_id)
+            VALUES (?, ?, ?)",
+            user,
+            alias,
+            group_uuid,
+        )
+        .execute(&mut self.connection)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn remove_alias(&mut self, user: String, alias: String) -> Result<()> {
+        let deleted = query!(
+            "DELETE FROM client_group_alias WHERE username = ? AND alias = ?",
+            user,
+            alias,
+        )
+        .execute(&mut self.connection)
+        .await?
+        .rows_affected();
+        if deleted == 0 {
+            return Err(not_found(format!("Alias {alias}")));
+        }
+        Ok(())
+    }
+
+    pub async fn aliases(&mut self, user: String) -> Result<Vec<GroupAlias>> {
+        let aliases = query_as!(
+            GroupAlias,
+            r#"SELECT alias, group_id AS "group_id: Uuid"
+            FROM client_group_alias
+            WHERE username = ?
+            ORDER BY alias"#,
+            user,
+        )
+        .fetch_all(&mut self.connection)
+        .await?;
+        Ok(aliases)
+    }
+
+    /// Group named by `group`, either its id or an alias of `user`.
+    pub async fn resolve_group(&mut self, user: &str, group: &str) -> Result<Uuid> {
+        if let Ok(group_uuid) = Uuid::from_str(group) {
+            return Ok(group_uuid);
+        }
+        query_scalar!(
+            r#"SELECT group_id AS "group_id: Uuid"
+            FROM client_group_alias
+            WHERE username = ? AND alias = ?"#,
+            user,
+            group,
+        )
+        .fetch_optional(&mut self.connection)
+        .await?
+        .ok_or_else(|| not_found(format!("Group {group}")))
+    }
+
+    /// Names a group after its name when it is first seen, unless the alias is
+    /// taken already.
+    pub(crate) async fn auto_alias(
+        &mut self,
+        user: &str,
+        name: &str,
+        group_uuid: Uuid,
+    ) -> anyhow::Result<()> {
+        let alias = alias_of(name);
+        if !is_valid_alias(&alias) {
+            return Ok(());
+        }
+        query!(
+            "INSERT OR IGNORE INTO client_group_alias (username, alias, group_id)
+            VALUES (?, ?, ?)",
+            user,
+            alias,
+            group_uuid,
+        )
+        .execute(&mut self.connection)
+        .await?;
+        Ok(())
+    }
+}
+
+/// Aliases are single words that can't be mistaken for a group id.
+fn is_valid_alias(alias: &str) -> bool {
+    !alias.is_empty() && !alias.chars().any(char::is_whitespace) && Uuid::from_str(alias).is_err()
+}
+
+/// Lowercase `name` with runs of whitespace replaced by a dash.
+fn alias_of(name: &str) -> String {
+    name.split_whitespace()
+        .collect::<Vec<_>>()
+        .join("-")
+        .to_lowercase()
+}
@@ -0,0 +1,201 @@
+use std::{
+    ffi::OsStr,
+    io,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use openmls::prelude::{AeadType, HashType, OpenMlsCrypto, OpenMlsRand};
+use openmls_rust_crypto::RustCrypto;
+use prost::Message;
+use sqlx::types::chrono::Utc;
+use tokio::{fs::OpenOptions, io::AsyncWriteExt};
+use tracing::info;
+use uuid::Uuid;
+
+use crate::{
+    client::{
+        Client, Result,
+        context::timer_expiry,
+        envelope,
+        error::{bail, ensure},
+        history::{Direction, NewMessage},
+    },
+    grpc::{AttachmentPointer, ContentType, DownloadBlobRequest, MAX_BLOB_SIZE, UploadBlobRequest},
+};
+
+const KEY_LENGTH: usize = 32;
+const NONCE_LENGTH: usize = 12;
+/// Authentication tag appended by ChaCha20-Poly1305.
+const TAG_LENGTH: usize = 16;
+/// Files of the same name in the download directory before giving up.
+const MAX_NAME_COUNTER: u32 = 1000;
+
+impl Client {
+    /// Encrypts the file with a fresh key, uploads it as blob and sends a
+    /// pointer to it to the group.
+    pub async fn send_file(&mut self, user: String, group_uuid: Uuid, path: &Path) -> Result<()> {
+        let filename = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .context("Invalid file name")?
+            .to_string();
+        let content = tokio::fs::read(path)
+            .await
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        ensure!(
+            content.len() + TAG_LENGTH <= MAX_BLOB_SIZE,
+            "File exceeds {} bytes",
+            MAX_BLOB_SIZE - TAG_LENGTH
+        );
+
+        let crypto = RustCrypto::default();
+        let key = crypto.random_vec(KEY_LENGTH)?;
+        let nonce = crypto.random_vec(NONCE_LENGTH)?;
+        let blob = crypto.aead_encrypt(AeadType::ChaCha20Poly1305, &key, &content, &nonce, &[])?;
+        let hash = crypto.hash(HashType::Sha2_256, &blob)?;
+
+        let blob_id = self
+            .client
+            .upload_blob(UploadBlobRequest {
+                client_id: user.clone(),
+                content: blob,
+            })
+            .await?
+            .blob_id;
+
+        let pointer = AttachmentPointer {
+            blob_id,
+            key,
+            nonce,
+            hash,
+            filename: filename.clone(),
+            size: content.len() as u64,
+        };
+        let message_uuid = Uuid::new_v4();
+        let envelope = envelope::new(
+            ContentType::AttachmentPointer,
+            message_uuid,
+            pointer.encode_to_vec(),
+        );
+        let dispatched = self.send_envelope(&user, group_uuid, envelope).await?;
+        let group = self.load_member_group(&user, group_uuid).await?;
+        let message_id = self
+            .store_message(
+                &user,
+                group_uuid,
+                NewMessage {
+                    sender: &user,
+                    epoch: dispatched.epoch,
+                    direction: Direction::Outgoing,
+                    uuid: Some(message_uuid),
+                    body: &format!("[file] {filename}"),
+                    mentions: &[],
+                    reply_to: None,
+                    mimi_id: None,
+                    expires_at: timer_expiry(&group, Utc::now()),
+                },
+            )
+            .await?;
+        self.touch_group(&user, group_uuid).await?;
+        self.record_dispatch(message_id, dispatched.dispatch)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Downloads, verifies and decrypts the attachment that `body` points to
+    /// and saves it in the download directory. Returns the saved path.
+    pub(crate) async fn save_attachment(&mut self, body: &[u8]) -> anyhow::Result<PathBuf> {
+        let pointer = AttachmentPointer::decode(body)?;
+        let blob = self
+            .client
+            .download_blob(DownloadBlobRequest {
+                blob_id: pointer.blob_id.clone(),
+            })
+            .await?
+            .content;
+
+        let crypto = RustCrypto::default();
+        ensure!(
+            crypto.hash(HashType::Sha2_256, &blob)? == pointer.hash,
+            "Attachment hash mismatch"
+        );
+        let content = crypto
+            .aead_decrypt(
+                AeadType::ChaCha20Poly1305,
+                &pointer.key,
+                &blob,
+                &pointer.nonce,
+                &[],
+            )
+            .context("Failed to decrypt attachment")?;
+        ensure!(
+            content.len() as u64 == pointer.size,
+            "Attachment size mismatch"
+        );
+
+        let filename = attachment_filename(&pointer).context("Invalid attachment file name")?;
+        tokio::fs::create_dir_all(&self.download_dir).await?;
+        let path = write_new_file(&self.download_dir, filename, &content).await?;
+        info!(path = %path.display(), "Saved attachment");
+
+        Ok(path)
+    }
+}
+
+/// The file name of the attachment that `body` points to, for messages about
+/// attachments that couldn't be saved.
+pub(crate) fn attachment_name(body: &[u8]) -> String {
+    AttachmentPointer::decode(body)
+        .ok()
+        .and_then(|pointer| {
+            Some(
+                attachment_filename(&pointer)?
+                    .to_string_lossy()
+                    .into_owned(),
+            )
+        })
+        .unwrap_or_default()
+}
+
+/// Only the last component of the file name, so that senders can't write
+/// elsewhere.
+fn attachment_filename(pointer: &AttachmentPointer) -> Option<&OsStr> {
+    Path::new(&pointer.filename).file_name()
+}
+
+/// Writes `content` to a new file `filename` in `dir`. If one exists, a
+/// counter is added to the name instead of replacing it. Returns the path.
+async fn write_new_file(dir: &Path, filename: &OsStr, content: &[u8]) -> anyhow::Result<PathBuf> {
+    let filename = Path::new(filename);
+    let stem = filename.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = filename
+        .extension()
+        .map(|extension| format!(".{}", extension.to_string_lossy()))
+        .unwrap_or_default();
+    for counter in 0..MAX_NAME_COUNTER {
+        let path = match counter {
+            0 => dir.join(filename),
+            counter => dir.join(format!("{stem} ({counter}){extension}")),
+        };
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .await
+        {
+            Ok(mut file) => {
+                file.write_all(content).await?;
+                return Ok(path);
+            }
+            Err(error) if error.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(error) => return Err(error.into()),
+        }
+    }
+    bail!(
+        "Too many files named {} in {}",
+        filename.display(),
+        dir.display()
+    )
+}
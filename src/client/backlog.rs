@@ -0,0 +1,109 @@
+use openmls::{
+    group::GroupId,
+    prelude::{MlsMessageBodyIn, MlsMessageIn, ProtocolMessage, tls_codec::DeserializeBytes},
+};
+use sqlx::{TransactionManager, sqlite::SqliteTransactionManager};
+use tracing::{debug, warn};
+
+use crate::{
+    client::{Client, ledger::message_key},
+    grpc::ReceiveMessagesResponse,
+};
+
+/// Messages of a group handled in one transaction at most.
+const MAX_BATCH: usize = 100;
+
+/// Consecutive queued messages of the same group, see
+/// [`Client::handle_batch`].
+#[derive(Default)]
+pub(crate) struct Batch {
+    group_id: Option<GroupId>,
+    messages: Vec<ReceiveMessagesResponse>,
+}
+
+impl Batch {
+    /// Adds `message` if it belongs to the batch, otherwise returns the
+    /// batch so far to be handled first and starts the next one with it.
+    pub(crate) fn push(&mut self, message: ReceiveMessagesResponse) -> Option<Batch> {
+        let group_id = message_group_id(&message.content);
+        let done = !self.messages.is_empty()
+            && (group_id.is_none()
+                || group_id != self.group_id
+                || self.messages.len() == MAX_BATCH);
+        let batch = done.then(|| std::mem::take(self));
+        self.group_id = group_id;
+        self.messages.push(message);
+        batch
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+}
+
+/// Group of a protocol message, `None` for welcomes and anything that doesn't
+/// parse.
+fn message_group_id(content: &[u8]) -> Option<GroupId> {
+    let message = MlsMessageIn::tls_deserialize_exact_bytes(content).ok()?;
+    match message.extract() {
+        MlsMessageBodyIn::PublicMessage(message) => {
+            Some(ProtocolMessage::from(message).group_id().clone())
+        }
+        MlsMessageBodyIn::PrivateMessage(message) => {
+            Some(ProtocolMessage::from(message).group_id().clone())
+        }
+        _ => None,
+    }
+}
+
+impl Client {
+    /// Handles a batch of queued messages in one transaction, so that the
+    /// group is loaded once and the database written once for the batch.
+    /// Each message still gets a savepoint of its own, and a message that
+    /// can't be processed is quarantined without stopping the others.
+    ///
+    /// The server doesn't deliver the messages again, so one that fails for
+    /// a reason [`Client::receive_message`] leaves to the caller is
+    /// quarantined as well, to be retried. If even that fails, the batch is
+    /// rolled back and the error returned.
+    pub(crate) async fn handle_batch(&mut self, user: &str, batch: Batch) -> anyhow::Result<()> {
+        debug!(group_id = ?batch.group_id, messages = batch.messages.len(), "Handling batch");
+        let (events, deferred) = (self.events.len(), self.deferred.len());
+        SqliteTransactionManager::begin(&mut self.connection, None).await?;
+        for message in batch.messages {
+            let key = message_key(&message);
+            // Boxed, as handling a message may commit and catch up in turn.
+            let Err(error) =
+                Box::pin(self.receive_message(user, Some(&key), &message.content)).await
+            else {
+                continue;
+            };
+            warn!(%error, "Failed to handle message, quarantining it");
+            let result = self
+                .quarantine(user, Some(&key), &message.content, error.into())
+                .await;
+            if let Err(error) = result {
+                self.abort_batch(events, deferred).await?;
+                return Err(error);
+            }
+        }
+        if let Err(error) = SqliteTransactionManager::commit(&mut self.connection).await {
+            self.abort_batch(events, deferred).await?;
+            return Err(error.into());
+        }
+        if SqliteTransactionManager::get_transaction_depth(&self.connection) == 0 {
+            self.send_deferred(user).await;
+        }
+        Ok(())
+    }
+
+    /// Rolls back the transaction of a batch, with what handling its messages
+    /// left in memory past the first `events` and `deferred`.
+    async fn abort_batch(&mut self, events: usize, deferred: usize) -> anyhow::Result<()> {
+        SqliteTransactionManager::rollback(&mut self.connection).await?;
+        self.events.truncate(events);
+        self.deferred.truncate(deferred);
+        self.group_cache.clear();
+        Ok(())
+    }
+}
@@ -0,0 +1,168 @@
+use std::path::Path;
+
+use anyhow::{Context, anyhow};
+use argon2::Argon2;
+use openmls::prelude::{AeadType, OpenMlsCrypto, OpenMlsRand};
+use openmls_rust_crypto::RustCrypto;
+use sqlx::{ConnectOptions, Connection, query, sqlite::SqliteConnectOptions};
+
+use crate::client::{Client, Result, error::ensure};
+
+/// Start of a backup file, followed by the salt, the nonce and the encrypted
+/// database, see [`seal_with_passphrase`].
+const MAGIC: &[u8] = b"mls-chat backup 1\n";
+const SALT_LENGTH: usize = 16;
+const KEY_LENGTH: usize = 32;
+const NONCE_LENGTH: usize = 12;
+
+impl Client {
+    /// Writes the whole database, i.e. the signature keys, credentials and
+    /// group states of all profiles with their history and settings,
+    /// encrypted with a key derived from `passphrase` to `path`, which must
+    /// not exist.
+    ///
+    /// Signature keys kept in the OS keychain are copied into the backup, as
+    /// the database only refers to them; a restored database moves them to
+    /// the keychain again if it is used.
+    ///
+    /// If the database is encrypted, so is the database in the backup: it can
+    /// only be opened with the passphrase or key file of the database.
+    pub async fn backup(&mut self, path: &Path, passphrase: &str) -> Result<()> {
+        ensure!(
+            self.memory_storage.is_none(),
+            "The state of the client is only kept in memory"
+        );
+        ensure!(!path.exists(), "{} already exists", path.display());
+        let snapshot = path.with_extension("snapshot");
+        ensure!(!snapshot.exists(), "{} already exists", snapshot.display());
+
+        let keychain_users = query!(
+            "SELECT username, keychain_account AS \"keychain_account!\"
+            FROM client_user WHERE keychain_account IS NOT NULL"
+        )
+        .fetch_all(&mut self.connection)
+        .await?;
+        let mut keychain_keys = Vec::with_capacity(keychain_users.len());
+        for user in keychain_users {
+            let private_key = self
+                .load_private_key(Vec::new(), Some(user.keychain_account))
+                .await
+                .with_context(|| {
+                    format!(
+                        "Failed to copy the keychain key of {} into the backup",
+                        user.username
+                    )
+                })?;
+            keychain_keys.push((user.username, private_key));
+        }
+
+        sqlx::query("VACUUM INTO ?")
+            .bind(snapshot.to_string_lossy())
+            .execute(&mut self.connection)
+            .await?;
+        let database = async {
+            if !keychain_keys.is_empty() {
+                let options = SqliteConnectOptions::new().filename(&snapshot);
+                // The snapshot is encrypted with the key of the database.
+                #[cfg(feature = "sqlcipher")]
+                let options = match &self.database_key {
+                    Some(key) => options.pragma("key", key.pragma()),
+                    None => options,
+                };
+                let mut connection = options.connect().await?;
+                for (username, column) in keychain_keys {
+                    query!(
+                        "UPDATE client_user
+                        SET signature_private_key = ?, keychain_account = NULL
+                        WHERE username = ?",
+                        column,
+                        username,
+                    )
+                    .execute(&mut connection)
+                    .await?;
+                }
+                connection.close().await?;
+            }
+            anyhow::Ok(tokio::fs::read(&snapshot).await?)
+        }
+        .await;
+        tokio::fs::remove_file(&snapshot).await?;
+        let database = database.context("Failed to read database snapshot")?;
+
+        let backup = seal_with_passphrase(MAGIC, passphrase, &database)?;
+        tokio::fs::write(path, backup)
+            .await
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        Ok(())
+    }
+
+    /// Restores the database of a [`Client::backup`] to `db_path`, which must
+    /// not exist. Open it with [`Client::builder`] afterwards.
+    ///
+    /// The restored devices keep their ids, so the database the backup was
+    /// taken from must not be used anymore.
+    pub async fn restore_backup(backup: &Path, passphrase: &str, db_path: &Path) -> Result<()> {
+        ensure!(!db_path.exists(), "{} already exists", db_path.display());
+        let content = tokio::fs::read(backup)
+            .await
+            .with_context(|| format!("Failed to read {}", backup.display()))?;
+        let database = open_with_passphrase(MAGIC, passphrase, &content)?
+            .ok_or_else(|| anyhow!("{} is not a backup", backup.display()))?;
+        if let Some(dir) = db_path.parent() {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+        tokio::fs::write(db_path, database)
+            .await
+            .with_context(|| format!("Failed to write {}", db_path.display()))?;
+        Ok(())
+    }
+}
+
+/// Encrypts `plaintext` with a key derived from `passphrase`. The result
+/// starts with `magic`, followed by the salt, the nonce and the ciphertext.
+pub(crate) fn seal_with_passphrase(
+    magic: &[u8],
+    passphrase: &str,
+    plaintext: &[u8],
+) -> anyhow::Result<Vec<u8>> {
+    let crypto = RustCrypto::default();
+    let salt = crypto.random_vec(SALT_LENGTH)?;
+    let nonce = crypto.random_vec(NONCE_LENGTH)?;
+    let mut sealed = [magic, &salt, &nonce].concat();
+    let key = derive_key(passphrase.as_bytes(), &salt)?;
+    let ciphertext =
+        crypto.aead_encrypt(AeadType::ChaCha20Poly1305, &key, plaintext, &nonce, &sealed)?;
+    sealed.extend(ciphertext);
+    Ok(sealed)
+}
+
+/// Decrypts the output of [`seal_with_passphrase`]. Returns `None` if
+/// `content` doesn't start with `magic`.
+pub(crate) fn open_with_passphrase(
+    magic: &[u8],
+    passphrase: &str,
+    content: &[u8],
+) -> anyhow::Result<Option<Vec<u8>>> {
+    let header_length = magic.len() + SALT_LENGTH + NONCE_LENGTH;
+    if content.len() <= header_length || !content.starts_with(magic) {
+        return Ok(None);
+    }
+    let (header, ciphertext) = content.split_at(header_length);
+    let salt = &header[magic.len()..magic.len() + SALT_LENGTH];
+    let nonce = &header[magic.len() + SALT_LENGTH..];
+
+    let key = derive_key(passphrase.as_bytes(), salt)?;
+    let plaintext = RustCrypto::default()
+        .aead_decrypt(AeadType::ChaCha20Poly1305, &key, ciphertext, nonce, header)
+        .map_err(|_| anyhow!("Wrong passphrase or damaged file"))?;
+    Ok(Some(plaintext))
+}
+
+/// Derives a key from a passphrase or other secret with Argon2id.
+fn derive_key(secret: &[u8], salt: &[u8]) -> anyhow::Result<[u8; KEY_LENGTH]> {
+    let mut key = [0; KEY_LENGTH];
+    Argon2::default()
+        .hash_password_into(secret, salt, &mut key)
+        .map_err(|error| anyhow!("Failed to derive key: {error}"))?;
+    Ok(key)
+}
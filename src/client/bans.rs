@@ -0,0 +1,98 @@
+use anyhow::Context;
+use openmls::{
+    group::{MlsGroup, StagedCommit},
+    prelude::Sender,
+};
+use uuid::Uuid;
+
+use crate::client::{
+    Client, Result,
+    error::ensure,
+    group::{group_metadata, set_metadata_extension},
+    message::member_identities,
+};
+
+impl Client {
+    /// Removes `member` from the group, if it is a member, and bans it from
+    /// being added again.
+    pub async fn ban(&mut self, user: String, group_uuid: Uuid, member: String) -> Result<()> {
+        ensure!(member != user, "Can't ban yourself");
+        self.commit_group_changes(
+            &user,
+            group_uuid,
+            std::slice::from_ref(&member),
+            |extensions| {
+                let mut metadata = group_metadata(extensions).unwrap_or_default();
+                ensure!(
+                    !metadata.banned.contains(&member),
+                    "{member} is already banned"
+                );
+                metadata.banned.push(member.clone());
+                set_metadata_extension(extensions, &metadata)
+            },
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Allows `member` to be added to the group again.
+    pub async fn unban(&mut self, user: String, group_uuid: Uuid, member: String) -> Result<()> {
+        self.commit_group_context_extensions(&user, group_uuid, |extensions| {
+            let mut metadata = group_metadata(extensions).unwrap_or_default();
+            ensure!(metadata.banned.contains(&member), "{member} is not banned");
+            metadata.banned.retain(|banned| *banned != member);
+            set_metadata_extension(extensions, &metadata)
+        })
+        .await?;
+        Ok(())
+    }
+}
+
+/// Checks that `commit` adds nobody who is banned from `group`, neither with
+/// an add proposal nor by joining with an external commit.
+pub(crate) fn check_bans(group: &MlsGroup, commit: &StagedCommit) -> anyhow::Result<()> {
+    let Some(metadata) = group_metadata(group.extensions()) else {
+        return Ok(());
+    };
+    if metadata.banned.is_empty() {
+        return Ok(());
+    }
+    let members = member_identities(group);
+
+    let mut added: Vec<String> = commit
+        .add_proposals()
+        .map(|add| {
+            String::from_utf8_lossy(
+                add.add_proposal()
+                    .key_package()
+                    .leaf_node()
+                    .credential()
+                    .serialized_content(),
+            )
+            .into_owned()
+        })
+        .collect();
+    if commit
+        .queued_proposals()
+        .any(|proposal| matches!(proposal.sender(), Sender::NewMemberCommit))
+    {
+        let leaf_node = commit
+            .update_path_leaf_node()
+            .context("External commit without path")?;
+        added.push(
+            String::from_utf8_lossy(leaf_node.credential().serialized_content()).into_owned(),
+        );
+    }
+
+    for identity in added {
+        // Devices of existing members are added when they are linked.
+        if members.contains(&identity) {
+            continue;
+        }
+        ensure!(
+            !metadata.banned.contains(&identity),
+            "{identity} is banned from the group"
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,225 @@
+//! Bots that answer messages, on top of [`Client::spawn`].
+//!
+//! An echo bot:
+//!
+//! ```no_run
+//! use mls_chat::client::{Client, bot::ChatBot};
+//!
+//! #[tokio::main]
+//! async fn main() -> mls_chat::client::Result<()> {
+//!     let client = Client::builder("http://localhost:50051", "echo.db")
+//!         .build()
+//!         .await?;
+//!     ChatBot::new(client, "echo")
+//!         .on_message(|message| async move {
+//!             let text = message.text.clone();
+//!             message.reply(text).await
+//!         })
+//!         .run()
+//!         .await
+//! }
+//! ```
+
+use std::{future::Future, sync::Arc};
+
+use anyhow::anyhow;
+use futures_util::future::BoxFuture;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::client::{Client, Result, daemon::ClientHandle, events::ChatEvent};
+
+/// Which invites a [`ChatBot`] accepts.
+#[derive(Debug, Clone, Default)]
+pub enum InvitePolicy {
+    #[default]
+    All,
+    /// Invites of the listed inviters only.
+    Inviters(Vec<String>),
+    /// None, invites are left to be accepted or declined otherwise.
+    Manual,
+}
+
+impl InvitePolicy {
+    fn accepts(&self, inviter: &str) -> bool {
+        match self {
+            InvitePolicy::All => true,
+            InvitePolicy::Inviters(inviters) => inviters.iter().any(|i| i == inviter),
+            InvitePolicy::Manual => false,
+        }
+    }
+}
+
+/// A text message or downloaded attachment received by a [`ChatBot`], with
+/// helpers to answer it.
+#[derive(Debug, Clone)]
+pub struct BotMessage {
+    pub group_id: Uuid,
+    /// Id of the message in the history of the bot.
+    pub message_id: i64,
+    pub sender: String,
+    pub text: String,
+    /// Whether the message mentions the bot.
+    pub mentioned: bool,
+    user: String,
+    handle: ClientHandle,
+}
+
+impl BotMessage {
+    /// Sends `text` to the group as reply to this message.
+    pub async fn reply(&self, text: impl Into<String>) -> Result<()> {
+        let (user, group_id, message_id) = (self.user.clone(), self.group_id, self.message_id);
+        let text = text.into();
+        self.handle
+            .call(move |client| Box::pin(client.reply(user, group_id, message_id, text)))
+            .await?;
+        Ok(())
+    }
+
+    /// Sends `text` to the group of this message.
+    pub async fn send(&self, text: impl Into<String>) -> Result<()> {
+        let (user, group_id) = (self.user.clone(), self.group_id);
+        let text = text.into();
+        self.handle
+            .call(move |client| Box::pin(client.send(user, group_id, text)))
+            .await?;
+        Ok(())
+    }
+
+    /// The client of the bot, e.g. to react or to look at the history, see
+    /// [`ClientHandle::call`].
+    pub fn client(&self) -> &ClientHandle {
+        &self.handle
+    }
+
+    /// The user that the bot acts as.
+    pub fn user(&self) -> &str {
+        &self.user
+    }
+}
+
+type Handler = Arc<dyn Fn(BotMessage) -> BoxFuture<'static, Result<()>> + Send + Sync>;
+
+/// A client that runs message handlers, see the [module](self) for an example.
+///
+/// The bot follows the messages of its user, reconnecting as
+/// [`Client::follow`] does, and accepts invites according to its
+/// [`InvitePolicy`]. Messages of the user itself, e.g. sent by the bot, are not
+/// handled.
+pub struct ChatBot {
+    client: Client,
+    user: String,
+    invites: InvitePolicy,
+    handlers: Vec<Handler>,
+}
+
+impl ChatBot {
+    /// A bot that acts as `user`, which must be registered with `client`.
+    pub fn new(client: Client, user: impl Into<String>) -> Self {
+        Self {
+            client,
+            user: user.into(),
+            invites: InvitePolicy::default(),
+            handlers: Vec::new(),
+        }
+    }
+
+    /// Which invites the bot accepts, all by default.
+    pub fn with_invite_policy(mut self, invites: InvitePolicy) -> Self {
+        self.invites = invites;
+        self
+    }
+
+    /// Calls `handler` with each incoming message, after the handlers added
+    /// before. A failing handler is logged and doesn't stop the bot.
+    pub fn on_message<F, Fut>(mut self, handler: F) -> Self
+    where
+        F: Fn(BotMessage) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        self.handlers
+            .push(Arc::new(move |message| Box::pin(handler(message))));
+        self
+    }
+
+    /// Runs the bot until its client stops, e.g. because of an error that
+    /// can't be recovered from by reconnecting.
+    pub async fn run(self) -> Result<()> {
+        let ChatBot {
+            mut client,
+            user,
+            invites,
+            handlers,
+        } = self;
+        // Invites that arrived while the bot wasn't running.
+        for invite in client.invites(user.clone()).await? {
+            if invites.accepts(&invite.inviter) {
+                client.accept_invite(user.clone(), invite.group_id).await?;
+                info!(group_id = %invite.group_id, inviter = invite.inviter, "Accepted invite");
+            }
+        }
+        let (handle, mut events) = client.spawn(user.clone());
+
+        loop {
+            let event = match events.recv().await {
+                Ok(event) => event,
+                Err(RecvError::Lagged(missed)) => {
+                    warn!(missed, "Bot fell behind and missed events");
+                    continue;
+                }
+                Err(RecvError::Closed) => return Err(anyhow!("The client stopped").into()),
+            };
+            match event {
+                ChatEvent::WelcomeReceived {
+                    group_id, inviter, ..
+                } => accept_invite(&handle, &invites, &user, group_id, &inviter).await,
+                ChatEvent::Message {
+                    group_id,
+                    message_id,
+                    sender,
+                    text,
+                    mentioned,
+                } if sender != user => {
+                    let message = BotMessage {
+                        group_id,
+                        message_id,
+                        sender,
+                        text,
+                        mentioned,
+                        user: user.clone(),
+                        handle: handle.clone(),
+                    };
+                    for handler in &handlers {
+                        if let Err(error) = handler(message.clone()).await {
+                            warn!(%group_id, message_id, %error, "Message handler failed");
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Accepts the invite if `invites` allows, logging failures.
+async fn accept_invite(
+    handle: &ClientHandle,
+    invites: &InvitePolicy,
+    user: &str,
+    group_id: Uuid,
+    inviter: &str,
+) {
+    if !invites.accepts(inviter) {
+        info!(%group_id, inviter, "Leaving invite to be handled manually");
+        return;
+    }
+    let user = user.to_string();
+    match handle
+        .call(move |client| Box::pin(client.accept_invite(user, group_id)))
+        .await
+    {
+        Ok(()) => info!(%group_id, inviter, "Accepted invite"),
+        Err(error) => warn!(%group_id, inviter, %error, "Failed to accept invite"),
+    }
+}
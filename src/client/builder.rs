@@ -0,0 +1,446 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    path::PathBuf,
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::anyhow;
+use openmls_memory_storage::MemoryStorage;
+use openmls_sqlx_storage::SqliteStorageProvider;
+use sqlx::{
+    ConnectOptions,
+    sqlite::{SqliteConnectOptions, SqliteJournalMode, SqliteSynchronous},
+};
+use tonic::{
+    Request, Status,
+    metadata::{Ascii, MetadataValue},
+    service::{Interceptor, interceptor::InterceptedService},
+    transport::{Channel, ClientTlsConfig, Endpoint},
+};
+use tracing::info;
+
+#[cfg(feature = "sqlcipher")]
+use crate::client::encryption::{self, DatabaseSecret};
+#[cfg(feature = "quic")]
+use crate::client::quic::{QuicDeliveryService, QuicOptions};
+#[cfg(feature = "websocket")]
+use crate::{
+    client::websocket::{WebSocketDeliveryService, WebSocketOptions},
+    websocket::WebSocketEncoding,
+};
+use crate::{
+    client::{
+        Client, GroupConfig, Result,
+        cache::GroupCache,
+        context::GroupChangeValidator,
+        delivery::{DeliveryService, GrpcDeliveryService},
+        policy::AcceptPolicy,
+        signer::ChatSigner,
+        sneakernet::SneakernetDeliveryService,
+        storage,
+        transport::{RetryPolicy, Transport},
+        validator::CredentialValidator,
+    },
+    grpc::{chat_service_client::ChatServiceClient, version_service_client::VersionServiceClient},
+    migrations::adopt_split_migrations,
+    provider::{JsonCodec, StorageCodec},
+};
+
+/// Connection options of a [`Client`], see [`Client::builder`].
+#[derive(Debug, Clone)]
+pub struct ClientBuilder {
+    endpoint: String,
+    db_path: PathBuf,
+    tls: Option<ClientTlsConfig>,
+    connect_timeout: Option<Duration>,
+    timeout: Option<Duration>,
+    keepalive: Option<Duration>,
+    user_agent: Option<String>,
+    auth_token: Option<String>,
+    eager: bool,
+    memory: bool,
+    cbor: bool,
+    retry_policy: RetryPolicy,
+    #[cfg(feature = "sqlcipher")]
+    database_secret: Option<DatabaseSecret>,
+    #[cfg(feature = "keychain")]
+    keychain: bool,
+    signers: HashMap<String, Arc<dyn ChatSigner>>,
+    credential_validators: Vec<Arc<dyn CredentialValidator>>,
+    change_validators: Vec<Arc<dyn GroupChangeValidator>>,
+    delivery_service: Option<Arc<dyn DeliveryService>>,
+    #[cfg(feature = "websocket")]
+    websocket_encoding: WebSocketEncoding,
+    #[cfg(feature = "quic")]
+    quic_ca_certificate: Option<Vec<u8>>,
+}
+
+impl Client {
+    /// Starts configuring a client of the server at `endpoint`, keeping its
+    /// state in the database at `db_path`. Endpoints with a `ws` or `wss`
+    /// scheme are reached over a WebSocket instead of gRPC, with `quic` over
+    /// QUIC. With a `file` scheme there is no server, messages are exchanged
+    /// in files instead, see [`Client::export_message_file`].
+    pub fn builder(endpoint: impl Into<String>, db_path: impl Into<PathBuf>) -> ClientBuilder {
+        ClientBuilder {
+            endpoint: endpoint.into(),
+            db_path: db_path.into(),
+            tls: None,
+            connect_timeout: None,
+            timeout: None,
+            keepalive: None,
+            user_agent: None,
+            auth_token: None,
+            eager: false,
+            memory: false,
+            cbor: false,
+            retry_policy: RetryPolicy::default(),
+            #[cfg(feature = "sqlcipher")]
+            database_secret: None,
+            #[cfg(feature = "keychain")]
+            keychain: false,
+            signers: HashMap::new(),
+            credential_validators: Vec::new(),
+            change_validators: Vec::new(),
+            delivery_service: None,
+            #[cfg(feature = "websocket")]
+            websocket_encoding: WebSocketEncoding::default(),
+            #[cfg(feature = "quic")]
+            quic_ca_certificate: None,
+        }
+    }
+}
+
+impl ClientBuilder {
+    /// Connects with TLS. Endpoints with an `https` scheme use the native root
+    /// certificates unless configured otherwise, `wss` endpoints always do.
+    pub fn with_tls(mut self, tls: ClientTlsConfig) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// How long to wait for a connection to the server.
+    pub fn with_connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// How long to wait for the response of a request. Doesn't limit how long
+    /// the receive stream stays open.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Pings the server at this interval, also while idle, so that broken
+    /// connections are noticed and proxies don't close idle ones.
+    pub fn with_keepalive(mut self, interval: Duration) -> Self {
+        self.keepalive = Some(interval);
+        self
+    }
+
+    pub fn with_user_agent(mut self, user_agent: impl Into<String>) -> Self {
+        self.user_agent = Some(user_agent.into());
+        self
+    }
+
+    /// Sends `token` as bearer token with every request, e.g. for a gateway
+    /// in front of the server.
+    pub fn with_auth_token(mut self, token: impl Into<String>) -> Self {
+        self.auth_token = Some(token.into());
+        self
+    }
+
+    /// Connects to the server right away, so that `build` fails if it can't
+    /// be reached or doesn't speak a version of the API of this client. By
+    /// default the connection is made on the first request.
+    pub fn with_eager_connection(mut self) -> Self {
+        self.eager = true;
+        self
+    }
+
+    /// How requests are retried when the server can't be reached.
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Keeps all state in memory instead of the database at `db_path`, e.g.
+    /// for tests and bots that don't need it afterwards. Everything, including
+    /// the identity keys, is lost when the client is dropped.
+    pub fn with_memory_storage(mut self) -> Self {
+        self.memory = true;
+        self
+    }
+
+    /// Encodes the MLS state in the database with CBOR instead of JSON, which
+    /// takes less space and is faster to load for large groups. A database
+    /// using JSON is converted when it's first opened this way and can't be
+    /// used by older versions afterwards.
+    pub fn with_cbor_storage(mut self) -> Self {
+        self.cbor = true;
+        self
+    }
+
+    /// Encrypts the whole database with SQLCipher, with a key derived from
+    /// `passphrase`: the signature private keys, the MLS group states, the
+    /// history and everything else the client stores. An unencrypted database
+    /// is encrypted when it's first opened this way, an encrypted one can't be
+    /// opened without.
+    ///
+    /// What the client writes outside the database stays unencrypted:
+    /// downloaded attachments and exported histories. Backups are encrypted
+    /// with the database key in addition to their own passphrase, so
+    /// restoring one needs both. An in-memory client ignores the passphrase.
+    #[cfg(feature = "sqlcipher")]
+    pub fn with_passphrase(mut self, passphrase: impl Into<String>) -> Self {
+        self.database_secret = Some(DatabaseSecret::Passphrase(passphrase.into()));
+        self
+    }
+
+    /// Like [`ClientBuilder::with_passphrase`], with the content of a file as
+    /// the secret.
+    #[cfg(feature = "sqlcipher")]
+    pub fn with_key_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.database_secret = Some(DatabaseSecret::KeyFile(path.into()));
+        self
+    }
+
+    /// Keeps the signature private keys in the keychain of the OS instead of
+    /// the database, moving the keys stored so far. Keys stay in the database
+    /// if the keychain can't be used.
+    #[cfg(feature = "keychain")]
+    pub fn with_keychain(mut self) -> Self {
+        self.keychain = true;
+        self
+    }
+
+    /// Signs as `username` with `signer` instead of a key stored by the
+    /// client. Users registered or linked this way can't be used without the
+    /// signer, and their identity key can't be rotated by the client.
+    pub fn with_signer(mut self, username: impl Into<String>, signer: Arc<dyn ChatSigner>) -> Self {
+        self.signers.insert(username.into(), signer);
+        self
+    }
+
+    /// Checks the credentials of added and updated members with `validator`,
+    /// in addition to validators given before. See [`CredentialValidator`].
+    pub fn with_credential_validator(mut self, validator: Arc<dyn CredentialValidator>) -> Self {
+        self.credential_validators.push(validator);
+        self
+    }
+
+    /// Checks proposed and committed changes of the group settings with
+    /// `validator`, in addition to validators given before. See
+    /// [`GroupChangeValidator`].
+    pub fn with_group_change_validator(mut self, validator: Arc<dyn GroupChangeValidator>) -> Self {
+        self.change_validators.push(validator);
+        self
+    }
+
+    /// How the frames are encoded on a WebSocket, protobuf by default.
+    #[cfg(feature = "websocket")]
+    pub fn with_websocket_encoding(mut self, encoding: WebSocketEncoding) -> Self {
+        self.websocket_encoding = encoding;
+        self
+    }
+
+    /// PEM of the CA certificate to trust for `quic` endpoints instead of the
+    /// native roots, see [`ClientBuilder::with_tls`] for the other endpoints.
+    #[cfg(feature = "quic")]
+    pub fn with_quic_ca_certificate(mut self, pem: impl Into<Vec<u8>>) -> Self {
+        self.quic_ca_certificate = Some(pem.into());
+        self
+    }
+
+    /// Sends the requests to `service` instead of the server at the
+    /// endpoint, e.g. an in-process fake in tests or another transport. The
+    /// connection options don't apply to it, the retry policy does.
+    pub fn with_delivery_service(mut self, service: Arc<dyn DeliveryService>) -> Self {
+        self.delivery_service = Some(service);
+        self
+    }
+
+    /// Opens the database, applying pending migrations, and sets up the
+    /// connection to the server.
+    pub async fn build(mut self) -> Result<Client> {
+        #[cfg(feature = "sqlcipher")]
+        let mut database_key = None;
+        let (mut connection, memory_storage) = if self.memory {
+            info!("Opening in-memory client database");
+            let connection = SqliteConnectOptions::new()
+                .in_memory(true)
+                .connect()
+                .await?;
+            (connection, Some(MemoryStorage::default()))
+        } else {
+            info!(db_path = %self.db_path.display(), "Opening client database");
+            let options = SqliteConnectOptions::new()
+                .filename(&self.db_path)
+                .create_if_missing(true)
+                .journal_mode(SqliteJournalMode::Wal)
+                .synchronous(SqliteSynchronous::Normal);
+            #[cfg(feature = "sqlcipher")]
+            let connection = {
+                let key = match &self.database_secret {
+                    Some(secret) => Some(secret.key().await?),
+                    None => None,
+                };
+                let connection = encryption::connect(options, &self.db_path, key.as_ref()).await?;
+                database_key = key;
+                connection
+            };
+            #[cfg(not(feature = "sqlcipher"))]
+            let connection = options.connect().await?;
+            (connection, None)
+        };
+        // Not `run`, whose `Acquire` bound keeps this future from being `Send`.
+        let mut migrator = sqlx::migrate!("migrations/client");
+        adopt_split_migrations(&mut connection, &mut migrator).await?;
+        migrator.run_direct(&mut connection).await?;
+        let storage_codec = if memory_storage.is_none() {
+            SqliteStorageProvider::<JsonCodec>::new(&mut connection).run_migrations()?;
+            storage::storage_codec(&mut connection, self.cbor).await?
+        } else {
+            StorageCodec::Json
+        };
+        let mut sneakernet = None;
+        let service = match self.delivery_service.take() {
+            Some(service) => service,
+            None => match self.endpoint.strip_prefix("file://") {
+                Some(dir) => {
+                    let service = Arc::new(SneakernetDeliveryService::new(dir));
+                    sneakernet = Some(service.clone());
+                    service
+                }
+                None => self.connect().await?,
+            },
+        };
+
+        let mut client = Client {
+            client: Transport::new(service, self.retry_policy),
+            sneakernet,
+            connection,
+            memory_storage,
+            storage_codec,
+            #[cfg(feature = "sqlcipher")]
+            database_key,
+            #[cfg(feature = "keychain")]
+            keychain: self.keychain,
+            signers: self.signers,
+            credential_validators: self.credential_validators,
+            change_validators: self.change_validators,
+            accept_policy: AcceptPolicy::default(),
+            group_config: GroupConfig::default(),
+            key_rotation: None,
+            pruning_horizon: None,
+            mimi_content: false,
+            message_expiry: None,
+            download_dir: PathBuf::from("downloads"),
+            #[cfg(feature = "notifications")]
+            notifications: None,
+            profile: None,
+            events: VecDeque::new(),
+            deferred: Vec::new(),
+            group_cache: GroupCache::default(),
+        };
+        if self.eager {
+            client.client.negotiate().await?;
+        }
+        #[cfg(feature = "keychain")]
+        if client.keychain {
+            client.move_keys_to_keychain().await?;
+        }
+        Ok(client)
+    }
+
+    /// Sets up the connection to the server at the endpoint.
+    async fn connect(&self) -> Result<Arc<dyn DeliveryService>> {
+        #[cfg(feature = "websocket")]
+        if self.endpoint.starts_with("ws://") || self.endpoint.starts_with("wss://") {
+            let options = WebSocketOptions {
+                encoding: self.websocket_encoding,
+                connect_timeout: self.connect_timeout,
+                timeout: self.timeout,
+                keepalive: self.keepalive,
+                user_agent: self.user_agent.clone(),
+                auth_token: self.auth_token.clone(),
+            };
+            let service = WebSocketDeliveryService::new(&self.endpoint, options)?;
+            return Ok(Arc::new(service));
+        }
+        #[cfg(feature = "quic")]
+        if self.endpoint.starts_with("quic://") {
+            let options = QuicOptions {
+                ca_certificate: self.quic_ca_certificate.clone(),
+                connect_timeout: self.connect_timeout,
+                timeout: self.timeout,
+                keepalive: self.keepalive,
+            };
+            return Ok(Arc::new(QuicDeliveryService::new(&self.endpoint, options)?));
+        }
+
+        let mut endpoint = Endpoint::from_str(&self.endpoint)?;
+        if let Some(tls) = self.tls.clone() {
+            endpoint = endpoint.tls_config(tls)?;
+        } else if endpoint.uri().scheme_str() == Some("https") {
+            endpoint = endpoint.tls_config(ClientTlsConfig::new().with_native_roots())?;
+        }
+        if let Some(timeout) = self.connect_timeout {
+            endpoint = endpoint.connect_timeout(timeout);
+        }
+        if let Some(timeout) = self.timeout {
+            endpoint = endpoint.timeout(timeout);
+        }
+        if let Some(interval) = self.keepalive {
+            endpoint = endpoint
+                .http2_keep_alive_interval(interval)
+                .keep_alive_while_idle(true);
+        }
+        if let Some(user_agent) = self.user_agent.clone() {
+            endpoint = endpoint.user_agent(user_agent)?;
+        }
+        let channel = if self.eager {
+            endpoint
+                .connect()
+                .await
+                .map_err(|error| Status::unavailable(error.to_string()))?
+        } else {
+            endpoint.connect_lazy()
+        };
+
+        let token = self
+            .auth_token
+            .as_ref()
+            .map(|token| MetadataValue::try_from(format!("Bearer {token}")))
+            .transpose()
+            .map_err(|_| anyhow!("Invalid auth token"))?;
+        let interceptor = AuthInterceptor { token };
+        Ok(Arc::new(GrpcDeliveryService {
+            versions: VersionServiceClient::with_interceptor(channel.clone(), interceptor.clone()),
+            client: ChatServiceClient::with_interceptor(channel, interceptor),
+        }))
+    }
+}
+
+/// Adds the auth token, if any, to requests.
+#[derive(Debug, Clone)]
+pub(crate) struct AuthInterceptor {
+    token: Option<MetadataValue<Ascii>>,
+}
+
+impl Interceptor for AuthInterceptor {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        if let Some(token) = &self.token {
+            request
+                .metadata_mut()
+                .insert("authorization", token.clone());
+        }
+        Ok(request)
+    }
+}
+
+pub(crate) type ServiceClient = ChatServiceClient<InterceptedService<Channel, AuthInterceptor>>;
+pub(crate) type VersionClient = VersionServiceClient<InterceptedService<Channel, AuthInterceptor>>;
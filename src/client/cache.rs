@@ -0,0 +1,85 @@
+use std::{
+    collections::VecDeque,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use openmls::group::{GroupId, MlsGroup};
+use openmls_traits::OpenMlsProvider;
+use tracing::debug;
+
+use crate::client::Client;
+
+/// Groups kept loaded at most.
+const CAPACITY: usize = 16;
+
+/// Recently used groups, so that a busy group isn't loaded from the storage
+/// for every message. Writes to the MLS state other than through the cached
+/// groups themselves invalidate the cache.
+#[derive(Default)]
+pub(crate) struct GroupCache {
+    /// Most recently used first.
+    groups: VecDeque<MlsGroup>,
+    /// Writes to the MLS state that didn't go through a cached group.
+    pub(crate) writes: AtomicU64,
+    /// Value of `writes` the cached groups are current with.
+    current_with: u64,
+    /// `PRAGMA data_version` of the database, which changes when other
+    /// connections write to it.
+    data_version: Option<i64>,
+}
+
+impl GroupCache {
+    /// Drops all cached groups, e.g. after rolling back a transaction.
+    pub(crate) fn clear(&mut self) {
+        self.groups.clear();
+        self.writes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn writes(&self) -> u64 {
+        self.writes.load(Ordering::Relaxed)
+    }
+}
+
+impl Client {
+    /// Takes the group out of the cache, or loads it from the storage, along
+    /// with the writes to pass to [`Client::cache_group`]. Changes to the
+    /// group have to be made with [`Client::cached_provider`].
+    pub(crate) async fn take_group(
+        &mut self,
+        group_id: &GroupId,
+    ) -> anyhow::Result<Option<(MlsGroup, u64)>> {
+        let data_version = sqlx::query_scalar("PRAGMA data_version")
+            .fetch_one(&mut self.connection)
+            .await?;
+        let cache = &mut self.group_cache;
+        if cache.data_version.replace(data_version) != Some(data_version)
+            || cache.writes() != cache.current_with
+        {
+            cache.groups.clear();
+        }
+        let writes = cache.writes();
+        if let Some(index) = cache
+            .groups
+            .iter()
+            .position(|group| group.group_id() == group_id)
+        {
+            debug!(?group_id, "Group cache hit");
+            return Ok(cache.groups.remove(index).map(|group| (group, writes)));
+        }
+        let group = MlsGroup::load(self.cached_provider().storage(), group_id)?;
+        Ok(group.map(|group| (group, writes)))
+    }
+
+    /// Puts a group taken with [`Client::take_group`] back into the cache,
+    /// unless the MLS state was written to in the meantime, which may have
+    /// left it stale.
+    pub(crate) fn cache_group(&mut self, group: MlsGroup, writes: u64) {
+        let cache = &mut self.group_cache;
+        if cache.writes() != writes {
+            return;
+        }
+        cache.current_with = writes;
+        cache.groups.push_front(group);
+        cache.groups.truncate(CAPACITY);
+    }
+}
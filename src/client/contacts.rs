@@ -0,0 +1,117 @@
+use openmls::group::MlsGroup;
+use sqlx::{
+    query, query_scalar,
+    types::chrono::{DateTime, Utc},
+};
+use tracing::warn;
+
+use crate::client::{Client, Result, error::ensure, member::fingerprint};
+
+impl Client {
+    /// Trusts all signature keys seen for `contact`, after a change of them
+    /// was verified out of band. Returns the number of newly trusted keys.
+    pub async fn trust(&mut self, user: String, contact: String) -> Result<u64> {
+        let trusted = query!(
+            "UPDATE client_contact SET trusted = TRUE
+            WHERE username = ? AND contact = ? AND NOT trusted",
+            user,
+            contact,
+        )
+        .execute(&mut self.connection)
+        .await?
+        .rows_affected();
+        Ok(trusted)
+    }
+
+    /// Pins the signature key of `contact` on first sight. A key that differs
+    /// from the pinned ones is recorded as untrusted until
+    /// [`Client::trust`] is called. Returns whether the key is trusted.
+    pub(crate) async fn pin_key(
+        &mut self,
+        user: &str,
+        contact: &str,
+        signature_key: &[u8],
+    ) -> anyhow::Result<bool> {
+        // Own devices are bound to the user by device certificates instead.
+        if contact == user {
+            return Ok(true);
+        }
+        let trusted = query_scalar!(
+            "SELECT trusted AS \"trusted: bool\" FROM client_contact
+            WHERE username = ? AND contact = ? AND signature_key = ?",
+            user,
+            contact,
+            signature_key,
+        )
+        .fetch_optional(&mut self.connection)
+        .await?;
+        if let Some(trusted) = trusted {
+            return Ok(trusted);
+        }
+
+        let known = query_scalar!(
+            "SELECT EXISTS (SELECT 1 FROM client_contact WHERE username = ? AND contact = ?)",
+            user,
+            contact,
+        )
+        .fetch_one(&mut self.connection)
+        .await?
+            != 0;
+        let trusted = !known;
+        let first_seen_at: DateTime<Utc> = Utc::now();
+        query!(
+            "INSERT INTO client_contact (
+                username, contact, signature_key, trusted, first_seen_at
+            ) VALUES (?, ?, ?, ?, ?)",
+            user,
+            contact,
+            signature_key,
+            trusted,
+            first_seen_at,
+        )
+        .execute(&mut self.connection)
+        .await?;
+        if !trusted {
+            warn!(
+                contact,
+                fingerprint = fingerprint(signature_key),
+                "Signature key of {contact} changed, verify it and run `trust {contact}`"
+            );
+        }
+        Ok(trusted)
+    }
+
+    /// Pins the signature keys of all members of `group`. Returns the members
+    /// with untrusted keys.
+    pub(crate) async fn pin_member_keys(
+        &mut self,
+        user: &str,
+        group: &MlsGroup,
+    ) -> anyhow::Result<Vec<String>> {
+        let mut untrusted = Vec::new();
+        for member in group.members() {
+            let identity = String::from_utf8_lossy(member.credential.serialized_content());
+            if !self.pin_key(user, &identity, &member.signature_key).await?
+                && !untrusted.contains(&identity.to_string())
+            {
+                untrusted.push(identity.into_owned());
+            }
+        }
+        Ok(untrusted)
+    }
+
+    /// Fails if any member of `group` has an untrusted signature key.
+    pub(crate) async fn ensure_trusted_members(
+        &mut self,
+        user: &str,
+        group: &MlsGroup,
+    ) -> anyhow::Result<()> {
+        let untrusted = self.pin_member_keys(user, group).await?;
+        ensure!(
+            untrusted.is_empty(),
+            "Signature keys of {} changed; verify them and run `trust`",
+            untrusted.join(", ")
+        );
+        Ok(())
+    }
+}
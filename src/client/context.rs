@@ -0,0 +1,272 @@
+use std::{fmt, sync::Arc, time::Duration};
+
+use anyhow::Context;
+use openmls::{
+    group::{GroupId, MlsGroup, StagedCommit},
+    prelude::{Extensions, GroupContext, OpenMlsProvider, Proposal, Sender, tls_codec::Serialize},
+};
+use sqlx::types::chrono::{DateTime, Utc};
+use tracing::{debug, info};
+use uuid::Uuid;
+
+use crate::client::{
+    Client, Result,
+    error::{bail, ensure, not_found},
+    group::{group_metadata, set_metadata_extension},
+    message::{identity, member_identities},
+    rebase::rebasing,
+    roles::{group_roles, set_roles_extension},
+};
+
+/// The settings of a group carried in its group context extensions.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GroupSettings {
+    pub name: String,
+    pub topic: String,
+    /// Admins of the group, `None` if it has no roles and everybody may
+    /// change it.
+    pub admins: Option<Vec<String>>,
+    /// After how long messages sent to the group disappear.
+    pub message_timer: Option<Duration>,
+    pub open: bool,
+    pub read_only: bool,
+}
+
+impl GroupSettings {
+    pub(crate) fn from_extensions(extensions: &Extensions<GroupContext>) -> Self {
+        let metadata = group_metadata(extensions).unwrap_or_default();
+        Self {
+            name: metadata.name,
+            topic: metadata.topic,
+            admins: group_roles(extensions).map(|roles| roles.admins),
+            message_timer: (metadata.message_timer > 0)
+                .then(|| Duration::from_secs(metadata.message_timer.into())),
+            open: metadata.open,
+            read_only: metadata.read_only,
+        }
+    }
+}
+
+/// A change of the settings of a group, see [`Client::change_group`].
+/// `None` keeps the current value.
+#[derive(Debug, Clone, Default)]
+pub struct GroupChange {
+    pub name: Option<String>,
+    pub topic: Option<String>,
+    /// Replaces the admins of the group.
+    pub admins: Option<Vec<String>>,
+    /// After how long messages disappear, in whole seconds. Zero turns the
+    /// timer off.
+    pub message_timer: Option<Duration>,
+}
+
+impl GroupChange {
+    fn is_empty(&self) -> bool {
+        self.name.is_none()
+            && self.topic.is_none()
+            && self.admins.is_none()
+            && self.message_timer.is_none()
+    }
+
+    /// Applies the change to the group context extensions of `group`.
+    fn apply(
+        &self,
+        group: &MlsGroup,
+        extensions: &mut Extensions<GroupContext>,
+    ) -> anyhow::Result<()> {
+        if self.name.is_some() || self.topic.is_some() || self.message_timer.is_some() {
+            let mut metadata = group_metadata(extensions).unwrap_or_default();
+            if let Some(name) = &self.name {
+                metadata.name = name.clone();
+            }
+            if let Some(topic) = &self.topic {
+                metadata.topic = topic.clone();
+            }
+            if let Some(message_timer) = self.message_timer {
+                metadata.message_timer =
+                    u32::try_from(message_timer.as_secs()).context("Message timer is too long")?;
+            }
+            set_metadata_extension(extensions, &metadata)?;
+        }
+        if let Some(admins) = &self.admins {
+            ensure!(!admins.is_empty(), "The group needs at least one admin");
+            let members = member_identities(group);
+            if let Some(admin) = admins.iter().find(|admin| !members.contains(admin)) {
+                bail!("{admin} is not a member of the group");
+            }
+            let mut roles = group_roles(extensions).unwrap_or_default();
+            roles.admins = admins.clone();
+            set_roles_extension(extensions, &roles)?;
+        }
+        Ok(())
+    }
+}
+
+/// A proposed or committed change of the settings of a group, see
+/// [`GroupChangeValidator`].
+#[derive(Debug, Clone)]
+pub struct ProposedChange {
+    pub group_id: Uuid,
+    /// Member that proposed the change.
+    pub proposer: String,
+    pub before: GroupSettings,
+    pub after: GroupSettings,
+}
+
+/// Checks changes of the group settings before the client accepts them, for
+/// policies of a deployment, e.g. on names or timers. See
+/// [`ClientBuilder::with_group_change_validator`](crate::client::builder::ClientBuilder::with_group_change_validator).
+///
+/// Rejected proposals aren't kept for the next commit, rejected commits are
+/// dropped, and the client refuses to propose or commit rejected changes
+/// itself.
+#[tonic::async_trait]
+pub trait GroupChangeValidator: fmt::Debug + Send + Sync {
+    /// Accepts the change, or returns why it is rejected.
+    async fn validate(&self, change: &ProposedChange) -> anyhow::Result<()>;
+}
+
+impl Client {
+    /// The settings of the group.
+    pub async fn group_settings(
+        &mut self,
+        user: String,
+        group_uuid: Uuid,
+    ) -> Result<GroupSettings> {
+        let group = self.load_member_group(&user, group_uuid).await?;
+        Ok(GroupSettings::from_extensions(group.extensions()))
+    }
+
+    /// Changes the settings of the group with a commit. Only admins may do
+    /// so, other members propose changes with
+    /// [`Client::propose_group_change`].
+    pub async fn change_group(
+        &mut self,
+        user: String,
+        group_uuid: Uuid,
+        change: GroupChange,
+    ) -> Result<()> {
+        ensure!(!change.is_empty(), "Nothing to change");
+        let group = self.load_member_group(&user, group_uuid).await?;
+        self.commit_group_context_extensions(&user, group_uuid, |extensions| {
+            change.apply(&group, extensions)
+        })
+        .await?;
+        info!(%group_uuid, "Changed group settings");
+        Ok(())
+    }
+
+    /// Proposes a change of the settings of the group without committing.
+    /// It takes effect once an admin commits it, see
+    /// [`Client::commit_pending`].
+    pub async fn propose_group_change(
+        &mut self,
+        user: String,
+        group_uuid: Uuid,
+        change: GroupChange,
+    ) -> Result<()> {
+        ensure!(!change.is_empty(), "Nothing to change");
+        rebasing!(self, &user, |client| client
+            .propose_group_change_once(&user, group_uuid, &change))?;
+        info!(%group_uuid, "Proposed group settings change");
+        Ok(())
+    }
+
+    async fn propose_group_change_once(
+        &mut self,
+        user: &str,
+        group_uuid: Uuid,
+        change: &GroupChange,
+    ) -> anyhow::Result<()> {
+        let (signing_private_key, _credential_with_key) = self.credential(user).await?;
+
+        let group_id = GroupId::from_slice(group_uuid.as_bytes());
+        let group = MlsGroup::load(self.provider().storage(), &group_id)?
+            .ok_or_else(|| not_found("Group"))?;
+        let mut extensions = group.extensions().clone();
+        change.apply(&group, &mut extensions)?;
+        validate_change(
+            &self.change_validators,
+            group_uuid,
+            &group,
+            user,
+            &extensions,
+        )
+        .await?;
+
+        let provider = self.provider();
+        let mut group =
+            MlsGroup::load(provider.storage(), &group_id)?.ok_or_else(|| not_found("Group"))?;
+        let (proposal, _proposal_ref) =
+            group.propose_group_context_extensions(&provider, extensions, &signing_private_key)?;
+        let recipients = member_identities(&group);
+        self.fanout(user, recipients, proposal.tls_serialize_detached()?)
+            .await?;
+        Ok(())
+    }
+}
+
+/// When a message sent or received `at` disappears by the timer of the group.
+pub(crate) fn timer_expiry(group: &MlsGroup, at: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    let message_timer = GroupSettings::from_extensions(group.extensions()).message_timer?;
+    Some(at + message_timer)
+}
+
+/// Runs `validators` on the change of `group` to `extensions` that
+/// `proposer` proposed.
+pub(crate) async fn validate_change(
+    validators: &[Arc<dyn GroupChangeValidator>],
+    group_uuid: Uuid,
+    group: &MlsGroup,
+    proposer: &str,
+    extensions: &Extensions<GroupContext>,
+) -> anyhow::Result<()> {
+    if validators.is_empty() {
+        return Ok(());
+    }
+    let change = ProposedChange {
+        group_id: group_uuid,
+        proposer: proposer.to_string(),
+        before: GroupSettings::from_extensions(group.extensions()),
+        after: GroupSettings::from_extensions(extensions),
+    };
+    for validator in validators {
+        if let Err(error) = validator.validate(&change).await {
+            bail!("Change of the group by {proposer} was rejected: {error}");
+        }
+    }
+    debug!(%group_uuid, proposer, "Validated group change");
+    Ok(())
+}
+
+/// Runs `validators` on the change of the group context extensions that
+/// `commit` of `committer` makes, if any.
+pub(crate) async fn validate_commit_change(
+    validators: &[Arc<dyn GroupChangeValidator>],
+    group_uuid: Uuid,
+    group: &MlsGroup,
+    committer: &str,
+    commit: &StagedCommit,
+) -> anyhow::Result<()> {
+    let Some(proposal) = commit
+        .queued_proposals()
+        .find(|proposal| matches!(proposal.proposal(), Proposal::GroupContextExtensions(_)))
+    else {
+        return Ok(());
+    };
+    let proposer = match proposal.sender() {
+        Sender::Member(leaf_index) => group
+            .member_at(*leaf_index)
+            .and_then(|member| identity(&member.credential))
+            .unwrap_or_else(|| committer.to_string()),
+        _ => committer.to_string(),
+    };
+    validate_change(
+        validators,
+        group_uuid,
+        group,
+        &proposer,
+        commit.group_context().extensions(),
+    )
+    .await
+}
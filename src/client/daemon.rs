@@ -0,0 +1,246 @@
+use std::{future::pending, time::Duration};
+
+use anyhow::anyhow;
+use futures_util::{Stream, TryStreamExt, future::BoxFuture, stream};
+use openmls::prelude::OpenMlsRand;
+use openmls_rust_crypto::RustCrypto;
+use tokio::{
+    sync::{broadcast, mpsc, oneshot},
+    time::{Instant, Interval, MissedTickBehavior, interval, sleep_until},
+};
+use tonic::Status;
+use tracing::{error, info, warn};
+
+use crate::{
+    client::{
+        Client, Result, delivery::MessageStream, events::ChatEvent, ledger::message_key,
+        outbox::is_transient,
+    },
+    grpc::ReceiveMessagesResponse,
+};
+
+/// Delay before the first reconnect, doubled with each further failure.
+const INITIAL_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(5 * 60);
+/// How often queued messages are retried while connected.
+const OUTBOX_INTERVAL: Duration = Duration::from_secs(30);
+/// How often the key packages are replaced, well within their lifetime.
+const KEY_PACKAGE_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+/// Calls of a [`ClientHandle`] that may wait for the client.
+const QUEUED_CALLS: usize = 32;
+/// Events kept for subscribers of a [`ClientHandle`] that fall behind.
+const BUFFERED_EVENTS: usize = 256;
+
+/// Call of a [`ClientHandle`], run on the task of the client.
+type Call = Box<dyn for<'a> FnOnce(&'a mut Client) -> BoxFuture<'a, ()> + Send>;
+
+impl Client {
+    /// Receives messages and yields the resulting events until dropped.
+    /// Reconnects with backoff when the connection fails, resuming with the
+    /// messages that were not delivered yet, and keeps the outbox and the key
+    /// packages of the device current.
+    pub fn follow(&mut self, user: String) -> impl Stream<Item = Result<ChatEvent>> + '_ {
+        let follow = Follow::new(self, user, None);
+        stream::try_unfold(follow, |mut follow| async move {
+            let event = follow.next_event().await?;
+            Ok(event.map(|event| (event, follow)))
+        })
+    }
+
+    /// Moves the client to a task that follows the messages of `user` like
+    /// [`Client::follow`], so that other tasks can send, receive and run
+    /// maintenance at the same time through the returned handle. The task
+    /// stops once all handles are dropped. The returned receiver gets all
+    /// events from the start, including those of the queued messages.
+    pub fn spawn(self, user: String) -> (ClientHandle, broadcast::Receiver<ChatEvent>) {
+        let (calls, receiver) = mpsc::channel(QUEUED_CALLS);
+        let (events, first_events) = broadcast::channel(BUFFERED_EVENTS);
+        let handle = ClientHandle {
+            calls,
+            events: events.clone(),
+        };
+        tokio::spawn(async move {
+            let mut client = self;
+            let mut follow = Follow::new(&mut client, user, Some(receiver));
+            loop {
+                match follow.next_event().await {
+                    // Nobody may be subscribed.
+                    Ok(Some(event)) => _ = events.send(event),
+                    Ok(None) => break,
+                    Err(error) => {
+                        error!(%error, "Client stopped");
+                        break;
+                    }
+                }
+            }
+        });
+        (handle, first_events)
+    }
+}
+
+/// Shared access to a client running on its own task, see [`Client::spawn`].
+#[derive(Debug, Clone)]
+pub struct ClientHandle {
+    calls: mpsc::Sender<Call>,
+    events: broadcast::Sender<ChatEvent>,
+}
+
+impl ClientHandle {
+    /// Runs `call` with the client once the message or call at hand is
+    /// done, e.g.
+    /// `handle.call(|client| Box::pin(client.send(user, group, text)))`.
+    pub async fn call<T, F>(&self, call: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: for<'a> FnOnce(&'a mut Client) -> BoxFuture<'a, Result<T>> + Send + 'static,
+    {
+        let (result, receiver) = oneshot::channel();
+        let call: Call = Box::new(move |client| {
+            Box::pin(async move {
+                // The caller may have given up waiting.
+                _ = result.send(call(client).await);
+            })
+        });
+        self.calls
+            .send(call)
+            .await
+            .map_err(|_| anyhow!("The client stopped"))?;
+        receiver.await.map_err(|_| anyhow!("The client stopped"))?
+    }
+
+    /// Events of the messages processed from now on. A subscriber that falls
+    /// too far behind misses the oldest events, see
+    /// [`broadcast::error::RecvError::Lagged`].
+    pub fn events(&self) -> broadcast::Receiver<ChatEvent> {
+        self.events.subscribe()
+    }
+}
+
+struct Follow<'a> {
+    client: &'a mut Client,
+    user: String,
+    messages: Option<MessageStream>,
+    /// When to connect again while disconnected.
+    reconnect_at: Instant,
+    delay: Duration,
+    outbox: Interval,
+    key_packages: Interval,
+    calls: Option<mpsc::Receiver<Call>>,
+}
+
+enum Wake {
+    Message(Result<Option<ReceiveMessagesResponse>, Status>),
+    Reconnect,
+    Outbox,
+    KeyPackages,
+    Call(Option<Call>),
+}
+
+impl<'a> Follow<'a> {
+    fn new(client: &'a mut Client, user: String, calls: Option<mpsc::Receiver<Call>>) -> Self {
+        let mut outbox = interval(OUTBOX_INTERVAL);
+        outbox.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut key_packages = interval(KEY_PACKAGE_INTERVAL);
+        key_packages.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        Self {
+            client,
+            user,
+            messages: None,
+            reconnect_at: Instant::now(),
+            delay: INITIAL_RECONNECT_DELAY,
+            outbox,
+            key_packages,
+            calls,
+        }
+    }
+
+    /// Next event, or `None` once the calls ended.
+    async fn next_event(&mut self) -> anyhow::Result<Option<ChatEvent>> {
+        loop {
+            if let Some(event) = self.client.events.pop_front() {
+                return Ok(Some(event));
+            }
+            let connected = self.messages.is_some();
+            let wake = tokio::select! {
+                message = next_message(&mut self.messages) => Wake::Message(message),
+                _ = sleep_until(self.reconnect_at), if !connected => Wake::Reconnect,
+                _ = self.outbox.tick(), if connected => Wake::Outbox,
+                _ = self.key_packages.tick(), if connected => Wake::KeyPackages,
+                call = next_call(&mut self.calls) => Wake::Call(call),
+            };
+            match wake {
+                Wake::Message(Ok(Some(message))) => {
+                    // A message that can't be processed is quarantined, and a
+                    // failing database shouldn't stop the others either.
+                    if let Err(error) = self
+                        .client
+                        .receive_message(&self.user, Some(&message_key(&message)), &message.content)
+                        .await
+                    {
+                        warn!(%error, "Failed to handle message");
+                    }
+                    self.client.send_receipts(&self.user).await?;
+                }
+                Wake::Message(Ok(None)) => {
+                    self.reconnect_later(anyhow!("Server closed the stream"))
+                }
+                Wake::Message(Err(status)) => self.reconnect_later(status.into()),
+                Wake::Reconnect => match self.client.open_stream(&self.user).await {
+                    Ok(messages) => {
+                        info!("Connected");
+                        self.messages = Some(messages);
+                        self.delay = INITIAL_RECONNECT_DELAY;
+                    }
+                    Err(error) if is_transient(&error) => self.reconnect_later(error),
+                    Err(error) => return Err(error),
+                },
+                Wake::Outbox => {
+                    self.client.flush_outbox(&self.user).await?;
+                }
+                Wake::KeyPackages => match self.client.replenish_key_packages(&self.user).await {
+                    Ok(()) => info!("Replenished key packages"),
+                    Err(error) => warn!(%error, "Failed to replenish key packages"),
+                },
+                Wake::Call(Some(call)) => call(self.client).await,
+                Wake::Call(None) => return Ok(None),
+            }
+        }
+    }
+
+    fn reconnect_later(&mut self, error: anyhow::Error) {
+        self.messages = None;
+        let wait = jitter(self.delay);
+        warn!(%error, ?wait, "Disconnected, reconnecting");
+        self.reconnect_at = Instant::now() + wait;
+        self.delay = self.delay.saturating_mul(2).min(MAX_RECONNECT_DELAY);
+    }
+}
+
+/// Next message of the stream, if connected.
+async fn next_message(
+    messages: &mut Option<MessageStream>,
+) -> Result<Option<ReceiveMessagesResponse>, Status> {
+    match messages {
+        Some(messages) => messages.try_next().await,
+        None => pending().await,
+    }
+}
+
+/// Next call of a [`ClientHandle`], or `None` once all handles are dropped.
+async fn next_call(calls: &mut Option<mpsc::Receiver<Call>>) -> Option<Call> {
+    match calls {
+        Some(calls) => calls.recv().await,
+        None => pending().await,
+    }
+}
+
+/// Random delay between half and all of `delay`, so that clients don't
+/// reconnect in lockstep after an outage.
+pub(crate) fn jitter(delay: Duration) -> Duration {
+    let random = RustCrypto::default()
+        .random_array::<8>()
+        .map(u64::from_le_bytes)
+        .unwrap_or_default();
+    let half = delay / 2;
+    half + half.mul_f64(random as f64 / u64::MAX as f64)
+}
@@ -0,0 +1,92 @@
+use openmls::{
+    prelude::{GroupContext, tls_codec::Serialize},
+    treesync::{LeafNode, Node},
+};
+use openmls_traits::{
+    OpenMlsProvider, crypto::OpenMlsCrypto, storage::StorageProvider, types::HashType,
+};
+use uuid::Uuid;
+
+use crate::client::{Client, Result, error::not_found};
+
+/// The state of a group as seen by this device, see [`Client::debug_group`].
+/// Members that disagree about the group differ in some of it.
+#[derive(Debug, Clone)]
+pub struct GroupState {
+    pub epoch: u64,
+    pub ciphersuite: u16,
+    pub tree_hash: Vec<u8>,
+    pub confirmed_transcript_hash: Vec<u8>,
+    pub epoch_authenticator: Vec<u8>,
+    pub own_leaf_index: u32,
+    /// Proposals received or sent in this epoch, but not committed yet.
+    pub pending_proposals: usize,
+    /// Whether an own commit waits to be merged.
+    pub pending_commit: bool,
+    /// The nodes of the ratchet tree in array order, where leaves have even
+    /// indices, without trailing blank nodes.
+    pub tree: Vec<TreeNode>,
+}
+
+/// A node of the ratchet tree. The digest is the SHA-256 hash of its
+/// encoding, i.e. of its public keys, parent hash and, for parents, the
+/// unmerged leaves; nodes with equal digests agree.
+#[derive(Debug, Clone)]
+pub enum TreeNode {
+    Blank,
+    Leaf { identity: String, digest: Vec<u8> },
+    Parent { digest: Vec<u8> },
+}
+
+impl Client {
+    /// The epoch, hashes and ratchet tree of a group, to find where two
+    /// members that fail to decrypt each other's messages diverged.
+    pub async fn debug_group(&mut self, user: String, group_uuid: Uuid) -> Result<GroupState> {
+        let group = self.load_member_group(&user, group_uuid).await?;
+        let context: GroupContext = self
+            .provider()
+            .storage()
+            .group_context(group.group_id())?
+            .ok_or_else(|| not_found("Group"))?;
+        // The nodes of the exported tree are only reachable through its
+        // serialized form.
+        let nodes: Vec<Option<Node>> =
+            serde_json::from_value(serde_json::to_value(group.export_ratchet_tree())?)?;
+        let provider = self.provider();
+        let crypto = provider.crypto();
+        let digest = |node: &Node| -> anyhow::Result<Vec<u8>> {
+            Ok(crypto.hash(HashType::Sha2_256, &node.tls_serialize_detached()?)?)
+        };
+        let tree = nodes
+            .iter()
+            .map(|node| {
+                Ok(match node {
+                    None => TreeNode::Blank,
+                    Some(node @ Node::LeafNode(leaf)) => TreeNode::Leaf {
+                        identity: leaf_identity(leaf),
+                        digest: digest(node)?,
+                    },
+                    Some(node @ Node::ParentNode(_)) => TreeNode::Parent {
+                        digest: digest(node)?,
+                    },
+                })
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(GroupState {
+            epoch: context.epoch().as_u64(),
+            ciphersuite: u16::from(context.ciphersuite()),
+            tree_hash: context.tree_hash().to_vec(),
+            confirmed_transcript_hash: context.confirmed_transcript_hash().to_vec(),
+            epoch_authenticator: group.epoch_authenticator().as_slice().to_vec(),
+            own_leaf_index: group.own_leaf_index().u32(),
+            pending_proposals: group.pending_proposals().count(),
+            pending_commit: group.pending_commit().is_some(),
+            tree,
+        })
+    }
+}
+
+fn leaf_identity(leaf: &LeafNode) -> String {
+    String::from_utf8_lossy(leaf.credential().serialized_content()).into_owned()
+}
@@ -0,0 +1,346 @@
+use std::fmt;
+
+use futures_util::{StreamExt, stream::BoxStream};
+use tonic::Status;
+
+use crate::{
+    client::builder::{ServiceClient, VersionClient},
+    grpc::*,
+};
+
+/// Messages of the user as they are received, see
+/// [`DeliveryService::receive_messages`].
+pub type MessageStream = BoxStream<'static, Result<ReceiveMessagesResponse, Status>>;
+
+/// The requests of the client to the delivery service. The client talks gRPC
+/// to the server by default, see
+/// [`ClientBuilder::with_delivery_service`](crate::client::builder::ClientBuilder::with_delivery_service)
+/// for other transports or an in-process fake.
+///
+/// Errors are reported as [`Status`] whatever the transport, as the client
+/// decides by their code whether to retry a request, see
+/// [`RetryPolicy`](crate::client::transport::RetryPolicy).
+#[tonic::async_trait]
+pub trait DeliveryService: fmt::Debug + Send + Sync {
+    async fn get_versions(
+        &self,
+        request: GetVersionsRequest,
+    ) -> Result<GetVersionsResponse, Status>;
+
+    async fn send_message(
+        &self,
+        request: SendMessageRequest,
+    ) -> Result<SendMessageResponse, Status>;
+
+    /// Opens the stream of messages queued for the user, followed by new ones
+    /// as they arrive.
+    async fn receive_messages(
+        &self,
+        request: ReceiveMessagesRequest,
+    ) -> Result<MessageStream, Status>;
+
+    async fn get_queue_status(
+        &self,
+        request: GetQueueStatusRequest,
+    ) -> Result<GetQueueStatusResponse, Status>;
+
+    /// Takes the oldest messages queued for the user, without waiting for
+    /// new ones.
+    async fn fetch_messages(
+        &self,
+        request: FetchMessagesRequest,
+    ) -> Result<FetchMessagesResponse, Status>;
+
+    async fn upload_key_package(
+        &self,
+        request: UploadKeyPackageRequest,
+    ) -> Result<UploadKeyPackageResponse, Status>;
+
+    async fn fetch_key_package(
+        &self,
+        request: FetchKeyPackageRequest,
+    ) -> Result<FetchKeyPackageResponse, Status>;
+
+    async fn publish_group_info(
+        &self,
+        request: PublishGroupInfoRequest,
+    ) -> Result<PublishGroupInfoResponse, Status>;
+
+    async fn fetch_group_info(
+        &self,
+        request: FetchGroupInfoRequest,
+    ) -> Result<FetchGroupInfoResponse, Status>;
+
+    async fn upload_blob(&self, request: UploadBlobRequest) -> Result<UploadBlobResponse, Status>;
+
+    async fn download_blob(
+        &self,
+        request: DownloadBlobRequest,
+    ) -> Result<DownloadBlobResponse, Status>;
+
+    async fn upload_device_certificate(
+        &self,
+        request: UploadDeviceCertificateRequest,
+    ) -> Result<UploadDeviceCertificateResponse, Status>;
+
+    async fn fetch_device_certificates(
+        &self,
+        request: FetchDeviceCertificatesRequest,
+    ) -> Result<FetchDeviceCertificatesResponse, Status>;
+
+    async fn list_devices(
+        &self,
+        request: ListDevicesRequest,
+    ) -> Result<ListDevicesResponse, Status>;
+
+    async fn revoke_device(
+        &self,
+        request: RevokeDeviceRequest,
+    ) -> Result<RevokeDeviceResponse, Status>;
+
+    async fn rotate_device_key(
+        &self,
+        request: RotateDeviceKeyRequest,
+    ) -> Result<RotateDeviceKeyResponse, Status>;
+
+    async fn get_key_log_root(
+        &self,
+        request: GetKeyLogRootRequest,
+    ) -> Result<GetKeyLogRootResponse, Status>;
+
+    async fn get_inclusion_proof(
+        &self,
+        request: GetInclusionProofRequest,
+    ) -> Result<GetInclusionProofResponse, Status>;
+
+    async fn get_consistency_proof(
+        &self,
+        request: GetConsistencyProofRequest,
+    ) -> Result<GetConsistencyProofResponse, Status>;
+}
+
+/// The delivery service of a server reached with gRPC, the default.
+#[derive(Debug, Clone)]
+pub(crate) struct GrpcDeliveryService {
+    pub(crate) client: ServiceClient,
+    pub(crate) versions: VersionClient,
+}
+
+#[tonic::async_trait]
+impl DeliveryService for GrpcDeliveryService {
+    async fn get_versions(
+        &self,
+        request: GetVersionsRequest,
+    ) -> Result<GetVersionsResponse, Status> {
+        Ok(self
+            .versions
+            .clone()
+            .get_versions(request)
+            .await?
+            .into_inner())
+    }
+
+    async fn send_message(
+        &self,
+        request: SendMessageRequest,
+    ) -> Result<SendMessageResponse, Status> {
+        Ok(self
+            .client
+            .clone()
+            .send_message(request)
+            .await?
+            .into_inner())
+    }
+
+    async fn get_queue_status(
+        &self,
+        request: GetQueueStatusRequest,
+    ) -> Result<GetQueueStatusResponse, Status> {
+        Ok(self
+            .client
+            .clone()
+            .get_queue_status(request)
+            .await?
+            .into_inner())
+    }
+
+    async fn fetch_messages(
+        &self,
+        request: FetchMessagesRequest,
+    ) -> Result<FetchMessagesResponse, Status> {
+        Ok(self
+            .client
+            .clone()
+            .fetch_messages(request)
+            .await?
+            .into_inner())
+    }
+
+    async fn upload_key_package(
+        &self,
+        request: UploadKeyPackageRequest,
+    ) -> Result<UploadKeyPackageResponse, Status> {
+        Ok(self
+            .client
+            .clone()
+            .upload_key_package(request)
+            .await?
+            .into_inner())
+    }
+
+    async fn fetch_key_package(
+        &self,
+        request: FetchKeyPackageRequest,
+    ) -> Result<FetchKeyPackageResponse, Status> {
+        Ok(self
+            .client
+            .clone()
+            .fetch_key_package(request)
+            .await?
+            .into_inner())
+    }
+
+    async fn publish_group_info(
+        &self,
+        request: PublishGroupInfoRequest,
+    ) -> Result<PublishGroupInfoResponse, Status> {
+        Ok(self
+            .client
+            .clone()
+            .publish_group_info(request)
+            .await?
+            .into_inner())
+    }
+
+    async fn fetch_group_info(
+        &self,
+        request: FetchGroupInfoRequest,
+    ) -> Result<FetchGroupInfoResponse, Status> {
+        Ok(self
+            .client
+            .clone()
+            .fetch_group_info(request)
+            .await?
+            .into_inner())
+    }
+
+    async fn upload_blob(&self, request: UploadBlobRequest) -> Result<UploadBlobResponse, Status> {
+        Ok(self.client.clone().upload_blob(request).await?.into_inner())
+    }
+
+    async fn download_blob(
+        &self,
+        request: DownloadBlobRequest,
+    ) -> Result<DownloadBlobResponse, Status> {
+        Ok(self
+            .client
+            .clone()
+            .download_blob(request)
+            .await?
+            .into_inner())
+    }
+
+    async fn upload_device_certificate(
+        &self,
+        request: UploadDeviceCertificateRequest,
+    ) -> Result<UploadDeviceCertificateResponse, Status> {
+        Ok(self
+            .client
+            .clone()
+            .upload_device_certificate(request)
+            .await?
+            .into_inner())
+    }
+
+    async fn fetch_device_certificates(
+        &self,
+        request: FetchDeviceCertificatesRequest,
+    ) -> Result<FetchDeviceCertificatesResponse, Status> {
+        Ok(self
+            .client
+            .clone()
+            .fetch_device_certificates(request)
+            .await?
+            .into_inner())
+    }
+
+    async fn list_devices(
+        &self,
+        request: ListDevicesRequest,
+    ) -> Result<ListDevicesResponse, Status> {
+        Ok(self
+            .client
+            .clone()
+            .list_devices(request)
+            .await?
+            .into_inner())
+    }
+
+    async fn revoke_device(
+        &self,
+        request: RevokeDeviceRequest,
+    ) -> Result<RevokeDeviceResponse, Status> {
+        Ok(self
+            .client
+            .clone()
+            .revoke_device(request)
+            .await?
+            .into_inner())
+    }
+
+    async fn rotate_device_key(
+        &self,
+        request: RotateDeviceKeyRequest,
+    ) -> Result<RotateDeviceKeyResponse, Status> {
+        Ok(self
+            .client
+            .clone()
+            .rotate_device_key(request)
+            .await?
+            .into_inner())
+    }
+
+    async fn get_key_log_root(
+        &self,
+        request: GetKeyLogRootRequest,
+    ) -> Result<GetKeyLogRootResponse, Status> {
+        Ok(self
+            .client
+            .clone()
+            .get_key_log_root(request)
+            .await?
+            .into_inner())
+    }
+
+    async fn get_inclusion_proof(
+        &self,
+        request: GetInclusionProofRequest,
+    ) -> Result<GetInclusionProofResponse, Status> {
+        Ok(self
+            .client
+            .clone()
+            .get_inclusion_proof(request)
+            .await?
+            .into_inner())
+    }
+
+    async fn get_consistency_proof(
+        &self,
+        request: GetConsistencyProofRequest,
+    ) -> Result<GetConsistencyProofResponse, Status> {
+        Ok(self
+            .client
+            .clone()
+            .get_consistency_proof(request)
+            .await?
+            .into_inner())
+    }
+
+    async fn receive_messages(
+        &self,
+        request: ReceiveMessagesRequest,
+    ) -> Result<MessageStream, Status> {
+        let stream = self.client.clone().receive_messages(request).await?;
+        Ok(stream.into_inner().boxed())
+    }
+}
@@ -0,0 +1,214 @@
+use anyhow::anyhow;
+use openmls::{
+    group::{GroupId, MlsGroup},
+    prelude::{BasicCredential, Credential, CredentialWithKey},
+};
+use openmls_sqlx_storage::Codec;
+use openmls_traits::{OpenMlsProvider, signatures::Signer};
+use sqlx::{query, query_scalar};
+use tracing::info;
+use uuid::Uuid;
+
+use crate::{
+    client::{
+        Client, Result,
+        error::{bail, ensure, not_found},
+        message::member_identities,
+        rebase::rebasing,
+    },
+    device,
+    grpc::{
+        DeviceCertificate, FetchDeviceCertificatesRequest, ListDevicesRequest, RevokeDeviceRequest,
+        UploadDeviceCertificateRequest,
+    },
+    provider::JsonCodec,
+};
+
+impl Client {
+    /// Links this client as an additional device of `username`.
+    ///
+    /// The first call generates the device's signature key and returns a link
+    /// code, which must be approved on an existing device with
+    /// [`Client::approve_device`]. Once approved, calling this again uploads the
+    /// key package of the new device and returns `None`.
+    pub async fn link_device(&mut self, username: String) -> Result<Option<String>> {
+        let device_id = query_scalar!(
+            "SELECT device_id FROM client_user WHERE username = ?",
+            username
+        )
+        .fetch_optional(&mut self.connection)
+        .await?;
+
+        match device_id {
+            None => {
+                let credential: Credential =
+                    BasicCredential::new(username.as_bytes().to_vec()).into();
+                let (signature_private_key, signature_key) = self.new_identity_key(&username);
+                let device_id = Uuid::new_v4().to_string();
+
+                let code = device::encode_link_code(&device_id, signature_key.as_slice());
+                let (private_key_column, keychain_account) = self
+                    .store_identity_key(&signature_private_key, &signature_key)
+                    .await?;
+
+                let credential_with_key = CredentialWithKey {
+                    credential,
+                    signature_key,
+                };
+                let credential_with_key_blob = JsonCodec::to_vec(&credential_with_key)?;
+                query!(
+                    "INSERT INTO client_user (
+                        username,
+                        signature_private_key,
+                        credential_with_key,
+                        device_id,
+                        keychain_account
+                    ) VALUES (?, ?, ?, ?, ?)",
+                    username,
+                    private_key_column,
+                    credential_with_key_blob,
+                    device_id,
+                    keychain_account,
+                )
+                .execute(&mut self.connection)
+                .await?;
+
+                Ok(Some(code))
+            }
+            Some(device_id) if device_id.is_empty() => {
+                bail!("User {username} is registered as the primary device")
+            }
+            Some(device_id) => {
+                let (signature_private_key, credential_with_key) =
+                    self.credential(&username).await?;
+                self.upload_key_package(
+                    username,
+                    device_id.clone(),
+                    &signature_private_key,
+                    credential_with_key,
+                )
+                .await?;
+                info!(device_id, "Device linked");
+                Ok(None)
+            }
+        }
+    }
+
+    /// Signs a device certificate for the device that produced the link `code`.
+    pub async fn approve_device(&mut self, username: String, code: &str) -> Result<()> {
+        let (device_id, signature_key) = device::decode_link_code(code)?;
+        let (signature_private_key, credential_with_key) = self.credential(&username).await?;
+
+        let mut certificate = DeviceCertificate {
+            client_id: username,
+            device_id,
+            signature_key,
+            issuer_signature_key: credential_with_key.signature_key.as_slice().to_vec(),
+            signature: Vec::new(),
+        };
+        certificate.signature = signature_private_key
+            .sign(&device::certificate_payload(&certificate))
+            .map_err(|error| anyhow!("Failed to sign device certificate: {error:?}"))?;
+
+        self.client
+            .upload_device_certificate(UploadDeviceCertificateRequest {
+                certificate: Some(certificate),
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn list_devices(&mut self, username: String) -> Result<Vec<String>> {
+        let device_ids = self
+            .client
+            .list_devices(ListDevicesRequest {
+                client_id: username,
+            })
+            .await?
+            .device_ids;
+        Ok(device_ids)
+    }
+
+    /// Revokes a linked device on the server and removes its leaves from every
+    /// local group.
+    pub async fn revoke_device(&mut self, username: String, device_id: String) -> Result<()> {
+        let own_device_id = self.device_id(&username).await?;
+        ensure!(
+            own_device_id != device_id,
+            "Cannot revoke the current device"
+        );
+
+        let (signature_private_key, credential_with_key) = self.credential(&username).await?;
+
+        let certificate = self
+            .client
+            .fetch_device_certificates(FetchDeviceCertificatesRequest {
+                client_id: username.clone(),
+            })
+            .await?
+            .certificates
+            .into_iter()
+            .find(|certificate| certificate.device_id == device_id)
+            .ok_or_else(|| not_found("Device"))?;
+
+        let mut request = RevokeDeviceRequest {
+            client_id: username.clone(),
+            device_id: device_id.clone(),
+            issuer_signature_key: credential_with_key.signature_key.as_slice().to_vec(),
+            signature: Vec::new(),
+        };
+        request.signature = signature_private_key
+            .sign(&device::revocation_payload(&request))
+            .map_err(|error| anyhow!("Failed to sign device revocation: {error:?}"))?;
+        self.client.revoke_device(request).await?;
+
+        for group_uuid in self.group_ids(&username).await? {
+            let removed = rebasing!(self, &username, |client| client.remove_device_leaves_once(
+                &username,
+                group_uuid,
+                &certificate.signature_key,
+            ))?;
+            if removed {
+                self.publish_group_info(&username, group_uuid).await?;
+                info!(%group_uuid, device_id, "Removed revoked device from group");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Removes the leaves with `signature_key` from the group. Returns whether
+    /// there were any.
+    async fn remove_device_leaves_once(
+        &mut self,
+        username: &str,
+        group_uuid: Uuid,
+        signature_key: &[u8],
+    ) -> anyhow::Result<bool> {
+        let (signature_private_key, _credential_with_key) = self.credential(username).await?;
+
+        let provider = self.provider();
+        let group_id = GroupId::from_slice(group_uuid.as_bytes());
+        let Some(mut group) = MlsGroup::load(provider.storage(), &group_id)? else {
+            return Ok(false);
+        };
+
+        let leaf_indices: Vec<_> = group
+            .members()
+            .filter(|member| member.signature_key == signature_key)
+            .map(|member| member.index)
+            .collect();
+        if leaf_indices.is_empty() {
+            return Ok(false);
+        }
+
+        let recipients = member_identities(&group);
+        let (commit, _welcome, _group_info) =
+            group.remove_members(&provider, &signature_private_key, &leaf_indices)?;
+        self.send_commit(username, group_uuid, recipients, &commit)
+            .await?;
+
+        Ok(true)
+    }
+}
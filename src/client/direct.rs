@@ -0,0 +1,114 @@
+use openmls::group::{GroupId, MlsGroup};
+use openmls_traits::OpenMlsProvider;
+use sqlx::{query, query_scalar};
+use tracing::info;
+use uuid::Uuid;
+
+use crate::{
+    client::{
+        Client, Result, error::ensure, group::group_metadata, message::member_identities,
+        policy::RequiredCapabilities,
+    },
+    grpc::GroupMetadata,
+};
+
+impl Client {
+    /// Sends `message` to the one-on-one conversation with `peer`, which is
+    /// created on first use. Returns the id of its group.
+    pub async fn dm(&mut self, user: String, peer: String, message: String) -> Result<Uuid> {
+        ensure!(peer != user, "Can't message yourself");
+        let group_uuid = match self.direct_group(&user, &peer).await? {
+            Some(group_uuid) => group_uuid,
+            None => {
+                let metadata = GroupMetadata {
+                    name: format!("{user} and {peer}"),
+                    direct: true,
+                    ..Default::default()
+                };
+                let group_uuid = self
+                    .create_group(
+                        user.clone(),
+                        Some(metadata),
+                        None,
+                        RequiredCapabilities::default(),
+                    )
+                    .await?;
+                self.add_members(user.clone(), group_uuid, vec![peer.clone()])
+                    .await?;
+                self.set_direct_group(&user, &peer, group_uuid).await?;
+                info!(peer, %group_uuid, "Created direct conversation");
+                group_uuid
+            }
+        };
+        self.send(user, group_uuid, message).await?;
+        Ok(group_uuid)
+    }
+
+    /// Group of the conversation with `peer`, if both are still members.
+    async fn direct_group(&mut self, user: &str, peer: &str) -> anyhow::Result<Option<Uuid>> {
+        let group_uuid = query_scalar!(
+            "SELECT d.group_id AS \"group_id: Uuid\"
+            FROM client_direct d
+            JOIN client_group g ON g.group_id = d.group_id AND g.username = d.username
+            WHERE d.username = ? AND d.peer = ? AND g.left_at IS NULL",
+            user,
+            peer,
+        )
+        .fetch_optional(&mut self.connection)
+        .await?;
+        let Some(group_uuid) = group_uuid else {
+            return Ok(None);
+        };
+
+        let group_id = GroupId::from_slice(group_uuid.as_bytes());
+        let group = MlsGroup::load(self.provider().storage(), &group_id)?;
+        let active = group.is_some_and(|group| {
+            group.is_active()
+                && member_identities(&group)
+                    .iter()
+                    .any(|member| member == peer)
+        });
+        Ok(active.then_some(group_uuid))
+    }
+
+    /// Remembers a joined group as the conversation with the inviter, if it
+    /// is one.
+    pub(crate) async fn record_direct_group(
+        &mut self,
+        user: &str,
+        group: &MlsGroup,
+    ) -> anyhow::Result<()> {
+        if !group_metadata(group.extensions()).is_some_and(|metadata| metadata.direct) {
+            return Ok(());
+        }
+        let members = member_identities(group);
+        let [first, second] = members.as_slice() else {
+            return Ok(());
+        };
+        let peer = match (first == user, second == user) {
+            (true, false) => second,
+            (false, true) => first,
+            _ => return Ok(()),
+        };
+        let group_uuid = Uuid::from_slice(group.group_id().as_slice())?;
+        self.set_direct_group(user, peer, group_uuid).await
+    }
+
+    async fn set_direct_group(
+        &mut self,
+        user: &str,
+        peer: &str,
+        group_uuid: Uuid,
+    ) -> anyhow::Result<()> {
+        query!(
+            "INSERT INTO client_direct (username, peer, group_id) VALUES (?, ?, ?)
+            ON CONFLICT (username, peer) DO UPDATE SET group_id = excluded.group_id",
+            user,
+            peer,
+            group_uuid,
+        )
+        .execute(&mut self.connection)
+        .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,271 @@
+use anyhow::Context;
+use openmls::group::MlsGroup;
+use prost::Message;
+use sqlx::{
+    query, query_as, query_scalar,
+    types::chrono::{DateTime, Utc},
+};
+use tracing::debug;
+use uuid::Uuid;
+
+use crate::{
+    client::{
+        Client, Result,
+        error::{ensure, not_found},
+        events::ChatEvent,
+    },
+    grpc::{ContentType, Control, Edit, control::Action},
+};
+
+/// An earlier version of an edited message.
+#[derive(Debug)]
+pub struct MessageEdit {
+    pub body: String,
+    /// When this version was replaced.
+    pub edited_at: DateTime<Utc>,
+}
+
+impl Client {
+    /// Replaces the text of an own message, identified by its id in the
+    /// history, for all members.
+    pub async fn edit_message(
+        &mut self,
+        user: String,
+        group_uuid: Uuid,
+        message_id: i64,
+        text: String,
+    ) -> Result<()> {
+        let (message_uuid, mimi_id) = self.own_message(&user, group_uuid, message_id).await?;
+        if let Some(mimi_id) = mimi_id {
+            self.send_mimi_replacement(&user, group_uuid, mimi_id, Some(&text))
+                .await?;
+            return Ok(self.apply_edit(message_id, &text).await?);
+        }
+        let edit = Edit {
+            message_id: message_uuid.as_bytes().to_vec(),
+            text: text.clone(),
+        };
+        self.send_content(
+            &user,
+            group_uuid,
+            ContentType::Edit,
+            Uuid::new_v4(),
+            edit.encode_to_vec(),
+        )
+        .await?;
+        Ok(self.apply_edit(message_id, &text).await?)
+    }
+
+    /// Deletes an own message, identified by its id in the history, for all
+    /// members. Only a tombstone remains in the history.
+    pub async fn delete_message(
+        &mut self,
+        user: String,
+        group_uuid: Uuid,
+        message_id: i64,
+    ) -> Result<()> {
+        let (message_uuid, mimi_id) = self.own_message(&user, group_uuid, message_id).await?;
+        if let Some(mimi_id) = mimi_id {
+            self.send_mimi_replacement(&user, group_uuid, mimi_id, None)
+                .await?;
+            return Ok(self.tombstone(message_id).await?);
+        }
+        let control = Control {
+            action: Some(Action::DeleteMessage(message_uuid.as_bytes().to_vec())),
+        };
+        self.send_content(
+            &user,
+            group_uuid,
+            ContentType::Control,
+            Uuid::new_v4(),
+            control.encode_to_vec(),
+        )
+        .await?;
+        Ok(self.tombstone(message_id).await?)
+    }
+
+    /// Earlier versions of an edited message, oldest first.
+    pub async fn message_edits(
+        &mut self,
+        user: String,
+        message_id: i64,
+    ) -> Result<Vec<MessageEdit>> {
+        let edits = query_as!(
+            MessageEdit,
+            "SELECT e.body, e.edited_at AS \"edited_at: DateTime<Utc>\"
+            FROM client_message_edit e
+            JOIN client_message m ON m.message_id = e.message_id
+            WHERE e.message_id = ? AND m.username = ?
+            ORDER BY e.edit_id",
+            message_id,
+            user,
+        )
+        .fetch_all(&mut self.connection)
+        .await?;
+        Ok(edits)
+    }
+
+    /// Applies a control message of `sender`. Only the original sender may
+    /// delete a message, and only admins may pin one.
+    pub(crate) async fn handle_control(
+        &mut self,
+        user: &str,
+        group: &MlsGroup,
+        sender: &str,
+        body: &[u8],
+    ) -> anyhow::Result<()> {
+        let group_uuid = Uuid::from_slice(group.group_id().as_slice())?;
+        let control = Control::decode(body)?;
+        match control.action {
+            Some(Action::DeleteMessage(message_uuid)) => {
+                let message_uuid = Uuid::from_slice(&message_uuid)?;
+                let Some(message_id) = self
+                    .message_of(user, group_uuid, sender, message_uuid)
+                    .await?
+                else {
+                    debug!(%group_uuid, sender, %message_uuid, "Ignoring deletion of unknown message");
+                    return Ok(());
+                };
+                self.tombstone(message_id).await?;
+                self.emit(ChatEvent::MessageDeleted {
+                    group_id: group_uuid,
+                    message_id,
+                    sender: sender.to_string(),
+                });
+            }
+            Some(Action::PinMessage(message_uuid)) => {
+                self.handle_pin(user, group, sender, &message_uuid, true)
+                    .await?;
+            }
+            Some(Action::UnpinMessage(message_uuid)) => {
+                self.handle_pin(user, group, sender, &message_uuid, false)
+                    .await?;
+            }
+            None => debug!(%group_uuid, sender, "Ignoring unknown control message"),
+        }
+        Ok(())
+    }
+
+    /// Applies an edit of `sender`. Only the original sender may edit a
+    /// message; other edits and edits of unknown messages are ignored.
+    pub(crate) async fn handle_edit(
+        &mut self,
+        user: &str,
+        group_uuid: Uuid,
+        sender: &str,
+        body: &[u8],
+    ) -> anyhow::Result<()> {
+        let edit = Edit::decode(body)?;
+        let message_uuid = Uuid::from_slice(&edit.message_id)?;
+        let message_id = self
+            .message_of(user, group_uuid, sender, message_uuid)
+            .await?;
+        let Some(message_id) = message_id else {
+            debug!(%group_uuid, sender, %message_uuid, "Ignoring edit of unknown message");
+            return Ok(());
+        };
+
+        self.apply_edit(message_id, &edit.text).await?;
+        self.emit(ChatEvent::MessageEdited {
+            group_id: group_uuid,
+            message_id,
+            sender: sender.to_string(),
+            text: edit.text,
+        });
+        Ok(())
+    }
+
+    /// Envelope id and MIMI id, if in that format, of an own message that
+    /// wasn't deleted yet.
+    async fn own_message(
+        &mut self,
+        user: &str,
+        group_uuid: Uuid,
+        message_id: i64,
+    ) -> anyhow::Result<(Uuid, Option<Vec<u8>>)> {
+        let message = query!(
+            "SELECT sender, message_uuid AS \"message_uuid: Uuid\", mimi_id, deleted_at
+            FROM client_message
+            WHERE message_id = ? AND group_id = ? AND username = ?",
+            message_id,
+            group_uuid,
+            user,
+        )
+        .fetch_optional(&mut self.connection)
+        .await?
+        .ok_or_else(|| not_found("Message"))?;
+        ensure!(
+            message.sender == user,
+            "Only the sender can change a message"
+        );
+        ensure!(message.deleted_at.is_none(), "Message was deleted");
+        let message_uuid = message
+            .message_uuid
+            .context("Message predates message ids and can't be changed")?;
+        Ok((message_uuid, message.mimi_id))
+    }
+
+    /// History id of the message `message_uuid` of `sender`, unless deleted.
+    async fn message_of(
+        &mut self,
+        user: &str,
+        group_uuid: Uuid,
+        sender: &str,
+        message_uuid: Uuid,
+    ) -> anyhow::Result<Option<i64>> {
+        let message_id = query_scalar!(
+            "SELECT message_id FROM client_message
+            WHERE username = ? AND group_id = ? AND message_uuid = ? AND sender = ?
+                AND deleted_at IS NULL",
+            user,
+            group_uuid,
+            message_uuid,
+            sender,
+        )
+        .fetch_optional(&mut self.connection)
+        .await?;
+        Ok(message_id)
+    }
+
+    /// Replaces the message with a tombstone and forgets its earlier versions.
+    pub(crate) async fn tombstone(&mut self, message_id: i64) -> anyhow::Result<()> {
+        let deleted_at = Utc::now();
+        query!(
+            "DELETE FROM client_message_edit WHERE message_id = ?",
+            message_id
+        )
+        .execute(&mut self.connection)
+        .await?;
+        query!(
+            "UPDATE client_message SET body = '', deleted_at = ? WHERE message_id = ?",
+            deleted_at,
+            message_id,
+        )
+        .execute(&mut self.connection)
+        .await?;
+        Ok(())
+    }
+
+    /// Keeps the current body of the message as earlier version and replaces
+    /// it with `text`.
+    pub(crate) async fn apply_edit(&mut self, message_id: i64, text: &str) -> anyhow::Result<()> {
+        let edited_at = Utc::now();
+        query!(
+            "INSERT INTO client_message_edit (message_id, body, edited_at)
+            SELECT message_id, body, ? FROM client_message WHERE message_id = ?",
+            edited_at,
+            message_id,
+        )
+        .execute(&mut self.connection)
+        .await?;
+        query!(
+            "UPDATE client_message SET body = ?, edited_at = ? WHERE message_id = ?",
+            text,
+            edited_at,
+            message_id,
+        )
+        .execute(&mut self.connection)
+        .await?;
+        Ok(())
+    }
+}
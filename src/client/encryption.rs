@@ -0,0 +1,165 @@
+use std::{
+    fmt, io,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use sqlx::{ConnectOptions, Connection, SqliteConnection, query, sqlite::SqliteConnectOptions};
+use tracing::info;
+
+use crate::client::error::bail;
+
+/// Code of SQLite's error for files that aren't databases, which SQLCipher
+/// also returns for a wrong key.
+const NOT_A_DATABASE: &str = "26";
+
+/// Secret that the database is encrypted with, see
+/// [`ClientBuilder::with_passphrase`](crate::client::builder::ClientBuilder::with_passphrase).
+#[derive(Clone)]
+pub(crate) enum DatabaseSecret {
+    Passphrase(String),
+    KeyFile(PathBuf),
+}
+
+impl fmt::Debug for DatabaseSecret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Passphrase(_) => f.write_str("Passphrase(..)"),
+            Self::KeyFile(path) => f.debug_tuple("KeyFile").field(path).finish(),
+        }
+    }
+}
+
+impl DatabaseSecret {
+    /// The passphrase, or the content of the key file in hex, which SQLCipher
+    /// derives the key of the database from.
+    pub(crate) async fn key(&self) -> anyhow::Result<DatabaseKey> {
+        match self {
+            Self::Passphrase(passphrase) => Ok(DatabaseKey(passphrase.clone())),
+            Self::KeyFile(path) => {
+                let content = tokio::fs::read(path)
+                    .await
+                    .with_context(|| format!("Failed to read key file {}", path.display()))?;
+                Ok(DatabaseKey(
+                    content.iter().map(|byte| format!("{byte:02x}")).collect(),
+                ))
+            }
+        }
+    }
+}
+
+/// Passphrase of the database for SQLCipher, see [`DatabaseSecret::key`].
+#[derive(Clone)]
+pub(crate) struct DatabaseKey(String);
+
+impl fmt::Debug for DatabaseKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("DatabaseKey(..)")
+    }
+}
+
+impl DatabaseKey {
+    /// Value of the `key` pragma, which isn't bound but pasted into the
+    /// statement.
+    pub(crate) fn pragma(&self) -> String {
+        format!("'{}'", self.0.replace('\'', "''"))
+    }
+}
+
+/// Connects to the database at `path` with `key`. The first time a key is
+/// given, an unencrypted database is encrypted with it.
+pub(crate) async fn connect(
+    options: SqliteConnectOptions,
+    path: &Path,
+    key: Option<&DatabaseKey>,
+) -> anyhow::Result<SqliteConnection> {
+    let Some(key) = key else {
+        return match readable(options.connect().await).await? {
+            Some(connection) => Ok(connection),
+            None => bail!("The database is encrypted, a passphrase or key file is needed"),
+        };
+    };
+    let encrypted = options.clone().pragma("key", key.pragma());
+    if let Some(connection) = readable(encrypted.connect().await).await? {
+        return Ok(connection);
+    }
+    let Some(connection) = readable(options.connect().await).await? else {
+        bail!("Wrong passphrase or key file of the database");
+    };
+    encrypt(connection, path, key).await?;
+    Ok(encrypted.connect().await?)
+}
+
+/// The connection if the database can be read with its key, `None` if it is
+/// encrypted with another one.
+async fn readable(
+    connection: sqlx::Result<SqliteConnection>,
+) -> anyhow::Result<Option<SqliteConnection>> {
+    let mut connection = match connection {
+        Ok(connection) => connection,
+        Err(error) if is_not_a_database(&error) => return Ok(None),
+        Err(error) => return Err(error.into()),
+    };
+    match query!("SELECT COUNT(*) AS count FROM sqlite_master")
+        .fetch_one(&mut connection)
+        .await
+    {
+        Ok(_) => Ok(Some(connection)),
+        Err(error) if is_not_a_database(&error) => Ok(None),
+        Err(error) => Err(error.into()),
+    }
+}
+
+fn is_not_a_database(error: &sqlx::Error) -> bool {
+    error
+        .as_database_error()
+        .and_then(|error| error.code())
+        .is_some_and(|code| code == NOT_A_DATABASE)
+}
+
+/// Replaces the unencrypted database at `path`, which `connection` is
+/// connected to, with a copy encrypted with `key`.
+async fn encrypt(
+    mut connection: SqliteConnection,
+    path: &Path,
+    key: &DatabaseKey,
+) -> anyhow::Result<()> {
+    info!("Encrypting the database");
+    let encrypted = path.with_extension("encrypting");
+    remove_if_exists(&encrypted).await?;
+    let result = async {
+        sqlx::query("ATTACH DATABASE ? AS encrypted KEY ?")
+            .bind(encrypted.to_string_lossy())
+            .bind(&key.0)
+            .execute(&mut connection)
+            .await?;
+        sqlx::query("SELECT sqlcipher_export('encrypted')")
+            .execute(&mut connection)
+            .await?;
+        sqlx::query("DETACH DATABASE encrypted")
+            .execute(&mut connection)
+            .await?;
+        connection.close().await?;
+        anyhow::Ok(())
+    }
+    .await;
+    if let Err(error) = result {
+        remove_if_exists(&encrypted).await?;
+        return Err(error.context("Failed to encrypt the database"));
+    }
+    // The log of the unencrypted database, checkpointed when it was closed.
+    for suffix in ["-wal", "-shm"] {
+        let mut log = path.as_os_str().to_owned();
+        log.push(suffix);
+        remove_if_exists(Path::new(&log)).await?;
+    }
+    tokio::fs::rename(&encrypted, path).await?;
+    Ok(())
+}
+
+async fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match tokio::fs::remove_file(path).await {
+        Err(error) if error.kind() != io::ErrorKind::NotFound => Err(error),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,59 @@
+use prost::Message;
+use uuid::Uuid;
+
+use crate::grpc::{ContentType, Envelope};
+
+/// Envelope of an application message of `content_type`.
+pub(crate) fn new(content_type: ContentType, message_id: Uuid, body: Vec<u8>) -> Envelope {
+    Envelope {
+        content_type: content_type.into(),
+        client_version: env!("CARGO_PKG_VERSION").to_string(),
+        body,
+        message_id: message_id.as_bytes().to_vec(),
+        mentions: Vec::new(),
+        reply_to: Vec::new(),
+    }
+}
+
+/// Unwraps an application message. Payloads that are no envelope are plain
+/// text of older clients.
+pub(crate) fn open(payload: Vec<u8>) -> Envelope {
+    match Envelope::decode(payload.as_slice()) {
+        Ok(envelope) if envelope.content_type != i32::from(ContentType::Unspecified) => envelope,
+        _ => Envelope {
+            content_type: ContentType::Text.into(),
+            client_version: String::new(),
+            body: payload,
+            message_id: Vec::new(),
+            mentions: Vec::new(),
+            reply_to: Vec::new(),
+        },
+    }
+}
+
+/// Id of the enveloped message, if the sender assigned one.
+pub(crate) fn message_id(envelope: &Envelope) -> Option<Uuid> {
+    Uuid::from_slice(&envelope.message_id).ok()
+}
+
+/// Id of the message that the enveloped message replies to.
+pub(crate) fn reply_to(envelope: &Envelope) -> Option<Uuid> {
+    Uuid::from_slice(&envelope.reply_to).ok()
+}
+
+/// Members mentioned as `@identity` in `text`, in order of appearance.
+pub(crate) fn parse_mentions(text: &str, members: &[String]) -> Vec<String> {
+    let mut mentions: Vec<String> = Vec::new();
+    for word in text.split_whitespace() {
+        let Some(identity) = word.strip_prefix('@') else {
+            continue;
+        };
+        let identity = identity.trim_end_matches(|c: char| c.is_ascii_punctuation());
+        if members.iter().any(|member| member == identity)
+            && !mentions.iter().any(|mention| mention == identity)
+        {
+            mentions.push(identity.to_string());
+        }
+    }
+    mentions
+}
@@ -0,0 +1,168 @@
+use openmls::{
+    credentials::errors::BasicCredentialError,
+    error::LibraryError,
+    group::{
+        AddMembersError, CommitToPendingProposalsError, CreateMessageError, ExportGroupInfoError,
+        ExportSecretError, LeaveGroupError, MergeCommitError, MergePendingCommitError,
+        NewGroupError, ProcessMessageError, ProposeAddMemberError, ProposeRemoveMemberError,
+        RemoveMembersError, SelfUpdateError, WelcomeError,
+    },
+    prelude::{CryptoError, KeyPackageNewError, KeyPackageVerifyError, tls_codec},
+};
+use openmls_rust_crypto::RandError;
+use sqlx::migrate::MigrateError;
+use tonic::Status;
+
+/// Errors of the client API.
+#[derive(Debug, thiserror::Error)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Error), uniffi(flat_error))]
+pub enum ClientError {
+    /// The server couldn't be reached or rejected the request. Requests
+    /// failing with `Unavailable` may succeed when retried.
+    #[error(transparent)]
+    Transport(#[from] Status),
+    /// Creating or processing MLS messages or groups failed.
+    #[error(transparent)]
+    Mls(Box<dyn std::error::Error + Send + Sync>),
+    /// The local database failed.
+    #[error(transparent)]
+    Storage(#[from] sqlx::Error),
+    /// A user, group, message or invite doesn't exist locally.
+    #[error("{0} not found")]
+    NotFound(String),
+    #[error(transparent)]
+    Other(anyhow::Error),
+}
+
+pub type Result<T, E = ClientError> = std::result::Result<T, E>;
+
+impl ClientError {
+    /// Whether a later attempt may succeed, as the server was unreachable.
+    pub fn is_transient(&self) -> bool {
+        matches!(self, Self::Transport(status) if matches!(
+            status.code(),
+            tonic::Code::Unavailable | tonic::Code::DeadlineExceeded
+        ))
+    }
+}
+
+pub(crate) fn not_found(what: impl Into<String>) -> ClientError {
+    ClientError::NotFound(what.into())
+}
+
+/// Implements the conversion of MLS errors into [`ClientError::Mls`], also
+/// when they were wrapped in an [`anyhow::Error`] on the way.
+macro_rules! mls_errors {
+    ($($error:ty),* $(,)?) => {
+        $(
+            impl From<$error> for ClientError {
+                fn from(error: $error) -> Self {
+                    Self::Mls(Box::new(error))
+                }
+            }
+        )*
+
+        fn downcast_mls(error: anyhow::Error) -> Result<ClientError, anyhow::Error> {
+            $(
+                let error = match error.downcast::<$error>() {
+                    Ok(error) => return Ok(error.into()),
+                    Err(error) => error,
+                };
+            )*
+            Err(error)
+        }
+    };
+}
+
+mls_errors!(
+    AddMembersError<sqlx::Error>,
+    BasicCredentialError,
+    CommitToPendingProposalsError<sqlx::Error>,
+    CryptoError,
+    CreateMessageError,
+    ExportGroupInfoError,
+    ExportSecretError,
+    KeyPackageNewError,
+    KeyPackageVerifyError,
+    LeaveGroupError<sqlx::Error>,
+    LibraryError,
+    MergeCommitError<sqlx::Error>,
+    MergePendingCommitError<sqlx::Error>,
+    NewGroupError<sqlx::Error>,
+    ProcessMessageError<sqlx::Error>,
+    ProposeAddMemberError<sqlx::Error>,
+    ProposeRemoveMemberError<sqlx::Error>,
+    RandError,
+    RemoveMembersError<sqlx::Error>,
+    SelfUpdateError<sqlx::Error>,
+    WelcomeError<sqlx::Error>,
+    tls_codec::Error,
+);
+
+/// Implements the conversion of other errors into [`ClientError::Other`].
+macro_rules! other_errors {
+    ($($error:ty),* $(,)?) => {
+        $(
+            impl From<$error> for ClientError {
+                fn from(error: $error) -> Self {
+                    Self::Other(error.into())
+                }
+            }
+        )*
+    };
+}
+
+other_errors!(
+    prost::DecodeError,
+    serde_json::Error,
+    std::io::Error,
+    tonic::transport::Error,
+    uuid::Error,
+);
+
+impl From<MigrateError> for ClientError {
+    fn from(error: MigrateError) -> Self {
+        Self::Storage(sqlx::Error::Migrate(Box::new(error)))
+    }
+}
+
+impl From<anyhow::Error> for ClientError {
+    fn from(error: anyhow::Error) -> Self {
+        let error = match error.downcast::<ClientError>() {
+            Ok(error) => return error,
+            Err(error) => error,
+        };
+        let error = match error.downcast::<Status>() {
+            Ok(status) => return Self::Transport(status),
+            Err(error) => error,
+        };
+        let error = match error.downcast::<sqlx::Error>() {
+            Ok(error) => return Self::Storage(error),
+            Err(error) => error,
+        };
+        match downcast_mls(error) {
+            Ok(error) => error,
+            Err(error) => Self::Other(error),
+        }
+    }
+}
+
+/// Like [`anyhow::bail!`], but also returns from functions with a
+/// [`ClientError`].
+macro_rules! bail {
+    ($($arg:tt)+) => {
+        return Err(anyhow::anyhow!($($arg)+).into())
+    };
+}
+
+/// Like [`anyhow::ensure!`], but also returns from functions with a
+/// [`ClientError`].
+macro_rules! ensure {
+    ($cond:expr, $($arg:tt)+) => {
+        if !$cond {
+            $crate::client::error::bail!($($arg)+);
+        }
+    };
+}
+
+pub(crate) use {bail, ensure};
@@ -0,0 +1,186 @@
+use futures_util::{Stream, TryStreamExt, stream};
+use tokio::time::sleep;
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::{
+    client::{
+        Client, Result, delivery::MessageStream, ledger::message_key, transport::is_retryable,
+    },
+    grpc::ReceiveMessagesResponse,
+};
+
+/// Something that happened in a group, as observed while processing incoming
+/// messages.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Enum))]
+#[cfg_attr(feature = "pyo3", pyo3::pyclass(frozen))]
+pub enum ChatEvent {
+    /// A text message or a downloaded attachment.
+    Message {
+        group_id: Uuid,
+        /// Id of the message in the history.
+        message_id: i64,
+        sender: String,
+        text: String,
+        /// Whether the message mentions the user.
+        mentioned: bool,
+    },
+    /// An own message that the group didn't accept, as only admins may send.
+    Bounced { group_id: Uuid, text: String },
+    MessageEdited {
+        group_id: Uuid,
+        message_id: i64,
+        sender: String,
+        text: String,
+    },
+    MessageDeleted {
+        group_id: Uuid,
+        message_id: i64,
+        sender: String,
+    },
+    /// `sender` reacted to the message, e.g. with an emoji.
+    Reaction {
+        group_id: Uuid,
+        message_id: i64,
+        sender: String,
+        reaction: String,
+    },
+    /// Earlier messages shared by `sender` were added to the history.
+    HistoryShared {
+        group_id: Uuid,
+        sender: String,
+        messages: u64,
+    },
+    /// An invite to a group, see [`Client::accept_invite`].
+    WelcomeReceived {
+        group_id: Uuid,
+        name: String,
+        inviter: String,
+    },
+    /// `member` was added by `added_by`, or joined by itself if both are the
+    /// same.
+    MemberAdded {
+        group_id: Uuid,
+        member: String,
+        added_by: String,
+    },
+    MemberRemoved {
+        group_id: Uuid,
+        member: String,
+        removed_by: String,
+    },
+    /// A commit was merged and the group moved on to `epoch`.
+    EpochChanged { group_id: Uuid, epoch: u64 },
+    /// A message couldn't be decrypted or processed. Messages of past epochs
+    /// are dropped, others are kept in the quarantine under `quarantined`,
+    /// see [`Client::poison_messages`].
+    /// Group and epoch are unknown if the message doesn't parse.
+    DecryptionFailed {
+        group_id: Option<Uuid>,
+        epoch: Option<u64>,
+        error: String,
+        quarantined: Option<i64>,
+    },
+}
+
+impl Client {
+    /// Receives messages and yields the resulting events, until the server
+    /// closes the stream. A broken stream is reopened according to the
+    /// [`RetryPolicy`](crate::client::transport::RetryPolicy), resuming with
+    /// the messages that were not delivered yet.
+    pub fn receive(&mut self, user: String) -> impl Stream<Item = Result<ChatEvent>> + '_ {
+        let state: (&mut Self, String, Option<MessageStream>, u32) = (self, user, None, 0);
+        stream::try_unfold(
+            state,
+            |(client, user, mut messages, mut retries)| async move {
+                loop {
+                    if let Some(event) = client.events.pop_front() {
+                        return Ok(Some((event, (client, user, messages, retries))));
+                    }
+                    let Some(stream) = &mut messages else {
+                        // Opening the stream handles the queued messages,
+                        // whose events come first.
+                        messages = Some(client.open_stream(&user).await?);
+                        continue;
+                    };
+                    let message = match stream.try_next().await {
+                        Ok(Some(message)) => message,
+                        Ok(None) => return Ok(None),
+                        Err(status) if is_retryable(&status) => {
+                            let Some(wait) = client.client.retry_policy.backoff(retries) else {
+                                return Err(status.into());
+                            };
+                            warn!(%status, ?wait, "Receive stream broke, reopening");
+                            messages = None;
+                            retries += 1;
+                            sleep(wait).await;
+                            continue;
+                        }
+                        Err(status) => return Err(status.into()),
+                    };
+                    retries = 0;
+                    client
+                        .receive_message(&user, Some(&message_key(&message)), &message.content)
+                        .await?;
+                    client.accept_verified_invites(&user).await?;
+                    // The stream stays open for new messages, so receipts go
+                    // out as messages arrive.
+                    client.send_receipts(&user).await?;
+                }
+            },
+        )
+    }
+
+    /// Opens the stream of messages of `user`, to be processed with
+    /// [`Client::process`]. Unlike [`Client::receive`], the stream doesn't
+    /// borrow the client, which stays usable while waiting for messages.
+    /// The events of the queued messages, which are handled first, are left
+    /// for [`Client::drain_events`].
+    pub async fn subscribe(&mut self, user: String) -> Result<Subscription> {
+        let messages = self.open_stream(&user).await?;
+        Ok(Subscription { user, messages })
+    }
+
+    /// Processes a message of a [`Subscription`] and returns the resulting
+    /// events.
+    pub async fn process(
+        &mut self,
+        subscription: &Subscription,
+        message: ReceiveMessagesResponse,
+    ) -> Result<Vec<ChatEvent>> {
+        self.receive_message(
+            &subscription.user,
+            Some(&message_key(&message)),
+            &message.content,
+        )
+        .await?;
+        self.accept_verified_invites(&subscription.user).await?;
+        self.send_receipts(&subscription.user).await?;
+        Ok(self.drain_events())
+    }
+
+    /// Takes the events of messages processed outside of [`Client::receive`],
+    /// e.g. while catching up before sending.
+    pub fn drain_events(&mut self) -> Vec<ChatEvent> {
+        self.events.drain(..).collect()
+    }
+
+    pub(crate) fn emit(&mut self, event: ChatEvent) {
+        self.events.push_back(event);
+    }
+}
+
+/// Open stream of incoming messages of a user, see [`Client::subscribe`].
+pub struct Subscription {
+    user: String,
+    messages: MessageStream,
+}
+
+impl Subscription {
+    /// Waits for the next message, or returns `None` when the server closed
+    /// the stream.
+    pub async fn next(&mut self) -> Result<Option<ReceiveMessagesResponse>> {
+        Ok(self.messages.try_next().await?)
+    }
+}
@@ -0,0 +1,46 @@
+use openmls::group::{GroupId, MlsGroup};
+use openmls_traits::OpenMlsProvider;
+use uuid::Uuid;
+
+use crate::client::{Client, Result, error::not_found};
+
+impl Client {
+    /// Epoch authenticator of the current epoch of the group. Members that
+    /// agree on it share the same group state.
+    pub async fn epoch_authenticator(&mut self, user: String, group_uuid: Uuid) -> Result<Vec<u8>> {
+        let group = self.load_member_group(&user, group_uuid).await?;
+        Ok(group.epoch_authenticator().as_slice().to_vec())
+    }
+
+    /// Derives a secret of `length` bytes from the key schedule of the current
+    /// epoch, to bind keys of other protocols to the group. Every member
+    /// derives the same secret for the same `label` and `context`, and a new
+    /// one after each commit.
+    pub async fn export_secret(
+        &mut self,
+        user: String,
+        group_uuid: Uuid,
+        label: &str,
+        context: &[u8],
+        length: usize,
+    ) -> Result<Vec<u8>> {
+        let group = self.load_member_group(&user, group_uuid).await?;
+        let secret = group.export_secret(self.provider().crypto(), label, context, length)?;
+        Ok(secret)
+    }
+
+    /// Loads a group the user is a member of.
+    pub(crate) async fn load_member_group(
+        &mut self,
+        user: &str,
+        group_uuid: Uuid,
+    ) -> anyhow::Result<MlsGroup> {
+        if !self.group_ids(user).await?.contains(&group_uuid) {
+            return Err(not_found("Group").into());
+        }
+        let group_id = GroupId::from_slice(group_uuid.as_bytes());
+        let group = MlsGroup::load(self.provider().storage(), &group_id)?
+            .ok_or_else(|| not_found("Group"))?;
+        Ok(group)
+    }
+}
@@ -0,0 +1,169 @@
+use std::fmt;
+
+use tonic::Status;
+
+use crate::{
+    client::delivery::{DeliveryService, MessageStream},
+    grpc::{web_socket_request::Request, web_socket_response::Response, *},
+};
+
+/// A transport of the frames of `proto/mlschat/v1/websocket.proto`, which
+/// makes a [`DeliveryService`] of it.
+#[tonic::async_trait]
+pub(crate) trait FrameTransport: fmt::Debug + Send + Sync {
+    /// Sends a request other than `receive_messages` and waits for its
+    /// response, failing with the status of an error response.
+    async fn unary(&self, request: Request) -> Result<Response, Status>;
+
+    async fn receive_messages(
+        &self,
+        request: ReceiveMessagesRequest,
+    ) -> Result<MessageStream, Status>;
+}
+
+pub(crate) fn unexpected_response() -> Status {
+    Status::internal("Unexpected response")
+}
+
+/// Unary request `$variant`.
+macro_rules! unary {
+    ($self:ident, $variant:ident($request:expr)) => {
+        match $self.unary(Request::$variant($request)).await? {
+            Response::$variant(response) => Ok(response),
+            _ => Err(unexpected_response()),
+        }
+    };
+}
+
+#[tonic::async_trait]
+impl<T: FrameTransport> DeliveryService for T {
+    async fn get_versions(
+        &self,
+        request: GetVersionsRequest,
+    ) -> Result<GetVersionsResponse, Status> {
+        unary!(self, GetVersions(request))
+    }
+
+    async fn send_message(
+        &self,
+        request: SendMessageRequest,
+    ) -> Result<SendMessageResponse, Status> {
+        unary!(self, SendMessage(request))
+    }
+
+    async fn receive_messages(
+        &self,
+        request: ReceiveMessagesRequest,
+    ) -> Result<MessageStream, Status> {
+        FrameTransport::receive_messages(self, request).await
+    }
+
+    async fn get_queue_status(
+        &self,
+        request: GetQueueStatusRequest,
+    ) -> Result<GetQueueStatusResponse, Status> {
+        unary!(self, GetQueueStatus(request))
+    }
+
+    async fn fetch_messages(
+        &self,
+        request: FetchMessagesRequest,
+    ) -> Result<FetchMessagesResponse, Status> {
+        unary!(self, FetchMessages(request))
+    }
+
+    async fn upload_key_package(
+        &self,
+        request: UploadKeyPackageRequest,
+    ) -> Result<UploadKeyPackageResponse, Status> {
+        unary!(self, UploadKeyPackage(request))
+    }
+
+    async fn fetch_key_package(
+        &self,
+        request: FetchKeyPackageRequest,
+    ) -> Result<FetchKeyPackageResponse, Status> {
+        unary!(self, FetchKeyPackage(request))
+    }
+
+    async fn publish_group_info(
+        &self,
+        request: PublishGroupInfoRequest,
+    ) -> Result<PublishGroupInfoResponse, Status> {
+        unary!(self, PublishGroupInfo(request))
+    }
+
+    async fn fetch_group_info(
+        &self,
+        request: FetchGroupInfoRequest,
+    ) -> Result<FetchGroupInfoResponse, Status> {
+        unary!(self, FetchGroupInfo(request))
+    }
+
+    async fn upload_blob(&self, request: UploadBlobRequest) -> Result<UploadBlobResponse, Status> {
+        unary!(self, UploadBlob(request))
+    }
+
+    async fn download_blob(
+        &self,
+        request: DownloadBlobRequest,
+    ) -> Result<DownloadBlobResponse, Status> {
+        unary!(self, DownloadBlob(request))
+    }
+
+    async fn upload_device_certificate(
+        &self,
+        request: UploadDeviceCertificateRequest,
+    ) -> Result<UploadDeviceCertificateResponse, Status> {
+        unary!(self, UploadDeviceCertificate(request))
+    }
+
+    async fn fetch_device_certificates(
+        &self,
+        request: FetchDeviceCertificatesRequest,
+    ) -> Result<FetchDeviceCertificatesResponse, Status> {
+        unary!(self, FetchDeviceCertificates(request))
+    }
+
+    async fn list_devices(
+        &self,
+        request: ListDevicesRequest,
+    ) -> Result<ListDevicesResponse, Status> {
+        unary!(self, ListDevices(request))
+    }
+
+    async fn revoke_device(
+        &self,
+        request: RevokeDeviceRequest,
+    ) -> Result<RevokeDeviceResponse, Status> {
+        unary!(self, RevokeDevice(request))
+    }
+
+    async fn rotate_device_key(
+        &self,
+        request: RotateDeviceKeyRequest,
+    ) -> Result<RotateDeviceKeyResponse, Status> {
+        unary!(self, RotateDeviceKey(request))
+    }
+
+    async fn get_key_log_root(
+        &self,
+        request: GetKeyLogRootRequest,
+    ) -> Result<GetKeyLogRootResponse, Status> {
+        unary!(self, GetKeyLogRoot(request))
+    }
+
+    async fn get_inclusion_proof(
+        &self,
+        request: GetInclusionProofRequest,
+    ) -> Result<GetInclusionProofResponse, Status> {
+        unary!(self, GetInclusionProof(request))
+    }
+
+    async fn get_consistency_proof(
+        &self,
+        request: GetConsistencyProofRequest,
+    ) -> Result<GetConsistencyProofResponse, Status> {
+        unary!(self, GetConsistencyProof(request))
+    }
+}
@@ -1,62 +1,732 @@
-use anyhow::Context;
 use openmls::{
-    group::{GroupId, MlsGroup},
-    prelude::{LeafNodeParameters, OpenMlsProvider, tls_codec::Serialize},
+    group::{GroupId, MlsGroup, MlsGroupJoinConfig},
+    prelude::{
+        Ciphersuite, Credential, DeserializeBytes, Extension, ExtensionType, Extensions,
+        GroupContext, LeafNodeIndex, LeafNodeParameters, MlsMessageBodyIn, MlsMessageIn,
+        NewSignerBundle, OpenMlsProvider, Proposal, RequiredCapabilitiesExtension,
+        SenderRatchetConfiguration, UnknownExtension, tls_codec::Serialize,
+    },
 };
-use tracing::debug;
+use prost::Message;
+use sqlx::{
+    query, query_scalar,
+    types::chrono::{DateTime, Utc},
+};
+use tracing::{debug, info, warn};
 use uuid::Uuid;
 
-use crate::{client::Client, grpc::SendMessageRequest, provider::CIPHERSUITE};
+use crate::{
+    client::{
+        Client, Result,
+        context::validate_change,
+        error::{bail, not_found},
+        member::pending_additions,
+        message::member_identities,
+        policy::RequiredCapabilities,
+        rebase::{is_epoch_conflict, rebasing},
+        roles::{ensure_admin, set_roles_extension},
+    },
+    grpc::{FetchGroupInfoRequest, GroupMetadata, GroupRoles, PublishGroupInfoRequest},
+    provider::{GROUP_METADATA_EXTENSION, capabilities, check_ciphersuite},
+};
+
+/// A group the user is a member of, as recorded locally.
+#[derive(Debug)]
+pub struct GroupSummary {
+    pub group_id: Uuid,
+    pub name: String,
+    pub topic: String,
+    pub ciphersuite: Option<Ciphersuite>,
+    /// Identity of the member who created the group, if known.
+    pub creator: String,
+    /// Identity of the member who invited the user, if the user was invited.
+    pub inviter: Option<String>,
+    /// When the group was created or joined on this client.
+    pub created_at: DateTime<Utc>,
+    pub last_activity_at: Option<DateTime<Utc>>,
+    /// Messages received from others since the group was last marked as
+    /// read, see [`Client::mark_read`].
+    pub unread: u64,
+    /// Whether notifications of the group are suppressed, see
+    /// [`Client::set_muted`].
+    pub muted: bool,
+    /// Whether the group should be left out of listings unless asked for,
+    /// see [`Client::set_archived`].
+    pub archived: bool,
+}
 
 impl Client {
-    pub async fn create_group(&mut self, user: String) -> anyhow::Result<Uuid> {
-        let (signing_private_key, credential_with_key) = self.credential(&user).await?;
+    /// Creates a group. A name or topic is stored in the group context, so that
+    /// it is shared with all members. Without `ciphersuite`, the one chosen at
+    /// registration is used. Members added later must support the `required`
+    /// capabilities.
+    pub async fn create_group(
+        &mut self,
+        user: String,
+        metadata: Option<GroupMetadata>,
+        ciphersuite: Option<Ciphersuite>,
+        required: RequiredCapabilities,
+    ) -> Result<Uuid> {
+        let ciphersuite = match ciphersuite {
+            Some(ciphersuite) => ciphersuite,
+            None => self.default_ciphersuite(&user).await?,
+        };
+        check_ciphersuite(ciphersuite)?;
+
+        let mut metadata = metadata.unwrap_or_default();
+        metadata.creator = user.clone();
+        let mut extensions = Extensions::empty();
+        if !required.is_empty() {
+            // Before our own extensions, which add to the requirements.
+            required.apply(&mut extensions, &mut metadata)?;
+        }
+        set_metadata_extension(&mut extensions, &metadata)?;
+        set_roles_extension(
+            &mut extensions,
+            &GroupRoles {
+                admins: vec![user.clone()],
+            },
+        )?;
 
         let group_uuid = Uuid::new_v4();
-        let group_id = GroupId::from_slice(group_uuid.as_bytes());
+        let group = self
+            .new_group(&user, group_uuid, ciphersuite, extensions)
+            .await?;
 
+        debug!(?group, "Created group");
+
+        self.insert_group(&user, group_uuid, &user).await?;
+        self.publish_group_info(&user, group_uuid).await?;
+
+        Ok(group_uuid)
+    }
+
+    /// Builds a group with the own leaf as only member.
+    pub(crate) async fn new_group(
+        &mut self,
+        user: &str,
+        group_uuid: Uuid,
+        ciphersuite: Ciphersuite,
+        extensions: Extensions<GroupContext>,
+    ) -> anyhow::Result<MlsGroup> {
+        let (signing_private_key, credential_with_key) = self.credential(user).await?;
+
+        let group_id = GroupId::from_slice(group_uuid.as_bytes());
         let group = MlsGroup::builder()
             .with_group_id(group_id)
-            .ciphersuite(CIPHERSUITE)
+            .ciphersuite(ciphersuite)
+            .with_capabilities(capabilities())
             .use_ratchet_tree_extension(true)
+            .sender_ratchet_configuration(self.sender_ratchet_configuration())
+            .max_past_epochs(self.group_config.max_past_epochs)
+            .with_group_context_extensions(extensions)
             .build(&self.provider(), &signing_private_key, credential_with_key)?;
+        Ok(group)
+    }
 
-        debug!(?group, "Created group");
+    /// Changes the name and topic of the group with a commit. `None` keeps the
+    /// current value.
+    pub async fn set_group_metadata(
+        &mut self,
+        user: String,
+        group_uuid: Uuid,
+        name: Option<String>,
+        topic: Option<String>,
+    ) -> Result<()> {
+        self.commit_group_metadata(&user, group_uuid, |metadata| {
+            if let Some(name) = &name {
+                metadata.name = name.clone();
+            }
+            if let Some(topic) = &topic {
+                metadata.topic = topic.clone();
+            }
+        })
+        .await?;
+        Ok(())
+    }
 
-        Ok(group_uuid)
+    /// Opens the group for anyone knowing its id to join with an external
+    /// commit, or closes it again.
+    pub async fn set_group_open(
+        &mut self,
+        user: String,
+        group_uuid: Uuid,
+        open: bool,
+    ) -> Result<()> {
+        self.commit_group_metadata(&user, group_uuid, |metadata| metadata.open = open)
+            .await?;
+        Ok(())
     }
 
-    pub async fn update_group(&mut self, user: String, group_uuid: Uuid) -> anyhow::Result<()> {
-        let (signing_private_key, _credential_with_key) = self.credential(&user).await?;
+    /// Makes the group an announcement group, in which only admins may send
+    /// messages, or lifts the restriction.
+    pub async fn set_group_read_only(
+        &mut self,
+        user: String,
+        group_uuid: Uuid,
+        read_only: bool,
+    ) -> Result<()> {
+        self.commit_group_metadata(&user, group_uuid, |metadata| metadata.read_only = read_only)
+            .await?;
+        Ok(())
+    }
+
+    pub(crate) async fn commit_group_metadata(
+        &mut self,
+        user: &str,
+        group_uuid: Uuid,
+        update: impl Fn(&mut GroupMetadata),
+    ) -> anyhow::Result<()> {
+        self.commit_group_context_extensions(user, group_uuid, |extensions| {
+            let mut metadata = group_metadata(extensions).unwrap_or_default();
+            update(&mut metadata);
+            set_metadata_extension(extensions, &metadata)
+        })
+        .await
+    }
+
+    /// Changes the group context extensions with a commit. Only admins may do
+    /// so.
+    pub(crate) async fn commit_group_context_extensions(
+        &mut self,
+        user: &str,
+        group_uuid: Uuid,
+        update: impl Fn(&mut Extensions<GroupContext>) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        self.commit_group_changes(user, group_uuid, &[], update)
+            .await
+    }
+
+    /// Removes all leaves of the `removed` members and updates the group
+    /// context extensions with a single commit.
+    pub(crate) async fn commit_group_changes(
+        &mut self,
+        user: &str,
+        group_uuid: Uuid,
+        removed: &[String],
+        update: impl Fn(&mut Extensions<GroupContext>) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        rebasing!(self, user, |client| client
+            .commit_group_changes_once(user, group_uuid, removed, &update))?;
+        self.publish_group_info(user, group_uuid).await
+    }
+
+    async fn commit_group_changes_once(
+        &mut self,
+        user: &str,
+        group_uuid: Uuid,
+        removed: &[String],
+        update: &impl Fn(&mut Extensions<GroupContext>) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        let (signing_private_key, _credential_with_key) = self.credential(user).await?;
 
         let group_id = GroupId::from_slice(group_uuid.as_bytes());
+        let validators = self.change_validators.clone();
         let provider = self.provider();
         let mut group =
-            MlsGroup::load(provider.storage(), &group_id)?.context("Group not found")?;
+            MlsGroup::load(provider.storage(), &group_id)?.ok_or_else(|| not_found("Group"))?;
+        ensure_admin(&group, user)?;
 
-        let bundle = group.self_update(
-            &provider,
-            &signing_private_key,
-            LeafNodeParameters::builder().build(),
-        )?;
-        group.merge_pending_commit(&provider)?;
+        let mut extensions = group.extensions().clone();
+        update(&mut extensions)?;
+        if extensions != *group.extensions() {
+            validate_change(&validators, group_uuid, &group, user, &extensions).await?;
+        }
 
-        let recipients = group
+        let leaf_indices: Vec<LeafNodeIndex> = group
             .members()
-            .filter_map(|member| {
-                let member = str::from_utf8(member.credential.serialized_content()).ok()?;
-                (member != user).then(|| member.to_string())
+            .filter(|member| {
+                removed
+                    .iter()
+                    .any(|identity| member.credential.serialized_content() == identity.as_bytes())
             })
+            .map(|member| member.index)
             .collect();
+        // Determined before merging, so that removed members see the commit.
+        let recipients = member_identities(&group);
 
-        self.client
-            .send_message(SendMessageRequest {
-                sender: user,
+        let bundle = group
+            .commit_builder()
+            .propose_removals(leaf_indices)
+            .propose_group_context_extensions(extensions)?
+            .load_psks(provider.storage())?
+            .build(
+                provider.rand(),
+                provider.crypto(),
+                &signing_private_key,
+                |_| true,
+            )?
+            .stage_commit(&provider)?;
+
+        self.send_commit(user, group_uuid, recipients, bundle.commit())
+            .await
+    }
+
+    /// Leaves the group by proposing the removal of the own leaf. The remaining
+    /// members commit the proposal, see [`Client::commit_pending_removals`].
+    pub async fn leave_group(&mut self, user: String, group_uuid: Uuid) -> Result<()> {
+        rebasing!(self, &user, |client| client
+            .leave_group_once(&user, group_uuid))?;
+        self.mark_group_left(&user, group_uuid).await?;
+
+        Ok(())
+    }
+
+    async fn leave_group_once(&mut self, user: &str, group_uuid: Uuid) -> anyhow::Result<()> {
+        let (signing_private_key, _credential_with_key) = self.credential(user).await?;
+
+        let group_id = GroupId::from_slice(group_uuid.as_bytes());
+        let provider = self.provider();
+        let mut group =
+            MlsGroup::load(provider.storage(), &group_id)?.ok_or_else(|| not_found("Group"))?;
+
+        let proposal = group.leave_group(&provider, &signing_private_key)?;
+
+        let recipients = member_identities(&group);
+        self.fanout(user, recipients, proposal.tls_serialize_detached()?)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Commits the pending remove proposals of the group, together with the
+    /// removal of all other devices of the leaving users.
+    ///
+    /// Only the remaining member with the lowest leaf index commits, so that the
+    /// members don't race each other with competing commits.
+    pub(crate) async fn commit_pending_removals(
+        &mut self,
+        user: &str,
+        group_uuid: Uuid,
+    ) -> anyhow::Result<()> {
+        let (signing_private_key, _credential_with_key) = self.credential(user).await?;
+
+        let group_id = GroupId::from_slice(group_uuid.as_bytes());
+        let provider = self.provider();
+        let mut group =
+            MlsGroup::load(provider.storage(), &group_id)?.ok_or_else(|| not_found("Group"))?;
+
+        let proposed: Vec<LeafNodeIndex> = group
+            .pending_proposals()
+            .filter_map(|proposal| match proposal.proposal() {
+                Proposal::Remove(remove) => Some(remove.removed()),
+                _ => None,
+            })
+            .collect();
+        if proposed.is_empty() {
+            return Ok(());
+        }
+
+        let leaving: Vec<Credential> = proposed
+            .iter()
+            .filter_map(|index| group.member_at(*index))
+            .map(|member| member.credential)
+            .collect();
+        let (departing, remaining): (Vec<_>, Vec<_>) = group
+            .members()
+            .partition(|member| leaving.contains(&member.credential));
+
+        let Some(committer) = remaining.iter().map(|member| member.index).min() else {
+            return Ok(());
+        };
+        if committer != group.own_leaf_index() {
+            return Ok(());
+        }
+
+        let removals: Vec<LeafNodeIndex> = departing
+            .iter()
+            .map(|member| member.index)
+            .filter(|index| !proposed.contains(index))
+            .collect();
+
+        // Determined before merging, so that the leaving members see the commit.
+        let recipients = member_identities(&group);
+
+        let bundle = group
+            .commit_builder()
+            .propose_removals(removals)
+            .load_psks(provider.storage())?
+            .build(
+                provider.rand(),
+                provider.crypto(),
+                &signing_private_key,
+                |_| true,
+            )?
+            .stage_commit(&provider)?;
+        // Pending add proposals are committed along with the removals.
+        let added = pending_additions(&group);
+
+        let (commit, welcome, _group_info) = bundle.into_messages();
+        if let Err(error) = self
+            .send_commit(user, group_uuid, recipients, &commit)
+            .await
+        {
+            if is_epoch_conflict(&error) {
+                // Another member committed first; its commit is processed
+                // with the next receive.
+                warn!(%group_uuid, %error, "Removal commit lost the race for the epoch");
+                return Ok(());
+            }
+            return Err(error);
+        }
+        if let Some(welcome) = welcome {
+            self.send_welcome(user, welcome, added).await?;
+        }
+        self.publish_group_info(user, group_uuid).await?;
+
+        info!(%group_uuid, "Committed member removals");
+
+        Ok(())
+    }
+
+    /// Joins an open group with an external commit, using the GroupInfo that
+    /// its members published on the server.
+    pub async fn join_group_externally(&mut self, user: String, group_uuid: Uuid) -> Result<()> {
+        // After losing the race, the next attempt is based on the GroupInfo
+        // the winner published.
+        rebasing!(self, &user, |client| client
+            .join_group_externally_once(&user, group_uuid))?;
+        self.publish_group_info(&user, group_uuid).await?;
+
+        info!(%group_uuid, "Joined group with an external commit");
+
+        Ok(())
+    }
+
+    async fn join_group_externally_once(
+        &mut self,
+        user: &str,
+        group_uuid: Uuid,
+    ) -> anyhow::Result<()> {
+        let (signing_private_key, credential_with_key) = self.credential(user).await?;
+
+        let group_info = self
+            .client
+            .fetch_group_info(FetchGroupInfoRequest {
+                group_id: group_uuid.as_bytes().to_vec(),
+            })
+            .await?
+            .group_info;
+        let MlsMessageBodyIn::GroupInfo(group_info) =
+            MlsMessageIn::tls_deserialize_exact_bytes(&group_info)?.extract()
+        else {
+            bail!("Server returned no GroupInfo");
+        };
+        if group_info.group_id().as_slice() != group_uuid.as_bytes() {
+            bail!("Server returned the GroupInfo of another group");
+        }
+        self.ensure_group_available(user, group_uuid).await?;
+
+        let join_config = self.join_config();
+        let provider = self.provider();
+        let (mut group, bundle) = MlsGroup::external_commit_builder()
+            .with_config(join_config)
+            .build_group(&provider, group_info, credential_with_key)?
+            .leaf_node_parameters(
+                LeafNodeParameters::builder()
+                    .with_capabilities(capabilities())
+                    .build(),
+            )
+            .load_psks(provider.storage())?
+            .build(
+                provider.rand(),
+                provider.crypto(),
+                &signing_private_key,
+                |_| true,
+            )?
+            .finalize(&provider)?;
+
+        let creator = group_creator(&group);
+        let recipients = member_identities(&group);
+        let result = self
+            .fanout(
+                user,
                 recipients,
-                content: bundle.into_commit().tls_serialize_detached()?,
+                bundle.into_commit().tls_serialize_detached()?,
+            )
+            .await;
+        if let Err(error) = result {
+            // The group was already created from the rejected commit.
+            group.delete(self.provider().storage())?;
+            return Err(error);
+        }
+        self.insert_group(user, group_uuid, &creator).await?;
+        self.pin_member_keys(user, &group).await?;
+
+        Ok(())
+    }
+
+    /// Publishes the GroupInfo of the current epoch if the group is open, so
+    /// that the next external commit is based on it. Called after each commit
+    /// of this client.
+    pub(crate) async fn publish_group_info(
+        &mut self,
+        user: &str,
+        group_uuid: Uuid,
+    ) -> anyhow::Result<()> {
+        let (signing_private_key, _credential_with_key) = self.credential(user).await?;
+
+        let group_id = GroupId::from_slice(group_uuid.as_bytes());
+        let provider = self.provider();
+        let group =
+            MlsGroup::load(provider.storage(), &group_id)?.ok_or_else(|| not_found("Group"))?;
+        let open = group_metadata(group.extensions())
+            .is_some_and(|metadata| metadata.open && metadata.successor.is_empty());
+        if !group.is_active() || !open {
+            return Ok(());
+        }
+        let group_info = group.export_group_info(provider.crypto(), &signing_private_key, true)?;
+
+        self.client
+            .publish_group_info(PublishGroupInfoRequest {
+                group_info: group_info.tls_serialize_detached()?,
             })
             .await?;
+        debug!(%group_uuid, epoch = group.epoch().as_u64(), "Published group info");
 
         Ok(())
     }
+
+    /// Groups of `user`, most recently active first, including archived ones.
+    pub async fn list_groups(&mut self, user: String) -> Result<Vec<GroupSummary>> {
+        let rows = query!(
+            "SELECT
+                group_id AS \"group_id: Uuid\",
+                creator,
+                inviter,
+                created_at AS \"created_at: DateTime<Utc>\",
+                last_activity_at AS \"last_activity_at: DateTime<Utc>\",
+                (
+                    SELECT COUNT(*) FROM client_message
+                    WHERE client_message.group_id = client_group.group_id
+                        AND client_message.username = client_group.username
+                        AND direction = 'in' AND sender != client_group.username
+                        AND deleted_at IS NULL
+                        AND message_id > COALESCE(last_read_message_id, 0)
+                ) AS \"unread!: i64\",
+                muted AS \"muted: bool\",
+                archived AS \"archived: bool\"
+            FROM client_group
+            WHERE username = ? AND left_at IS NULL
+            ORDER BY COALESCE(last_activity_at, created_at) DESC",
+            user
+        )
+        .fetch_all(&mut self.connection)
+        .await?;
+
+        let provider = self.provider();
+        let mut groups = Vec::with_capacity(rows.len());
+        for row in rows {
+            let group_id = GroupId::from_slice(row.group_id.as_bytes());
+            let group = MlsGroup::load(provider.storage(), &group_id)?;
+            let metadata = group
+                .as_ref()
+                .and_then(|group| group_metadata(group.extensions()))
+                .unwrap_or_default();
+            groups.push(GroupSummary {
+                group_id: row.group_id,
+                name: metadata.name,
+                topic: metadata.topic,
+                ciphersuite: group.as_ref().map(MlsGroup::ciphersuite),
+                creator: row.creator,
+                inviter: row.inviter,
+                created_at: row.created_at,
+                last_activity_at: row.last_activity_at,
+                unread: row.unread as u64,
+                muted: row.muted,
+                archived: row.archived,
+            });
+        }
+        Ok(groups)
+    }
+
+    pub(crate) async fn insert_group(
+        &mut self,
+        user: &str,
+        group_uuid: Uuid,
+        creator: &str,
+    ) -> anyhow::Result<()> {
+        let created_at = Utc::now();
+        query!(
+            "INSERT INTO client_group (
+                group_id, username, creator, created_at
+            ) VALUES (?, ?, ?, ?)
+            ON CONFLICT (group_id) DO UPDATE SET username = excluded.username, left_at = NULL",
+            group_uuid,
+            user,
+            creator,
+            created_at,
+        )
+        .execute(&mut self.connection)
+        .await?;
+        Ok(())
+    }
+
+    pub(crate) async fn mark_group_left(
+        &mut self,
+        user: &str,
+        group_uuid: Uuid,
+    ) -> anyhow::Result<()> {
+        let left_at = Utc::now();
+        query!(
+            "UPDATE client_group
+            SET left_at = ?
+            WHERE group_id = ? AND username = ? AND left_at IS NULL",
+            left_at,
+            group_uuid,
+            user,
+        )
+        .execute(&mut self.connection)
+        .await?;
+        Ok(())
+    }
+
+    /// Records that a message was sent or received in the group.
+    pub(crate) async fn touch_group(&mut self, user: &str, group_uuid: Uuid) -> anyhow::Result<()> {
+        let now = Utc::now();
+        query!(
+            "UPDATE client_group SET last_activity_at = ? WHERE group_id = ? AND username = ?",
+            now,
+            group_uuid,
+            user,
+        )
+        .execute(&mut self.connection)
+        .await?;
+        Ok(())
+    }
+
+    /// Configuration of groups joined from a welcome or an external commit.
+    pub(crate) fn join_config(&self) -> MlsGroupJoinConfig {
+        MlsGroupJoinConfig::builder()
+            .use_ratchet_tree_extension(true)
+            .sender_ratchet_configuration(self.sender_ratchet_configuration())
+            .max_past_epochs(self.group_config.max_past_epochs)
+            .build()
+    }
+
+    fn sender_ratchet_configuration(&self) -> SenderRatchetConfiguration {
+        SenderRatchetConfiguration::new(
+            self.group_config.out_of_order_tolerance,
+            self.group_config.maximum_forward_distance,
+        )
+    }
+
+    pub(crate) async fn group_ids(&mut self, user: &str) -> anyhow::Result<Vec<Uuid>> {
+        let group_ids = query_scalar!(
+            "SELECT group_id AS \"group_id: Uuid\"
+            FROM client_group
+            WHERE username = ? AND left_at IS NULL",
+            user
+        )
+        .fetch_all(&mut self.connection)
+        .await?;
+        Ok(group_ids)
+    }
+
+    pub async fn update_group(&mut self, user: String, group_uuid: Uuid) -> Result<()> {
+        rebasing!(self, &user, |client| client
+            .update_group_once(&user, group_uuid))?;
+        self.mark_keys_updated(&user, group_uuid).await?;
+        self.publish_group_info(&user, group_uuid).await?;
+
+        Ok(())
+    }
+
+    async fn update_group_once(&mut self, user: &str, group_uuid: Uuid) -> anyhow::Result<()> {
+        let (signing_private_key, credential_with_key) = self.credential(user).await?;
+
+        let group_id = GroupId::from_slice(group_uuid.as_bytes());
+        let mut group = MlsGroup::load(self.provider().storage(), &group_id)?
+            .ok_or_else(|| not_found("Group"))?;
+        let leaf_signer = self.leaf_signer(user, &group).await?;
+        let provider = self.provider();
+
+        // Moves the leaf to the current key if it still uses a retired one, see
+        // `Client::rotate_identity_key`.
+        let leaf_key = group.own_leaf_node().map(|leaf| leaf.signature_key());
+        let bundle = if leaf_key == Some(&credential_with_key.signature_key) {
+            group.self_update(
+                &provider,
+                &signing_private_key,
+                LeafNodeParameters::builder().build(),
+            )?
+        } else {
+            group.self_update_with_new_signer(
+                &provider,
+                &leaf_signer,
+                NewSignerBundle {
+                    signer: &signing_private_key,
+                    credential_with_key: credential_with_key.clone(),
+                },
+                LeafNodeParameters::builder()
+                    .with_credential_with_key(credential_with_key)
+                    .build(),
+            )?
+        };
+
+        let recipients = member_identities(&group);
+        self.send_commit(user, group_uuid, recipients, bundle.commit())
+            .await
+    }
+}
+
+/// Creator of the group as recorded in its metadata. Groups created before it
+/// was recorded fall back to the first leaf, which the creator occupies until
+/// it leaves.
+pub(crate) fn group_creator(group: &MlsGroup) -> String {
+    group_metadata(group.extensions())
+        .map(|metadata| metadata.creator)
+        .filter(|creator| !creator.is_empty())
+        .or_else(|| {
+            let member = group.member_at(LeafNodeIndex::new(0))?;
+            Some(String::from_utf8_lossy(member.credential.serialized_content()).into_owned())
+        })
+        .unwrap_or_default()
+}
+
+/// Name and topic of the group, if set.
+pub(crate) fn group_metadata(extensions: &Extensions<GroupContext>) -> Option<GroupMetadata> {
+    let extension = extensions.unknown(GROUP_METADATA_EXTENSION)?;
+    GroupMetadata::decode(extension.0.as_slice()).ok()
+}
+
+/// Sets the metadata extension of the group context.
+pub(crate) fn set_metadata_extension(
+    extensions: &mut Extensions<GroupContext>,
+    metadata: &GroupMetadata,
+) -> anyhow::Result<()> {
+    set_custom_extension(
+        extensions,
+        GROUP_METADATA_EXTENSION,
+        metadata.encode_to_vec(),
+    )
+}
+
+/// Sets one of our extensions and requires support for it from all members,
+/// as group context extensions must be.
+pub(crate) fn set_custom_extension(
+    extensions: &mut Extensions<GroupContext>,
+    extension_type: u16,
+    data: Vec<u8>,
+) -> anyhow::Result<()> {
+    let required_type = ExtensionType::Unknown(extension_type);
+    let required = match extensions.required_capabilities() {
+        Some(required) if required.extension_types().contains(&required_type) => None,
+        Some(required) => Some(RequiredCapabilitiesExtension::new(
+            &[required.extension_types(), &[required_type]].concat(),
+            required.proposal_types(),
+            required.credential_types(),
+        )),
+        None => Some(RequiredCapabilitiesExtension::new(
+            &[required_type],
+            &[],
+            &[],
+        )),
+    };
+    if let Some(required) = required {
+        extensions.add_or_replace(Extension::RequiredCapabilities(required))?;
+    }
+
+    extensions.add_or_replace(Extension::Unknown(extension_type, UnknownExtension(data)))?;
+    Ok(())
 }
@@ -0,0 +1,506 @@
+use sqlx::{
+    query, query_as, query_scalar,
+    types::chrono::{DateTime, Utc},
+};
+use uuid::Uuid;
+
+use crate::{
+    client::{
+        Client, Result,
+        error::{bail, not_found},
+    },
+    grpc::SharedMessage,
+};
+
+/// Whether a message was received or sent by this device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Incoming,
+    Outgoing,
+}
+
+impl Direction {
+    fn as_str(self) -> &'static str {
+        match self {
+            Direction::Incoming => "in",
+            Direction::Outgoing => "out",
+        }
+    }
+
+    fn parse(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "in" => Ok(Direction::Incoming),
+            "out" => Ok(Direction::Outgoing),
+            _ => bail!("Invalid message direction {s}"),
+        }
+    }
+}
+
+/// How far an outgoing message got, see [`HistoryMessage::status`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SendStatus {
+    /// In the outbox, as the server couldn't be reached.
+    Queued,
+    /// Accepted by the server.
+    Sent,
+    /// Confirmed by a receipt of at least one member.
+    Delivered,
+    /// Dropped from the outbox after the server rejected it.
+    Failed,
+}
+
+impl SendStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SendStatus::Queued => "queued",
+            SendStatus::Sent => "sent",
+            SendStatus::Delivered => "delivered",
+            SendStatus::Failed => "failed",
+        }
+    }
+
+    fn parse(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "queued" => Ok(SendStatus::Queued),
+            "sent" => Ok(SendStatus::Sent),
+            "delivered" => Ok(SendStatus::Delivered),
+            "failed" => Ok(SendStatus::Failed),
+            _ => bail!("Invalid send status {s}"),
+        }
+    }
+}
+
+/// Format of [`Client::export_history`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HistoryFormat {
+    /// A JSON document with one object per message.
+    Json,
+    Markdown,
+}
+
+/// Version of the JSON schema of exported histories.
+const EXPORT_VERSION: u32 = 2;
+
+/// A decrypted application message kept in the local history.
+#[derive(Debug)]
+pub struct HistoryMessage {
+    pub message_id: i64,
+    pub group_id: Uuid,
+    pub sender: String,
+    pub epoch: u64,
+    pub direction: Direction,
+    pub body: String,
+    /// Envelope id, the same for all members. Missing for messages of
+    /// older clients.
+    pub uuid: Option<Uuid>,
+    /// History id of the message this one replies to, if known.
+    pub reply_to: Option<i64>,
+    /// Identities mentioned by the sender.
+    pub mentions: Vec<String>,
+    /// When the message was sent or received by this device.
+    pub created_at: DateTime<Utc>,
+    /// How far an outgoing message got, `None` for incoming ones.
+    pub status: Option<SendStatus>,
+    /// When the server accepted an outgoing message, by its clock.
+    pub sent_at: Option<DateTime<Utc>>,
+    /// When the sender last edited the message, see [`Client::message_edits`].
+    pub edited_at: Option<DateTime<Utc>>,
+    /// When the sender deleted the message, which leaves an empty body.
+    pub deleted_at: Option<DateTime<Utc>>,
+    /// Members that confirmed the delivery of the message.
+    pub delivered_to: Vec<String>,
+    /// Members that have seen the message, a subset of `delivered_to`.
+    pub read_by: Vec<String>,
+    /// Member who shared the message from their history, see
+    /// [`Client::share_history`]. The message isn't authenticated by its
+    /// `sender` then, only by this member.
+    pub shared_by: Option<String>,
+}
+
+/// A message to add to the history.
+pub(crate) struct NewMessage<'a> {
+    pub sender: &'a str,
+    pub epoch: u64,
+    pub direction: Direction,
+    /// Envelope id of the message, which receipts refer to.
+    pub uuid: Option<Uuid>,
+    pub body: &'a str,
+    pub mentions: &'a [String],
+    /// Envelope id of the message this one replies to.
+    pub reply_to: Option<Uuid>,
+    /// Id of a message in the MIMI content format.
+    pub mimi_id: Option<&'a [u8]>,
+    /// When the message is to be replaced with a tombstone.
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+struct HistoryRow {
+    message_id: i64,
+    group_id: Uuid,
+    sender: String,
+    epoch: i64,
+    direction: String,
+    body: String,
+    message_uuid: Option<Uuid>,
+    reply_to: Option<i64>,
+    mentions: String,
+    created_at: DateTime<Utc>,
+    send_status: Option<String>,
+    sent_at: Option<DateTime<Utc>>,
+    edited_at: Option<DateTime<Utc>>,
+    deleted_at: Option<DateTime<Utc>>,
+    shared_by: Option<String>,
+}
+
+impl TryFrom<HistoryRow> for HistoryMessage {
+    type Error = anyhow::Error;
+
+    fn try_from(row: HistoryRow) -> anyhow::Result<Self> {
+        Ok(Self {
+            message_id: row.message_id,
+            group_id: row.group_id,
+            sender: row.sender,
+            epoch: row.epoch as u64,
+            direction: Direction::parse(&row.direction)?,
+            body: row.body,
+            uuid: row.message_uuid,
+            reply_to: row.reply_to,
+            mentions: serde_json::from_str(&row.mentions)?,
+            created_at: row.created_at,
+            status: row
+                .send_status
+                .as_deref()
+                .map(SendStatus::parse)
+                .transpose()?,
+            sent_at: row.sent_at,
+            edited_at: row.edited_at,
+            deleted_at: row.deleted_at,
+            delivered_to: Vec::new(),
+            read_by: Vec::new(),
+            shared_by: row.shared_by,
+        })
+    }
+}
+
+impl Client {
+    /// Up to `limit` messages of the group, oldest first. Older pages are
+    /// fetched by passing the id of the first message of a page as `before`.
+    pub async fn history(
+        &mut self,
+        user: String,
+        group_uuid: Uuid,
+        limit: u32,
+        before: Option<i64>,
+    ) -> Result<Vec<HistoryMessage>> {
+        self.expire_messages(&user).await?;
+        let mut rows = query_as!(
+            HistoryRow,
+            "SELECT
+                m.message_id,
+                m.group_id AS \"group_id: Uuid\",
+                m.sender,
+                m.epoch,
+                m.direction,
+                m.body,
+                m.message_uuid AS \"message_uuid: Uuid\",
+                p.message_id AS \"reply_to?: i64\",
+                m.mentions,
+                m.created_at AS \"created_at: DateTime<Utc>\",
+                m.send_status,
+                m.sent_at AS \"sent_at: DateTime<Utc>\",
+                m.edited_at AS \"edited_at: DateTime<Utc>\",
+                m.deleted_at AS \"deleted_at: DateTime<Utc>\",
+                m.shared_by
+            FROM client_message m
+            LEFT JOIN client_message p ON p.username = m.username
+                AND p.group_id = m.group_id AND p.message_uuid = m.reply_to
+            WHERE m.group_id = ? AND m.username = ? AND (? IS NULL OR m.message_id < ?)
+            ORDER BY m.message_id DESC
+            LIMIT ?",
+            group_uuid,
+            user,
+            before,
+            before,
+            limit,
+        )
+        .fetch_all(&mut self.connection)
+        .await?;
+        rows.reverse();
+        let mut messages = rows
+            .into_iter()
+            .map(HistoryMessage::try_from)
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        self.add_receipts(&mut messages).await?;
+        Ok(messages)
+    }
+
+    /// The message `message_id` of the group and all replies to it, directly
+    /// or to other replies, in thread order with their depth below the
+    /// message.
+    pub async fn thread(
+        &mut self,
+        user: String,
+        group_uuid: Uuid,
+        message_id: i64,
+    ) -> Result<Vec<(usize, HistoryMessage)>> {
+        self.expire_messages(&user).await?;
+        let root = query_as!(
+            HistoryRow,
+            "SELECT
+                m.message_id,
+                m.group_id AS \"group_id: Uuid\",
+                m.sender,
+                m.epoch,
+                m.direction,
+                m.body,
+                m.message_uuid AS \"message_uuid: Uuid\",
+                p.message_id AS \"reply_to?: i64\",
+                m.mentions,
+                m.created_at AS \"created_at: DateTime<Utc>\",
+                m.send_status,
+                m.sent_at AS \"sent_at: DateTime<Utc>\",
+                m.edited_at AS \"edited_at: DateTime<Utc>\",
+                m.deleted_at AS \"deleted_at: DateTime<Utc>\",
+                m.shared_by
+            FROM client_message m
+            LEFT JOIN client_message p ON p.username = m.username
+                AND p.group_id = m.group_id AND p.message_uuid = m.reply_to
+            WHERE m.message_id = ? AND m.group_id = ? AND m.username = ?",
+            message_id,
+            group_uuid,
+            user,
+        )
+        .fetch_optional(&mut self.connection)
+        .await?
+        .ok_or_else(|| not_found("Message"))?;
+
+        let mut depths = Vec::new();
+        let mut messages = Vec::new();
+        let mut stack = vec![(0, HistoryMessage::try_from(root)?)];
+        while let Some((depth, message)) = stack.pop() {
+            if let Some(message_uuid) = message.uuid {
+                let replies = query_as!(
+                    HistoryRow,
+                    "SELECT
+                        message_id,
+                        group_id AS \"group_id: Uuid\",
+                        sender,
+                        epoch,
+                        direction,
+                        body,
+                        message_uuid AS \"message_uuid: Uuid\",
+                        ? AS \"reply_to?: i64\",
+                        mentions,
+                        created_at AS \"created_at: DateTime<Utc>\",
+                        send_status,
+                        sent_at AS \"sent_at: DateTime<Utc>\",
+                        edited_at AS \"edited_at: DateTime<Utc>\",
+                        deleted_at AS \"deleted_at: DateTime<Utc>\",
+                        shared_by
+                    FROM client_message
+                    WHERE group_id = ? AND username = ? AND reply_to = ?
+                    ORDER BY message_id DESC",
+                    message.message_id,
+                    group_uuid,
+                    user,
+                    message_uuid,
+                )
+                .fetch_all(&mut self.connection)
+                .await?;
+                // Newest first on the stack, so that the oldest reply is next.
+                for reply in replies {
+                    stack.push((depth + 1, HistoryMessage::try_from(reply)?));
+                }
+            }
+            depths.push(depth);
+            messages.push(message);
+        }
+
+        self.add_receipts(&mut messages).await?;
+        Ok(depths.into_iter().zip(messages).collect())
+    }
+
+    /// Adds the members that confirmed delivery or reading to `messages`.
+    async fn add_receipts(&mut self, messages: &mut [HistoryMessage]) -> anyhow::Result<()> {
+        let ids = messages.iter().map(|message| message.message_id);
+        let (Some(first), Some(last)) = (ids.clone().min(), ids.max()) else {
+            return Ok(());
+        };
+        let receipts = query!(
+            "SELECT message_id, member, read_at IS NOT NULL AS \"read!: bool\"
+            FROM client_receipt
+            WHERE message_id BETWEEN ? AND ?
+            ORDER BY delivered_at",
+            first,
+            last,
+        )
+        .fetch_all(&mut self.connection)
+        .await?;
+        for receipt in receipts {
+            if let Some(message) = messages
+                .iter_mut()
+                .find(|message| message.message_id == receipt.message_id)
+            {
+                if receipt.read {
+                    message.read_by.push(receipt.member.clone());
+                }
+                message.delivered_to.push(receipt.member);
+            }
+        }
+        Ok(())
+    }
+
+    /// Renders the whole history of the group, oldest first, for archiving or
+    /// for other tools.
+    ///
+    /// The JSON document has the fields `version`, `group_id` and `messages`,
+    /// and each message the fields `message_id`, `sender`, `epoch`,
+    /// `timestamp` (RFC 3339), `direction` (`in` or `out`) and `body`, and
+    /// since version 2 `shared_by`, see [`HistoryMessage::shared_by`]. Fields
+    /// are only added in later versions.
+    pub async fn export_history(
+        &mut self,
+        user: String,
+        group_uuid: Uuid,
+        format: HistoryFormat,
+    ) -> Result<String> {
+        let messages = self.history(user, group_uuid, u32::MAX, None).await?;
+        match format {
+            HistoryFormat::Json => {
+                let messages: Vec<_> = messages
+                    .iter()
+                    .map(|message| {
+                        serde_json::json!({
+                            "message_id": message.message_id,
+                            "sender": message.sender,
+                            "epoch": message.epoch,
+                            "timestamp": message.created_at.to_rfc3339(),
+                            "direction": message.direction.as_str(),
+                            "body": message.body,
+                            "shared_by": message.shared_by,
+                        })
+                    })
+                    .collect();
+                let export = serde_json::json!({
+                    "version": EXPORT_VERSION,
+                    "group_id": group_uuid.to_string(),
+                    "messages": messages,
+                });
+                Ok(serde_json::to_string_pretty(&export)?)
+            }
+            HistoryFormat::Markdown => {
+                let mut export = format!("# {group_uuid}\n\n");
+                for message in messages {
+                    let shared = match &message.shared_by {
+                        Some(member) => format!("shared by {member}, "),
+                        None => String::new(),
+                    };
+                    export.push_str(&format!(
+                        "**{}** ({shared}{}, epoch {})\n\n{}\n\n",
+                        message.sender,
+                        message.created_at.format("%Y-%m-%d %H:%M:%S"),
+                        message.epoch,
+                        message.body,
+                    ));
+                }
+                Ok(export)
+            }
+        }
+    }
+
+    /// Adds a decrypted application message to the history. Incoming
+    /// messages with an id are marked for a delivery receipt. Returns the id of
+    /// the message in the history.
+    pub(crate) async fn store_message(
+        &mut self,
+        user: &str,
+        group_uuid: Uuid,
+        message: NewMessage<'_>,
+    ) -> anyhow::Result<i64> {
+        let epoch = message.epoch as i64;
+        let direction = message.direction.as_str();
+        let receipt_pending = message.direction == Direction::Incoming
+            && message.sender != user
+            && message.uuid.is_some();
+        let mentions = serde_json::to_string(message.mentions)?;
+        let created_at = Utc::now();
+        let result = query!(
+            "INSERT INTO client_message (
+                group_id, username, sender, epoch, direction, body, created_at,
+                message_uuid, receipt_pending, mentions, reply_to, mimi_id, expires_at
+            ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            group_uuid,
+            user,
+            message.sender,
+            epoch,
+            direction,
+            message.body,
+            created_at,
+            message.uuid,
+            receipt_pending,
+            mentions,
+            message.reply_to,
+            message.mimi_id,
+            message.expires_at,
+        )
+        .execute(&mut self.connection)
+        .await?;
+        Ok(result.last_insert_rowid())
+    }
+
+    /// Adds messages shared by the member `shared_by` to the history,
+    /// skipping those it already has. They are kept as shared, as nothing but
+    /// the word of that member vouches for their senders. Returns the number
+    /// of added messages.
+    pub(crate) async fn import_messages(
+        &mut self,
+        user: &str,
+        group_uuid: Uuid,
+        shared_by: &str,
+        messages: Vec<SharedMessage>,
+    ) -> anyhow::Result<u64> {
+        let mut imported = 0;
+        for message in messages {
+            let message_uuid = Uuid::from_slice(&message.message_id).ok();
+            let reply_to = Uuid::from_slice(&message.reply_to).ok();
+            let known = query_scalar!(
+                "SELECT COUNT(*) > 0 AS \"known!: bool\" FROM client_message
+                WHERE username = ? AND group_id = ? AND message_uuid = ?",
+                user,
+                group_uuid,
+                message_uuid,
+            )
+            .fetch_one(&mut self.connection)
+            .await?;
+            if known {
+                continue;
+            }
+
+            let epoch = message.epoch as i64;
+            let direction = Direction::Incoming.as_str();
+            let created_at =
+                DateTime::from_timestamp_millis(message.sent_at).unwrap_or_else(Utc::now);
+            query!(
+                "INSERT INTO client_message (
+                    group_id, username, sender, epoch, direction, body, created_at,
+                    message_uuid, reply_to, shared_by
+                ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+                group_uuid,
+                user,
+                message.sender,
+                epoch,
+                direction,
+                message.body,
+                created_at,
+                message_uuid,
+                reply_to,
+                shared_by,
+            )
+            .execute(&mut self.connection)
+            .await?;
+            imported += 1;
+        }
+        Ok(imported)
+    }
+}
@@ -0,0 +1,247 @@
+use anyhow::{Context, anyhow};
+use openmls::{
+    group::{GroupId, MlsGroup},
+    prelude::{Ciphersuite, CredentialWithKey},
+};
+use openmls_sqlx_storage::Codec;
+use openmls_traits::{OpenMlsProvider, signatures::Signer};
+use sqlx::{
+    query, query_scalar,
+    types::chrono::{DateTime, Utc},
+};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::{
+    client::{
+        Client, Result, error::bail, member::fingerprint, message::identity,
+        register::SignaturePrivateKey, signer::IdentitySigner,
+    },
+    device,
+    grpc::{DeviceCertificate, GetQueueStatusRequest, RotateDeviceKeyRequest},
+    provider::JsonCodec,
+};
+
+/// Identity and key material of a user, see [`Client::whoami`].
+#[derive(Debug)]
+pub struct IdentityStatus {
+    /// Identity in the credential of the user.
+    pub identity: String,
+    /// Empty for the primary device.
+    pub device_id: String,
+    /// Ciphersuite of new groups and of the key packages.
+    pub ciphersuite: Ciphersuite,
+    /// Fingerprint of the current signature key, see [`fingerprint`].
+    pub fingerprint: String,
+    /// Key packages of the device on the server. Others can't add the device
+    /// to groups without one.
+    pub key_packages: u64,
+    /// Retired signature keys that groups still use.
+    pub retired_keys: u64,
+    /// Groups the user is a member of.
+    pub groups: usize,
+    /// Messages waiting in the outbox to be sent.
+    pub queued_messages: u64,
+}
+
+impl Client {
+    /// Shows the identity and key material of `user`, locally and on the
+    /// server.
+    pub async fn whoami(&mut self, user: String) -> Result<IdentityStatus> {
+        let (_signature_private_key, credential_with_key) = self.credential(&user).await?;
+        let device_id = self.device_id(&user).await?;
+        let ciphersuite = self.default_ciphersuite(&user).await?;
+        let status = self
+            .client
+            .get_queue_status(GetQueueStatusRequest {
+                client_id: user.clone(),
+                device_id: device_id.clone(),
+            })
+            .await?;
+        let retired_keys = query_scalar!(
+            "SELECT COUNT(*) FROM client_retired_key WHERE username = ?",
+            user
+        )
+        .fetch_one(&mut self.connection)
+        .await?;
+        let queued_messages = query_scalar!(
+            "SELECT COUNT(*) FROM client_outbox WHERE username = ?",
+            user
+        )
+        .fetch_one(&mut self.connection)
+        .await?;
+        let groups = self.group_ids(&user).await?.len();
+
+        Ok(IdentityStatus {
+            identity: identity(&credential_with_key.credential).unwrap_or_default(),
+            device_id,
+            ciphersuite,
+            fingerprint: fingerprint(credential_with_key.signature_key.as_slice()),
+            key_packages: status.key_packages,
+            retired_keys: retired_keys as u64,
+            groups,
+            queued_messages: queued_messages as u64,
+        })
+    }
+
+    /// Replaces the signature key of the device with a new one.
+    ///
+    /// The server accepts the new key on the strength of a certificate signed
+    /// by the old one. The own leaf of every group is then moved to the new key
+    /// with a self-update, and the old key is kept as retired until no group
+    /// uses it anymore. Returns the groups that still use a retired key; they
+    /// are moved by the next update of the group.
+    pub async fn rotate_identity_key(&mut self, user: String) -> Result<Vec<Uuid>> {
+        let device_id = self.device_id(&user).await?;
+        let (old_private_key, old_credential_with_key) = self.credential(&user).await?;
+        let IdentitySigner::Local(old_private_key) = old_private_key else {
+            bail!("The identity key of {user} is held by an external signer");
+        };
+        let (signature_private_key, signature_key) = SignaturePrivateKey::generate();
+
+        let mut certificate = DeviceCertificate {
+            client_id: user.clone(),
+            device_id: device_id.clone(),
+            signature_key: signature_key.as_slice().to_vec(),
+            issuer_signature_key: old_credential_with_key.signature_key.as_slice().to_vec(),
+            signature: Vec::new(),
+        };
+        certificate.signature = old_private_key
+            .sign(&device::certificate_payload(&certificate))
+            .map_err(|error| anyhow!("Failed to sign device certificate: {error:?}"))?;
+        self.client
+            .rotate_device_key(RotateDeviceKeyRequest {
+                certificate: Some(certificate),
+            })
+            .await?;
+
+        let old_signature_key = old_credential_with_key.signature_key.as_slice().to_vec();
+        let retired_at: DateTime<Utc> = Utc::now();
+        query!(
+            "INSERT OR IGNORE INTO client_retired_key (
+                username, signature_key, signature_private_key, retired_at
+            ) VALUES (?, ?, ?, ?)",
+            user,
+            old_signature_key,
+            old_private_key.key,
+            retired_at,
+        )
+        .execute(&mut self.connection)
+        .await?;
+
+        let credential_with_key = CredentialWithKey {
+            credential: old_credential_with_key.credential,
+            signature_key,
+        };
+        let credential_with_key_blob = JsonCodec::to_vec(&credential_with_key)?;
+        let old_keychain_account = self.keychain_account(&user).await?;
+        let (private_key_column, keychain_account) = self
+            .store_private_key(
+                credential_with_key.signature_key.as_slice(),
+                &signature_private_key.key,
+            )
+            .await?;
+        query!(
+            "UPDATE client_user
+            SET signature_private_key = ?, credential_with_key = ?, keychain_account = ?
+            WHERE username = ?",
+            private_key_column,
+            credential_with_key_blob,
+            keychain_account,
+            user,
+        )
+        .execute(&mut self.connection)
+        .await?;
+        // The retired key is kept in the database until no group uses it.
+        self.delete_private_key(old_keychain_account).await;
+        info!(device_id, "Rotated identity key");
+
+        self.upload_key_package(
+            user.clone(),
+            device_id,
+            &IdentitySigner::Local(signature_private_key),
+            credential_with_key,
+        )
+        .await?;
+
+        for group_uuid in self.group_ids(&user).await? {
+            // The old key stays usable for the groups that fail to update.
+            if let Err(error) = self.update_group(user.clone(), group_uuid).await {
+                warn!(%group_uuid, %error, "Failed to move group to the new identity key");
+            }
+        }
+
+        Ok(self.drop_retired_keys(&user).await?)
+    }
+
+    /// Private key of the own leaf of `group`. Differs from the current key of
+    /// the user in groups that were not updated since the key was rotated.
+    pub(crate) async fn leaf_signer(
+        &mut self,
+        user: &str,
+        group: &MlsGroup,
+    ) -> anyhow::Result<IdentitySigner> {
+        let signature_key = group
+            .own_leaf_node()
+            .context("Not a member of the group")?
+            .signature_key()
+            .as_slice()
+            .to_vec();
+        let retired = query_scalar!(
+            "SELECT signature_private_key
+            FROM client_retired_key
+            WHERE username = ? AND signature_key = ?",
+            user,
+            signature_key,
+        )
+        .fetch_optional(&mut self.connection)
+        .await?;
+        match retired {
+            Some(key) => Ok(IdentitySigner::Local(SignaturePrivateKey { key })),
+            None => Ok(self.credential(user).await?.0),
+        }
+    }
+
+    /// Deletes the retired keys that no group uses anymore. Returns the groups
+    /// that still use one.
+    pub(crate) async fn drop_retired_keys(&mut self, user: &str) -> anyhow::Result<Vec<Uuid>> {
+        let (_signature_private_key, credential_with_key) = self.credential(user).await?;
+
+        let mut pending = Vec::new();
+        let mut used_keys = Vec::new();
+        for group_uuid in self.group_ids(user).await? {
+            let group_id = GroupId::from_slice(group_uuid.as_bytes());
+            let Some(group) = MlsGroup::load(self.provider().storage(), &group_id)? else {
+                continue;
+            };
+            let Some(leaf) = group.own_leaf_node() else {
+                continue;
+            };
+            if leaf.signature_key() != &credential_with_key.signature_key {
+                pending.push(group_uuid);
+                used_keys.push(leaf.signature_key().as_slice().to_vec());
+            }
+        }
+
+        let retired_keys = query_scalar!(
+            "SELECT signature_key FROM client_retired_key WHERE username = ?",
+            user
+        )
+        .fetch_all(&mut self.connection)
+        .await?;
+        for signature_key in retired_keys {
+            if used_keys.contains(&signature_key) {
+                continue;
+            }
+            query!(
+                "DELETE FROM client_retired_key WHERE username = ? AND signature_key = ?",
+                user,
+                signature_key,
+            )
+            .execute(&mut self.connection)
+            .await?;
+        }
+
+        Ok(pending)
+    }
+}
@@ -0,0 +1,323 @@
+use std::collections::HashSet;
+
+use anyhow::Context;
+use openmls::{
+    group::{StagedWelcome, WelcomeError},
+    prelude::{BasicCredential, DeserializeBytes, MlsMessageBodyIn, MlsMessageIn, Welcome},
+};
+use sqlx::{
+    query, query_scalar,
+    types::chrono::{DateTime, Utc},
+};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::client::{
+    Client, Result,
+    error::{bail, ensure, not_found},
+    group::{group_creator, group_metadata},
+    validator::{CredentialChange, MemberCredential},
+};
+
+/// A welcome to a group that the user has not accepted yet.
+#[derive(Debug)]
+pub struct Invite {
+    pub group_id: Uuid,
+    pub name: String,
+    /// Identity of the member who sent the welcome.
+    pub inviter: String,
+    pub members: Vec<String>,
+    pub received_at: DateTime<Utc>,
+}
+
+impl Client {
+    pub async fn invites(&mut self, user: String) -> Result<Vec<Invite>> {
+        let rows = query!(
+            "SELECT
+                group_id AS \"group_id: Uuid\",
+                name,
+                inviter,
+                members,
+                received_at AS \"received_at: DateTime<Utc>\"
+            FROM client_invite
+            WHERE username = ?
+            ORDER BY received_at",
+            user
+        )
+        .fetch_all(&mut self.connection)
+        .await?;
+
+        rows.into_iter()
+            .map(|row| {
+                Ok(Invite {
+                    group_id: row.group_id,
+                    name: row.name,
+                    inviter: row.inviter,
+                    members: serde_json::from_str(&row.members)?,
+                    received_at: row.received_at,
+                })
+            })
+            .collect()
+    }
+
+    /// Joins the group of a pending invite and processes the messages of the
+    /// group that arrived in the meantime.
+    pub async fn accept_invite(&mut self, user: String, group_uuid: Uuid) -> Result<()> {
+        let invite = query!(
+            "SELECT welcome, key_package_ref FROM client_invite WHERE group_id = ? AND username = ?",
+            group_uuid,
+            user,
+        )
+        .fetch_optional(&mut self.connection)
+        .await?
+        .ok_or_else(|| not_found("Invite"))?;
+
+        let MlsMessageBodyIn::Welcome(welcome) =
+            MlsMessageIn::tls_deserialize_exact_bytes(&invite.welcome)?.extract()
+        else {
+            bail!("Invalid stored welcome");
+        };
+
+        self.ensure_group_available(&user, group_uuid).await?;
+        let group_config = self.join_config();
+        let provider = self.provider();
+        let staged_welcome =
+            StagedWelcome::new_from_welcome(&provider, &group_config, welcome, None)?;
+        let group = staged_welcome.into_group(&provider)?;
+
+        // A re-initialized group keeps the creator of the group it replaces.
+        let creator = match self.predecessor_creator(&user, group_uuid).await? {
+            Some(creator) => creator,
+            None => group_creator(&group),
+        };
+        self.insert_group(&user, group_uuid, &creator).await?;
+        self.pin_member_keys(&user, &group).await?;
+        self.record_direct_group(&user, &group).await?;
+        query!(
+            "UPDATE client_group
+            SET inviter = (
+                SELECT inviter FROM client_invite WHERE group_id = ? AND username = ?
+            )
+            WHERE group_id = ? AND username = ?",
+            group_uuid,
+            user,
+            group_uuid,
+            user,
+        )
+        .execute(&mut self.connection)
+        .await?;
+
+        let buffered = query_scalar!(
+            "SELECT content
+            FROM client_invite_message
+            WHERE group_id = ? AND username = ?
+            ORDER BY message_id",
+            group_uuid,
+            user,
+        )
+        .fetch_all(&mut self.connection)
+        .await?;
+        self.delete_invite(&user, group_uuid).await?;
+        if let Some(key_package_ref) = &invite.key_package_ref {
+            self.consume_key_package(&user, key_package_ref, group_uuid)
+                .await?;
+        }
+
+        for content in buffered {
+            self.handle_message(&user, None, &content).await?;
+        }
+
+        info!(%group_uuid, "Accepted invite and joined group");
+
+        Ok(())
+    }
+
+    /// Discards a pending invite. The other members are not notified; the
+    /// leaf of the invited user stays in their group until it is removed.
+    pub async fn decline_invite(&mut self, user: String, group_uuid: Uuid) -> Result<()> {
+        let key_package_ref = query_scalar!(
+            "SELECT key_package_ref FROM client_invite WHERE group_id = ? AND username = ?",
+            group_uuid,
+            user,
+        )
+        .fetch_optional(&mut self.connection)
+        .await?
+        .ok_or_else(|| not_found("Invite"))?;
+        self.delete_invite(&user, group_uuid).await?;
+        if let Some(key_package_ref) = &key_package_ref {
+            self.release_key_package(&user, key_package_ref).await?;
+        }
+        Ok(())
+    }
+
+    /// Stores a received welcome as pending invite until the user accepts or
+    /// declines it. Returns `None` if the welcome was rejected, see
+    /// [`Client::check_welcome`].
+    pub(crate) async fn store_invite(
+        &mut self,
+        user: &str,
+        welcome: Welcome,
+        content: &[u8],
+    ) -> anyhow::Result<Option<Invite>> {
+        let key_package_ref = self
+            .welcome_key_package(&welcome)?
+            .map(|key_package_ref| key_package_ref.as_slice().to_vec());
+        let group_config = self.join_config();
+        let provider = self.provider();
+        let staged_welcome =
+            match StagedWelcome::new_from_welcome(&provider, &group_config, welcome, None) {
+                Ok(staged_welcome) => staged_welcome,
+                // The MLS state of a group is stored once per database.
+                Err(WelcomeError::GroupAlreadyExists) => {
+                    warn!("Rejecting welcome to a group that is already joined in this database");
+                    return Ok(None);
+                }
+                Err(error) => return Err(error.into()),
+            };
+
+        let group_id = Uuid::from_slice(staged_welcome.group_context().group_id().as_slice())?;
+        let inviter = match self.check_welcome(user, &staged_welcome).await {
+            Ok(inviter) => inviter,
+            Err(error) => {
+                warn!(%group_id, %error, "Rejecting welcome");
+                return Ok(None);
+            }
+        };
+        let name = group_metadata(staged_welcome.group_context().extensions())
+            .unwrap_or_default()
+            .name;
+        let members: Vec<String> = staged_welcome
+            .members()
+            .map(|member| {
+                String::from_utf8_lossy(member.credential.serialized_content()).into_owned()
+            })
+            .collect();
+        if let Some(max_members) = self.accept_policy.max_members {
+            let count = members.iter().collect::<HashSet<_>>().len();
+            if count > max_members {
+                warn!(%group_id, count, max_members, "Rejecting welcome to a group that is too large");
+                return Ok(None);
+            }
+        }
+
+        let members_json = serde_json::to_string(&members)?;
+        let received_at = Utc::now();
+        query!(
+            "INSERT OR REPLACE INTO client_invite (
+                group_id, username, welcome, inviter, name, members, received_at, key_package_ref
+            ) VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+            group_id,
+            user,
+            content,
+            inviter,
+            name,
+            members_json,
+            received_at,
+            key_package_ref,
+        )
+        .execute(&mut self.connection)
+        .await?;
+        self.auto_alias(user, &name, group_id).await?;
+
+        Ok(Some(Invite {
+            group_id,
+            name,
+            inviter,
+            members,
+            received_at,
+        }))
+    }
+
+    /// Checks the members of a welcome before it is offered to the user, and
+    /// returns the inviter.
+    ///
+    /// The GroupInfo is signed by the inviter, whose key must be trusted; see
+    /// [`Client::pin_key`]. The keys of the other members are pinned as well,
+    /// which warns about changed ones. Every leaf must carry a basic credential
+    /// and a signature key of its own, and pass the credential validators.
+    async fn check_welcome(
+        &mut self,
+        user: &str,
+        staged_welcome: &StagedWelcome,
+    ) -> anyhow::Result<String> {
+        let mut signature_keys = HashSet::new();
+        let mut members = Vec::new();
+        for member in staged_welcome.members() {
+            let credential = BasicCredential::try_from(member.credential)
+                .context("Member with unsupported credential")?;
+            let identity = String::from_utf8(credential.identity().to_vec())
+                .context("Member with invalid identity")?;
+            ensure!(!identity.is_empty(), "Member with empty identity");
+            ensure!(
+                signature_keys.insert(member.signature_key.clone()),
+                "Signature key of {identity} is used by several leaves"
+            );
+            members.push((identity, member.signature_key));
+        }
+        let group_id = Uuid::from_slice(staged_welcome.group_context().group_id().as_slice())?;
+        let credentials: Vec<MemberCredential> = members
+            .iter()
+            .map(|(identity, signature_key)| MemberCredential {
+                group_id,
+                identity: identity.clone(),
+                signature_key: signature_key.clone(),
+                change: CredentialChange::Invited,
+            })
+            .collect();
+        self.validate_credentials(&credentials).await?;
+
+        let sender = staged_welcome.welcome_sender()?;
+        let inviter =
+            String::from_utf8_lossy(sender.credential().serialized_content()).into_owned();
+        let sender_key = sender.signature_key().as_slice();
+        for (identity, signature_key) in &members {
+            let trusted = self.pin_key(user, identity, signature_key).await?;
+            ensure!(
+                trusted || signature_key != sender_key,
+                "Signature key of the inviter {inviter} changed; verify it and run `trust`"
+            );
+        }
+        Ok(inviter)
+    }
+
+    /// Keeps a message of a group with a pending invite, so that it can be
+    /// processed once the invite is accepted. Returns `false` if there is no
+    /// such invite.
+    pub(crate) async fn buffer_invite_message(
+        &mut self,
+        user: &str,
+        group_uuid: Uuid,
+        content: &[u8],
+    ) -> anyhow::Result<bool> {
+        let result = query!(
+            "INSERT INTO client_invite_message (group_id, username, content)
+            SELECT group_id, username, ?
+            FROM client_invite
+            WHERE group_id = ? AND username = ?",
+            content,
+            group_uuid,
+            user,
+        )
+        .execute(&mut self.connection)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn delete_invite(&mut self, user: &str, group_uuid: Uuid) -> anyhow::Result<bool> {
+        query!(
+            "DELETE FROM client_invite_message WHERE group_id = ? AND username = ?",
+            group_uuid,
+            user,
+        )
+        .execute(&mut self.connection)
+        .await?;
+        let result = query!(
+            "DELETE FROM client_invite WHERE group_id = ? AND username = ?",
+            group_uuid,
+            user,
+        )
+        .execute(&mut self.connection)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+}
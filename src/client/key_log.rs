@@ -0,0 +1,182 @@
+use anyhow::Context;
+use openmls::{
+    group::{GroupId, MlsGroup},
+    prelude::BasicCredential,
+};
+use openmls_traits::OpenMlsProvider;
+use prost::Message;
+use sqlx::query;
+
+use crate::{
+    client::{
+        Client, Result,
+        error::{bail, ensure},
+    },
+    grpc::{
+        GetConsistencyProofRequest, GetInclusionProofRequest, GetKeyLogRootRequest, KeyLogEntry,
+    },
+    transparency,
+};
+
+/// A signature key of a user found in the key transparency log.
+#[derive(Debug)]
+pub struct LoggedKey {
+    pub device_id: String,
+    pub signature_key: Vec<u8>,
+    pub leaf_index: u64,
+    pub timestamp: i64,
+}
+
+impl Client {
+    /// Checks that every signature key of `client_id` is included in the
+    /// server's key transparency log.
+    ///
+    /// The keys are taken from the current key packages of `client_id` and from
+    /// its leaves in the local groups. The log root is also checked to be
+    /// consistent with the root seen on the previous call, so the server cannot
+    /// rewrite the log without being noticed.
+    ///
+    /// Only users of the own server are logged there; users of other domains
+    /// can't be checked.
+    pub async fn verify_keys(
+        &mut self,
+        username: String,
+        client_id: String,
+    ) -> Result<Vec<LoggedKey>> {
+        if let Some((_, domain)) = client_id.rsplit_once('@')
+            && username.rsplit_once('@').map(|(_, own)| own) != Some(domain)
+        {
+            bail!(
+                "{client_id} belongs to domain {domain}, whose keys aren't in the log of this server"
+            );
+        }
+        let root = self
+            .client
+            .get_key_log_root(GetKeyLogRootRequest {})
+            .await?;
+        // A tree size of zero asks the server for the whole log, which may have
+        // grown since the root was taken.
+        ensure!(root.tree_size > 0, "The key log is empty");
+        self.check_key_log_consistency(&username, root.tree_size, &root.root_hash)
+            .await?;
+
+        let mut signature_keys: Vec<Vec<u8>> = self
+            .fetch_key_packages(&client_id, None)
+            .await?
+            .iter()
+            .map(|(_, key_package)| key_package.leaf_node().signature_key().as_slice().to_vec())
+            .collect();
+        for group_uuid in self.group_ids(&username).await? {
+            let provider = self.provider();
+            let group_id = GroupId::from_slice(group_uuid.as_bytes());
+            let Some(group) = MlsGroup::load(provider.storage(), &group_id)? else {
+                continue;
+            };
+            for member in group.members() {
+                let credential = BasicCredential::try_from(member.credential)?;
+                if credential.identity() == client_id.as_bytes() {
+                    signature_keys.push(member.signature_key);
+                }
+            }
+        }
+        signature_keys.sort();
+        signature_keys.dedup();
+
+        let mut logged_keys = Vec::with_capacity(signature_keys.len());
+        for signature_key in signature_keys {
+            let response = self
+                .client
+                .get_inclusion_proof(GetInclusionProofRequest {
+                    client_id: client_id.clone(),
+                    signature_key: signature_key.clone(),
+                    tree_size: root.tree_size,
+                })
+                .await
+                .with_context(|| format!("Signature key of {client_id} is not logged"))?;
+
+            let entry = KeyLogEntry::decode(response.entry.as_slice())?;
+            ensure!(
+                entry.client_id == client_id && entry.signature_key == signature_key,
+                "Key log entry does not match the signature key of {client_id}"
+            );
+            ensure!(
+                transparency::verify_inclusion(
+                    response.leaf_index,
+                    root.tree_size,
+                    &transparency::leaf_hash(&response.entry),
+                    &response.proof,
+                    &root.root_hash,
+                ),
+                "Invalid inclusion proof for a signature key of {client_id}"
+            );
+
+            logged_keys.push(LoggedKey {
+                device_id: entry.device_id,
+                signature_key,
+                leaf_index: response.leaf_index,
+                timestamp: entry.timestamp,
+            });
+        }
+
+        Ok(logged_keys)
+    }
+
+    /// Verifies that the log with the given root extends the last root seen by
+    /// `username` and remembers it.
+    async fn check_key_log_consistency(
+        &mut self,
+        username: &str,
+        tree_size: u64,
+        root_hash: &[u8],
+    ) -> anyhow::Result<()> {
+        let seen = query!(
+            "SELECT tree_size, root_hash FROM client_key_log_root WHERE username = ?",
+            username
+        )
+        .fetch_optional(&mut self.connection)
+        .await?;
+
+        if let Some(seen) = seen {
+            let old_size = seen.tree_size as u64;
+            if old_size > tree_size {
+                bail!("Key log shrank from {old_size} to {tree_size} entries");
+            }
+            let proof = if old_size == tree_size || old_size == 0 {
+                Vec::new()
+            } else {
+                self.client
+                    .get_consistency_proof(GetConsistencyProofRequest {
+                        old_size,
+                        new_size: tree_size,
+                    })
+                    .await?
+                    .proof
+            };
+            ensure!(
+                transparency::verify_consistency(
+                    old_size,
+                    tree_size,
+                    &seen.root_hash,
+                    root_hash,
+                    &proof
+                ),
+                "Key log is inconsistent with the previously seen log"
+            );
+        }
+
+        let tree_size = tree_size as i64;
+        query!(
+            "INSERT INTO client_key_log_root (username, tree_size, root_hash)
+            VALUES (?, ?, ?)
+            ON CONFLICT (username)
+            DO UPDATE SET tree_size = excluded.tree_size, root_hash = excluded.root_hash",
+            username,
+            tree_size,
+            root_hash,
+        )
+        .execute(&mut self.connection)
+        .await?;
+
+        Ok(())
+    }
+}
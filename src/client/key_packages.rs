@@ -0,0 +1,336 @@
+use openmls::prelude::{
+    KeyPackageBundle, KeyPackageRef, OpenMlsProvider, Welcome,
+    tls_codec::{DeserializeBytes, Serialize, VLBytes},
+};
+use openmls_traits::storage::StorageProvider;
+use sqlx::{
+    query, query_as, query_scalar,
+    types::chrono::{DateTime, Utc},
+};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::client::{Client, Result, error::bail};
+
+/// Where a key package of the device is in its life, see
+/// [`Client::key_packages`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyPackageState {
+    /// Generated, but not uploaded, e.g. as the server couldn't be reached.
+    Created,
+    /// On the server, which hands it out to members adding the user.
+    Uploaded,
+    /// Used by the welcome of an accepted invite.
+    Consumed,
+    /// Replaced on the server by a newer key package.
+    Replaced,
+    /// Past the end of its lifetime.
+    Expired,
+}
+
+impl KeyPackageState {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            KeyPackageState::Created => "created",
+            KeyPackageState::Uploaded => "uploaded",
+            KeyPackageState::Consumed => "consumed",
+            KeyPackageState::Replaced => "replaced",
+            KeyPackageState::Expired => "expired",
+        }
+    }
+
+    fn parse(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "created" => Ok(KeyPackageState::Created),
+            "uploaded" => Ok(KeyPackageState::Uploaded),
+            "consumed" => Ok(KeyPackageState::Consumed),
+            "replaced" => Ok(KeyPackageState::Replaced),
+            "expired" => Ok(KeyPackageState::Expired),
+            _ => bail!("Invalid key package state {s}"),
+        }
+    }
+}
+
+/// A key package generated by this device.
+#[derive(Debug, Clone)]
+pub struct KeyPackageInfo {
+    pub key_package_ref: Vec<u8>,
+    pub ciphersuite: u16,
+    pub state: KeyPackageState,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub uploaded_at: Option<DateTime<Utc>>,
+    pub consumed_at: Option<DateTime<Utc>>,
+    /// Group whose welcome consumed the key package.
+    pub consumed_by: Option<Uuid>,
+    /// When its private keys were deleted. Consumed key packages keep them
+    /// while other pending invites were encrypted to them.
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+#[derive(sqlx::FromRow)]
+struct KeyPackageRow {
+    key_package_ref: Vec<u8>,
+    ciphersuite: i64,
+    state: String,
+    created_at: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+    uploaded_at: Option<DateTime<Utc>>,
+    consumed_at: Option<DateTime<Utc>>,
+    consumed_by: Option<Uuid>,
+    deleted_at: Option<DateTime<Utc>>,
+}
+
+impl TryFrom<KeyPackageRow> for KeyPackageInfo {
+    type Error = anyhow::Error;
+
+    fn try_from(row: KeyPackageRow) -> anyhow::Result<Self> {
+        Ok(KeyPackageInfo {
+            key_package_ref: row.key_package_ref,
+            ciphersuite: u16::try_from(row.ciphersuite)?,
+            state: KeyPackageState::parse(&row.state)?,
+            created_at: row.created_at,
+            expires_at: row.expires_at,
+            uploaded_at: row.uploaded_at,
+            consumed_at: row.consumed_at,
+            consumed_by: row.consumed_by,
+            deleted_at: row.deleted_at,
+        })
+    }
+}
+
+/// The reference with the given value, which OpenMLS only constructs by
+/// hashing or decoding.
+fn key_package_ref_from_slice(value: &[u8]) -> anyhow::Result<KeyPackageRef> {
+    let encoded = VLBytes::from(value).tls_serialize_detached()?;
+    Ok(KeyPackageRef::tls_deserialize_exact_bytes(&encoded)?)
+}
+
+impl Client {
+    /// The key packages generated by this device for `user`, oldest first.
+    /// Those past their lifetime are marked as expired and deleted first.
+    pub async fn key_packages(&mut self, user: String) -> Result<Vec<KeyPackageInfo>> {
+        self.expire_key_packages(&user).await?;
+        let rows: Vec<KeyPackageRow> = query_as(
+            "SELECT
+                key_package_ref,
+                ciphersuite,
+                state,
+                created_at,
+                expires_at,
+                uploaded_at,
+                consumed_at,
+                consumed_by,
+                deleted_at
+            FROM client_key_package
+            WHERE username = ?
+            ORDER BY created_at",
+        )
+        .bind(&user)
+        .fetch_all(&mut self.connection)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(KeyPackageInfo::try_from)
+            .collect::<anyhow::Result<_>>()?)
+    }
+
+    /// Records a key package that was just generated. Returns its reference.
+    pub(crate) async fn track_key_package(
+        &mut self,
+        user: &str,
+        bundle: &KeyPackageBundle,
+    ) -> anyhow::Result<KeyPackageRef> {
+        let key_package = bundle.key_package();
+        let key_package_ref = key_package.hash_ref(self.provider().crypto())?;
+        let key_package_ref_bytes = key_package_ref.as_slice();
+        let ciphersuite = u16::from(key_package.ciphersuite());
+        let created_at: DateTime<Utc> = Utc::now();
+        let expires_at =
+            DateTime::<Utc>::from_timestamp(i64::try_from(key_package.life_time().not_after())?, 0)
+                .unwrap_or(DateTime::<Utc>::MAX_UTC);
+        query!(
+            "INSERT INTO client_key_package (
+                key_package_ref, username, ciphersuite, state, created_at, expires_at
+            ) VALUES (?, ?, ?, 'created', ?, ?)",
+            key_package_ref_bytes,
+            user,
+            ciphersuite,
+            created_at,
+            expires_at,
+        )
+        .execute(&mut self.connection)
+        .await?;
+        Ok(key_package_ref)
+    }
+
+    /// Marks a key package as uploaded, and the one it replaced on the server
+    /// as replaced.
+    pub(crate) async fn mark_key_package_uploaded(
+        &mut self,
+        key_package_ref: &KeyPackageRef,
+    ) -> anyhow::Result<()> {
+        let key_package_ref = key_package_ref.as_slice();
+        let uploaded_at: DateTime<Utc> = Utc::now();
+        query!(
+            "UPDATE client_key_package SET state = 'replaced'
+            WHERE state = 'uploaded' AND (username, ciphersuite) = (
+                SELECT username, ciphersuite FROM client_key_package WHERE key_package_ref = ?
+            )",
+            key_package_ref,
+        )
+        .execute(&mut self.connection)
+        .await?;
+        query!(
+            "UPDATE client_key_package SET state = 'uploaded', uploaded_at = ?
+            WHERE key_package_ref = ?",
+            uploaded_at,
+            key_package_ref,
+        )
+        .execute(&mut self.connection)
+        .await?;
+        Ok(())
+    }
+
+    /// The key package of this device that `welcome` was encrypted to, if it
+    /// still has its private keys.
+    pub(crate) fn welcome_key_package(
+        &mut self,
+        welcome: &Welcome,
+    ) -> anyhow::Result<Option<KeyPackageRef>> {
+        for secrets in welcome.secrets() {
+            let key_package_ref = secrets.new_member();
+            let bundle: Option<KeyPackageBundle> =
+                self.provider().storage().key_package(&key_package_ref)?;
+            if bundle.is_some() {
+                return Ok(Some(key_package_ref));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Marks a key package as consumed by joining `group_uuid` and deletes
+    /// its private keys, unless other pending invites still need them. As
+    /// the server keeps handing out the latest key package of a ciphersuite,
+    /// it is replaced there if it is still the latest.
+    pub(crate) async fn consume_key_package(
+        &mut self,
+        user: &str,
+        key_package_ref: &[u8],
+        group_uuid: Uuid,
+    ) -> anyhow::Result<()> {
+        let key_package = query!(
+            "SELECT ciphersuite, state FROM client_key_package
+            WHERE key_package_ref = ? AND username = ?",
+            key_package_ref,
+            user,
+        )
+        .fetch_optional(&mut self.connection)
+        .await?;
+        let consumed_at: DateTime<Utc> = Utc::now();
+        query!(
+            "UPDATE client_key_package
+            SET state = 'consumed', consumed_at = ?, consumed_by = ?
+            WHERE key_package_ref = ? AND state != 'consumed'",
+            consumed_at,
+            group_uuid,
+            key_package_ref,
+        )
+        .execute(&mut self.connection)
+        .await?;
+        self.release_key_package(user, key_package_ref).await?;
+
+        let Some(key_package) = key_package else {
+            return Ok(());
+        };
+        if key_package.state == KeyPackageState::Uploaded.as_str() {
+            let ciphersuite = u16::try_from(key_package.ciphersuite)?.try_into()?;
+            // Welcomes to the consumed key package still work until then.
+            if let Err(error) = self.replace_key_package(user, ciphersuite).await {
+                warn!(%error, "Failed to replace consumed key package");
+            }
+        }
+        Ok(())
+    }
+
+    /// Deletes the private keys of a consumed key package once no pending
+    /// invite was encrypted to it anymore.
+    pub(crate) async fn release_key_package(
+        &mut self,
+        user: &str,
+        key_package_ref: &[u8],
+    ) -> anyhow::Result<()> {
+        let needed = query_scalar!(
+            "SELECT EXISTS (
+                SELECT 1 FROM client_invite WHERE username = ? AND key_package_ref = ?
+            ) AS \"needed!: bool\"",
+            user,
+            key_package_ref,
+        )
+        .fetch_one(&mut self.connection)
+        .await?;
+        let consumed = query_scalar!(
+            "SELECT EXISTS (
+                SELECT 1 FROM client_key_package
+                WHERE key_package_ref = ? AND state = 'consumed' AND deleted_at IS NULL
+            ) AS \"consumed!: bool\"",
+            key_package_ref,
+        )
+        .fetch_one(&mut self.connection)
+        .await?;
+        if consumed && !needed {
+            self.delete_key_package(&key_package_ref_from_slice(key_package_ref)?)
+                .await?;
+            info!("Deleted consumed key package");
+        }
+        Ok(())
+    }
+
+    /// Deletes the private keys of a key package, recording when.
+    pub(crate) async fn delete_key_package(
+        &mut self,
+        key_package_ref: &KeyPackageRef,
+    ) -> anyhow::Result<()> {
+        self.provider()
+            .storage()
+            .delete_key_package(key_package_ref)?;
+        let key_package_ref = key_package_ref.as_slice();
+        let deleted_at: DateTime<Utc> = Utc::now();
+        query!(
+            "UPDATE client_key_package SET deleted_at = ? WHERE key_package_ref = ?",
+            deleted_at,
+            key_package_ref,
+        )
+        .execute(&mut self.connection)
+        .await?;
+        Ok(())
+    }
+
+    /// Marks the key packages past their lifetime as expired and deletes
+    /// their private keys. Returns how many expired.
+    pub(crate) async fn expire_key_packages(&mut self, user: &str) -> anyhow::Result<usize> {
+        let now: DateTime<Utc> = Utc::now();
+        let expired = query_scalar!(
+            "UPDATE client_key_package SET state = 'expired'
+            WHERE username = ? AND state IN ('created', 'uploaded', 'replaced')
+                AND expires_at < ?
+            RETURNING key_package_ref",
+            user,
+            now,
+        )
+        .fetch_all(&mut self.connection)
+        .await?;
+        for key_package_ref in &expired {
+            self.delete_key_package(&key_package_ref_from_slice(key_package_ref)?)
+                .await?;
+        }
+        if !expired.is_empty() {
+            info!(
+                user,
+                expired = expired.len(),
+                "Deleted expired key packages"
+            );
+        }
+        Ok(expired.len())
+    }
+}
@@ -0,0 +1,153 @@
+#[cfg(feature = "keychain")]
+use openmls::prelude::CredentialWithKey;
+#[cfg(feature = "keychain")]
+use openmls_sqlx_storage::Codec;
+use sqlx::query;
+#[cfg(feature = "keychain")]
+use tracing::{info, warn};
+
+use crate::client::Client;
+#[cfg(not(feature = "keychain"))]
+use crate::client::error::bail;
+#[cfg(feature = "keychain")]
+use crate::provider::JsonCodec;
+
+/// Service of the keychain entries, whose accounts are the public signature
+/// keys in hex.
+#[cfg(feature = "keychain")]
+const SERVICE: &str = "mls-chat";
+
+impl Client {
+    /// Stores a signature private key, in the keychain if enabled and
+    /// available. Returns the value of the private key column and the
+    /// keychain account, if any.
+    pub(crate) async fn store_private_key(
+        &self,
+        signature_key: &[u8],
+        private_key: &[u8],
+    ) -> anyhow::Result<(Vec<u8>, Option<String>)> {
+        #[cfg(feature = "keychain")]
+        if self.keychain {
+            let account: String = signature_key
+                .iter()
+                .map(|byte| format!("{byte:02x}"))
+                .collect();
+            match os::set(account.clone(), private_key.to_vec()).await {
+                Ok(()) => return Ok((Vec::new(), Some(account))),
+                Err(error) => {
+                    warn!(%error, "Failed to store key in keychain, keeping it in the database")
+                }
+            }
+        }
+        #[cfg(not(feature = "keychain"))]
+        let _ = signature_key;
+        Ok((private_key.to_vec(), None))
+    }
+
+    /// Signature private key stored with [`Client::store_private_key`].
+    pub(crate) async fn load_private_key(
+        &self,
+        column: Vec<u8>,
+        keychain_account: Option<String>,
+    ) -> anyhow::Result<Vec<u8>> {
+        match keychain_account {
+            #[cfg(feature = "keychain")]
+            Some(account) => os::get(account).await,
+            #[cfg(not(feature = "keychain"))]
+            Some(_) => bail!("The key is in the OS keychain, which this build doesn't support"),
+            None => Ok(column),
+        }
+    }
+
+    /// Deletes the keychain entry of a key that was replaced.
+    pub(crate) async fn delete_private_key(&self, keychain_account: Option<String>) {
+        #[cfg(feature = "keychain")]
+        if let Some(account) = keychain_account
+            && let Err(error) = os::delete(account).await
+        {
+            warn!(%error, "Failed to delete key from keychain");
+        }
+        #[cfg(not(feature = "keychain"))]
+        let _ = keychain_account;
+    }
+
+    /// Moves the signature private keys still stored in the database to the
+    /// keychain.
+    #[cfg(feature = "keychain")]
+    pub(crate) async fn move_keys_to_keychain(&mut self) -> anyhow::Result<()> {
+        let users = query!(
+            "SELECT username, signature_private_key, credential_with_key
+            FROM client_user WHERE keychain_account IS NULL"
+        )
+        .fetch_all(&mut self.connection)
+        .await?;
+        for user in users {
+            let credential_with_key: CredentialWithKey =
+                JsonCodec::from_slice(&user.credential_with_key)?;
+            let (column, account) = self
+                .store_private_key(
+                    credential_with_key.signature_key.as_slice(),
+                    &user.signature_private_key,
+                )
+                .await?;
+            if account.is_none() {
+                break;
+            }
+            query!(
+                "UPDATE client_user SET signature_private_key = ?, keychain_account = ?
+                WHERE username = ?",
+                column,
+                account,
+                user.username,
+            )
+            .execute(&mut self.connection)
+            .await?;
+            info!(username = user.username, "Moved signature key to keychain");
+        }
+        Ok(())
+    }
+
+    /// Keychain account of the current signature key of the user, if any.
+    pub(crate) async fn keychain_account(
+        &mut self,
+        username: &str,
+    ) -> anyhow::Result<Option<String>> {
+        let account = query!(
+            "SELECT keychain_account FROM client_user WHERE username = ?",
+            username
+        )
+        .fetch_optional(&mut self.connection)
+        .await?
+        .and_then(|row| row.keychain_account);
+        Ok(account)
+    }
+}
+
+/// The platform keychain. Its calls block, so they run on the blocking pool.
+#[cfg(feature = "keychain")]
+mod os {
+    use anyhow::Context;
+    use keyring::Entry;
+
+    use super::SERVICE;
+
+    pub(super) async fn set(account: String, secret: Vec<u8>) -> anyhow::Result<()> {
+        let result =
+            tokio::task::spawn_blocking(move || Entry::new(SERVICE, &account)?.set_secret(&secret))
+                .await?;
+        Ok(result?)
+    }
+
+    pub(super) async fn get(account: String) -> anyhow::Result<Vec<u8>> {
+        tokio::task::spawn_blocking(move || Entry::new(SERVICE, &account)?.get_secret())
+            .await?
+            .context("Failed to read signature key from keychain")
+    }
+
+    pub(super) async fn delete(account: String) -> anyhow::Result<()> {
+        let result =
+            tokio::task::spawn_blocking(move || Entry::new(SERVICE, &account)?.delete_credential())
+                .await?;
+        Ok(result?)
+    }
+}
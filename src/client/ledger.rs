@@ -0,0 +1,78 @@
+use std::time::Duration;
+
+use openmls_rust_crypto::RustCrypto;
+use openmls_traits::{crypto::OpenMlsCrypto, types::HashType};
+use sqlx::{
+    query, query_scalar,
+    types::chrono::{DateTime, Utc},
+};
+
+use crate::{client::Client, grpc::ReceiveMessagesResponse};
+
+/// Key of a received message in the ledger of processed messages: its id on
+/// the server, or the SHA-256 hash of its content from servers that don't
+/// send ids and for imported message files.
+pub(crate) fn message_key(message: &ReceiveMessagesResponse) -> Vec<u8> {
+    if message.message_id.is_empty() {
+        content_key(&message.content)
+    } else {
+        message.message_id.clone()
+    }
+}
+
+pub(crate) fn content_key(content: &[u8]) -> Vec<u8> {
+    RustCrypto::default()
+        .hash(HashType::Sha2_256, content)
+        .expect("SHA-256 is supported")
+}
+
+impl Client {
+    /// Whether the message with `key` was processed already, e.g. before a
+    /// crash kept it from being acknowledged and the server delivered it
+    /// again.
+    pub(crate) async fn is_processed(&mut self, user: &str, key: &[u8]) -> anyhow::Result<bool> {
+        let processed = query_scalar!(
+            "SELECT EXISTS (
+                SELECT 1 FROM client_processed_message WHERE username = ? AND message_key = ?
+            ) AS \"processed!: bool\"",
+            user,
+            key,
+        )
+        .fetch_one(&mut self.connection)
+        .await?;
+        Ok(processed)
+    }
+
+    /// Records a message as processed, in the transaction that applied it.
+    pub(crate) async fn record_processed(&mut self, user: &str, key: &[u8]) -> anyhow::Result<()> {
+        let processed_at: DateTime<Utc> = Utc::now();
+        query!(
+            "INSERT OR IGNORE INTO client_processed_message (username, message_key, processed_at)
+            VALUES (?, ?, ?)",
+            user,
+            key,
+            processed_at,
+        )
+        .execute(&mut self.connection)
+        .await?;
+        Ok(())
+    }
+
+    /// Forgets the messages processed more than `horizon` ago, which the
+    /// server won't deliver again. Returns how many.
+    pub(crate) async fn prune_processed_messages(
+        &mut self,
+        user: &str,
+        horizon: Duration,
+    ) -> anyhow::Result<u64> {
+        let processed_before = Utc::now() - horizon;
+        let result = query!(
+            "DELETE FROM client_processed_message WHERE username = ? AND processed_at < ?",
+            user,
+            processed_before,
+        )
+        .execute(&mut self.connection)
+        .await?;
+        Ok(result.rows_affected())
+    }
+}
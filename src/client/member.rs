@@ -1,144 +1,491 @@
-use anyhow::{Context, ensure};
+use std::collections::HashSet;
+
+use anyhow::Context;
 use openmls::{
     group::{GroupId, MlsGroup},
-    prelude::{BasicCredential, DeserializeBytes, KeyPackageIn, tls_codec::Serialize},
+    prelude::{
+        Ciphersuite, CredentialType, DeserializeBytes, HashType, KeyPackage, KeyPackageIn,
+        LeafNodeIndex, MlsMessageOut, OpenMlsCrypto, tls_codec::Serialize,
+    },
 };
+use openmls_rust_crypto::RustCrypto;
 use openmls_traits::OpenMlsProvider;
+use tracing::info;
 use uuid::Uuid;
 
 use crate::{
-    client::Client,
-    grpc::{FetchKeyPackageRequest, SendMessageRequest},
+    client::{
+        Client, Result,
+        error::{ensure, not_found},
+        message::member_identities,
+        policy::check_capabilities,
+        rebase::rebasing,
+        roles::check_pending_commit,
+        validator::key_package_credential,
+    },
+    grpc::{
+        DeviceAddress, FetchDeviceCertificatesRequest, FetchKeyPackageRequest, ListDevicesRequest,
+        SendMessageRequest,
+    },
     provider::PROTOCOL_VERSION,
 };
 
+/// A leaf of a group's ratchet tree.
+#[derive(Debug)]
+pub struct MemberInfo {
+    pub leaf_index: u32,
+    pub identity: String,
+    pub credential_type: CredentialType,
+    pub signature_key: Vec<u8>,
+    /// Whether this is the leaf of the current device.
+    pub own: bool,
+}
+
 impl Client {
-    pub async fn add_member(
+    /// Adds all devices of `new_members` with a single commit.
+    pub async fn add_members(
         &mut self,
         username: String,
         group_uuid: Uuid,
-        new_member: String,
-    ) -> anyhow::Result<()> {
-        let (signing_private_key, _credential_with_key) = self.credential(&username).await?;
+        mut new_members: Vec<String>,
+    ) -> Result<()> {
+        ensure!(!new_members.is_empty(), "No members to add");
+        // Each one gets a key package per device, which can't be added twice.
+        let mut seen = HashSet::new();
+        new_members.retain(|new_member| seen.insert(new_member.clone()));
+        rebasing!(self, &username, |client| client.add_members_once(
+            &username,
+            group_uuid,
+            &new_members
+        ))?;
+        self.publish_group_info(&username, group_uuid).await?;
 
-        let response = self
-            .client
-            .fetch_key_package(FetchKeyPackageRequest {
-                client_id: new_member.clone(),
-            })
-            .await?
-            .into_inner();
-
-        let provider = self.provider();
+        Ok(())
+    }
 
-        let key_package_bytes = response
-            .key_package
-            .context("Missing key package")?
-            .key_package_bytes;
-        let key_package = KeyPackageIn::tls_deserialize_exact_bytes(&key_package_bytes)?;
-        let key_package = key_package.validate(provider.crypto(), PROTOCOL_VERSION)?;
+    async fn add_members_once(
+        &mut self,
+        username: &str,
+        group_uuid: Uuid,
+        new_members: &[String],
+    ) -> anyhow::Result<()> {
+        let (signing_private_key, _credential_with_key) = self.credential(username).await?;
 
         let group_id = GroupId::from_slice(group_uuid.as_bytes());
-        let mut group =
-            MlsGroup::load(provider.storage(), &group_id)?.context("Group not found")?;
+        let group = MlsGroup::load(self.provider().storage(), &group_id)?
+            .ok_or_else(|| not_found("Group"))?;
+        let ciphersuite = group.ciphersuite();
+        let members = member_identities(&group);
+        for new_member in new_members {
+            ensure!(
+                !members.contains(new_member),
+                "Member {new_member} already exists"
+            );
+        }
 
-        let members: Vec<String> = group
-            .members()
-            .filter_map(|member| {
-                let credential = BasicCredential::try_from(member.credential).ok()?;
-                let member = str::from_utf8(credential.identity()).ok()?;
-                if member != username {
-                    Some(member.to_string())
-                } else {
-                    None
-                }
-            })
-            .collect();
+        let mut welcome_recipients = Vec::new();
+        let mut key_packages: Vec<KeyPackage> = Vec::new();
+        for new_member in new_members {
+            for (device_id, key_package) in self
+                .fetch_key_packages(new_member, Some(ciphersuite))
+                .await?
+            {
+                check_capabilities(&group, new_member, &key_package)?;
+                self.validate_credentials(&[key_package_credential(group_uuid, &key_package)?])
+                    .await?;
+                let signature_key = key_package.leaf_node().signature_key().as_slice();
+                ensure!(
+                    self.pin_key(username, new_member, signature_key).await?,
+                    "Signature key of {new_member} changed; verify it and run `trust`"
+                );
+                welcome_recipients.push(DeviceAddress {
+                    client_id: new_member.clone(),
+                    device_id,
+                });
+                key_packages.push(key_package);
+            }
+        }
 
-        ensure!(!members.contains(&new_member), "Member already exists");
+        let provider = self.provider();
+        let mut group =
+            MlsGroup::load(provider.storage(), &group_id)?.ok_or_else(|| not_found("Group"))?;
 
         let (commit, welcome, _group_info) =
-            group.add_members(&provider, &signing_private_key, &[key_package])?;
-
-        group.merge_pending_commit(&provider)?;
+            group.add_members(&provider, &signing_private_key, &key_packages)?;
+        check_pending_commit(&mut group, &provider)?;
 
-        if !members.is_empty() {
-            self.client
-                .send_message(SendMessageRequest {
-                    sender: username.clone(),
-                    recipients: members,
-                    content: commit.tls_serialize_detached()?,
-                })
-                .await?;
-        }
+        self.send_commit(username, group_uuid, members, &commit)
+            .await?;
 
+        let sender_device_id = self.device_id(username).await?;
         self.client
             .send_message(SendMessageRequest {
-                sender: username.clone(),
-                recipients: vec![new_member],
+                sender: username.to_string(),
+                recipients: Vec::new(),
                 content: welcome.tls_serialize_detached()?,
+                device_recipients: welcome_recipients,
+                sender_device_id,
             })
             .await?;
 
         Ok(())
     }
 
-    pub async fn remove_member(
+    /// Fetches one key package for every device of `client_id`, paired with the
+    /// device id it belongs to. Only key packages of `ciphersuite` are
+    /// considered, if given.
+    pub(crate) async fn fetch_key_packages(
+        &mut self,
+        client_id: &str,
+        ciphersuite: Option<Ciphersuite>,
+    ) -> anyhow::Result<Vec<(String, KeyPackage)>> {
+        let mut device_ids = self
+            .client
+            .list_devices(ListDevicesRequest {
+                client_id: client_id.to_string(),
+            })
+            .await?
+            .device_ids;
+        if device_ids.is_empty() {
+            device_ids.push(String::new());
+        }
+
+        let mut key_packages = Vec::with_capacity(device_ids.len());
+        for device_id in device_ids {
+            let response = self
+                .client
+                .fetch_key_package(FetchKeyPackageRequest {
+                    client_id: client_id.to_string(),
+                    device_id,
+                    ciphersuite: ciphersuite.map_or(0, |ciphersuite| u16::from(ciphersuite).into()),
+                })
+                .await?;
+
+            let key_package_bytes = response
+                .key_package
+                .context("Missing key package")?
+                .key_package_bytes;
+            let key_package = KeyPackageIn::tls_deserialize_exact_bytes(&key_package_bytes)?;
+            let key_package = key_package.validate(self.provider().crypto(), PROTOCOL_VERSION)?;
+            key_packages.push((response.device_id, key_package));
+        }
+
+        Ok(key_packages)
+    }
+
+    /// Removes all devices of `remove_members` with a single commit.
+    pub async fn remove_members(
         &mut self,
         sender: String,
         group_uuid: Uuid,
-        remove_member: String,
+        remove_members: Vec<String>,
+    ) -> Result<()> {
+        ensure!(!remove_members.is_empty(), "No members to remove");
+        rebasing!(self, &sender, |client| client.remove_members_once(
+            &sender,
+            group_uuid,
+            &remove_members
+        ))?;
+        self.publish_group_info(&sender, group_uuid).await?;
+
+        Ok(())
+    }
+
+    async fn remove_members_once(
+        &mut self,
+        sender: &str,
+        group_uuid: Uuid,
+        remove_members: &[String],
     ) -> anyhow::Result<()> {
-        let (signing_private_key, _credential_with_key) = self.credential(&sender).await?;
+        let (signing_private_key, _credential_with_key) = self.credential(sender).await?;
 
         let provider = self.provider();
 
         let group_id = GroupId::from_slice(group_uuid.as_bytes());
         let mut group =
-            MlsGroup::load(provider.storage(), &group_id)?.context("Group not found")?;
+            MlsGroup::load(provider.storage(), &group_id)?.ok_or_else(|| not_found("Group"))?;
 
-        let leaf_index = group
-            .members()
-            .find_map(|member| {
-                let credential = BasicCredential::try_from(member.credential).ok()?;
-                let user = str::from_utf8(credential.identity()).ok()?;
-                if user == remove_member {
-                    Some(member.index)
-                } else {
-                    None
-                }
-            })
-            .context("Member not found")?;
+        let mut leaf_indices = Vec::new();
+        for remove_member in remove_members {
+            let leaves: Vec<LeafNodeIndex> = group
+                .members()
+                .filter(|member| member.credential.serialized_content() == remove_member.as_bytes())
+                .map(|member| member.index)
+                .collect();
+            if leaves.is_empty() {
+                return Err(not_found(format!("Member {remove_member}")).into());
+            }
+            leaf_indices.extend(leaves);
+        }
+
+        // Determined before merging, so that removed members see the commit.
+        let recipients = member_identities(&group);
 
         let (commit, welcome, _) =
-            group.remove_members(&provider, &signing_private_key, &[leaf_index])?;
+            group.remove_members(&provider, &signing_private_key, &leaf_indices)?;
         ensure!(welcome.is_none(), "Nobody should be added to the group");
+        check_pending_commit(&mut group, &provider)?;
 
-        group.merge_pending_commit(&provider)?;
+        self.send_commit(sender, group_uuid, recipients, &commit)
+            .await
+    }
 
-        let recipients: Vec<String> = group
+    /// Proposes adding all devices of `new_member` without committing. The
+    /// proposals take effect with the next commit of any member, see
+    /// [`Client::commit_pending`].
+    pub async fn propose_add_member(
+        &mut self,
+        username: String,
+        group_uuid: Uuid,
+        new_member: String,
+    ) -> Result<()> {
+        rebasing!(self, &username, |client| client.propose_add_member_once(
+            &username,
+            group_uuid,
+            &new_member
+        ))?;
+        Ok(())
+    }
+
+    async fn propose_add_member_once(
+        &mut self,
+        username: &str,
+        group_uuid: Uuid,
+        new_member: &str,
+    ) -> anyhow::Result<()> {
+        let (signing_private_key, _credential_with_key) = self.credential(username).await?;
+
+        let group_id = GroupId::from_slice(group_uuid.as_bytes());
+        let group = MlsGroup::load(self.provider().storage(), &group_id)?
+            .ok_or_else(|| not_found("Group"))?;
+        let key_packages = self
+            .fetch_key_packages(new_member, Some(group.ciphersuite()))
+            .await?;
+        for (_device_id, key_package) in &key_packages {
+            check_capabilities(&group, new_member, key_package)?;
+            self.validate_credentials(&[key_package_credential(group_uuid, key_package)?])
+                .await?;
+            let signature_key = key_package.leaf_node().signature_key().as_slice();
+            ensure!(
+                self.pin_key(username, new_member, signature_key).await?,
+                "Signature key of {new_member} changed; verify it and run `trust`"
+            );
+        }
+
+        let provider = self.provider();
+        let mut group =
+            MlsGroup::load(provider.storage(), &group_id)?.ok_or_else(|| not_found("Group"))?;
+        let recipients = member_identities(&group);
+        ensure!(
+            !recipients.iter().any(|member| member == new_member),
+            "Member already exists"
+        );
+
+        let mut proposals = Vec::with_capacity(key_packages.len());
+        for (_device_id, key_package) in &key_packages {
+            let (proposal, _proposal_ref) =
+                group.propose_add_member(&provider, &signing_private_key, key_package)?;
+            proposals.push(proposal.tls_serialize_detached()?);
+        }
+
+        for proposal in proposals {
+            self.fanout(username, recipients.clone(), proposal).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Proposes removing all devices of `member` without committing.
+    pub async fn propose_remove_member(
+        &mut self,
+        username: String,
+        group_uuid: Uuid,
+        member: String,
+    ) -> Result<()> {
+        rebasing!(self, &username, |client| client
+            .propose_remove_member_once(&username, group_uuid, &member))?;
+        Ok(())
+    }
+
+    async fn propose_remove_member_once(
+        &mut self,
+        username: &str,
+        group_uuid: Uuid,
+        member: &str,
+    ) -> anyhow::Result<()> {
+        let (signing_private_key, _credential_with_key) = self.credential(username).await?;
+
+        let provider = self.provider();
+        let group_id = GroupId::from_slice(group_uuid.as_bytes());
+        let mut group =
+            MlsGroup::load(provider.storage(), &group_id)?.ok_or_else(|| not_found("Group"))?;
+
+        let leaf_indices: Vec<LeafNodeIndex> = group
             .members()
-            .filter_map(|member| {
-                let credential = BasicCredential::try_from(member.credential).ok()?;
-                let user = str::from_utf8(credential.identity()).ok()?;
-                if user != sender {
-                    Some(user.to_string())
-                } else {
-                    None
-                }
-            })
+            .filter(|leaf| leaf.credential.serialized_content() == member.as_bytes())
+            .map(|leaf| leaf.index)
             .collect();
+        if leaf_indices.is_empty() {
+            return Err(not_found("Member").into());
+        }
+
+        let mut proposals = Vec::with_capacity(leaf_indices.len());
+        for leaf_index in leaf_indices {
+            let (proposal, _proposal_ref) =
+                group.propose_remove_member(&provider, &signing_private_key, leaf_index)?;
+            proposals.push(proposal.tls_serialize_detached()?);
+        }
+
+        let recipients = member_identities(&group);
+        for proposal in proposals {
+            self.fanout(username, recipients.clone(), proposal).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Commits all pending proposals of the group, both the own ones and those
+    /// received from other members, and welcomes the added devices.
+    pub async fn commit_pending(&mut self, username: String, group_uuid: Uuid) -> Result<()> {
+        rebasing!(self, &username, |client| client
+            .commit_pending_once(&username, group_uuid))?;
+        self.publish_group_info(&username, group_uuid).await?;
 
-        if !recipients.is_empty() {
-            self.client
-                .send_message(SendMessageRequest {
-                    sender: sender.clone(),
-                    recipients,
-                    content: commit.tls_serialize_detached()?,
+        info!(%group_uuid, "Committed pending proposals");
+
+        Ok(())
+    }
+
+    async fn commit_pending_once(
+        &mut self,
+        username: &str,
+        group_uuid: Uuid,
+    ) -> anyhow::Result<()> {
+        let (signing_private_key, _credential_with_key) = self.credential(username).await?;
+
+        let provider = self.provider();
+        let group_id = GroupId::from_slice(group_uuid.as_bytes());
+        let mut group =
+            MlsGroup::load(provider.storage(), &group_id)?.ok_or_else(|| not_found("Group"))?;
+        ensure!(
+            group.pending_proposals().next().is_some(),
+            "No pending proposals"
+        );
+
+        // Determined before merging, so that removed members see the commit.
+        let recipients = member_identities(&group);
+
+        let (commit, welcome, _group_info) =
+            group.commit_to_pending_proposals(&provider, &signing_private_key)?;
+        check_pending_commit(&mut group, &provider)?;
+        let added = pending_additions(&group);
+
+        self.send_commit(username, group_uuid, recipients, &commit)
+            .await?;
+        if let Some(welcome) = welcome {
+            self.send_welcome(username, welcome, added).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Sends `welcome` to the devices that own the given signature keys.
+    pub(crate) async fn send_welcome(
+        &mut self,
+        sender: &str,
+        welcome: MlsMessageOut,
+        added: Vec<(String, Vec<u8>)>,
+    ) -> anyhow::Result<()> {
+        let mut device_recipients = Vec::with_capacity(added.len());
+        for (client_id, signature_key) in added {
+            // Linked devices have a certificate for their key; the primary
+            // device has none.
+            let device_id = self
+                .client
+                .fetch_device_certificates(FetchDeviceCertificatesRequest {
+                    client_id: client_id.clone(),
                 })
-                .await?;
+                .await?
+                .certificates
+                .into_iter()
+                .find(|certificate| certificate.signature_key == signature_key)
+                .map(|certificate| certificate.device_id)
+                .unwrap_or_default();
+            device_recipients.push(DeviceAddress {
+                client_id,
+                device_id,
+            });
         }
 
+        let sender_device_id = self.device_id(sender).await?;
+        self.client
+            .send_message(SendMessageRequest {
+                sender: sender.to_string(),
+                recipients: Vec::new(),
+                content: welcome.tls_serialize_detached()?,
+                device_recipients,
+                sender_device_id,
+            })
+            .await?;
         Ok(())
     }
+
+    /// Members of the group as found in the local copy of the ratchet tree.
+    pub async fn list_members(
+        &mut self,
+        user: String,
+        group_uuid: Uuid,
+    ) -> Result<Vec<MemberInfo>> {
+        if !self.group_ids(&user).await?.contains(&group_uuid) {
+            return Err(not_found("Group"));
+        }
+
+        let provider = self.provider();
+        let group_id = GroupId::from_slice(group_uuid.as_bytes());
+        let group =
+            MlsGroup::load(provider.storage(), &group_id)?.ok_or_else(|| not_found("Group"))?;
+        let own_leaf_index = group.own_leaf_index();
+
+        let members = group
+            .members()
+            .map(|member| MemberInfo {
+                leaf_index: member.index.u32(),
+                identity: String::from_utf8_lossy(member.credential.serialized_content())
+                    .into_owned(),
+                credential_type: member.credential.credential_type(),
+                signature_key: member.signature_key,
+                own: member.index == own_leaf_index,
+            })
+            .collect();
+        Ok(members)
+    }
+}
+
+/// Identities and signature keys of the members added by the pending commit.
+pub(crate) fn pending_additions(group: &MlsGroup) -> Vec<(String, Vec<u8>)> {
+    group
+        .pending_commit()
+        .into_iter()
+        .flat_map(|commit| commit.add_proposals())
+        .map(|proposal| {
+            let leaf_node = proposal.add_proposal().key_package().leaf_node();
+            (
+                String::from_utf8_lossy(leaf_node.credential().serialized_content()).into_owned(),
+                leaf_node.signature_key().as_slice().to_vec(),
+            )
+        })
+        .collect()
+}
+
+/// Short, human comparable fingerprint of a signature public key: the first 16
+/// bytes of its SHA-256 hash in colon separated hex.
+pub fn fingerprint(signature_key: &[u8]) -> String {
+    let hash = RustCrypto::default()
+        .hash(HashType::Sha2_256, signature_key)
+        .expect("SHA-256 is supported");
+    hash[..16]
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect::<Vec<_>>()
+        .join(":")
 }
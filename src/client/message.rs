@@ -1,138 +1,1026 @@
-use anyhow::{Context, bail};
+use anyhow::Context;
 use openmls::{
-    group::{GroupId, MlsGroup, MlsGroupJoinConfig, StagedWelcome},
+    group::{GroupId, MlsGroup, ProcessMessageError, ValidationError},
     prelude::{
-        BasicCredential, DeserializeBytes, MlsMessageBodyIn, MlsMessageIn, ProcessedMessageContent,
-        ProtocolMessage, Sender, tls_codec::Serialize,
+        BasicCredential, Credential, DeserializeBytes, MlsMessageBodyIn, MlsMessageIn,
+        ProcessedMessageContent, Proposal, ProtocolMessage, Sender, tls_codec::Serialize,
     },
 };
 use openmls_traits::OpenMlsProvider;
-use tracing::{info, warn};
+use prost::Message;
+use sqlx::{
+    TransactionManager, query,
+    sqlite::SqliteTransactionManager,
+    types::chrono::{DateTime, Utc},
+};
+use tracing::{debug, info, warn};
 use uuid::Uuid;
 
 use crate::{
-    client::Client,
-    grpc::{ReceiveMessagesRequest, SendMessageRequest},
+    client::{
+        Client, Result,
+        attachments::attachment_name,
+        context::{timer_expiry, validate_change, validate_commit_change},
+        delivery::MessageStream,
+        envelope,
+        error::{bail, ensure, not_found},
+        events::ChatEvent,
+        group::group_metadata,
+        history::{Direction, NewMessage},
+        mimi::MimiContent,
+        outbox::is_unsent,
+        rebase::rebasing,
+        roles::{check_commit, group_roles, is_admin, may_send},
+        validator::validate_commit,
+    },
+    grpc::{
+        ContentType, Envelope, GetQueueStatusRequest, GetQueueStatusResponse,
+        ReceiveMessagesRequest, SendMessageRequest, SendMessageResponse,
+    },
 };
 
+/// Authenticated data marking an application message as bounce of a rejected
+/// message.
+const BOUNCE_AAD: &[u8] = b"bounce";
+
+/// An outgoing message added to the history, see [`Client::send`].
+#[derive(Debug, Clone)]
+pub struct SentMessage {
+    /// Id of the message in the history.
+    pub message_id: i64,
+    /// Id of the message on the server. Missing while the message is queued
+    /// and from servers that don't assign ids.
+    pub server_id: Option<Uuid>,
+    /// When the server accepted the message, missing while it is queued.
+    pub server_timestamp: Option<DateTime<Utc>>,
+}
+
+/// How an application message was handed to the server.
+pub(crate) struct Dispatched {
+    /// Epoch the message was sent in.
+    pub epoch: u64,
+    pub dispatch: Dispatch,
+}
+
+/// An application message encrypted for the current epoch of its group.
+pub(crate) struct Encrypted {
+    pub epoch: u64,
+    /// Identities of the members, whose devices receive the message.
+    pub recipients: Vec<String>,
+    pub content: Vec<u8>,
+}
+
+pub(crate) enum Dispatch {
+    Sent(SendMessageResponse),
+    /// Queued in the outbox under this id, as the server couldn't be
+    /// reached or earlier messages of the group wait there.
+    Queued(i64),
+}
+
+/// A message to send in reaction to a received one, once the transaction
+/// handling it is committed, see [`Client::handle_message`].
+pub(crate) enum Deferred {
+    /// Commit the pending removals of the group.
+    Removals(Uuid),
+    /// Tell `sender` that its message `text` was rejected.
+    Bounce {
+        group_uuid: Uuid,
+        sender: String,
+        text: String,
+    },
+}
+
 impl Client {
+    /// Sends a text message to the group. The server's timestamp and id of the
+    /// message are returned unless it had to be queued, see
+    /// [`Client::flush_outbox`].
     pub async fn send(
         &mut self,
         user: String,
         group_uuid: Uuid,
         message: String,
-    ) -> anyhow::Result<()> {
-        let (signing_private_key, _credential_with_key) = self.credential(&user).await?;
+    ) -> Result<SentMessage> {
+        Ok(self.send_text(user, group_uuid, message, None).await?)
+    }
+
+    /// Sends `message` as reply to the message `parent` of the history.
+    pub async fn reply(
+        &mut self,
+        user: String,
+        group_uuid: Uuid,
+        parent: i64,
+        message: String,
+    ) -> Result<SentMessage> {
+        let parent = query!(
+            "SELECT message_uuid AS \"message_uuid: Uuid\", mimi_id FROM client_message
+            WHERE message_id = ? AND group_id = ? AND username = ?",
+            parent,
+            group_uuid,
+            user,
+        )
+        .fetch_optional(&mut self.connection)
+        .await?
+        .ok_or_else(|| not_found("Message"))?;
+        let parent_uuid = parent
+            .message_uuid
+            .context("Message predates message ids and can't be replied to")?;
+        Ok(self
+            .send_text(
+                user,
+                group_uuid,
+                message,
+                Some((parent_uuid, parent.mimi_id)),
+            )
+            .await?)
+    }
 
+    /// Sends a text message, in the MIMI content format if enabled unless it
+    /// replies to a message that isn't.
+    async fn send_text(
+        &mut self,
+        user: String,
+        group_uuid: Uuid,
+        message: String,
+        reply_to: Option<(Uuid, Option<Vec<u8>>)>,
+    ) -> anyhow::Result<SentMessage> {
         let group_id = GroupId::from_slice(group_uuid.as_bytes());
+        let group = MlsGroup::load(self.provider().storage(), &group_id)?
+            .ok_or_else(|| not_found("Group"))?;
+        let mentions = envelope::parse_mentions(&message, &member_identities(&group));
+        let reply_to = match reply_to {
+            Some((parent_uuid, Some(mimi_id))) if self.mimi_content => {
+                return self
+                    .send_mimi_text(
+                        &user,
+                        group_uuid,
+                        &message,
+                        &mentions,
+                        Some((parent_uuid, mimi_id)),
+                    )
+                    .await;
+            }
+            None if self.mimi_content => {
+                return self
+                    .send_mimi_text(&user, group_uuid, &message, &mentions, None)
+                    .await;
+            }
+            reply_to => reply_to.map(|(parent_uuid, _)| parent_uuid),
+        };
+
+        let expires_at = timer_expiry(&group, Utc::now());
+        let message_uuid = Uuid::new_v4();
+        let mut envelope = envelope::new(
+            ContentType::Text,
+            message_uuid,
+            message.clone().into_bytes(),
+        );
+        envelope.mentions = mentions.clone();
+        if let Some(reply_to) = reply_to {
+            envelope.reply_to = reply_to.as_bytes().to_vec();
+        }
+        let dispatched = self.send_envelope(&user, group_uuid, envelope).await?;
+        let message_id = self
+            .store_message(
+                &user,
+                group_uuid,
+                NewMessage {
+                    sender: &user,
+                    epoch: dispatched.epoch,
+                    direction: Direction::Outgoing,
+                    uuid: Some(message_uuid),
+                    body: &message,
+                    mentions: &mentions,
+                    reply_to,
+                    mimi_id: None,
+                    expires_at,
+                },
+            )
+            .await?;
+        self.touch_group(&user, group_uuid).await?;
+
+        self.record_dispatch(message_id, dispatched.dispatch).await
+    }
 
+    /// Records in the history whether its outgoing message `message_id` was
+    /// sent or queued.
+    pub(crate) async fn record_dispatch(
+        &mut self,
+        message_id: i64,
+        dispatch: Dispatch,
+    ) -> anyhow::Result<SentMessage> {
+        match dispatch {
+            Dispatch::Sent(response) => {
+                let server_timestamp = DateTime::from_timestamp_millis(response.timestamp);
+                query!(
+                    "UPDATE client_message SET send_status = 'sent', sent_at = ?
+                    WHERE message_id = ?",
+                    server_timestamp,
+                    message_id,
+                )
+                .execute(&mut self.connection)
+                .await?;
+                Ok(SentMessage {
+                    message_id,
+                    server_id: Uuid::from_slice(&response.message_id).ok(),
+                    server_timestamp,
+                })
+            }
+            Dispatch::Queued(outbox_id) => {
+                query!(
+                    "UPDATE client_message SET send_status = 'queued' WHERE message_id = ?",
+                    message_id,
+                )
+                .execute(&mut self.connection)
+                .await?;
+                query!(
+                    "UPDATE client_outbox SET message_id = ? WHERE outbox_id = ?",
+                    message_id,
+                    outbox_id,
+                )
+                .execute(&mut self.connection)
+                .await?;
+                Ok(SentMessage {
+                    message_id,
+                    server_id: None,
+                    server_timestamp: None,
+                })
+            }
+        }
+    }
+
+    /// Sends an application message of `content_type` and returns the epoch
+    /// it was sent in.
+    pub(crate) async fn send_content(
+        &mut self,
+        user: &str,
+        group_uuid: Uuid,
+        content_type: ContentType,
+        message_uuid: Uuid,
+        body: Vec<u8>,
+    ) -> anyhow::Result<u64> {
+        let dispatched = self
+            .send_envelope(
+                user,
+                group_uuid,
+                envelope::new(content_type, message_uuid, body),
+            )
+            .await?;
+        Ok(dispatched.epoch)
+    }
+
+    /// Sends an application message.
+    pub(crate) async fn send_envelope(
+        &mut self,
+        user: &str,
+        group_uuid: Uuid,
+        envelope: Envelope,
+    ) -> anyhow::Result<Dispatched> {
+        self.send_payload(user, group_uuid, &envelope.encode_to_vec())
+            .await
+    }
+
+    /// Sends an encoded application message, or queues it if earlier
+    /// messages of the group are still queued or the server can't be reached.
+    pub(crate) async fn send_payload(
+        &mut self,
+        user: &str,
+        group_uuid: Uuid,
+        payload: &[u8],
+    ) -> anyhow::Result<Dispatched> {
+        rebasing!(self, user, |client| client
+            .send_once(user, group_uuid, payload))
+    }
+
+    async fn send_once(
+        &mut self,
+        user: &str,
+        group_uuid: Uuid,
+        payload: &[u8],
+    ) -> anyhow::Result<Dispatched> {
+        let Encrypted {
+            epoch,
+            recipients,
+            content,
+        } = self.encrypt_payload(user, group_uuid, payload).await?;
+        if self.has_queued(user, group_uuid).await? {
+            let outbox_id = self
+                .enqueue(user, group_uuid, &recipients, &content, payload)
+                .await?;
+            info!(%group_uuid, "Queued message behind earlier ones");
+            return Ok(Dispatched {
+                epoch,
+                dispatch: Dispatch::Queued(outbox_id),
+            });
+        }
+        let dispatch = match self.fanout(user, recipients.clone(), content.clone()).await {
+            Ok(response) => Dispatch::Sent(response),
+            Err(error) if is_unsent(&error) => {
+                warn!(%group_uuid, %error, "Server unreachable, queued message for retry");
+                let outbox_id = self
+                    .enqueue(user, group_uuid, &recipients, &content, payload)
+                    .await?;
+                Dispatch::Queued(outbox_id)
+            }
+            Err(error) => return Err(error),
+        };
+
+        Ok(Dispatched { epoch, dispatch })
+    }
+
+    /// Encrypts an encoded application message in the current epoch of the
+    /// group.
+    pub(crate) async fn encrypt_payload(
+        &mut self,
+        user: &str,
+        group_uuid: Uuid,
+        payload: &[u8],
+    ) -> anyhow::Result<Encrypted> {
+        let group_id = GroupId::from_slice(group_uuid.as_bytes());
+        let (mut group, writes) = self
+            .take_group(&group_id)
+            .await?
+            .ok_or_else(|| not_found("Group"))?;
+        ensure!(
+            may_send(&group, user),
+            "Only admins can send messages to this group"
+        );
+        self.ensure_trusted_members(user, &group).await?;
+        let signing_private_key = self.leaf_signer(user, &group).await?;
+
+        let provider = self.cached_provider();
+        let message = group.create_message(&provider, &signing_private_key, payload)?;
+
+        let epoch = group.epoch().as_u64();
+        let recipients = member_identities(&group);
+        self.cache_group(group, writes);
+        Ok(Encrypted {
+            epoch,
+            recipients,
+            content: message.tls_serialize_detached()?,
+        })
+    }
+
+    /// Tells `sender` that its message `text` was rejected because only admins
+    /// may send to the group. Sent within the group, but only to the sender.
+    async fn bounce(
+        &mut self,
+        user: &str,
+        group_uuid: Uuid,
+        sender: String,
+        text: &str,
+    ) -> anyhow::Result<()> {
+        let (signing_private_key, _credential_with_key) = self.credential(user).await?;
+
+        let group_id = GroupId::from_slice(group_uuid.as_bytes());
         let provider = self.provider();
         let mut group =
-            MlsGroup::load(provider.storage(), &group_id)?.context("Group not found")?;
-        let message = group.create_message(&provider, &signing_private_key, message.as_bytes())?;
-
-        let recipients = group
-            .members()
-            .filter_map(|member| {
-                let credential = BasicCredential::try_from(member.credential).ok()?;
-                let member = str::from_utf8(credential.identity()).ok()?;
-                if member != user {
-                    Some(member.to_string())
-                } else {
-                    None
-                }
-            })
-            .collect();
+            MlsGroup::load(provider.storage(), &group_id)?.ok_or_else(|| not_found("Group"))?;
+        group.set_aad(BOUNCE_AAD.to_vec());
+        let payload = envelope::new(ContentType::Text, Uuid::new_v4(), text.as_bytes().to_vec())
+            .encode_to_vec();
+        let message = group.create_message(&provider, &signing_private_key, &payload)?;
+
+        self.fanout(user, vec![sender], message.tls_serialize_detached()?)
+            .await?;
+        Ok(())
+    }
 
-        self.client
+    /// Sends `content` to all devices of `recipients`, except the sending device.
+    pub(crate) async fn fanout(
+        &mut self,
+        sender: &str,
+        recipients: Vec<String>,
+        content: Vec<u8>,
+    ) -> anyhow::Result<SendMessageResponse> {
+        let sender_device_id = self.device_id(sender).await?;
+        let response = self
+            .client
             .send_message(SendMessageRequest {
-                sender: user.clone(),
+                sender: sender.to_string(),
                 recipients,
-                content: message.tls_serialize_detached()?,
+                content,
+                device_recipients: Vec::new(),
+                sender_device_id,
             })
             .await?;
-
-        Ok(())
+        Ok(response)
     }
 
-    pub async fn receive(&mut self, user: String) -> anyhow::Result<()> {
-        let mut messages = self
+    pub async fn queue_status(&mut self, user: String) -> Result<GetQueueStatusResponse> {
+        let device_id = self.device_id(&user).await?;
+        let status = self
             .client
-            .receive_messages(ReceiveMessagesRequest { client_id: user })
-            .await?
-            .into_inner();
+            .get_queue_status(GetQueueStatusRequest {
+                client_id: user,
+                device_id,
+            })
+            .await?;
+        Ok(status)
+    }
 
-        while let Some(message) = messages.message().await? {
-            let message: MlsMessageIn =
-                MlsMessageIn::tls_deserialize_exact_bytes(&message.content)?;
+    /// Processes the messages of the user that were not delivered yet and
+    /// opens the stream of further ones.
+    pub(crate) async fn open_stream(&mut self, user: &str) -> anyhow::Result<MessageStream> {
+        self.client.negotiate().await?;
+        // Queued messages are handled in batches first, which also keeps the
+        // updates below from racing queued commits.
+        self.catch_up(user).await?;
+        self.accept_verified_invites(user).await?;
+        self.send_receipts(user).await?;
+        self.expire_messages(user).await?;
+        if let Some(max_age) = self.key_rotation {
+            self.rotate_stale_keys(user.to_string(), max_age).await?;
+        }
+        if let Some(horizon) = self.pruning_horizon {
+            self.prune_key_material(user.to_string(), horizon).await?;
+        }
 
-            let message = message.extract();
+        let device_id = self.device_id(user).await?;
+        let messages = self
+            .client
+            .receive_messages(ReceiveMessagesRequest {
+                client_id: user.to_string(),
+                device_id,
+            })
+            .await?;
+        Ok(messages)
+    }
 
-            info!(?message, "Incoming message");
+    /// Processes a single message received from the delivery service in a
+    /// transaction, so that a failure or crash on the way doesn't leave the
+    /// group state and the history half updated. Group state in memory
+    /// storage isn't rolled back.
+    ///
+    /// With the `key` of the message in the ledger, see
+    /// [`message_key`](crate::client::ledger::message_key), it is recorded
+    /// as processed in the same transaction, and skipped if it was already.
+    pub(crate) async fn handle_message(
+        &mut self,
+        user: &str,
+        key: Option<&[u8]>,
+        content: &[u8],
+    ) -> anyhow::Result<()> {
+        let events = self.events.len();
+        let deferred = self.deferred.len();
+        // A savepoint when nested, as handling a message may catch up first.
+        SqliteTransactionManager::begin(&mut self.connection, None).await?;
+        match self.process_message_once(user, key, content).await {
+            Ok(()) => {
+                SqliteTransactionManager::commit(&mut self.connection).await?;
+                if SqliteTransactionManager::get_transaction_depth(&self.connection) == 0 {
+                    self.send_deferred(user).await;
+                }
+                Ok(())
+            }
+            Err(error) => {
+                SqliteTransactionManager::rollback(&mut self.connection).await?;
+                self.events.truncate(events);
+                self.deferred.truncate(deferred);
+                self.group_cache.clear();
+                Err(error)
+            }
+        }
+    }
 
-            match message {
-                MlsMessageBodyIn::PublicMessage(message) => {
-                    self.handle_protocol_message(message)?;
+    /// Sends what handling messages left to send, now that their state is
+    /// committed. The messages stay processed if this fails: the removals
+    /// stay proposed for the next commit, bounces are lost.
+    pub(crate) async fn send_deferred(&mut self, user: &str) {
+        for deferred in std::mem::take(&mut self.deferred) {
+            match deferred {
+                Deferred::Removals(group_uuid) => {
+                    if let Err(error) = self.commit_pending_removals(user, group_uuid).await {
+                        warn!(%group_uuid, %error, "Failed to commit pending removals");
+                    }
                 }
-                MlsMessageBodyIn::PrivateMessage(message) => {
-                    self.handle_protocol_message(message)?;
+                Deferred::Bounce {
+                    group_uuid,
+                    sender,
+                    text,
+                } => {
+                    if let Err(error) = self.bounce(user, group_uuid, sender, &text).await {
+                        warn!(%group_uuid, %error, "Failed to bounce message");
+                    }
                 }
-                MlsMessageBodyIn::Welcome(welcome) => {
-                    let provider = self.provider();
-                    let group_config = MlsGroupJoinConfig::builder().build();
-                    let staged_welcome =
-                        StagedWelcome::new_from_welcome(&provider, &group_config, welcome, None)?;
-                    let group = staged_welcome.into_group(&provider)?;
-                    let group_id = Uuid::from_slice(group.group_id().as_slice())?;
-                    info!(%group_id, "Received welcome and joined group");
+            }
+        }
+    }
+
+    async fn process_message_once(
+        &mut self,
+        user: &str,
+        key: Option<&[u8]>,
+        content: &[u8],
+    ) -> anyhow::Result<()> {
+        let Some(key) = key else {
+            return self.process_message(user, content).await;
+        };
+        if self.is_processed(user, key).await? {
+            debug!("Skipping message that was processed already");
+            return Ok(());
+        }
+        self.process_message(user, content).await?;
+        self.record_processed(user, key).await
+    }
+
+    async fn process_message(&mut self, user: &str, content: &[u8]) -> anyhow::Result<()> {
+        let message: MlsMessageIn = MlsMessageIn::tls_deserialize_exact_bytes(content)?;
+
+        let message = message.extract();
+
+        info!(?message, "Incoming message");
+
+        match message {
+            MlsMessageBodyIn::PublicMessage(message) => {
+                self.handle_protocol_message(user, message, content).await?;
+            }
+            MlsMessageBodyIn::PrivateMessage(message) => {
+                self.handle_protocol_message(user, message, content).await?;
+            }
+            MlsMessageBodyIn::Welcome(welcome) => {
+                let Some(invite) = self.store_invite(user, welcome, content).await? else {
+                    return Ok(());
+                };
+                if self
+                    .predecessor_creator(user, invite.group_id)
+                    .await?
+                    .is_some()
+                {
+                    // Successor of a re-initialized group we are a member of.
+                    // Boxed, as accepting replays buffered messages through here.
+                    Box::pin(self.accept_invite(user.to_string(), invite.group_id)).await?;
+                    return Ok(());
                 }
-                MlsMessageBodyIn::GroupInfo(_) => bail!("GroupInfo not supported"),
-                MlsMessageBodyIn::KeyPackage(_) => bail!("KeyPackage not supported"),
+                info!(group_id = %invite.group_id, "Received invite");
+                self.emit(ChatEvent::WelcomeReceived {
+                    group_id: invite.group_id,
+                    name: invite.name,
+                    inviter: invite.inviter,
+                });
             }
+            MlsMessageBodyIn::GroupInfo(_) => bail!("GroupInfo not supported"),
+            MlsMessageBodyIn::KeyPackage(_) => bail!("KeyPackage not supported"),
         }
 
         Ok(())
     }
 
-    fn handle_protocol_message(
+    async fn handle_protocol_message(
         &mut self,
+        user: &str,
         message: impl Into<ProtocolMessage>,
+        content: &[u8],
     ) -> Result<(), anyhow::Error> {
         let message = message.into();
+        let group_uuid = Uuid::from_slice(message.group_id().as_slice())?;
 
-        let provider = self.provider();
-
-        let mut group =
-            MlsGroup::load(provider.storage(), message.group_id())?.context("Group not found")?;
-        let processed_message = group.process_message(&provider, message)?;
+        let Some((mut group, writes)) = self.take_group(message.group_id()).await? else {
+            if self
+                .buffer_invite_message(user, group_uuid, content)
+                .await?
+            {
+                return Ok(());
+            }
+            return Err(not_found("Group").into());
+        };
+        if !group.is_active() {
+            warn!(%group_uuid, "Dropping message for a group we were removed from");
+            return Ok(());
+        }
+        let epoch = message.epoch().as_u64();
+        if epoch > group.epoch().as_u64() {
+            self.buffer_pending_message(user, group_uuid, epoch, content)
+                .await?;
+            return Ok(());
+        }
+        // Own commits come back in the epoch they were merged from. Only
+        // application messages can still be decrypted in past epochs, as far
+        // as `GroupConfig::max_past_epochs` allows.
+        let past_epoch = epoch < group.epoch().as_u64();
+        if past_epoch && message.content_type() != openmls::prelude::ContentType::Application {
+            debug!(%group_uuid, epoch, "Skipping message of a past epoch");
+            return Ok(());
+        }
+        if let ProtocolMessage::PublicMessage(public_message) = &message
+            && *public_message.sender() == Sender::Member(group.own_leaf_index())
+        {
+            debug!(%group_uuid, "Skipping own message");
+            return Ok(());
+        }
+        // Taken before the provider borrows the client until the commit is
+        // merged.
+        let validators = self.credential_validators.clone();
+        let change_validators = self.change_validators.clone();
+        let accept_policy = self.accept_policy.clone();
+        let creator = if accept_policy.adds_from_admins_only {
+            self.group_creator(user, group_uuid).await?
+        } else {
+            None
+        };
+        let provider = self.cached_provider();
+        let processed_message = match group.process_message(&provider, message) {
+            Ok(processed_message) => processed_message,
+            Err(ProcessMessageError::ValidationError(ValidationError::CannotDecryptOwnMessage)) => {
+                debug!(%group_uuid, "Skipping own message");
+                return Ok(());
+            }
+            Err(error) if past_epoch => {
+                warn!(%group_uuid, epoch, %error, "Dropping message of a past epoch");
+                self.emit(ChatEvent::DecryptionFailed {
+                    group_id: Some(group_uuid),
+                    epoch: Some(epoch),
+                    error: error.to_string(),
+                    quarantined: None,
+                });
+                return Ok(());
+            }
+            Err(error) => return Err(error.into()),
+        };
 
         let sender = match processed_message.sender() {
             Sender::Member(leaf_index) => {
                 let member = group.member_at(*leaf_index).context("Member not found")?;
                 String::from_utf8_lossy(member.credential.serialized_content()).into_owned()
             }
+            Sender::NewMemberCommit => {
+                if !group_metadata(group.extensions()).is_some_and(|metadata| metadata.open) {
+                    warn!(%group_uuid, "Dropping external commit to a closed group");
+                    return Ok(());
+                }
+                String::from_utf8_lossy(processed_message.credential().serialized_content())
+                    .into_owned()
+            }
             _ => {
                 warn!("Received message from non-member");
                 return Ok(());
             }
         };
 
+        let external = matches!(processed_message.sender(), Sender::NewMemberCommit);
+        let bounce = processed_message.aad() == BOUNCE_AAD;
         match processed_message.into_content() {
             ProcessedMessageContent::ApplicationMessage(application_message) => {
-                let text = String::from_utf8_lossy(&application_message.into_bytes()).into_owned();
-                println!("{sender}: {text}");
+                let payload = application_message.into_bytes();
+                if let Some(content) = MimiContent::decode(&payload) {
+                    self.handle_mimi_message(user, &group, sender, epoch, &payload, content)
+                        .await?;
+                } else {
+                    let envelope = envelope::open(payload);
+                    self.handle_application_message(user, &group, sender, epoch, bounce, envelope)
+                        .await?;
+                }
             }
             ProcessedMessageContent::ProposalMessage(queued_proposal) => {
-                group.store_pending_proposal(provider.storage(), (*queued_proposal).clone())?
+                // Leaving members can't commit their own removal; other
+                // proposals wait for an explicit commit.
+                let self_remove = match (queued_proposal.proposal(), queued_proposal.sender()) {
+                    (Proposal::Remove(remove), Sender::Member(leaf_index)) => {
+                        remove.removed() == *leaf_index
+                    }
+                    _ => false,
+                };
+                if let Proposal::GroupContextExtensions(proposal) = queued_proposal.proposal()
+                    && let Err(error) = validate_change(
+                        &change_validators,
+                        group_uuid,
+                        &group,
+                        &sender,
+                        proposal.extensions(),
+                    )
+                    .await
+                {
+                    warn!(%group_uuid, sender, %error, "Dropping proposal");
+                    return Ok(());
+                }
+                group.store_pending_proposal(provider.storage(), (*queued_proposal).clone())?;
+                if self_remove {
+                    self.deferred.push(Deferred::Removals(group_uuid));
+                }
             }
             ProcessedMessageContent::ExternalJoinProposalMessage(queued_proposal) => {
                 group.store_pending_proposal(provider.storage(), (*queued_proposal).clone())?;
             }
             ProcessedMessageContent::StagedCommitMessage(staged_commit) => {
+                if let Err(error) = check_commit(&group, &sender, &staged_commit) {
+                    warn!(%group_uuid, %error, "Rejecting commit");
+                    return Ok(());
+                }
+                if let Err(error) = accept_policy.check_commit(
+                    &group,
+                    creator.as_deref(),
+                    &sender,
+                    &staged_commit,
+                    external,
+                ) {
+                    warn!(%group_uuid, %error, "Rejecting commit");
+                    return Ok(());
+                }
+                if let Err(error) =
+                    validate_commit(&validators, group_uuid, &staged_commit, external).await
+                {
+                    warn!(%group_uuid, %error, "Rejecting commit");
+                    return Ok(());
+                }
+                if let Err(error) = validate_commit_change(
+                    &change_validators,
+                    group_uuid,
+                    &group,
+                    &sender,
+                    &staged_commit,
+                )
+                .await
+                {
+                    warn!(%group_uuid, %error, "Rejecting commit");
+                    return Ok(());
+                }
+                let self_removed = staged_commit.self_removed();
+                let members_before = member_identities(&group);
+                let added: Vec<String> = staged_commit
+                    .add_proposals()
+                    .filter_map(|queued| {
+                        identity(queued.add_proposal().key_package().leaf_node().credential())
+                    })
+                    .collect();
+                let removed: Vec<(String, String)> = staged_commit
+                    .remove_proposals()
+                    .filter_map(|queued| {
+                        let member = group.member_at(queued.remove_proposal().removed())?;
+                        let removed_by = match queued.sender() {
+                            Sender::Member(leaf_index) => group
+                                .member_at(*leaf_index)
+                                .and_then(|member| identity(&member.credential)),
+                            _ => None,
+                        };
+                        Some((
+                            identity(&member.credential)?,
+                            removed_by.unwrap_or_else(|| sender.clone()),
+                        ))
+                    })
+                    .collect();
                 group.merge_staged_commit(&provider, *staged_commit)?;
+                let successor = group_metadata(group.extensions())
+                    .map(|metadata| metadata.successor)
+                    .filter(|successor| !successor.is_empty());
+                if self_removed {
+                    // Dropped so that the user can be welcomed to the group again.
+                    group.delete(provider.storage())?;
+                    self.mark_group_left(user, group_uuid).await?;
+                    self.drop_pending_messages(user, group_uuid).await?;
+                    info!(%group_uuid, "Removed from group");
+                } else if let Some(successor) = successor {
+                    let successor_uuid = Uuid::from_slice(&successor)?;
+                    self.mark_group_replaced(user, group_uuid, successor_uuid)
+                        .await?;
+                    info!(%group_uuid, %successor_uuid, "Group was re-initialized");
+                } else if external {
+                    self.emit(ChatEvent::MemberAdded {
+                        group_id: group_uuid,
+                        member: sender.clone(),
+                        added_by: sender.clone(),
+                    });
+                }
+                self.emit_membership_changes(
+                    group_uuid,
+                    &sender,
+                    &members_before,
+                    &member_identities(&group),
+                    added,
+                    removed,
+                );
+                if !self_removed {
+                    self.emit(ChatEvent::EpochChanged {
+                        group_id: group_uuid,
+                        epoch: group.epoch().as_u64(),
+                    });
+                    self.pin_member_keys(user, &group).await?;
+                    self.replay_pending_messages(user, group_uuid, group.epoch().as_u64())
+                        .await?;
+                }
             }
         }
+        if group.is_active() {
+            self.cache_group(group, writes);
+        }
 
         Ok(())
     }
+
+    async fn handle_application_message(
+        &mut self,
+        user: &str,
+        group: &MlsGroup,
+        sender: String,
+        epoch: u64,
+        bounce: bool,
+        envelope: Envelope,
+    ) -> anyhow::Result<()> {
+        let group_uuid = Uuid::from_slice(group.group_id().as_slice())?;
+        let content_type = ContentType::try_from(envelope.content_type);
+        if content_type == Ok(ContentType::Receipt) {
+            if let Err(error) = self
+                .handle_receipt(user, group_uuid, &sender, &envelope.body)
+                .await
+            {
+                warn!(%group_uuid, sender, %error, "Dropping invalid receipt");
+            }
+            return Ok(());
+        }
+        if !matches!(
+            content_type,
+            Ok(ContentType::Text
+                | ContentType::AttachmentPointer
+                | ContentType::Edit
+                | ContentType::Control
+                | ContentType::HistoryShare)
+        ) {
+            info!(
+                %group_uuid,
+                sender,
+                ?content_type,
+                client_version = envelope.client_version,
+                "Ignoring message of unsupported content type"
+            );
+            return Ok(());
+        }
+        if !may_send(group, &sender) {
+            warn!(%group_uuid, sender, "Dropping message to a read-only group");
+            if content_type == Ok(ContentType::Text) && self.sends_bounces(user, group).await? {
+                let text = String::from_utf8_lossy(&envelope.body).into_owned();
+                self.deferred.push(Deferred::Bounce {
+                    group_uuid,
+                    sender,
+                    text,
+                });
+            }
+            return Ok(());
+        }
+        if content_type == Ok(ContentType::Edit) {
+            if let Err(error) = self
+                .handle_edit(user, group_uuid, &sender, &envelope.body)
+                .await
+            {
+                warn!(%group_uuid, sender, %error, "Dropping invalid edit");
+            }
+            return Ok(());
+        }
+        if content_type == Ok(ContentType::Control) {
+            if let Err(error) = self
+                .handle_control(user, group, &sender, &envelope.body)
+                .await
+            {
+                warn!(%group_uuid, sender, %error, "Dropping invalid control message");
+            }
+            return Ok(());
+        }
+        if content_type == Ok(ContentType::HistoryShare) {
+            match self
+                .handle_history_share(user, group, &sender, &envelope.body)
+                .await
+            {
+                Ok(0) => {}
+                Ok(messages) => self.emit(ChatEvent::HistoryShared {
+                    group_id: group_uuid,
+                    sender,
+                    messages,
+                }),
+                Err(error) => warn!(%group_uuid, sender, %error, "Failed to import history"),
+            }
+            return Ok(());
+        }
+
+        let text = if content_type == Ok(ContentType::AttachmentPointer) {
+            match self.save_attachment(&envelope.body).await {
+                Ok(path) => format!("[file] {}", path.display()),
+                // The message is kept, only without the file, e.g. as the
+                // server no longer has it.
+                Err(error) => {
+                    warn!(%group_uuid, sender, %error, "Failed to download attachment");
+                    format!("[file unavailable] {}", attachment_name(&envelope.body))
+                }
+            }
+        } else {
+            String::from_utf8_lossy(&envelope.body).into_owned()
+        };
+        if bounce && is_admin(group, &sender) {
+            self.emit(ChatEvent::Bounced {
+                group_id: group_uuid,
+                text,
+            });
+            return Ok(());
+        }
+        self.add_incoming_message(
+            user,
+            group,
+            NewMessage {
+                sender: &sender,
+                epoch,
+                direction: Direction::Incoming,
+                uuid: envelope::message_id(&envelope),
+                body: &text,
+                mentions: &envelope.mentions,
+                reply_to: envelope::reply_to(&envelope),
+                mimi_id: None,
+                expires_at: timer_expiry(group, Utc::now()),
+            },
+        )
+        .await
+    }
+
+    /// Adds a received message to the history, notifies of it unless the
+    /// group is muted and emits it.
+    pub(crate) async fn add_incoming_message(
+        &mut self,
+        user: &str,
+        group: &MlsGroup,
+        message: NewMessage<'_>,
+    ) -> anyhow::Result<()> {
+        let group_uuid = Uuid::from_slice(group.group_id().as_slice())?;
+        #[cfg(feature = "notifications")]
+        if !self.is_muted(user, group_uuid).await? {
+            self.notify(group, message.sender, message.body);
+        }
+        let sender = message.sender.to_string();
+        let text = message.body.to_string();
+        let mentioned = message.mentions.iter().any(|mention| mention == user);
+        let message_id = self.store_message(user, group_uuid, message).await?;
+        self.touch_group(user, group_uuid).await?;
+        self.emit(ChatEvent::Message {
+            group_id: group_uuid,
+            message_id,
+            mentioned,
+            sender,
+            text,
+        });
+        Ok(())
+    }
+
+    /// Emits the members that a commit of `committer` added or removed. Added
+    /// and removed devices of members that stay don't count.
+    fn emit_membership_changes(
+        &mut self,
+        group_uuid: Uuid,
+        committer: &str,
+        members_before: &[String],
+        members_after: &[String],
+        mut added: Vec<String>,
+        mut removed: Vec<(String, String)>,
+    ) {
+        added.sort();
+        added.dedup();
+        for member in added {
+            if !members_before.contains(&member) {
+                self.emit(ChatEvent::MemberAdded {
+                    group_id: group_uuid,
+                    member,
+                    added_by: committer.to_string(),
+                });
+            }
+        }
+        removed.sort();
+        removed.dedup_by(|a, b| a.0 == b.0);
+        for (member, removed_by) in removed {
+            if !members_after.contains(&member) {
+                self.emit(ChatEvent::MemberRemoved {
+                    group_id: group_uuid,
+                    member,
+                    removed_by,
+                });
+            }
+        }
+    }
+
+    /// Whether this device answers rejected messages with a bounce. Only the
+    /// primary device of the first admin does, so that senders get one.
+    async fn sends_bounces(&mut self, user: &str, group: &MlsGroup) -> anyhow::Result<bool> {
+        let members = member_identities(group);
+        let first_admin = group_roles(group.extensions()).and_then(|roles| {
+            roles
+                .admins
+                .into_iter()
+                .find(|admin| members.contains(admin))
+        });
+        Ok(first_admin.as_deref() == Some(user) && self.device_id(user).await?.is_empty())
+    }
+}
+
+/// Identity of a basic credential.
+pub(crate) fn identity(credential: &Credential) -> Option<String> {
+    let credential = BasicCredential::try_from(credential.clone()).ok()?;
+    Some(String::from_utf8_lossy(credential.identity()).into_owned())
+}
+
+/// Identities of all members of the group. Includes the own user, so that the
+/// user's other devices receive everything sent to the group as well.
+pub(crate) fn member_identities(group: &MlsGroup) -> Vec<String> {
+    let mut identities: Vec<String> = group
+        .members()
+        .filter_map(|member| {
+            let credential = BasicCredential::try_from(member.credential).ok()?;
+            let identity = str::from_utf8(credential.identity()).ok()?;
+            Some(identity.to_string())
+        })
+        .collect();
+    identities.sort();
+    identities.dedup();
+    identities
 }
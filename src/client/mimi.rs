@@ -0,0 +1,591 @@
+//! Application messages in the MIMI content format
+//! (draft-ietf-mimi-content), for interoperability with other MLS-based
+//! messengers. Messages in the format are CBOR and sent as they are instead
+//! of in an [`Envelope`](crate::grpc::Envelope).
+//!
+//! Users are named by the URI `mimi://<domain>/u/<name>` of their identity
+//! `<name>@<domain>`, and groups by `mimi://<domain>/r/<group id>` with the
+//! domain of their creator. Identities without domain belong to `localhost`.
+
+use std::time::Duration;
+
+use anyhow::Context;
+use ciborium::Value;
+use openmls::{
+    group::{GroupId, MlsGroup},
+    prelude::{HashType, OpenMlsProvider},
+};
+use openmls_rust_crypto::RustCrypto;
+use openmls_traits::{crypto::OpenMlsCrypto, random::OpenMlsRand};
+use sqlx::{
+    query, query_scalar,
+    types::chrono::{DateTime, Utc},
+};
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+use crate::client::{
+    Client, Result,
+    context::timer_expiry,
+    envelope,
+    error::not_found,
+    events::ChatEvent,
+    history::{Direction, NewMessage},
+    message::{Dispatched, SentMessage, member_identities},
+    roles::may_send,
+};
+
+const SALT_LENGTH: usize = 16;
+/// First byte of message ids, for SHA-256 as their hash algorithm.
+const SHA256_MESSAGE_ID: u8 = 0x01;
+
+/// Disposition of a part, of those this client understands.
+const DISPOSITION_UNSPECIFIED: u64 = 0;
+const DISPOSITION_RENDER: u64 = 1;
+const DISPOSITION_REACTION: u64 = 2;
+
+/// Cardinality of a part.
+const NULL_PART: u64 = 0;
+const SINGLE_PART: u64 = 1;
+
+const TEXT_PLAIN: &str = "text/plain;charset=utf-8";
+
+/// The fields of MIMI content that this client reads or writes. Extensions are
+/// ignored.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct MimiContent {
+    pub salt: Vec<u8>,
+    /// Id of the message that this one edits, or deletes with a null part.
+    pub replaces: Option<Vec<u8>>,
+    pub topic_id: Vec<u8>,
+    pub expires: Option<Expiration>,
+    pub in_reply_to: Option<Vec<u8>>,
+    pub disposition: u64,
+    pub language: String,
+    pub part: Part,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Expiration {
+    /// Whether `time` is in seconds after receipt rather than since the Unix
+    /// epoch.
+    pub relative: bool,
+    pub time: u32,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) enum Part {
+    /// No content, e.g. of a deletion.
+    #[default]
+    Null,
+    Single {
+        content_type: String,
+        content: Vec<u8>,
+    },
+    /// External and multiple parts, which this client doesn't render.
+    Other,
+}
+
+impl MimiContent {
+    /// Content of a new message with a fresh salt.
+    fn new(disposition: u64, part: Part) -> anyhow::Result<Self> {
+        Ok(Self {
+            salt: RustCrypto::default().random_vec(SALT_LENGTH)?,
+            disposition,
+            part,
+            ..Self::default()
+        })
+    }
+
+    /// UTF-8 text of a single text part.
+    fn text(&self) -> Option<String> {
+        match &self.part {
+            Part::Single {
+                content_type,
+                content,
+            } if content_type.starts_with("text/plain")
+                || content_type.starts_with("text/markdown") =>
+            {
+                Some(String::from_utf8_lossy(content).into_owned())
+            }
+            _ => None,
+        }
+    }
+
+    fn expires_at(&self, received_at: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let expires = self.expires?;
+        if expires.relative {
+            Some(received_at + Duration::from_secs(expires.time.into()))
+        } else {
+            DateTime::from_timestamp(expires.time.into(), 0)
+        }
+    }
+
+    pub(crate) fn encode(&self) -> anyhow::Result<Vec<u8>> {
+        let optional_bytes = |bytes: &Option<Vec<u8>>| match bytes {
+            Some(bytes) => Value::Bytes(bytes.clone()),
+            None => Value::Null,
+        };
+        let expires = match self.expires {
+            Some(expires) => Value::Array(vec![
+                Value::Bool(expires.relative),
+                Value::Integer(expires.time.into()),
+            ]),
+            None => Value::Null,
+        };
+        let mut part = vec![
+            Value::Integer(self.disposition.into()),
+            Value::Text(self.language.clone()),
+            Value::Integer(0.into()),
+        ];
+        match &self.part {
+            Part::Null => part.push(Value::Integer(NULL_PART.into())),
+            Part::Single {
+                content_type,
+                content,
+            } => part.extend([
+                Value::Integer(SINGLE_PART.into()),
+                Value::Text(content_type.clone()),
+                Value::Bytes(content.clone()),
+            ]),
+            Part::Other => anyhow::bail!("Only null and single parts can be sent"),
+        }
+        let content = Value::Array(vec![
+            Value::Bytes(self.salt.clone()),
+            optional_bytes(&self.replaces),
+            Value::Bytes(self.topic_id.clone()),
+            expires,
+            optional_bytes(&self.in_reply_to),
+            Value::Map(Vec::new()),
+            Value::Array(part),
+        ]);
+        let mut bytes = Vec::new();
+        ciborium::into_writer(&content, &mut bytes)?;
+        Ok(bytes)
+    }
+
+    /// Decodes an application message, or returns `None` if it isn't MIMI
+    /// content. Envelopes can't be mistaken for it, as they don't start with
+    /// a CBOR array of seven items.
+    pub(crate) fn decode(payload: &[u8]) -> Option<Self> {
+        if payload.first() != Some(&0x87) {
+            return None;
+        }
+        let Value::Array(fields) = ciborium::from_reader(payload).ok()? else {
+            return None;
+        };
+        let [
+            salt,
+            replaces,
+            topic_id,
+            expires,
+            in_reply_to,
+            _extensions,
+            part,
+        ] = <[Value; 7]>::try_from(fields).ok()?;
+        let optional_bytes = |value: Value| match value {
+            Value::Null => Some(None),
+            Value::Bytes(bytes) => Some(Some(bytes)),
+            _ => None,
+        };
+        let expires = match expires {
+            Value::Null => None,
+            Value::Array(expires) => match expires.as_slice() {
+                [Value::Bool(relative), Value::Integer(time)] => Some(Expiration {
+                    relative: *relative,
+                    time: u32::try_from(*time).ok()?,
+                }),
+                _ => return None,
+            },
+            _ => return None,
+        };
+        let Value::Array(part) = part else {
+            return None;
+        };
+        let mut part = part.into_iter();
+        let disposition = u64::try_from(part.next()?.into_integer().ok()?).ok()?;
+        let language = part.next()?.into_text().ok()?;
+        let _part_index = part.next()?;
+        let cardinality = u64::try_from(part.next()?.into_integer().ok()?).ok()?;
+        let part = match cardinality {
+            NULL_PART => Part::Null,
+            SINGLE_PART => Part::Single {
+                content_type: part.next()?.into_text().ok()?,
+                content: part.next()?.into_bytes().ok()?,
+            },
+            _ => Part::Other,
+        };
+        Some(Self {
+            salt: salt.into_bytes().ok()?,
+            replaces: optional_bytes(replaces)?,
+            topic_id: topic_id.into_bytes().ok()?,
+            expires,
+            in_reply_to: optional_bytes(in_reply_to)?,
+            disposition,
+            language,
+            part,
+        })
+    }
+}
+
+/// URI of the user with `identity`.
+fn user_uri(identity: &str) -> String {
+    match identity.rsplit_once('@') {
+        Some((name, domain)) => format!("mimi://{domain}/u/{name}"),
+        None => format!("mimi://localhost/u/{identity}"),
+    }
+}
+
+fn room_uri(creator: &str, group_uuid: Uuid) -> String {
+    let domain = creator
+        .rsplit_once('@')
+        .map_or("localhost", |(_, domain)| domain);
+    format!("mimi://{domain}/r/{group_uuid}")
+}
+
+/// Id of the message, from its sender, group and encoded content.
+fn message_id(sender_uri: &str, room_uri: &str, content: &[u8], salt: &[u8]) -> Vec<u8> {
+    let input = [sender_uri.as_bytes(), room_uri.as_bytes(), content, salt].concat();
+    let hash = RustCrypto::default()
+        .hash(HashType::Sha2_256, &input)
+        .unwrap_or_default();
+    let mut id = vec![SHA256_MESSAGE_ID];
+    id.extend(hash.iter().take(31));
+    id
+}
+
+/// Id of a message in the history of messages in the MIMI content format,
+/// which receipts of other clients of this kind refer to.
+fn message_uuid(mimi_id: &[u8]) -> Option<Uuid> {
+    Uuid::from_slice(mimi_id.get(..16)?).ok()
+}
+
+impl Client {
+    /// Sends text messages in the MIMI content format instead of an envelope,
+    /// for groups with other MLS-based messengers. Edits and deletions of
+    /// messages sent this way are in the format as well, whatever this
+    /// setting.
+    pub fn with_mimi_content(mut self) -> Self {
+        self.mimi_content = true;
+        self
+    }
+
+    /// Lets own messages in the MIMI content format expire this long after
+    /// they were sent, when all members replace them with a tombstone. The
+    /// message timer of a group takes precedence, see
+    /// [`GroupChange::message_timer`](crate::client::context::GroupChange::message_timer).
+    pub fn with_message_expiry(mut self, after: Duration) -> Self {
+        self.message_expiry = Some(after);
+        self
+    }
+
+    /// Reacts to the message `message_id` of the history with `reaction`,
+    /// e.g. an emoji. Reactions are only sent in the MIMI content format and
+    /// only to messages in that format; they aren't kept in the history.
+    pub async fn react(
+        &mut self,
+        user: String,
+        group_uuid: Uuid,
+        message_id: i64,
+        reaction: String,
+    ) -> Result<()> {
+        let mimi_id = query_scalar!(
+            "SELECT mimi_id FROM client_message
+            WHERE message_id = ? AND group_id = ? AND username = ? AND deleted_at IS NULL",
+            message_id,
+            group_uuid,
+            user,
+        )
+        .fetch_optional(&mut self.connection)
+        .await?
+        .ok_or_else(|| not_found("Message"))?
+        .context("Only messages in the MIMI content format can be reacted to")?;
+        let mut content = MimiContent::new(
+            DISPOSITION_REACTION,
+            Part::Single {
+                content_type: TEXT_PLAIN.to_string(),
+                content: reaction.into_bytes(),
+            },
+        )?;
+        content.in_reply_to = Some(mimi_id);
+        self.send_mimi(&user, group_uuid, &content).await?;
+        Ok(())
+    }
+
+    /// Sends `text` in the MIMI content format, as reply to the message
+    /// `in_reply_to` if given, and adds it to the history.
+    pub(crate) async fn send_mimi_text(
+        &mut self,
+        user: &str,
+        group_uuid: Uuid,
+        text: &str,
+        mentions: &[String],
+        reply_to: Option<(Uuid, Vec<u8>)>,
+    ) -> anyhow::Result<SentMessage> {
+        let mut content = MimiContent::new(
+            DISPOSITION_RENDER,
+            Part::Single {
+                content_type: TEXT_PLAIN.to_string(),
+                content: text.as_bytes().to_vec(),
+            },
+        )?;
+        let group_id = GroupId::from_slice(group_uuid.as_bytes());
+        let group = MlsGroup::load(self.provider().storage(), &group_id)?
+            .ok_or_else(|| not_found("Group"))?;
+        let now = Utc::now();
+        // The timer of the group takes precedence over the own expiry.
+        let expires_at =
+            timer_expiry(&group, now).or_else(|| self.message_expiry.map(|after| now + after));
+        if let Some(expires_at) = expires_at {
+            content.expires = Some(Expiration {
+                relative: false,
+                time: expires_at.timestamp().try_into()?,
+            });
+        }
+        let (reply_to, in_reply_to) = reply_to.unzip();
+        content.in_reply_to = in_reply_to;
+        let (dispatched, mimi_id) = self.send_mimi(user, group_uuid, &content).await?;
+        let message_id = self
+            .store_message(
+                user,
+                group_uuid,
+                NewMessage {
+                    sender: user,
+                    epoch: dispatched.epoch,
+                    direction: Direction::Outgoing,
+                    uuid: message_uuid(&mimi_id),
+                    body: text,
+                    mentions,
+                    reply_to,
+                    mimi_id: Some(&mimi_id),
+                    expires_at,
+                },
+            )
+            .await?;
+        self.touch_group(user, group_uuid).await?;
+        self.record_dispatch(message_id, dispatched.dispatch).await
+    }
+
+    /// Replaces the own message `mimi_id` with `text`, or deletes it without.
+    pub(crate) async fn send_mimi_replacement(
+        &mut self,
+        user: &str,
+        group_uuid: Uuid,
+        mimi_id: Vec<u8>,
+        text: Option<&str>,
+    ) -> anyhow::Result<()> {
+        let part = match text {
+            Some(text) => Part::Single {
+                content_type: TEXT_PLAIN.to_string(),
+                content: text.as_bytes().to_vec(),
+            },
+            None => Part::Null,
+        };
+        let mut content = MimiContent::new(DISPOSITION_RENDER, part)?;
+        content.replaces = Some(mimi_id);
+        self.send_mimi(user, group_uuid, &content).await?;
+        Ok(())
+    }
+
+    /// Sends MIMI content and returns how it was sent and its id.
+    async fn send_mimi(
+        &mut self,
+        user: &str,
+        group_uuid: Uuid,
+        content: &MimiContent,
+    ) -> anyhow::Result<(Dispatched, Vec<u8>)> {
+        let payload = content.encode()?;
+        let room_uri = self.room_uri(user, group_uuid).await?;
+        let mimi_id = message_id(&user_uri(user), &room_uri, &payload, &content.salt);
+        let dispatched = self.send_payload(user, group_uuid, &payload).await?;
+        Ok((dispatched, mimi_id))
+    }
+
+    async fn room_uri(&mut self, user: &str, group_uuid: Uuid) -> anyhow::Result<String> {
+        let creator = query_scalar!(
+            "SELECT creator FROM client_group WHERE group_id = ? AND username = ?",
+            group_uuid,
+            user,
+        )
+        .fetch_optional(&mut self.connection)
+        .await?
+        .unwrap_or_default();
+        Ok(room_uri(&creator, group_uuid))
+    }
+
+    /// Handles an application message in the MIMI content format like one in
+    /// an envelope. Only text and null parts are understood.
+    pub(crate) async fn handle_mimi_message(
+        &mut self,
+        user: &str,
+        group: &MlsGroup,
+        sender: String,
+        epoch: u64,
+        payload: &[u8],
+        content: MimiContent,
+    ) -> anyhow::Result<()> {
+        let group_uuid = Uuid::from_slice(group.group_id().as_slice())?;
+        if !may_send(group, &sender) {
+            warn!(%group_uuid, sender, "Dropping message to a read-only group");
+            return Ok(());
+        }
+        let received_at = Utc::now();
+        let expires_at = content
+            .expires_at(received_at)
+            .or_else(|| timer_expiry(group, received_at));
+        if expires_at.is_some_and(|expires_at| expires_at <= received_at) {
+            debug!(%group_uuid, sender, "Dropping expired message");
+            return Ok(());
+        }
+        let room_uri = self.room_uri(user, group_uuid).await?;
+        let mimi_id = message_id(&user_uri(&sender), &room_uri, payload, &content.salt);
+
+        if let Some(replaces) = &content.replaces {
+            let Some(message_id) = self
+                .mimi_message_of(user, group_uuid, &sender, replaces)
+                .await?
+            else {
+                debug!(%group_uuid, sender, "Ignoring replacement of unknown message");
+                return Ok(());
+            };
+            match (&content.part, content.text()) {
+                (Part::Null, _) => {
+                    self.tombstone(message_id).await?;
+                    self.emit(ChatEvent::MessageDeleted {
+                        group_id: group_uuid,
+                        message_id,
+                        sender,
+                    });
+                }
+                (_, Some(text)) => {
+                    self.apply_edit(message_id, &text).await?;
+                    self.emit(ChatEvent::MessageEdited {
+                        group_id: group_uuid,
+                        message_id,
+                        sender,
+                        text,
+                    });
+                }
+                _ => debug!(%group_uuid, sender, "Ignoring replacement without text"),
+            }
+            return Ok(());
+        }
+
+        let Some(text) = content.text() else {
+            info!(%group_uuid, sender, "Ignoring MIMI content without text");
+            return Ok(());
+        };
+        match content.disposition {
+            DISPOSITION_REACTION => {
+                let target = match &content.in_reply_to {
+                    Some(in_reply_to) => self.mimi_message(user, group_uuid, in_reply_to).await?,
+                    None => None,
+                };
+                let Some(message_id) = target else {
+                    debug!(%group_uuid, sender, "Ignoring reaction to unknown message");
+                    return Ok(());
+                };
+                self.emit(ChatEvent::Reaction {
+                    group_id: group_uuid,
+                    message_id,
+                    sender,
+                    reaction: text,
+                });
+            }
+            DISPOSITION_UNSPECIFIED | DISPOSITION_RENDER => {
+                let mentions = envelope::parse_mentions(&text, &member_identities(group));
+                let reply_to = content.in_reply_to.as_deref().and_then(message_uuid);
+                self.add_incoming_message(
+                    user,
+                    group,
+                    NewMessage {
+                        sender: &sender,
+                        epoch,
+                        direction: Direction::Incoming,
+                        uuid: message_uuid(&mimi_id),
+                        body: &text,
+                        mentions: &mentions,
+                        reply_to,
+                        mimi_id: Some(&mimi_id),
+                        expires_at,
+                    },
+                )
+                .await?;
+            }
+            disposition => {
+                info!(%group_uuid, sender, disposition, "Ignoring MIMI content of unsupported disposition");
+            }
+        }
+        Ok(())
+    }
+
+    /// History id of the message `mimi_id`, unless deleted.
+    async fn mimi_message(
+        &mut self,
+        user: &str,
+        group_uuid: Uuid,
+        mimi_id: &[u8],
+    ) -> anyhow::Result<Option<i64>> {
+        let message_id = query_scalar!(
+            "SELECT message_id FROM client_message
+            WHERE username = ? AND group_id = ? AND mimi_id = ? AND deleted_at IS NULL",
+            user,
+            group_uuid,
+            mimi_id,
+        )
+        .fetch_optional(&mut self.connection)
+        .await?;
+        Ok(message_id)
+    }
+
+    /// History id of the message `mimi_id` of `sender`, unless deleted.
+    async fn mimi_message_of(
+        &mut self,
+        user: &str,
+        group_uuid: Uuid,
+        sender: &str,
+        mimi_id: &[u8],
+    ) -> anyhow::Result<Option<i64>> {
+        let message_id = query_scalar!(
+            "SELECT message_id FROM client_message
+            WHERE username = ? AND group_id = ? AND mimi_id = ? AND sender = ?
+                AND deleted_at IS NULL",
+            user,
+            group_uuid,
+            mimi_id,
+            sender,
+        )
+        .fetch_optional(&mut self.connection)
+        .await?;
+        Ok(message_id)
+    }
+
+    /// Replaces the messages of the user whose sender let them expire with
+    /// tombstones. Returns their number.
+    pub(crate) async fn expire_messages(&mut self, user: &str) -> anyhow::Result<u64> {
+        let now = Utc::now();
+        let expired = query_scalar!(
+            "SELECT message_id FROM client_message
+            WHERE username = ? AND expires_at <= ? AND deleted_at IS NULL",
+            user,
+            now,
+        )
+        .fetch_all(&mut self.connection)
+        .await?;
+        for &message_id in &expired {
+            self.tombstone(message_id).await?;
+        }
+        if !expired.is_empty() {
+            query!(
+                "UPDATE client_message SET expires_at = NULL
+                WHERE username = ? AND expires_at <= ?",
+                user,
+                now,
+            )
+            .execute(&mut self.connection)
+            .await?;
+            debug!(expired = expired.len(), "Expired messages");
+        }
+        Ok(expired.len() as u64)
+    }
+}
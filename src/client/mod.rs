@@ -1,41 +1,191 @@
-use std::{path::Path, str::FromStr};
-
-use openmls_sqlx_storage::SqliteStorageProvider;
-use sqlx::{
-    ConnectOptions, SqliteConnection,
-    sqlite::{SqliteConnectOptions, SqliteJournalMode, SqliteSynchronous},
+use std::{
+    collections::{HashMap, VecDeque},
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
 };
-use tonic::transport::{Channel, Endpoint};
-use tracing::info;
 
-use crate::{grpc::chat_service_client::ChatServiceClient, provider::JsonCodec};
+use openmls::prelude::SenderRatchetConfiguration;
+use openmls_memory_storage::MemoryStorage;
+use sqlx::SqliteConnection;
+
+#[cfg(feature = "sqlcipher")]
+use crate::client::encryption::DatabaseKey;
+#[cfg(feature = "notifications")]
+use crate::client::notify::Notifications;
+use crate::{
+    client::{
+        cache::GroupCache, context::GroupChangeValidator, events::ChatEvent, message::Deferred,
+        policy::AcceptPolicy, signer::ChatSigner, sneakernet::SneakernetDeliveryService,
+        transport::Transport, validator::CredentialValidator,
+    },
+    provider::StorageCodec,
+};
 
+pub mod alias;
+pub mod attachments;
+pub mod backlog;
+pub mod backup;
+pub mod bans;
+pub mod bot;
+pub mod builder;
+pub mod cache;
+pub mod contacts;
+pub mod context;
+pub mod daemon;
+pub mod debug;
+pub mod delivery;
+pub mod device;
+pub mod direct;
+pub mod edits;
+#[cfg(feature = "sqlcipher")]
+pub mod encryption;
+pub mod envelope;
+pub mod error;
+pub mod events;
+pub mod exporter;
+#[cfg(feature = "websocket")]
+pub(crate) mod frames;
 pub mod group;
+pub mod history;
+pub mod identity;
+pub mod invite;
+pub mod key_log;
+pub mod key_packages;
+pub mod keychain;
+pub mod ledger;
 pub mod member;
 pub mod message;
+pub mod mimi;
+#[cfg(feature = "notifications")]
+pub mod notify;
+pub mod outbox;
+pub mod pending;
+pub mod pins;
+pub mod policy;
+pub mod profile;
+pub mod pruning;
+pub mod quarantine;
+#[cfg(feature = "quic")]
+pub(crate) mod quic;
+pub mod rebase;
+pub mod receipts;
 pub mod register;
+pub mod reinit;
+pub mod roles;
+pub mod rotation;
+pub mod settings;
+pub mod share;
+pub mod signer;
+pub mod sneakernet;
+pub mod storage;
+pub mod transfer;
+pub mod transport;
+pub mod validator;
+pub mod verify;
+#[cfg(feature = "websocket")]
+pub(crate) mod websocket;
+
+pub use error::{ClientError, Result};
 
 pub struct Client {
-    pub(crate) client: ChatServiceClient<Channel>,
+    pub(crate) client: Transport,
+    /// The delivery service of a `file://` endpoint, which messages are
+    /// exported from.
+    pub(crate) sneakernet: Option<Arc<SneakernetDeliveryService>>,
     pub(crate) connection: SqliteConnection,
+    /// MLS state of the groups, if kept in memory instead of the database.
+    pub(crate) memory_storage: Option<MemoryStorage>,
+    /// Codec of the MLS state in the database.
+    pub(crate) storage_codec: StorageCodec,
+    /// Key of the database, if it is encrypted.
+    #[cfg(feature = "sqlcipher")]
+    pub(crate) database_key: Option<DatabaseKey>,
+    /// Whether new signature private keys are stored in the OS keychain.
+    #[cfg(feature = "keychain")]
+    pub(crate) keychain: bool,
+    /// Signers of users whose identity key the client doesn't hold.
+    pub(crate) signers: HashMap<String, Arc<dyn ChatSigner>>,
+    /// Validators of the credentials of added and updated members.
+    pub(crate) credential_validators: Vec<Arc<dyn CredentialValidator>>,
+    /// Validators of changes of the group settings.
+    pub(crate) change_validators: Vec<Arc<dyn GroupChangeValidator>>,
+    pub(crate) accept_policy: AcceptPolicy,
+    pub(crate) group_config: GroupConfig,
+    pub(crate) key_rotation: Option<Duration>,
+    pub(crate) pruning_horizon: Option<Duration>,
+    /// Whether text messages are sent in the MIMI content format.
+    pub(crate) mimi_content: bool,
+    /// Expiry of own messages in the MIMI content format.
+    pub(crate) message_expiry: Option<Duration>,
+    pub(crate) download_dir: PathBuf,
+    #[cfg(feature = "notifications")]
+    pub(crate) notifications: Option<Notifications>,
+    /// Active profile, see [`Client::switch_profile`].
+    pub(crate) profile: Option<String>,
+    /// Events of processed messages that weren't taken yet.
+    pub(crate) events: VecDeque<ChatEvent>,
+    /// Sends of the messages being handled, see [`Deferred`].
+    pub(crate) deferred: Vec<Deferred>,
+    pub(crate) group_cache: GroupCache,
+}
+
+/// Tolerance of groups for delayed and reordered messages, applied to groups
+/// created or joined afterwards. Higher values keep more key material around
+/// and so weaken forward secrecy.
+#[derive(Debug, Clone)]
+pub struct GroupConfig {
+    /// Number of skipped messages per sender that can still be decrypted
+    /// when they arrive late.
+    pub out_of_order_tolerance: u32,
+    /// Number of messages of a sender that may be skipped at once.
+    pub maximum_forward_distance: u32,
+    /// Number of past epochs in which application messages can still be
+    /// decrypted.
+    pub max_past_epochs: usize,
+}
+
+impl Default for GroupConfig {
+    fn default() -> Self {
+        let sender_ratchet = SenderRatchetConfiguration::default();
+        Self {
+            out_of_order_tolerance: sender_ratchet.out_of_order_tolerance(),
+            maximum_forward_distance: sender_ratchet.maximum_forward_distance(),
+            max_past_epochs: 0,
+        }
+    }
 }
 
 impl Client {
-    pub async fn connect(endpoint: &str, db_path: impl AsRef<Path>) -> anyhow::Result<Self> {
-        let db_path = db_path.as_ref();
-        info!(db_path = %db_path.display(), "Opening client database");
-        let mut connection = SqliteConnectOptions::new()
-            .filename(db_path)
-            .create_if_missing(true)
-            .journal_mode(SqliteJournalMode::Wal)
-            .synchronous(SqliteSynchronous::Normal)
-            .connect()
-            .await?;
-        sqlx::migrate!().run(&mut connection).await?;
-        SqliteStorageProvider::<JsonCodec>::new(&mut connection).run_migrations()?;
-
-        let channel = Endpoint::from_str(endpoint)?.connect_lazy();
-        let client = ChatServiceClient::new(channel);
-        Ok(Self { client, connection })
+    pub fn with_group_config(mut self, group_config: GroupConfig) -> Self {
+        self.group_config = group_config;
+        self
+    }
+
+    /// Updates the own keys of groups on `receive` once they are older than
+    /// `max_age`, see [`Client::rotate_stale_keys`].
+    pub fn with_key_rotation(mut self, max_age: Duration) -> Self {
+        self.key_rotation = Some(max_age);
+        self
+    }
+
+    /// Deletes key material older than `horizon` on `receive`, see
+    /// [`Client::prune_key_material`].
+    pub fn with_pruning(mut self, horizon: Duration) -> Self {
+        self.pruning_horizon = Some(horizon);
+        self
+    }
+
+    /// Directory in which received attachments are saved.
+    pub fn with_download_dir(mut self, download_dir: impl Into<PathBuf>) -> Self {
+        self.download_dir = download_dir.into();
+        self
+    }
+
+    /// Shows desktop notifications of incoming messages.
+    #[cfg(feature = "notifications")]
+    pub fn with_notifications(mut self, notifications: Notifications) -> Self {
+        self.notifications = Some(notifications);
+        self
     }
 }
@@ -0,0 +1,44 @@
+use notify_rust::Notification;
+use openmls::group::MlsGroup;
+use tracing::warn;
+
+use crate::client::{Client, group::group_metadata};
+
+/// Desktop notifications of incoming messages.
+#[derive(Debug, Clone, Copy)]
+pub struct Notifications {
+    /// Whether notifications show the text of messages, or only who sent
+    /// them where.
+    pub show_content: bool,
+}
+
+impl Client {
+    /// Shows a desktop notification of a message of `sender`, if enabled.
+    pub(crate) fn notify(&self, group: &MlsGroup, sender: &str, text: &str) {
+        let Some(notifications) = self.notifications else {
+            return;
+        };
+        let group_name = group_metadata(group.extensions())
+            .map(|metadata| metadata.name)
+            .unwrap_or_default();
+        let mut notification = Notification::new();
+        notification.appname("mls-chat");
+        if group_name.is_empty() {
+            notification.summary(sender);
+        } else {
+            notification.summary(&format!("{sender} in {group_name}"));
+        }
+        if notifications.show_content {
+            notification.body(text);
+        } else {
+            notification.body("New message");
+        }
+
+        // Showing blocks on the notification daemon.
+        tokio::task::spawn_blocking(move || {
+            if let Err(error) = notification.show() {
+                warn!(%error, "Failed to show notification");
+            }
+        });
+    }
+}
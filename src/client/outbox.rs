@@ -0,0 +1,250 @@
+use std::{collections::BTreeSet, time::Duration};
+
+use sqlx::{
+    query, query_scalar,
+    types::chrono::{DateTime, Utc},
+};
+use tonic::{Code, Status};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::client::{
+    Client, Result,
+    message::Encrypted,
+    rebase::{MAX_REBASES, is_epoch_conflict},
+};
+
+/// Delay before the first retry, doubled with each further attempt.
+const INITIAL_BACKOFF: Duration = Duration::from_secs(2);
+const MAX_BACKOFF: Duration = Duration::from_secs(60 * 60);
+
+impl Client {
+    /// Sends the queued messages that are due, oldest first. A group's
+    /// messages stay queued behind the first one that still fails, to keep
+    /// their order. A message rejected because the group moved on to a newer
+    /// epoch is encrypted again in the current one. Returns the number of sent
+    /// messages.
+    pub async fn flush_outbox(&mut self, user: &str) -> Result<usize> {
+        let now = Utc::now();
+        let rows = query!(
+            "SELECT
+                outbox_id,
+                message_id,
+                group_id AS \"group_id: Uuid\",
+                recipients,
+                content,
+                payload,
+                attempts,
+                next_attempt_at AS \"next_attempt_at: DateTime<Utc>\"
+            FROM client_outbox
+            WHERE username = ?
+            ORDER BY outbox_id",
+            user,
+        )
+        .fetch_all(&mut self.connection)
+        .await?;
+
+        let mut blocked = BTreeSet::new();
+        let mut sent = 0;
+        for row in rows {
+            if blocked.contains(&row.group_id) {
+                continue;
+            }
+            if row.next_attempt_at > now {
+                blocked.insert(row.group_id);
+                continue;
+            }
+
+            let mut recipients: Vec<String> = serde_json::from_str(&row.recipients)?;
+            let mut content = row.content;
+            let mut rebases = 0;
+            let result = loop {
+                match self.fanout(user, recipients.clone(), content.clone()).await {
+                    Err(error)
+                        if is_epoch_conflict(&error)
+                            && rebases < MAX_REBASES
+                            && let Some(payload) = &row.payload =>
+                    {
+                        rebases += 1;
+                        warn!(group_id = %row.group_id, rebases, "Encrypting queued message of an outdated epoch again");
+                        match self
+                            .reencrypt_queued(
+                                user,
+                                row.outbox_id,
+                                row.message_id,
+                                row.group_id,
+                                payload,
+                            )
+                            .await
+                        {
+                            Ok(encrypted) => (recipients, content) = encrypted,
+                            Err(error) => break Err(error),
+                        }
+                    }
+                    result => break result,
+                }
+            };
+            match result {
+                Ok(response) => {
+                    sent += 1;
+                    let sent_at = DateTime::from_timestamp_millis(response.timestamp);
+                    query!(
+                        "UPDATE client_message SET send_status = 'sent', sent_at = ?
+                        WHERE message_id = ? AND send_status = 'queued'",
+                        sent_at,
+                        row.message_id,
+                    )
+                    .execute(&mut self.connection)
+                    .await?;
+                    self.remove_queued(row.outbox_id).await?;
+                }
+                Err(error) if is_unsent(&error) => {
+                    blocked.insert(row.group_id);
+                    let attempts = row.attempts + 1;
+                    let next_attempt_at = now + backoff(attempts);
+                    query!(
+                        "UPDATE client_outbox SET attempts = ?, next_attempt_at = ?
+                        WHERE outbox_id = ?",
+                        attempts,
+                        next_attempt_at,
+                        row.outbox_id,
+                    )
+                    .execute(&mut self.connection)
+                    .await?;
+                    warn!(group_id = %row.group_id, attempts, %error, "Failed to send queued message");
+                }
+                Err(error) => {
+                    // Rejected for good, or maybe accepted by the server after
+                    // all if the request timed out, which sending it again
+                    // could duplicate.
+                    warn!(group_id = %row.group_id, %error, "Dropping queued message");
+                    query!(
+                        "UPDATE client_message SET send_status = 'failed' WHERE message_id = ?",
+                        row.message_id,
+                    )
+                    .execute(&mut self.connection)
+                    .await?;
+                    self.remove_queued(row.outbox_id).await?;
+                }
+            }
+        }
+        if sent > 0 {
+            info!(sent, "Sent queued messages");
+        }
+        Ok(sent)
+    }
+
+    /// Queues an encrypted message of the group for a later attempt, with its
+    /// encoded application message `payload`. Returns its id in the outbox.
+    pub(crate) async fn enqueue(
+        &mut self,
+        user: &str,
+        group_uuid: Uuid,
+        recipients: &[String],
+        content: &[u8],
+        payload: &[u8],
+    ) -> anyhow::Result<i64> {
+        let recipients = serde_json::to_string(recipients)?;
+        let created_at = Utc::now();
+        let result = query!(
+            "INSERT INTO client_outbox (
+                username, group_id, recipients, content, payload, next_attempt_at, created_at
+            ) VALUES (?, ?, ?, ?, ?, ?, ?)",
+            user,
+            group_uuid,
+            recipients,
+            content,
+            payload,
+            created_at,
+            created_at,
+        )
+        .execute(&mut self.connection)
+        .await?;
+        Ok(result.last_insert_rowid())
+    }
+
+    /// Whether messages of the group wait in the outbox, so that new ones
+    /// must queue behind them.
+    pub(crate) async fn has_queued(
+        &mut self,
+        user: &str,
+        group_uuid: Uuid,
+    ) -> anyhow::Result<bool> {
+        let queued = query_scalar!(
+            "SELECT COUNT(*) > 0 AS \"queued!: bool\" FROM client_outbox
+            WHERE username = ? AND group_id = ?",
+            user,
+            group_uuid,
+        )
+        .fetch_one(&mut self.connection)
+        .await?;
+        Ok(queued)
+    }
+
+    /// Catches up with the group and encrypts the queued message again in its
+    /// current epoch, for the recipients of that epoch. Returns the
+    /// recipients and the new content.
+    async fn reencrypt_queued(
+        &mut self,
+        user: &str,
+        outbox_id: i64,
+        message_id: Option<i64>,
+        group_uuid: Uuid,
+        payload: &[u8],
+    ) -> anyhow::Result<(Vec<String>, Vec<u8>)> {
+        self.catch_up(user).await?;
+        let Encrypted {
+            epoch,
+            recipients,
+            content,
+        } = self.encrypt_payload(user, group_uuid, payload).await?;
+        let recipients_json = serde_json::to_string(&recipients)?;
+        query!(
+            "UPDATE client_outbox SET recipients = ?, content = ? WHERE outbox_id = ?",
+            recipients_json,
+            content,
+            outbox_id,
+        )
+        .execute(&mut self.connection)
+        .await?;
+        let epoch = epoch as i64;
+        query!(
+            "UPDATE client_message SET epoch = ? WHERE message_id = ?",
+            epoch,
+            message_id,
+        )
+        .execute(&mut self.connection)
+        .await?;
+        Ok((recipients, content))
+    }
+
+    async fn remove_queued(&mut self, outbox_id: i64) -> anyhow::Result<()> {
+        query!("DELETE FROM client_outbox WHERE outbox_id = ?", outbox_id)
+            .execute(&mut self.connection)
+            .await?;
+        Ok(())
+    }
+}
+
+/// Whether the server can't be reached, so that a later attempt may succeed.
+pub(crate) fn is_transient(error: &anyhow::Error) -> bool {
+    error
+        .downcast_ref::<Status>()
+        .is_some_and(|status| matches!(status.code(), Code::Unavailable | Code::DeadlineExceeded))
+}
+
+/// Whether sending a message failed because the server couldn't be reached,
+/// so that it can be sent again. Unlike [`is_transient`] not after a timeout,
+/// when the server may have accepted the message, which would be duplicated.
+pub(crate) fn is_unsent(error: &anyhow::Error) -> bool {
+    error
+        .downcast_ref::<Status>()
+        .is_some_and(|status| status.code() == Code::Unavailable)
+}
+
+/// Delay after the `attempts`th failed attempt.
+fn backoff(attempts: i64) -> Duration {
+    INITIAL_BACKOFF
+        .saturating_mul(2u32.saturating_pow(attempts.clamp(1, 31) as u32 - 1))
+        .min(MAX_BACKOFF)
+}
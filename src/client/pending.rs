@@ -0,0 +1,86 @@
+use sqlx::{query, types::chrono::Utc};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::client::Client;
+
+impl Client {
+    /// Keeps a message of a future epoch of the group, which can't be
+    /// processed before the commits leading up to it are merged.
+    pub(crate) async fn buffer_pending_message(
+        &mut self,
+        user: &str,
+        group_uuid: Uuid,
+        epoch: u64,
+        content: &[u8],
+    ) -> anyhow::Result<()> {
+        let epoch = epoch as i64;
+        let received_at = Utc::now();
+        query!(
+            "INSERT INTO client_pending_message (group_id, username, epoch, content, received_at)
+            VALUES (?, ?, ?, ?, ?)",
+            group_uuid,
+            user,
+            epoch,
+            content,
+            received_at,
+        )
+        .execute(&mut self.connection)
+        .await?;
+        info!(%group_uuid, epoch, "Buffered message of a future epoch");
+        Ok(())
+    }
+
+    /// Processes the buffered messages of the group up to `epoch`, in the
+    /// order of their epochs. Messages that still fail are dropped.
+    pub(crate) async fn replay_pending_messages(
+        &mut self,
+        user: &str,
+        group_uuid: Uuid,
+        epoch: u64,
+    ) -> anyhow::Result<()> {
+        let epoch = epoch as i64;
+        let pending = query!(
+            "SELECT message_id, content
+            FROM client_pending_message
+            WHERE group_id = ? AND username = ? AND epoch <= ?
+            ORDER BY epoch, message_id",
+            group_uuid,
+            user,
+            epoch,
+        )
+        .fetch_all(&mut self.connection)
+        .await?;
+
+        for row in pending {
+            query!(
+                "DELETE FROM client_pending_message WHERE message_id = ?",
+                row.message_id
+            )
+            .execute(&mut self.connection)
+            .await?;
+            // Boxed, as handling a buffered commit replays further messages.
+            if let Err(error) = Box::pin(self.receive_message(user, None, &row.content)).await {
+                warn!(%group_uuid, %error, "Dropping buffered message");
+            }
+        }
+        Ok(())
+    }
+
+    /// Discards the buffered messages of a group the user is no longer a
+    /// member of.
+    pub(crate) async fn drop_pending_messages(
+        &mut self,
+        user: &str,
+        group_uuid: Uuid,
+    ) -> anyhow::Result<()> {
+        query!(
+            "DELETE FROM client_pending_message WHERE group_id = ? AND username = ?",
+            group_uuid,
+            user,
+        )
+        .execute(&mut self.connection)
+        .await?;
+        Ok(())
+    }
+}
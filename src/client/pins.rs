@@ -0,0 +1,193 @@
+use anyhow::Context;
+use openmls::group::{GroupId, MlsGroup};
+use openmls_traits::OpenMlsProvider;
+use prost::Message;
+use sqlx::{
+    query, query_as, query_scalar,
+    types::chrono::{DateTime, Utc},
+};
+use tracing::debug;
+use uuid::Uuid;
+
+use crate::{
+    client::{
+        Client, Result,
+        error::{ensure, not_found},
+        roles::ensure_admin,
+    },
+    grpc::{ContentType, Control, control::Action},
+};
+
+/// A message pinned to a group.
+#[derive(Debug)]
+pub struct Pin {
+    /// History id of the message, if this client has it.
+    pub message_id: Option<i64>,
+    pub sender: Option<String>,
+    pub body: Option<String>,
+    pub pinned_by: String,
+    pub pinned_at: DateTime<Utc>,
+}
+
+impl Client {
+    /// Pins the message `message_id` of the history to the group for all
+    /// members. Only admins can pin messages.
+    pub async fn pin_message(
+        &mut self,
+        user: String,
+        group_uuid: Uuid,
+        message_id: i64,
+    ) -> Result<()> {
+        Ok(self.set_pinned(&user, group_uuid, message_id, true).await?)
+    }
+
+    /// Unpins the message `message_id` of the history for all members.
+    pub async fn unpin_message(
+        &mut self,
+        user: String,
+        group_uuid: Uuid,
+        message_id: i64,
+    ) -> Result<()> {
+        Ok(self
+            .set_pinned(&user, group_uuid, message_id, false)
+            .await?)
+    }
+
+    /// Messages currently pinned to the group, oldest pin first.
+    pub async fn pins(&mut self, user: String, group_uuid: Uuid) -> Result<Vec<Pin>> {
+        let pins = query_as!(
+            Pin,
+            "SELECT
+                m.message_id AS \"message_id?: i64\",
+                m.sender AS \"sender?: String\",
+                m.body AS \"body?: String\",
+                p.pinned_by,
+                p.pinned_at AS \"pinned_at: DateTime<Utc>\"
+            FROM client_pin p
+            LEFT JOIN client_message m ON m.username = p.username
+                AND m.group_id = p.group_id AND m.message_uuid = p.message_uuid
+                AND m.deleted_at IS NULL
+            WHERE p.username = ? AND p.group_id = ?
+            ORDER BY p.pinned_at",
+            user,
+            group_uuid,
+        )
+        .fetch_all(&mut self.connection)
+        .await?;
+        Ok(pins)
+    }
+
+    async fn set_pinned(
+        &mut self,
+        user: &str,
+        group_uuid: Uuid,
+        message_id: i64,
+        pinned: bool,
+    ) -> anyhow::Result<()> {
+        let group_id = GroupId::from_slice(group_uuid.as_bytes());
+        let group = MlsGroup::load(self.provider().storage(), &group_id)?
+            .ok_or_else(|| not_found("Group"))?;
+        ensure_admin(&group, user)?;
+
+        let message_uuid = query_scalar!(
+            "SELECT message_uuid AS \"message_uuid: Uuid\" FROM client_message
+            WHERE message_id = ? AND group_id = ? AND username = ? AND deleted_at IS NULL",
+            message_id,
+            group_uuid,
+            user,
+        )
+        .fetch_optional(&mut self.connection)
+        .await?
+        .ok_or_else(|| not_found("Message"))?
+        .context("Message predates message ids and can't be pinned")?;
+
+        let is_pinned = query_scalar!(
+            "SELECT COUNT(*) > 0 AS \"pinned!: bool\" FROM client_pin
+            WHERE username = ? AND group_id = ? AND message_uuid = ?",
+            user,
+            group_uuid,
+            message_uuid,
+        )
+        .fetch_one(&mut self.connection)
+        .await?;
+        if pinned {
+            ensure!(!is_pinned, "Message is already pinned");
+        } else {
+            ensure!(is_pinned, "Message is not pinned");
+        }
+
+        let message_uuid_bytes = message_uuid.as_bytes().to_vec();
+        let action = if pinned {
+            Action::PinMessage(message_uuid_bytes)
+        } else {
+            Action::UnpinMessage(message_uuid_bytes)
+        };
+        let control = Control {
+            action: Some(action),
+        };
+        self.send_content(
+            user,
+            group_uuid,
+            ContentType::Control,
+            Uuid::new_v4(),
+            control.encode_to_vec(),
+        )
+        .await?;
+        self.store_pin(user, group_uuid, message_uuid, user, pinned)
+            .await
+    }
+
+    /// Applies a pin or unpin of `sender`, which only admins may do.
+    pub(crate) async fn handle_pin(
+        &mut self,
+        user: &str,
+        group: &MlsGroup,
+        sender: &str,
+        message_uuid: &[u8],
+        pinned: bool,
+    ) -> anyhow::Result<()> {
+        ensure_admin(group, sender)?;
+        let group_uuid = Uuid::from_slice(group.group_id().as_slice())?;
+        let message_uuid = Uuid::from_slice(message_uuid)?;
+        self.store_pin(user, group_uuid, message_uuid, sender, pinned)
+            .await?;
+        debug!(%group_uuid, sender, %message_uuid, pinned, "Updated pins");
+        Ok(())
+    }
+
+    async fn store_pin(
+        &mut self,
+        user: &str,
+        group_uuid: Uuid,
+        message_uuid: Uuid,
+        pinned_by: &str,
+        pinned: bool,
+    ) -> anyhow::Result<()> {
+        if pinned {
+            let pinned_at = Utc::now();
+            query!(
+                "INSERT INTO client_pin (username, group_id, message_uuid, pinned_by, pinned_at)
+                VALUES (?, ?, ?, ?, ?)
+                ON CONFLICT (username, group_id, message_uuid) DO NOTHING",
+                user,
+                group_uuid,
+                message_uuid,
+                pinned_by,
+                pinned_at,
+            )
+            .execute(&mut self.connection)
+            .await?;
+        } else {
+            query!(
+                "DELETE FROM client_pin
+                WHERE username = ? AND group_id = ? AND message_uuid = ?",
+                user,
+                group_uuid,
+                message_uuid,
+            )
+            .execute(&mut self.connection)
+            .await?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,276 @@
+use std::collections::HashSet;
+
+use anyhow::Context;
+use openmls::{
+    group::{MlsGroup, StagedCommit},
+    prelude::{
+        Ciphersuite, CredentialType, Extension, ExtensionType, Extensions, GroupContext,
+        KeyPackage, RequiredCapabilitiesExtension, Sender,
+    },
+};
+use sqlx::query_scalar;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::{
+    client::{
+        Client,
+        error::{bail, ensure},
+        events::ChatEvent,
+        group::group_metadata,
+        message::identity,
+        roles::group_roles,
+    },
+    grpc::GroupMetadata,
+    provider::{capabilities, check_ciphersuite},
+};
+
+/// Capabilities that every member of a group must support.
+///
+/// Extension and credential types are required in the group context, so that
+/// all members enforce them. Ciphersuites are kept in the group metadata, as
+/// MLS has no such requirement, and are checked by the adding member.
+#[derive(Debug, Clone, Default)]
+pub struct RequiredCapabilities {
+    pub extensions: Vec<ExtensionType>,
+    pub credentials: Vec<CredentialType>,
+    pub ciphersuites: Vec<Ciphersuite>,
+}
+
+/// What the client accepts from other members without asking the user, see
+/// [`Client::with_accept_policy`].
+#[derive(Debug, Clone, Default)]
+pub struct AcceptPolicy {
+    /// Joins groups on receive when the inviter is a contact whose keys were
+    /// all [verified](Client::verify_contact). Other invites wait for
+    /// [`Client::accept_invite`].
+    pub auto_accept_verified: bool,
+    /// Rejects commits that add members unless the committer and the proposers
+    /// of the adds are admins, or in groups without roles the creator.
+    /// External joins are rejected as well.
+    pub adds_from_admins_only: bool,
+    /// Rejects welcomes to, and commits that grow the group to, more than
+    /// this many members.
+    pub max_members: Option<usize>,
+}
+
+impl Client {
+    /// Applies `policy` to the welcomes and commits received from now on.
+    pub fn with_accept_policy(mut self, policy: AcceptPolicy) -> Self {
+        self.accept_policy = policy;
+        self
+    }
+
+    /// Accepts the pending invites of verified contacts, if the policy says
+    /// so. Runs after the messages at hand were handled, so that the key
+    /// package shared by several welcomes is only released after all of them
+    /// were stored.
+    pub(crate) async fn accept_verified_invites(&mut self, user: &str) -> anyhow::Result<()> {
+        if !self.accept_policy.auto_accept_verified {
+            return Ok(());
+        }
+        for invite in self.invites(user.to_string()).await? {
+            if !self.is_verified_contact(user, &invite.inviter).await? {
+                continue;
+            }
+            // The invite stays pending for the user to decide.
+            if let Err(error) = self.accept_invite(user.to_string(), invite.group_id).await {
+                warn!(group_id = %invite.group_id, %error, "Failed to accept invite of verified contact");
+                continue;
+            }
+            info!(group_id = %invite.group_id, "Accepted invite of verified contact");
+            self.emit(ChatEvent::MemberAdded {
+                group_id: invite.group_id,
+                member: user.to_string(),
+                added_by: invite.inviter,
+            });
+        }
+        Ok(())
+    }
+
+    /// Creator of the group, which stands in for the admins of groups without
+    /// roles.
+    pub(crate) async fn group_creator(
+        &mut self,
+        user: &str,
+        group_uuid: Uuid,
+    ) -> anyhow::Result<Option<String>> {
+        Ok(query_scalar!(
+            "SELECT creator FROM client_group WHERE group_id = ? AND username = ?",
+            group_uuid,
+            user,
+        )
+        .fetch_optional(&mut self.connection)
+        .await?)
+    }
+
+    /// Whether all pinned keys of `contact` were verified.
+    pub(crate) async fn is_verified_contact(
+        &mut self,
+        user: &str,
+        contact: &str,
+    ) -> anyhow::Result<bool> {
+        Ok(query_scalar!(
+            "SELECT COUNT(*) > 0 AND COUNT(*) = COUNT(verified_at) AS \"verified!: bool\"
+            FROM client_contact
+            WHERE username = ? AND contact = ?",
+            user,
+            contact,
+        )
+        .fetch_one(&mut self.connection)
+        .await?)
+    }
+}
+
+impl AcceptPolicy {
+    /// Checks a commit of `committer` to `group` against the policy. `creator`
+    /// stands in for the admins of groups without roles.
+    pub(crate) fn check_commit(
+        &self,
+        group: &MlsGroup,
+        creator: Option<&str>,
+        committer: &str,
+        commit: &StagedCommit,
+        external: bool,
+    ) -> anyhow::Result<()> {
+        let added: Vec<String> = commit
+            .add_proposals()
+            .filter_map(|queued| {
+                identity(queued.add_proposal().key_package().leaf_node().credential())
+            })
+            .collect();
+
+        if self.adds_from_admins_only && (external || !added.is_empty()) {
+            ensure!(!external, "{committer} is not permitted to join by policy");
+            let is_admin = |identity: &str| match group_roles(group.extensions()) {
+                Some(roles) => roles.admins.iter().any(|admin| admin == identity),
+                None => creator == Some(identity),
+            };
+            ensure!(
+                is_admin(committer),
+                "{committer} is not permitted to add members by policy"
+            );
+            for queued in commit.add_proposals() {
+                if let Sender::Member(index) = queued.sender() {
+                    let proposer = group
+                        .member_at(*index)
+                        .and_then(|member| identity(&member.credential))
+                        .context("Unknown proposal sender")?;
+                    ensure!(
+                        is_admin(&proposer),
+                        "{proposer} is not permitted to add members by policy"
+                    );
+                }
+            }
+        }
+
+        if let Some(max_members) = self.max_members {
+            let removed: HashSet<u32> = commit
+                .remove_proposals()
+                .map(|queued| queued.remove_proposal().removed().u32())
+                .collect();
+            let mut members: HashSet<String> = group
+                .members()
+                .filter(|member| !removed.contains(&member.index.u32()))
+                .filter_map(|member| identity(&member.credential))
+                .collect();
+            members.extend(added);
+            if external {
+                members.insert(committer.to_string());
+            }
+            if members.len() > max_members {
+                bail!(
+                    "The group would have {} members, more than {max_members}",
+                    members.len()
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+impl RequiredCapabilities {
+    pub fn is_empty(&self) -> bool {
+        self.extensions.is_empty() && self.credentials.is_empty() && self.ciphersuites.is_empty()
+    }
+
+    /// Adds the requirements to the extensions of a new group. Fails if this
+    /// client could not be a member itself.
+    pub(crate) fn apply(
+        &self,
+        extensions: &mut Extensions<GroupContext>,
+        metadata: &mut GroupMetadata,
+    ) -> anyhow::Result<()> {
+        let own = capabilities();
+        for extension_type in &self.extensions {
+            ensure!(
+                own.extensions().contains(extension_type),
+                "Extension {extension_type:?} is not supported by this client"
+            );
+        }
+        for credential_type in &self.credentials {
+            ensure!(
+                own.credentials().contains(credential_type),
+                "Credential type {credential_type:?} is not supported by this client"
+            );
+        }
+        for &ciphersuite in &self.ciphersuites {
+            check_ciphersuite(ciphersuite)?;
+        }
+
+        if !self.extensions.is_empty() || !self.credentials.is_empty() {
+            extensions.add_or_replace(Extension::RequiredCapabilities(
+                RequiredCapabilitiesExtension::new(&self.extensions, &[], &self.credentials),
+            ))?;
+        }
+        metadata.required_ciphersuites = self
+            .ciphersuites
+            .iter()
+            .map(|&ciphersuite| u16::from(ciphersuite).into())
+            .collect();
+        Ok(())
+    }
+}
+
+/// Checks that the key package of `identity` supports everything the group
+/// requires, before it is added. The error names what is missing, unlike the
+/// one of the commit that would fail later.
+pub(crate) fn check_capabilities(
+    group: &MlsGroup,
+    identity: &str,
+    key_package: &KeyPackage,
+) -> anyhow::Result<()> {
+    let capabilities = key_package.leaf_node().capabilities();
+    if let Some(required) = group.extensions().required_capabilities() {
+        for extension_type in required.extension_types() {
+            ensure!(
+                capabilities.extensions().contains(extension_type),
+                "{identity} does not support the extension {extension_type:?} required by the group"
+            );
+        }
+        for credential_type in required.credential_types() {
+            ensure!(
+                capabilities.credentials().contains(credential_type),
+                "{identity} does not support the credential type {credential_type:?} required by the group"
+            );
+        }
+    }
+
+    let required_ciphersuites = group_metadata(group.extensions())
+        .map(|metadata| metadata.required_ciphersuites)
+        .unwrap_or_default();
+    let ciphersuites = required_ciphersuites
+        .into_iter()
+        .filter_map(|code| u16::try_from(code).ok())
+        .chain([u16::from(group.ciphersuite())]);
+    for code in ciphersuites {
+        ensure!(
+            capabilities
+                .ciphersuites()
+                .iter()
+                .any(|ciphersuite| ciphersuite.value() == code),
+            "{identity} does not support the ciphersuite {code:#06x} required by the group"
+        );
+    }
+    Ok(())
+}
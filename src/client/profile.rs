@@ -0,0 +1,64 @@
+use sqlx::query_scalar;
+use uuid::Uuid;
+
+use crate::client::{
+    Client, Result,
+    error::{bail, not_found},
+};
+
+impl Client {
+    /// Users registered in the database, each a separate identity with its
+    /// own groups, contacts and history.
+    pub async fn profiles(&mut self) -> Result<Vec<String>> {
+        let profiles = query_scalar!("SELECT username FROM client_user ORDER BY username")
+            .fetch_all(&mut self.connection)
+            .await?;
+        Ok(profiles)
+    }
+
+    /// The user that the client acts as, if one was chosen with
+    /// [`Client::switch_profile`].
+    pub fn profile(&self) -> Option<&str> {
+        self.profile.as_deref()
+    }
+
+    /// Makes the registered user `username` the active profile. Events of the
+    /// previous profile that weren't taken yet are dropped.
+    pub async fn switch_profile(&mut self, username: &str) -> Result<()> {
+        let registered = query_scalar!(
+            r#"SELECT EXISTS (SELECT 1 FROM client_user WHERE username = ?) AS "registered!: bool""#,
+            username,
+        )
+        .fetch_one(&mut self.connection)
+        .await?;
+        if !registered {
+            return Err(not_found(format!("Profile {username}")));
+        }
+        if self.profile.as_deref() != Some(username) {
+            self.events.clear();
+            self.profile = Some(username.to_string());
+        }
+        Ok(())
+    }
+
+    /// Fails if another profile of the database is a member of the group, as
+    /// the MLS state of a group is stored once per database.
+    pub(crate) async fn ensure_group_available(
+        &mut self,
+        user: &str,
+        group_uuid: Uuid,
+    ) -> anyhow::Result<()> {
+        let member = query_scalar!(
+            "SELECT username FROM client_group WHERE group_id = ? AND left_at IS NULL",
+            group_uuid,
+        )
+        .fetch_optional(&mut self.connection)
+        .await?;
+        if let Some(member) = member
+            && member != user
+        {
+            bail!("Group {group_uuid} is already joined by profile {member} of this database");
+        }
+        Ok(())
+    }
+}
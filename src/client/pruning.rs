@@ -0,0 +1,159 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use openmls::{
+    group::{GroupId, MlsGroup},
+    prelude::{KeyPackageBundle, KeyPackageRef},
+};
+use openmls_sqlx_storage::Codec;
+use openmls_traits::{OpenMlsProvider, storage::StorageProvider};
+use sqlx::{query_scalar, types::chrono::Utc};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::{
+    client::{Client, Result, message::identity},
+    provider::{CborCodec, JsonCodec, StorageCodec},
+};
+
+/// Key material deleted by [`Client::prune_key_material`].
+#[derive(Debug, Default)]
+pub struct PrunedKeys {
+    /// Key package bundles replaced by newer ones.
+    pub key_packages: usize,
+    /// Groups that were left or replaced, whose MLS state was deleted.
+    pub groups: usize,
+    /// Groups whose own keys were updated, dropping the secrets of their
+    /// previous epoch.
+    pub updated_groups: Vec<Uuid>,
+}
+
+impl Client {
+    /// Deletes key material of `user` that is older than `horizon`, limiting
+    /// what a compromise of the device reveals:
+    ///
+    /// - the private keys of key packages that were replaced by newer ones
+    ///   more than `horizon` ago,
+    /// - the MLS state of groups left or replaced more than `horizon` ago,
+    /// - the secrets of epochs older than `horizon`, by updating the own keys
+    ///   of those groups, see [`Client::rotate_stale_keys`].
+    ///
+    /// The ledger of processed messages forgets those older than `horizon`
+    /// as well.
+    ///
+    /// Secrets of the past epochs kept for
+    /// [`GroupConfig::max_past_epochs`](crate::client::GroupConfig::max_past_epochs)
+    /// are only dropped as the groups move on.
+    pub async fn prune_key_material(
+        &mut self,
+        user: String,
+        horizon: Duration,
+    ) -> Result<PrunedKeys> {
+        let key_packages = self.prune_key_packages(&user, horizon).await?;
+        let groups = self.prune_left_groups(&user, horizon).await?;
+        let processed = self.prune_processed_messages(&user, horizon).await?;
+        if processed > 0 {
+            info!(user, processed, "Forgot processed messages");
+        }
+        let updated_groups = self.rotate_stale_keys(user, horizon).await?;
+        Ok(PrunedKeys {
+            key_packages,
+            groups,
+            updated_groups,
+        })
+    }
+
+    /// Deletes the key package bundles of the user that a newer one of the
+    /// same ciphersuite replaced more than `horizon` ago. The server only
+    /// hands out the latest key package of each ciphersuite, so the older
+    /// ones are only needed for welcomes that were already on the way.
+    async fn prune_key_packages(&mut self, user: &str, horizon: Duration) -> anyhow::Result<usize> {
+        let mut bundles = Vec::new();
+        for hash_ref in self.key_package_refs().await? {
+            let bundle: Option<KeyPackageBundle> =
+                self.provider().storage().key_package(&hash_ref)?;
+            let Some(bundle) = bundle else {
+                continue;
+            };
+            let key_package = bundle.key_package();
+            if identity(key_package.leaf_node().credential()).as_deref() == Some(user) {
+                let created_at = key_package.life_time().not_before();
+                bundles.push((hash_ref, key_package.ciphersuite(), created_at));
+            }
+        }
+
+        let mut latest = HashMap::new();
+        for (_, ciphersuite, created_at) in &bundles {
+            let latest_created_at = latest.entry(*ciphersuite).or_insert(*created_at);
+            *latest_created_at = (*latest_created_at).max(*created_at);
+        }
+        let cutoff = SystemTime::now()
+            .checked_sub(horizon)
+            .and_then(|cutoff| cutoff.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |cutoff| cutoff.as_secs());
+        let mut pruned = 0;
+        for (hash_ref, ciphersuite, created_at) in bundles {
+            let replaced_at = latest[&ciphersuite];
+            if created_at < replaced_at && replaced_at < cutoff {
+                self.delete_key_package(&hash_ref).await?;
+                pruned += 1;
+            }
+        }
+        if pruned > 0 {
+            info!(user, pruned, "Deleted replaced key packages");
+        }
+        Ok(pruned)
+    }
+
+    /// References of all key package bundles in the database.
+    async fn key_package_refs(&mut self) -> anyhow::Result<Vec<KeyPackageRef>> {
+        // Memory storage is lost with the client anyway.
+        if self.memory_storage.is_some() {
+            return Ok(Vec::new());
+        }
+        let keys: Vec<Vec<u8>> =
+            sqlx::query_scalar("SELECT key_package_ref FROM openmls_key_package")
+                .fetch_all(&mut self.connection)
+                .await?;
+        keys.iter()
+            .map(|key| match self.storage_codec {
+                StorageCodec::Json => Ok(JsonCodec::from_slice(key)?),
+                StorageCodec::Cbor => Ok(CborCodec::from_slice(key)?),
+            })
+            .collect()
+    }
+
+    /// Deletes the MLS state of groups the user left or that were replaced
+    /// more than `horizon` ago. Their history stays.
+    async fn prune_left_groups(&mut self, user: &str, horizon: Duration) -> anyhow::Result<usize> {
+        let left_before = Utc::now() - horizon;
+        let group_ids = query_scalar!(
+            "SELECT group_id AS \"group_id: Uuid\"
+            FROM client_group
+            WHERE username = ? AND left_at < ?",
+            user,
+            left_before,
+        )
+        .fetch_all(&mut self.connection)
+        .await?;
+
+        let mut pruned = 0;
+        for group_uuid in group_ids {
+            let provider = self.provider();
+            let group_id = GroupId::from_slice(group_uuid.as_bytes());
+            let Some(mut group) = MlsGroup::load(provider.storage(), &group_id)? else {
+                continue;
+            };
+            if let Err(error) = group.delete(provider.storage()) {
+                warn!(%group_uuid, %error, "Failed to delete group state");
+                continue;
+            }
+            self.drop_pending_messages(user, group_uuid).await?;
+            info!(%group_uuid, "Deleted state of left group");
+            pruned += 1;
+        }
+        Ok(pruned)
+    }
+}
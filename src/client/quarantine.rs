@@ -0,0 +1,178 @@
+use openmls::prelude::{
+    MlsMessageBodyIn, MlsMessageIn, ProtocolMessage, tls_codec::DeserializeBytes,
+};
+use sqlx::{
+    TransactionManager, query, query_scalar,
+    sqlite::SqliteTransactionManager,
+    types::chrono::{DateTime, Utc},
+};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::client::{Client, ClientError, Result, error::not_found, events::ChatEvent};
+
+/// A received message that couldn't be processed, see
+/// [`Client::poison_messages`].
+#[derive(Debug, Clone)]
+pub struct PoisonMessage {
+    pub message_id: i64,
+    /// Group and epoch of the message, unknown if it doesn't parse.
+    pub group_id: Option<Uuid>,
+    pub epoch: Option<u64>,
+    /// Why processing failed the last time.
+    pub error: String,
+    pub size: usize,
+    pub received_at: DateTime<Utc>,
+}
+
+impl Client {
+    /// The quarantined messages of the user, oldest first.
+    pub async fn poison_messages(&mut self, user: String) -> Result<Vec<PoisonMessage>> {
+        let rows = query!(
+            "SELECT
+                message_id,
+                group_id AS \"group_id: Uuid\",
+                epoch,
+                error,
+                length(content) AS \"size!: i64\",
+                received_at AS \"received_at: DateTime<Utc>\"
+            FROM client_poison_message
+            WHERE username = ?
+            ORDER BY message_id",
+            user,
+        )
+        .fetch_all(&mut self.connection)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(|row| PoisonMessage {
+                message_id: row.message_id,
+                group_id: row.group_id,
+                epoch: row.epoch.map(|epoch| epoch as u64),
+                error: row.error,
+                size: row.size as usize,
+                received_at: row.received_at,
+            })
+            .collect())
+    }
+
+    /// Processes a quarantined message again, e.g. after an update of the
+    /// client added support for what it failed on. It leaves the quarantine
+    /// if that succeeds, with its events left for [`Client::drain_events`].
+    /// Otherwise it stays with the new error, which is returned.
+    pub async fn retry_poison_message(&mut self, user: String, message_id: i64) -> Result<()> {
+        let content = query_scalar!(
+            "SELECT content FROM client_poison_message WHERE message_id = ? AND username = ?",
+            message_id,
+            user,
+        )
+        .fetch_optional(&mut self.connection)
+        .await?
+        .ok_or_else(|| not_found("Quarantined message"))?;
+        match self.handle_message(&user, None, &content).await {
+            Ok(()) => {
+                query!(
+                    "DELETE FROM client_poison_message WHERE message_id = ?",
+                    message_id
+                )
+                .execute(&mut self.connection)
+                .await?;
+                info!(message_id, "Processed quarantined message");
+                Ok(())
+            }
+            Err(error) => {
+                let message = error.to_string();
+                query!(
+                    "UPDATE client_poison_message SET error = ? WHERE message_id = ?",
+                    message,
+                    message_id,
+                )
+                .execute(&mut self.connection)
+                .await?;
+                Err(error.into())
+            }
+        }
+    }
+
+    /// Handles a message received from the delivery service. A message that
+    /// can't be processed, e.g. as it doesn't parse or decrypt, is moved to
+    /// the quarantine and reported with [`ChatEvent::DecryptionFailed`], so
+    /// that it doesn't hold up the ones after it. Failures of the database or
+    /// the connection are returned, as the message may well be fine.
+    /// Quarantined messages can be retried with
+    /// [`Client::retry_poison_message`].
+    ///
+    /// The message counts as processed in either case, see
+    /// [`Client::handle_message`].
+    pub(crate) async fn receive_message(
+        &mut self,
+        user: &str,
+        key: Option<&[u8]>,
+        content: &[u8],
+    ) -> anyhow::Result<()> {
+        let Err(error) = self.handle_message(user, key, content).await else {
+            return Ok(());
+        };
+        let error = match ClientError::from(error) {
+            error @ ClientError::Storage(_) => return Err(error.into()),
+            error if error.is_transient() => return Err(error.into()),
+            error => error,
+        };
+        self.quarantine(user, key, content, error).await
+    }
+
+    /// Moves a message that failed with `error` to the quarantine, recording
+    /// it as processed, see [`Client::receive_message`].
+    pub(crate) async fn quarantine(
+        &mut self,
+        user: &str,
+        key: Option<&[u8]>,
+        content: &[u8],
+        error: ClientError,
+    ) -> anyhow::Result<()> {
+        let (group_id, epoch) = message_origin(content).unzip();
+        warn!(?group_id, ?epoch, %error, "Quarantining message that can't be processed");
+        let error = error.to_string();
+        let received_at: DateTime<Utc> = Utc::now();
+        let epoch_column = epoch.map(|epoch| epoch as i64);
+        SqliteTransactionManager::begin(&mut self.connection, None).await?;
+        if let Some(key) = key {
+            self.record_processed(user, key).await?;
+        }
+        let message_id = query!(
+            "INSERT INTO client_poison_message (
+                username, group_id, epoch, content, error, received_at
+            ) VALUES (?, ?, ?, ?, ?, ?)",
+            user,
+            group_id,
+            epoch_column,
+            content,
+            error,
+            received_at,
+        )
+        .execute(&mut self.connection)
+        .await?
+        .last_insert_rowid();
+        SqliteTransactionManager::commit(&mut self.connection).await?;
+        self.emit(ChatEvent::DecryptionFailed {
+            group_id,
+            epoch,
+            error,
+            quarantined: Some(message_id),
+        });
+        Ok(())
+    }
+}
+
+/// Group and epoch of a protocol message, `None` for welcomes and anything
+/// that doesn't parse.
+fn message_origin(content: &[u8]) -> Option<(Uuid, u64)> {
+    let message = MlsMessageIn::tls_deserialize_exact_bytes(content).ok()?;
+    let message = match message.extract() {
+        MlsMessageBodyIn::PublicMessage(message) => ProtocolMessage::from(message),
+        MlsMessageBodyIn::PrivateMessage(message) => ProtocolMessage::from(message),
+        _ => return None,
+    };
+    let group_id = Uuid::from_slice(message.group_id().as_slice()).ok()?;
+    Some((group_id, message.epoch().as_u64()))
+}
@@ -0,0 +1,214 @@
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use futures_util::{StreamExt, stream};
+use quinn::{
+    ClientConfig, Connection, Endpoint, RecvStream, TransportConfig,
+    crypto::rustls::QuicClientConfig,
+};
+use rustls::RootCertStore;
+use rustls_pki_types::{CertificateDer, pem::PemObject};
+use tokio::{net::lookup_host, sync::Mutex, time::timeout};
+use tonic::Status;
+use tracing::{debug, warn};
+
+use crate::{
+    client::{
+        delivery::MessageStream,
+        frames::{FrameTransport, unexpected_response},
+    },
+    grpc::{
+        ReceiveMessagesRequest, WebSocketRequest, WebSocketResponse, web_socket_request::Request,
+        web_socket_response::Response,
+    },
+    quic::{ALPN, read_frame, write_frame},
+};
+
+/// Keepalive without one configured, well within the idle timeout of the
+/// server.
+const DEFAULT_KEEPALIVE: Duration = Duration::from_secs(5);
+
+/// Connection options of a [`QuicDeliveryService`], see
+/// [`ClientBuilder`](crate::client::builder::ClientBuilder).
+#[derive(Debug, Default)]
+pub(crate) struct QuicOptions {
+    /// PEM of the CA certificate to trust instead of the native roots.
+    pub(crate) ca_certificate: Option<Vec<u8>>,
+    pub(crate) connect_timeout: Option<Duration>,
+    pub(crate) timeout: Option<Duration>,
+    pub(crate) keepalive: Option<Duration>,
+}
+
+/// The delivery service of a server reached over QUIC, for `quic://`
+/// endpoints, see [`crate::quic::ALPN`]. Connects on the first request and
+/// again after the connection broke.
+#[derive(Debug)]
+pub(crate) struct QuicDeliveryService {
+    host: String,
+    port: u16,
+    options: QuicOptions,
+    connection: Mutex<Option<(Endpoint, Connection)>>,
+}
+
+impl QuicDeliveryService {
+    pub(crate) fn new(endpoint: &str, options: QuicOptions) -> Result<Self, Status> {
+        let uri: tonic::codegen::http::Uri = endpoint
+            .parse()
+            .map_err(|error| Status::invalid_argument(format!("Invalid endpoint: {error}")))?;
+        let (Some(host), Some(port)) = (uri.host(), uri.port_u16()) else {
+            return Err(Status::invalid_argument(
+                "QUIC endpoints need a host and a port",
+            ));
+        };
+        Ok(Self {
+            host: host.trim_matches(['[', ']']).to_string(),
+            port,
+            options,
+            connection: Mutex::new(None),
+        })
+    }
+
+    async fn connection(&self) -> Result<Connection, Status> {
+        let mut connection = self.connection.lock().await;
+        if let Some((_, connection)) = &*connection
+            && connection.close_reason().is_none()
+        {
+            return Ok(connection.clone());
+        }
+        let connect = self.connect();
+        let connected = match self.options.connect_timeout {
+            Some(connect_timeout) => timeout(connect_timeout, connect)
+                .await
+                .map_err(|_| Status::unavailable("Timed out connecting to the server"))?,
+            None => connect.await,
+        }?;
+        let established = connected.1.clone();
+        *connection = Some(connected);
+        Ok(established)
+    }
+
+    async fn connect(&self) -> Result<(Endpoint, Connection), Status> {
+        let address = lookup_host((self.host.as_str(), self.port))
+            .await
+            .map_err(|error| Status::unavailable(error.to_string()))?
+            .next()
+            .ok_or_else(|| Status::unavailable(format!("{} not found", self.host)))?;
+        let local: SocketAddr = if address.is_ipv4() {
+            ([0, 0, 0, 0], 0).into()
+        } else {
+            ([0u16; 8], 0).into()
+        };
+        let mut endpoint =
+            Endpoint::client(local).map_err(|error| Status::unavailable(error.to_string()))?;
+        endpoint.set_default_client_config(self.client_config()?);
+
+        debug!(%address, host = self.host, "Connecting QUIC");
+        let connection = endpoint
+            .connect(address, &self.host)
+            .map_err(|error| Status::invalid_argument(error.to_string()))?
+            .await
+            .map_err(|error| Status::unavailable(error.to_string()))?;
+        Ok((endpoint, connection))
+    }
+
+    fn client_config(&self) -> Result<ClientConfig, Status> {
+        let mut roots = RootCertStore::empty();
+        match &self.options.ca_certificate {
+            Some(pem) => {
+                for cert in CertificateDer::pem_slice_iter(pem) {
+                    let cert = cert.map_err(|error| {
+                        Status::invalid_argument(format!("Invalid CA certificate: {error}"))
+                    })?;
+                    roots.add(cert).map_err(|error| {
+                        Status::invalid_argument(format!("Invalid CA certificate: {error}"))
+                    })?;
+                }
+            }
+            None => {
+                let native = rustls_native_certs::load_native_certs();
+                for error in native.errors {
+                    warn!(%error, "Failed to load native root certificate");
+                }
+                roots.add_parsable_certificates(native.certs);
+            }
+        }
+        let mut crypto = rustls::ClientConfig::builder_with_provider(Arc::new(
+            rustls::crypto::ring::default_provider(),
+        ))
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .map_err(|error| Status::internal(error.to_string()))?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+        crypto.alpn_protocols = vec![ALPN.to_vec()];
+        let crypto = QuicClientConfig::try_from(crypto)
+            .map_err(|error| Status::internal(error.to_string()))?;
+
+        let mut transport = TransportConfig::default();
+        transport.keep_alive_interval(Some(self.options.keepalive.unwrap_or(DEFAULT_KEEPALIVE)));
+        let mut config = ClientConfig::new(Arc::new(crypto));
+        config.transport_config(Arc::new(transport));
+        Ok(config)
+    }
+
+    /// Sends `request` on a stream of its own, returning the stream of its
+    /// responses. Dropping it cancels the request.
+    async fn start(&self, request: Request) -> Result<RecvStream, Status> {
+        let connection = self.connection().await?;
+        let (mut send, recv) = connection
+            .open_bi()
+            .await
+            .map_err(|error| Status::unavailable(error.to_string()))?;
+        let request = WebSocketRequest {
+            id: 0,
+            request: Some(request),
+        };
+        write_frame(&mut send, &request).await?;
+        send.finish()
+            .map_err(|error| Status::unavailable(error.to_string()))?;
+        Ok(recv)
+    }
+}
+
+/// Reads the next response, `None` when the stream ended without one.
+async fn read_response(responses: &mut RecvStream) -> Result<Option<Response>, Status> {
+    let response: Option<WebSocketResponse> = read_frame(responses).await?;
+    Ok(response.and_then(|response| response.response))
+}
+
+#[tonic::async_trait]
+impl FrameTransport for QuicDeliveryService {
+    async fn unary(&self, request: Request) -> Result<Response, Status> {
+        let mut responses = self.start(request).await?;
+        let response = read_response(&mut responses);
+        let response = match self.options.timeout {
+            Some(request_timeout) => timeout(request_timeout, response)
+                .await
+                .map_err(|_| Status::deadline_exceeded("Request timed out"))?,
+            None => response.await,
+        };
+        match response?.ok_or_else(|| Status::unavailable("Stream ended without a response"))? {
+            Response::Status(status) => Err(status.into()),
+            response => Ok(response),
+        }
+    }
+
+    async fn receive_messages(
+        &self,
+        request: ReceiveMessagesRequest,
+    ) -> Result<MessageStream, Status> {
+        let responses = self.start(Request::ReceiveMessages(request)).await?;
+        let messages = stream::unfold(Some(responses), |responses| async move {
+            let mut responses = responses?;
+            let message = match read_response(&mut responses).await {
+                Ok(Some(Response::ReceiveMessages(message))) => Ok(message),
+                Ok(Some(Response::Status(status))) if status.code == 0 => return None,
+                Ok(Some(Response::Status(status))) => Err(status.into()),
+                Ok(Some(_)) => Err(unexpected_response()),
+                Ok(None) => Err(Status::unavailable("Stream ended without a status")),
+                Err(status) => Err(status),
+            };
+            let responses = message.is_ok().then_some(responses);
+            Some((message, responses))
+        });
+        Ok(messages.boxed())
+    }
+}
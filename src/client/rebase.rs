@@ -0,0 +1,110 @@
+use openmls::{
+    group::{GroupId, MlsGroup},
+    prelude::{MlsMessageOut, tls_codec::Serialize},
+};
+use openmls_traits::OpenMlsProvider;
+use tonic::{Code, Status};
+use uuid::Uuid;
+
+use crate::{
+    client::{Client, backlog::Batch, error::not_found},
+    grpc::FetchMessagesRequest,
+};
+
+/// How often an operation is repeated after losing the race for an epoch.
+pub(crate) const MAX_REBASES: usize = 3;
+
+/// Runs `$operation` with `$client` bound to the client and, if it lost the
+/// race for an epoch against a concurrent commit, processes the winning commit
+/// and runs it again on top of it. A macro rather than a method taking an async
+/// closure, whose futures the compiler can't show to be `Send`.
+macro_rules! rebasing {
+    ($this:expr, $user:expr, |$client:ident| $operation:expr) => {{
+        let mut rebases = 0;
+        loop {
+            let $client = &mut *$this;
+            match $operation.await {
+                Err(error)
+                    if $crate::client::rebase::is_epoch_conflict(&error)
+                        && rebases < $crate::client::rebase::MAX_REBASES =>
+                {
+                    rebases += 1;
+                    tracing::warn!(%error, rebases, "Lost the race for an epoch, catching up");
+                    $this.catch_up($user).await?;
+                }
+                result => break result,
+            }
+        }
+    }};
+}
+
+pub(crate) use rebasing;
+
+impl Client {
+    /// Sends the pending commit of the group and merges it once the server
+    /// accepted it. If another commit won the epoch, the pending commit is
+    /// discarded and the error returned, see [`is_epoch_conflict`].
+    pub(crate) async fn send_commit(
+        &mut self,
+        user: &str,
+        group_uuid: Uuid,
+        recipients: Vec<String>,
+        commit: &MlsMessageOut,
+    ) -> anyhow::Result<()> {
+        let result = self
+            .fanout(user, recipients, commit.tls_serialize_detached()?)
+            .await;
+
+        let group_id = GroupId::from_slice(group_uuid.as_bytes());
+        let provider = self.provider();
+        let mut group =
+            MlsGroup::load(provider.storage(), &group_id)?.ok_or_else(|| not_found("Group"))?;
+        match result {
+            Ok(_) => group.merge_pending_commit(&provider)?,
+            Err(error) => {
+                group.clear_pending_commit(provider.storage())?;
+                return Err(error);
+            }
+        }
+        Ok(())
+    }
+
+    /// Processes the messages that are queued on the server for this device
+    /// right now, without waiting for further ones, in batches of consecutive
+    /// messages of the same group. Fetches them rather than opening a stream
+    /// of messages, which would take over the stream of a running receive
+    /// loop, see [`Client::open_stream`].
+    pub(crate) async fn catch_up(&mut self, user: &str) -> anyhow::Result<()> {
+        let device_id = self.device_id(user).await?;
+        loop {
+            let response = self
+                .client
+                .fetch_messages(FetchMessagesRequest {
+                    client_id: user.to_string(),
+                    device_id: device_id.clone(),
+                    limit: 0,
+                })
+                .await?;
+            let mut batch = Batch::default();
+            for message in response.messages {
+                if let Some(done) = batch.push(message) {
+                    self.handle_batch(user, done).await?;
+                }
+            }
+            if !batch.is_empty() {
+                self.handle_batch(user, batch).await?;
+            }
+            if !response.more {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Whether the server rejected a message because a concurrent commit moved the
+/// group to a newer epoch first.
+pub(crate) fn is_epoch_conflict(error: &anyhow::Error) -> bool {
+    error
+        .downcast_ref::<Status>()
+        .is_some_and(|status| status.code() == Code::Aborted)
+}
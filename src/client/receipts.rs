@@ -0,0 +1,232 @@
+use std::collections::BTreeMap;
+
+use openmls::group::{GroupId, MlsGroup};
+use openmls_traits::OpenMlsProvider;
+use prost::Message;
+use sqlx::{query, query_scalar, types::chrono::Utc};
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+use crate::{
+    client::{Client, Result, error::not_found, roles::may_send},
+    grpc::{ContentType, Receipt, ReceiptType},
+};
+
+impl Client {
+    /// Marks all received messages of the group as read and, unless disabled
+    /// for the group, tells their senders. Returns the number of messages that
+    /// were unread.
+    pub async fn mark_read(&mut self, user: String, group_uuid: Uuid) -> Result<u64> {
+        let read_receipts = query_scalar!(
+            "SELECT read_receipts AS \"read_receipts: bool\" FROM client_group
+            WHERE group_id = ? AND username = ?",
+            group_uuid,
+            user,
+        )
+        .fetch_optional(&mut self.connection)
+        .await?
+        .ok_or_else(|| not_found("Group"))?;
+
+        let message_uuids = query_scalar!(
+            "SELECT message_uuid AS \"message_uuid: Uuid\" FROM client_message
+            WHERE group_id = ? AND username = ? AND direction = 'in' AND read_at IS NULL
+                AND sender != ?
+            ORDER BY message_id",
+            group_uuid,
+            user,
+            user,
+        )
+        .fetch_all(&mut self.connection)
+        .await?;
+        let read_at = Utc::now();
+        let read = query!(
+            "UPDATE client_message SET read_at = ?
+            WHERE group_id = ? AND username = ? AND direction = 'in' AND read_at IS NULL",
+            read_at,
+            group_uuid,
+            user,
+        )
+        .execute(&mut self.connection)
+        .await?
+        .rows_affected();
+        query!(
+            "UPDATE client_group SET last_read_message_id = (
+                SELECT MAX(message_id) FROM client_message WHERE group_id = ? AND username = ?
+            )
+            WHERE group_id = ? AND username = ?",
+            group_uuid,
+            user,
+            group_uuid,
+            user,
+        )
+        .execute(&mut self.connection)
+        .await?;
+
+        // Messages of older clients have no id to refer to.
+        let message_uuids: Vec<Uuid> = message_uuids.into_iter().flatten().collect();
+        if read_receipts && !message_uuids.is_empty() {
+            self.send_receipt(&user, group_uuid, ReceiptType::Read, &message_uuids)
+                .await?;
+        }
+        Ok(read)
+    }
+
+    /// Whether read receipts are sent to the group, which they are unless
+    /// disabled.
+    pub async fn set_read_receipts(
+        &mut self,
+        user: String,
+        group_uuid: Uuid,
+        enabled: bool,
+    ) -> Result<()> {
+        let updated = query!(
+            "UPDATE client_group SET read_receipts = ? WHERE group_id = ? AND username = ?",
+            enabled,
+            group_uuid,
+            user,
+        )
+        .execute(&mut self.connection)
+        .await?
+        .rows_affected();
+        if updated == 0 {
+            return Err(not_found("Group"));
+        }
+        Ok(())
+    }
+
+    /// Sends the delivery receipts of received messages, one receipt message
+    /// per group. Receipts are best effort and not retried.
+    pub(crate) async fn send_receipts(&mut self, user: &str) -> anyhow::Result<()> {
+        let rows = query!(
+            "SELECT
+                message_id,
+                group_id AS \"group_id: Uuid\",
+                message_uuid AS \"message_uuid!: Uuid\"
+            FROM client_message
+            WHERE username = ? AND receipt_pending
+            ORDER BY message_id",
+            user,
+        )
+        .fetch_all(&mut self.connection)
+        .await?;
+
+        let mut groups: BTreeMap<Uuid, (i64, Vec<Uuid>)> = BTreeMap::new();
+        for row in rows {
+            let (last_id, message_uuids) = groups.entry(row.group_id).or_default();
+            *last_id = row.message_id;
+            message_uuids.push(row.message_uuid);
+        }
+
+        for (group_uuid, (last_id, message_uuids)) in groups {
+            self.send_receipt(user, group_uuid, ReceiptType::Delivered, &message_uuids)
+                .await?;
+            query!(
+                "UPDATE client_message SET receipt_pending = FALSE
+                WHERE username = ? AND group_id = ? AND message_id <= ?",
+                user,
+                group_uuid,
+                last_id,
+            )
+            .execute(&mut self.connection)
+            .await?;
+        }
+        Ok(())
+    }
+
+    /// Sends a receipt for `message_uuids` to the group. Failures are only
+    /// logged, as receipts are best effort.
+    async fn send_receipt(
+        &mut self,
+        user: &str,
+        group_uuid: Uuid,
+        receipt_type: ReceiptType,
+        message_uuids: &[Uuid],
+    ) -> anyhow::Result<()> {
+        let group_id = GroupId::from_slice(group_uuid.as_bytes());
+        let group = MlsGroup::load(self.provider().storage(), &group_id)?;
+        // Members of read-only groups can't send anything.
+        if !group.is_some_and(|group| group.is_active() && may_send(&group, user)) {
+            return Ok(());
+        }
+
+        let receipt = Receipt {
+            receipt_type: receipt_type.into(),
+            message_ids: message_uuids
+                .iter()
+                .map(|uuid| uuid.as_bytes().to_vec())
+                .collect(),
+        };
+        if let Err(error) = self
+            .send_content(
+                user,
+                group_uuid,
+                ContentType::Receipt,
+                Uuid::new_v4(),
+                receipt.encode_to_vec(),
+            )
+            .await
+        {
+            warn!(%group_uuid, ?receipt_type, %error, "Failed to send receipt");
+        }
+        Ok(())
+    }
+
+    /// Records that `sender` received or read the messages acknowledged by
+    /// `body`, and marks own ones as delivered.
+    pub(crate) async fn handle_receipt(
+        &mut self,
+        user: &str,
+        group_uuid: Uuid,
+        sender: &str,
+        body: &[u8],
+    ) -> anyhow::Result<()> {
+        let receipt = Receipt::decode(body)?;
+        let receipt_type = ReceiptType::try_from(receipt.receipt_type);
+        let read = match receipt_type {
+            Ok(ReceiptType::Delivered) => false,
+            Ok(ReceiptType::Read) => true,
+            _ => {
+                debug!(%group_uuid, sender, ?receipt_type, "Ignoring receipt of unknown type");
+                return Ok(());
+            }
+        };
+
+        let now = Utc::now();
+        let read_at = read.then_some(now);
+        for message_id in receipt.message_ids {
+            let Ok(message_uuid) = Uuid::from_slice(&message_id) else {
+                continue;
+            };
+            // Read messages were delivered as well, even if the delivery
+            // receipt got lost.
+            query!(
+                "INSERT INTO client_receipt (message_id, member, delivered_at, read_at)
+                SELECT message_id, ?, ?, ? FROM client_message
+                WHERE username = ? AND group_id = ? AND message_uuid = ? AND sender != ?
+                ON CONFLICT (message_id, member)
+                DO UPDATE SET read_at = COALESCE(read_at, excluded.read_at)",
+                sender,
+                now,
+                read_at,
+                user,
+                group_uuid,
+                message_uuid,
+                sender,
+            )
+            .execute(&mut self.connection)
+            .await?;
+            query!(
+                "UPDATE client_message SET send_status = 'delivered'
+                WHERE username = ? AND group_id = ? AND message_uuid = ? AND sender != ?
+                    AND send_status = 'sent'",
+                user,
+                group_uuid,
+                message_uuid,
+                sender,
+            )
+            .execute(&mut self.connection)
+            .await?;
+        }
+        Ok(())
+    }
+}
@@ -1,24 +1,33 @@
 use anyhow::{Context, anyhow};
 use openmls::prelude::{
-    BasicCredential, Capabilities, Credential, CredentialWithKey, ExtensionType, KeyPackage,
-    OpenMlsCrypto, SignaturePublicKey, SignatureScheme, tls_codec::Serialize,
+    BasicCredential, Ciphersuite, Credential, CredentialWithKey, KeyPackage, OpenMlsCrypto,
+    SignaturePublicKey, SignatureScheme, tls_codec::Serialize,
 };
 use openmls_rust_crypto::RustCrypto;
 use openmls_sqlx_storage::Codec;
 use openmls_traits::signatures::{Signer, SignerError};
-use sqlx::query;
+use sqlx::{query, query_scalar};
 
 use crate::{
-    client::Client,
+    client::{
+        Client, Result,
+        error::{ensure, not_found},
+        signer::IdentitySigner,
+    },
     grpc::{self, UploadKeyPackageRequest},
-    provider::{CIPHERSUITE, JsonCodec},
+    provider::{JsonCodec, SUPPORTED_CIPHERSUITES, capabilities, check_ciphersuite},
 };
 
 impl Client {
-    pub async fn register(&mut self, username: String) -> anyhow::Result<()> {
+    /// Registers `username` with new groups using `ciphersuite` by default.
+    pub async fn register(&mut self, username: String, ciphersuite: Ciphersuite) -> Result<()> {
+        check_ciphersuite(ciphersuite)?;
         let credential: Credential = BasicCredential::new(username.as_bytes().to_vec()).into();
 
-        let (signature_private_key, signature_key) = SignaturePrivateKey::generate();
+        let (signature_private_key, signature_key) = self.new_identity_key(&username);
+        let (private_key_column, keychain_account) = self
+            .store_identity_key(&signature_private_key, &signature_key)
+            .await?;
 
         let credential_with_key = CredentialWithKey {
             credential,
@@ -26,77 +35,246 @@ impl Client {
         };
 
         let credential_with_key_blob = JsonCodec::to_vec(&credential_with_key)?;
+        let ciphersuite_code = u16::from(ciphersuite);
         query!(
             "INSERT INTO client_user (
                 username,
                 signature_private_key,
-                credential_with_key
-            ) VALUES (?, ?, ?)",
+                credential_with_key,
+                ciphersuite,
+                keychain_account
+            ) VALUES (?, ?, ?, ?, ?)",
             username,
-            signature_private_key.key,
+            private_key_column,
             credential_with_key_blob,
+            ciphersuite_code,
+            keychain_account,
         )
         .execute(&mut self.connection)
         .await?;
 
-        let key_package_bundle = KeyPackage::builder()
-            .leaf_node_capabilities(
-                Capabilities::builder()
-                    .extensions(vec![ExtensionType::LastResort])
-                    .build(),
+        self.upload_key_package(
+            username,
+            String::new(),
+            &signature_private_key,
+            credential_with_key,
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    /// Uploads a key package for each supported ciphersuite, so that the device
+    /// can be added to groups of any of them.
+    pub(crate) async fn upload_key_package(
+        &mut self,
+        username: String,
+        device_id: String,
+        signature_private_key: &IdentitySigner,
+        credential_with_key: CredentialWithKey,
+    ) -> anyhow::Result<()> {
+        for &ciphersuite in SUPPORTED_CIPHERSUITES {
+            self.upload_ciphersuite_key_package(
+                &username,
+                &device_id,
+                signature_private_key,
+                credential_with_key.clone(),
+                ciphersuite,
             )
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Generates a key package of `ciphersuite`, which replaces the previous
+    /// one on the server.
+    async fn upload_ciphersuite_key_package(
+        &mut self,
+        username: &str,
+        device_id: &str,
+        signature_private_key: &IdentitySigner,
+        credential_with_key: CredentialWithKey,
+        ciphersuite: Ciphersuite,
+    ) -> anyhow::Result<()> {
+        let key_package_bundle = KeyPackage::builder()
+            .leaf_node_capabilities(capabilities())
             .mark_as_last_resort()
             .build(
-                CIPHERSUITE,
+                ciphersuite,
                 &self.provider(),
-                &signature_private_key,
+                signature_private_key,
                 credential_with_key,
             )?;
+        let key_package_ref = self
+            .track_key_package(username, &key_package_bundle)
+            .await?;
 
         self.client
             .upload_key_package(UploadKeyPackageRequest {
-                client_id: username.clone(),
+                client_id: username.to_string(),
                 key_package: Some(grpc::KeyPackage {
                     key_package_bytes: key_package_bundle.key_package().tls_serialize_detached()?,
                 }),
+                device_id: device_id.to_string(),
             })
             .await?;
+        self.mark_key_package_uploaded(&key_package_ref).await?;
 
         Ok(())
     }
 
+    /// Replaces the key package of `ciphersuite` on the server, e.g. after a
+    /// welcome consumed it.
+    pub(crate) async fn replace_key_package(
+        &mut self,
+        username: &str,
+        ciphersuite: Ciphersuite,
+    ) -> anyhow::Result<()> {
+        let device_id = self.device_id(username).await?;
+        let (signature_private_key, credential_with_key) = self.credential(username).await?;
+        self.upload_ciphersuite_key_package(
+            username,
+            &device_id,
+            &signature_private_key,
+            credential_with_key,
+            ciphersuite,
+        )
+        .await
+    }
+
+    /// Replaces the key packages of the device with fresh ones, before the
+    /// current ones expire.
+    pub async fn replenish_key_packages(&mut self, username: &str) -> Result<()> {
+        let device_id = self.device_id(username).await?;
+        let (signature_private_key, credential_with_key) = self.credential(username).await?;
+        self.upload_key_package(
+            username.to_string(),
+            device_id,
+            &signature_private_key,
+            credential_with_key,
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Ciphersuite of new groups of the user, chosen at registration.
+    pub(crate) async fn default_ciphersuite(
+        &mut self,
+        username: &str,
+    ) -> anyhow::Result<Ciphersuite> {
+        let code = query_scalar!(
+            "SELECT ciphersuite FROM client_user WHERE username = ?",
+            username
+        )
+        .fetch_optional(&mut self.connection)
+        .await?
+        .ok_or_else(|| not_found(format!("User {username}")))?;
+        let ciphersuite = u16::try_from(code)
+            .ok()
+            .and_then(|code| Ciphersuite::try_from(code).ok())
+            .with_context(|| anyhow!("Invalid ciphersuite {code}"))?;
+        Ok(ciphersuite)
+    }
+
+    /// Identity key and credential of the user. The key is the one of the
+    /// [`ChatSigner`](crate::client::signer::ChatSigner) configured for the
+    /// user, if any.
     pub(crate) async fn credential(
         &mut self,
         username: &str,
-    ) -> anyhow::Result<(SignaturePrivateKey, CredentialWithKey)> {
+    ) -> anyhow::Result<(IdentitySigner, CredentialWithKey)> {
         let record = sqlx::query!(
             "SELECT
                 signature_private_key,
-                credential_with_key
+                credential_with_key,
+                keychain_account
             FROM client_user
             WHERE username = ?",
             username
         )
         .fetch_optional(&mut self.connection)
         .await?
-        .with_context(|| anyhow!("User {username} is not registered"))?;
+        .ok_or_else(|| not_found(format!("User {username}")))?;
 
-        let signature_private_key = SignaturePrivateKey {
-            key: record.signature_private_key,
-        };
         let credential_with_key: CredentialWithKey =
             JsonCodec::from_slice(&record.credential_with_key)?;
+        if let Some(signer) = self.signers.get(username) {
+            ensure!(
+                signer.public_key() == credential_with_key.signature_key.as_slice(),
+                "Signer of {username} doesn't hold its identity key"
+            );
+            return Ok((
+                IdentitySigner::External(signer.clone()),
+                credential_with_key,
+            ));
+        }
+        ensure!(
+            !record.signature_private_key.is_empty() || record.keychain_account.is_some(),
+            "Identity key of {username} is held by an external signer, which isn't configured"
+        );
+        let signature_private_key = SignaturePrivateKey {
+            key: self
+                .load_private_key(record.signature_private_key, record.keychain_account)
+                .await?,
+        };
+
+        Ok((
+            IdentitySigner::Local(signature_private_key),
+            credential_with_key,
+        ))
+    }
+
+    /// A new identity key of the user, the one of its
+    /// [`ChatSigner`](crate::client::signer::ChatSigner) if configured.
+    pub(crate) fn new_identity_key(&self, username: &str) -> (IdentitySigner, SignaturePublicKey) {
+        match self.signers.get(username) {
+            Some(signer) => (
+                IdentitySigner::External(signer.clone()),
+                SignaturePublicKey::from(signer.public_key()),
+            ),
+            None => {
+                let (signature_private_key, signature_key) = SignaturePrivateKey::generate();
+                (IdentitySigner::Local(signature_private_key), signature_key)
+            }
+        }
+    }
+
+    /// Values of the private key and keychain account columns of an identity
+    /// key. Keys of a [`ChatSigner`](crate::client::signer::ChatSigner) leave
+    /// both empty.
+    pub(crate) async fn store_identity_key(
+        &self,
+        signer: &IdentitySigner,
+        signature_key: &SignaturePublicKey,
+    ) -> anyhow::Result<(Vec<u8>, Option<String>)> {
+        match signer {
+            IdentitySigner::Local(private_key) => {
+                self.store_private_key(signature_key.as_slice(), &private_key.key)
+                    .await
+            }
+            IdentitySigner::External(_) => Ok((Vec::new(), None)),
+        }
+    }
 
-        Ok((signature_private_key, credential_with_key))
+    pub(crate) async fn device_id(&mut self, username: &str) -> anyhow::Result<String> {
+        let device_id = query_scalar!(
+            "SELECT device_id FROM client_user WHERE username = ?",
+            username
+        )
+        .fetch_optional(&mut self.connection)
+        .await?
+        .ok_or_else(|| not_found(format!("User {username}")))?;
+        Ok(device_id)
     }
 }
 
 pub(crate) struct SignaturePrivateKey {
-    key: Vec<u8>,
+    pub(crate) key: Vec<u8>,
 }
 
 impl SignaturePrivateKey {
-    fn generate() -> (Self, SignaturePublicKey) {
+    pub(crate) fn generate() -> (Self, SignaturePublicKey) {
         let (sk, pk) = RustCrypto::default()
             .signature_key_gen(SignatureScheme::ED25519)
             .unwrap();
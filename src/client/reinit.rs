@@ -0,0 +1,169 @@
+use openmls::{
+    group::{GroupId, MlsGroup},
+    prelude::{Ciphersuite, KeyPackage, tls_codec::Serialize},
+};
+use openmls_traits::OpenMlsProvider;
+use sqlx::{query, query_scalar, types::chrono::Utc};
+use tracing::info;
+use uuid::Uuid;
+
+use crate::{
+    client::{
+        Client, Result,
+        error::{ensure, not_found},
+        group::group_metadata,
+        message::member_identities,
+        roles::ensure_admin,
+    },
+    grpc::{DeviceAddress, SendMessageRequest},
+    provider::check_ciphersuite,
+};
+
+impl Client {
+    /// Replaces the group with a new group using `ciphersuite`, or the current
+    /// ciphersuite if `None`, with the same members, name and topic.
+    ///
+    /// The old group announces its successor in the metadata extension before
+    /// the members are welcomed, so that their clients join the new group
+    /// without asking. Returns the id of the new group.
+    pub async fn reinit_group(
+        &mut self,
+        user: String,
+        group_uuid: Uuid,
+        ciphersuite: Option<Ciphersuite>,
+    ) -> Result<Uuid> {
+        let group_id = GroupId::from_slice(group_uuid.as_bytes());
+        let provider = self.provider();
+        let group =
+            MlsGroup::load(provider.storage(), &group_id)?.ok_or_else(|| not_found("Group"))?;
+
+        ensure_admin(&group, &user)?;
+
+        let ciphersuite = ciphersuite.unwrap_or(group.ciphersuite());
+        check_ciphersuite(ciphersuite)?;
+        let metadata = group_metadata(group.extensions());
+        ensure!(
+            metadata
+                .as_ref()
+                .is_none_or(|metadata| metadata.successor.is_empty()),
+            "Group was already re-initialized"
+        );
+        let identities = member_identities(&group);
+        let extensions = group.extensions().clone();
+
+        let own_device_id = self.device_id(&user).await?;
+        let mut key_packages: Vec<KeyPackage> = Vec::new();
+        let mut welcome_recipients = Vec::new();
+        for identity in identities {
+            for (device_id, key_package) in self
+                .fetch_key_packages(&identity, Some(ciphersuite))
+                .await?
+            {
+                if identity == user && device_id == own_device_id {
+                    continue;
+                }
+                ensure!(
+                    key_package.ciphersuite() == ciphersuite,
+                    "{identity} has no key package for {ciphersuite:?}"
+                );
+                welcome_recipients.push(DeviceAddress {
+                    client_id: identity.clone(),
+                    device_id,
+                });
+                key_packages.push(key_package);
+            }
+        }
+
+        let successor_uuid = Uuid::new_v4();
+        // Name, topic and roles carry over with the group context extensions.
+        let mut successor = self
+            .new_group(&user, successor_uuid, ciphersuite, extensions)
+            .await?;
+
+        let (signing_private_key, _credential_with_key) = self.credential(&user).await?;
+        let provider = self.provider();
+        let welcome = if key_packages.is_empty() {
+            None
+        } else {
+            let (_commit, welcome, _group_info) =
+                successor.add_members(&provider, &signing_private_key, &key_packages)?;
+            successor.merge_pending_commit(&provider)?;
+            Some(welcome)
+        };
+
+        self.commit_group_metadata(&user, group_uuid, |metadata| {
+            metadata.successor = successor_uuid.as_bytes().to_vec();
+        })
+        .await?;
+
+        if let Some(welcome) = welcome {
+            let sender_device_id = own_device_id;
+            self.client
+                .send_message(SendMessageRequest {
+                    sender: user.clone(),
+                    recipients: Vec::new(),
+                    content: welcome.tls_serialize_detached()?,
+                    device_recipients: welcome_recipients,
+                    sender_device_id,
+                })
+                .await?;
+        }
+
+        let creator = query_scalar!(
+            "SELECT creator FROM client_group WHERE group_id = ? AND username = ?",
+            group_uuid,
+            user,
+        )
+        .fetch_optional(&mut self.connection)
+        .await?
+        .unwrap_or_else(|| user.clone());
+        self.insert_group(&user, successor_uuid, &creator).await?;
+        self.mark_group_replaced(&user, group_uuid, successor_uuid)
+            .await?;
+        self.publish_group_info(&user, successor_uuid).await?;
+
+        info!(%group_uuid, %successor_uuid, ?ciphersuite, "Re-initialized group");
+
+        Ok(successor_uuid)
+    }
+
+    /// Records that the group was replaced by `successor_uuid` and is no
+    /// longer used.
+    pub(crate) async fn mark_group_replaced(
+        &mut self,
+        user: &str,
+        group_uuid: Uuid,
+        successor_uuid: Uuid,
+    ) -> anyhow::Result<()> {
+        let left_at = Utc::now();
+        query!(
+            "UPDATE client_group
+            SET successor_id = ?, left_at = COALESCE(left_at, ?)
+            WHERE group_id = ? AND username = ? AND successor_id IS NULL",
+            successor_uuid,
+            left_at,
+            group_uuid,
+            user,
+        )
+        .execute(&mut self.connection)
+        .await?;
+        Ok(())
+    }
+
+    /// Creator of the group that `group_uuid` replaced, if it is the successor
+    /// of one of the user's groups.
+    pub(crate) async fn predecessor_creator(
+        &mut self,
+        user: &str,
+        group_uuid: Uuid,
+    ) -> anyhow::Result<Option<String>> {
+        let creator = query_scalar!(
+            "SELECT creator FROM client_group WHERE successor_id = ? AND username = ?",
+            group_uuid,
+            user,
+        )
+        .fetch_optional(&mut self.connection)
+        .await?;
+        Ok(creator)
+    }
+}
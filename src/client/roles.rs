@@ -0,0 +1,178 @@
+use anyhow::Context;
+use openmls::{
+    group::{GroupId, MlsGroup, StagedCommit},
+    prelude::{Extensions, GroupContext, LeafNodeIndex, Proposal, Sender},
+};
+use openmls_traits::OpenMlsProvider;
+use prost::Message;
+use uuid::Uuid;
+
+use crate::{
+    client::{
+        Client, Result,
+        bans::check_bans,
+        error::{bail, ensure, not_found},
+        group::{group_metadata, set_custom_extension},
+        message::identity,
+    },
+    grpc::GroupRoles,
+    provider::{GROUP_ROLES_EXTENSION, Provider},
+};
+
+impl Client {
+    /// Makes `member` an admin of the group.
+    pub async fn promote(&mut self, user: String, group_uuid: Uuid, member: String) -> Result<()> {
+        Ok(self.set_admin(&user, group_uuid, member, true).await?)
+    }
+
+    /// Revokes the admin role of `member`. The last admin can't be demoted.
+    pub async fn demote(&mut self, user: String, group_uuid: Uuid, member: String) -> Result<()> {
+        Ok(self.set_admin(&user, group_uuid, member, false).await?)
+    }
+
+    /// Admins of the group, or `None` if the group has no roles and everybody
+    /// may change it.
+    pub async fn admins(&mut self, user: String, group_uuid: Uuid) -> Result<Option<Vec<String>>> {
+        if !self.group_ids(&user).await?.contains(&group_uuid) {
+            return Err(not_found("Group"));
+        }
+        let group_id = GroupId::from_slice(group_uuid.as_bytes());
+        let group = MlsGroup::load(self.provider().storage(), &group_id)?
+            .ok_or_else(|| not_found("Group"))?;
+        Ok(group_roles(group.extensions()).map(|roles| roles.admins))
+    }
+
+    async fn set_admin(
+        &mut self,
+        user: &str,
+        group_uuid: Uuid,
+        member: String,
+        admin: bool,
+    ) -> anyhow::Result<()> {
+        self.commit_group_context_extensions(user, group_uuid, |extensions| {
+            let mut roles = group_roles(extensions).unwrap_or_default();
+            if admin {
+                ensure!(
+                    !roles.admins.contains(&member),
+                    "{member} is already an admin"
+                );
+                roles.admins.push(member.clone());
+            } else {
+                ensure!(roles.admins.contains(&member), "{member} is not an admin");
+                roles.admins.retain(|admin| *admin != member);
+                ensure!(!roles.admins.is_empty(), "Can't demote the last admin");
+            }
+            set_roles_extension(extensions, &roles)
+        })
+        .await
+    }
+}
+
+pub(crate) fn group_roles(extensions: &Extensions<GroupContext>) -> Option<GroupRoles> {
+    let extension = extensions.unknown(GROUP_ROLES_EXTENSION)?;
+    GroupRoles::decode(extension.0.as_slice()).ok()
+}
+
+pub(crate) fn set_roles_extension(
+    extensions: &mut Extensions<GroupContext>,
+    roles: &GroupRoles,
+) -> anyhow::Result<()> {
+    set_custom_extension(extensions, GROUP_ROLES_EXTENSION, roles.encode_to_vec())
+}
+
+/// Whether `identity` may add and remove members and change the group
+/// context. Groups without roles predate them and let everybody do so.
+pub(crate) fn is_admin(group: &MlsGroup, identity: &str) -> bool {
+    group_roles(group.extensions()).is_none_or(|roles| roles.admins.iter().any(|a| a == identity))
+}
+
+/// Whether `identity` may send application messages, which only admins may
+/// in read-only groups.
+pub(crate) fn may_send(group: &MlsGroup, identity: &str) -> bool {
+    !group_metadata(group.extensions()).is_some_and(|metadata| metadata.read_only)
+        || is_admin(group, identity)
+}
+
+pub(crate) fn ensure_admin(group: &MlsGroup, identity: &str) -> anyhow::Result<()> {
+    ensure!(is_admin(group, identity), "Only admins can do this");
+    Ok(())
+}
+
+/// Checks the own pending commit like the other members will, and discards it
+/// if they would reject it.
+pub(crate) fn check_pending_commit(
+    group: &mut MlsGroup,
+    provider: &Provider<'_>,
+) -> anyhow::Result<()> {
+    let Some(commit) = group.pending_commit() else {
+        return Ok(());
+    };
+    let committer = group
+        .own_leaf_node()
+        .and_then(|leaf| identity(leaf.credential()))
+        .context("Not a member of the group")?;
+    if let Err(error) = check_commit(group, &committer, commit) {
+        group.clear_pending_commit(provider.storage())?;
+        return Err(error);
+    }
+    Ok(())
+}
+
+/// Checks the proposals of `commit` by `committer` against the roles and bans
+/// of `group`, before the commit is merged.
+///
+/// Members other than admins may only remove their own leaves. Once a member
+/// proposed to leave, anyone may remove the member's other devices, which is
+/// how leaving is committed. Their changes of the group context take effect
+/// once an admin commits them.
+pub(crate) fn check_commit(
+    group: &MlsGroup,
+    committer: &str,
+    commit: &StagedCommit,
+) -> anyhow::Result<()> {
+    check_bans(group, commit)?;
+    let Some(roles) = group_roles(group.extensions()) else {
+        return Ok(());
+    };
+    let identity_at = |index: LeafNodeIndex| {
+        group.member_at(index).map(|member| {
+            String::from_utf8_lossy(member.credential.serialized_content()).into_owned()
+        })
+    };
+
+    let leaving: Vec<String> = commit
+        .remove_proposals()
+        .filter(|proposal| {
+            matches!(proposal.sender(), Sender::Member(index) if *index == proposal.remove_proposal().removed())
+        })
+        .filter_map(|proposal| identity_at(proposal.remove_proposal().removed()))
+        .collect();
+
+    for proposal in commit.queued_proposals() {
+        let sender = match proposal.sender() {
+            Sender::Member(index) => identity_at(*index).context("Unknown proposal sender")?,
+            // Only possible in open groups, see `Client::join_group_externally`.
+            Sender::NewMemberCommit => continue,
+            sender => bail!("Proposal from {sender:?} not permitted"),
+        };
+        if roles.admins.contains(&sender) {
+            continue;
+        }
+        match proposal.proposal() {
+            Proposal::Add(_) => bail!("{sender} is not permitted to add members"),
+            Proposal::GroupContextExtensions(_) => ensure!(
+                roles.admins.iter().any(|admin| admin == committer),
+                "{sender} is not permitted to change the group"
+            ),
+            Proposal::Remove(remove) => {
+                let removed = identity_at(remove.removed()).context("Unknown removed member")?;
+                ensure!(
+                    removed == sender || leaving.contains(&removed),
+                    "{sender} is not permitted to remove {removed}"
+                );
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,63 @@
+use std::time::Duration;
+
+use sqlx::{
+    query, query_scalar,
+    types::chrono::{DateTime, Utc},
+};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::client::{Client, Result};
+
+impl Client {
+    /// Updates the own leaf keys of every group in which they are older than
+    /// `max_age`, counting from joining the group if they were never updated.
+    /// Returns the updated groups.
+    pub async fn rotate_stale_keys(
+        &mut self,
+        user: String,
+        max_age: Duration,
+    ) -> Result<Vec<Uuid>> {
+        let updated_before = Utc::now() - max_age;
+        let group_ids = query_scalar!(
+            "SELECT group_id AS \"group_id: Uuid\"
+            FROM client_group
+            WHERE username = ? AND left_at IS NULL
+                AND COALESCE(keys_updated_at, created_at) < ?",
+            user,
+            updated_before,
+        )
+        .fetch_all(&mut self.connection)
+        .await?;
+
+        let mut updated = Vec::with_capacity(group_ids.len());
+        for group_uuid in group_ids {
+            // A group that can't be updated shouldn't keep the others stale.
+            if let Err(error) = self.update_group(user.clone(), group_uuid).await {
+                warn!(%group_uuid, %error, "Failed to update keys");
+                continue;
+            }
+            info!(%group_uuid, "Updated stale keys");
+            updated.push(group_uuid);
+        }
+        Ok(updated)
+    }
+
+    /// Records that the own leaf keys of the group were just updated.
+    pub(crate) async fn mark_keys_updated(
+        &mut self,
+        user: &str,
+        group_uuid: Uuid,
+    ) -> anyhow::Result<()> {
+        let keys_updated_at: DateTime<Utc> = Utc::now();
+        query!(
+            "UPDATE client_group SET keys_updated_at = ? WHERE group_id = ? AND username = ?",
+            keys_updated_at,
+            group_uuid,
+            user,
+        )
+        .execute(&mut self.connection)
+        .await?;
+        Ok(())
+    }
+}
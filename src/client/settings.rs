@@ -0,0 +1,63 @@
+use sqlx::query;
+#[cfg(feature = "notifications")]
+use sqlx::query_scalar;
+use uuid::Uuid;
+
+use crate::client::{Client, Result, error::not_found};
+
+impl Client {
+    /// Whether incoming messages of the group show notifications. Messages
+    /// of muted groups are still received and stored.
+    pub async fn set_muted(&mut self, user: String, group_uuid: Uuid, muted: bool) -> Result<()> {
+        let updated = query!(
+            "UPDATE client_group SET muted = ? WHERE group_id = ? AND username = ?",
+            muted,
+            group_uuid,
+            user,
+        )
+        .execute(&mut self.connection)
+        .await?
+        .rows_affected();
+        if updated == 0 {
+            return Err(not_found("Group"));
+        }
+        Ok(())
+    }
+
+    /// Whether the group is hidden from listings, see
+    /// [`GroupSummary::archived`](crate::client::group::GroupSummary::archived).
+    /// The user stays a member and its messages are still processed.
+    pub async fn set_archived(
+        &mut self,
+        user: String,
+        group_uuid: Uuid,
+        archived: bool,
+    ) -> Result<()> {
+        let updated = query!(
+            "UPDATE client_group SET archived = ? WHERE group_id = ? AND username = ?",
+            archived,
+            group_uuid,
+            user,
+        )
+        .execute(&mut self.connection)
+        .await?
+        .rows_affected();
+        if updated == 0 {
+            return Err(not_found("Group"));
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "notifications")]
+    pub(crate) async fn is_muted(&mut self, user: &str, group_uuid: Uuid) -> anyhow::Result<bool> {
+        let muted = query_scalar!(
+            "SELECT muted AS \"muted: bool\" FROM client_group
+            WHERE group_id = ? AND username = ?",
+            group_uuid,
+            user,
+        )
+        .fetch_optional(&mut self.connection)
+        .await?;
+        Ok(muted.unwrap_or_default())
+    }
+}
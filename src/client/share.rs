@@ -0,0 +1,167 @@
+use anyhow::Context;
+use openmls::{
+    group::MlsGroup,
+    prelude::{AeadType, HashType, OpenMlsCrypto, OpenMlsRand},
+};
+use openmls_rust_crypto::RustCrypto;
+use openmls_traits::OpenMlsProvider;
+use prost::Message;
+use tracing::debug;
+use uuid::Uuid;
+
+use crate::{
+    client::{Client, Result, error::ensure},
+    grpc::{
+        ContentType, DownloadBlobRequest, HistoryShare, MAX_BLOB_SIZE, SharedHistory,
+        SharedMessage, UploadBlobRequest,
+    },
+};
+
+/// Exporter label of the keys of history shares.
+const HISTORY_SHARE_LABEL: &str = "mls-chat history share";
+const KEY_LENGTH: usize = 32;
+const NONCE_LENGTH: usize = 12;
+
+impl Client {
+    /// Shares the last `limit` messages of the local history with `recipients`,
+    /// typically members that were just added and missed them.
+    ///
+    /// The history is uploaded as blob, encrypted with a key exported from
+    /// the current epoch, so only members of this epoch can decrypt it.
+    pub async fn share_history(
+        &mut self,
+        user: String,
+        group_uuid: Uuid,
+        recipients: Vec<String>,
+        limit: u32,
+    ) -> Result<()> {
+        let messages = self.history(user.clone(), group_uuid, limit, None).await?;
+        let shared = SharedHistory {
+            messages: messages
+                .iter()
+                .filter(|message| message.deleted_at.is_none())
+                .map(|message| SharedMessage {
+                    sender: message.sender.clone(),
+                    body: message.body.clone(),
+                    message_id: message
+                        .uuid
+                        .map(|uuid| uuid.as_bytes().to_vec())
+                        .unwrap_or_default(),
+                    reply_to: message
+                        .reply_to
+                        .and_then(|parent| {
+                            messages.iter().find(|message| message.message_id == parent)
+                        })
+                        .and_then(|parent| parent.uuid)
+                        .map(|uuid| uuid.as_bytes().to_vec())
+                        .unwrap_or_default(),
+                    sent_at: message.created_at.timestamp_millis(),
+                    epoch: message.epoch,
+                })
+                .collect(),
+        };
+
+        let group = self.load_member_group(&user, group_uuid).await?;
+        let epoch = group.epoch().as_u64();
+        let crypto = RustCrypto::default();
+        let nonce = crypto.random_vec(NONCE_LENGTH)?;
+        let key = group.export_secret(
+            self.provider().crypto(),
+            HISTORY_SHARE_LABEL,
+            &nonce,
+            KEY_LENGTH,
+        )?;
+        let blob = crypto.aead_encrypt(
+            AeadType::ChaCha20Poly1305,
+            &key,
+            &shared.encode_to_vec(),
+            &nonce,
+            &[],
+        )?;
+        ensure!(
+            blob.len() <= MAX_BLOB_SIZE,
+            "History exceeds {MAX_BLOB_SIZE} bytes, share fewer messages"
+        );
+        let hash = crypto.hash(HashType::Sha2_256, &blob)?;
+
+        let blob_id = self
+            .client
+            .upload_blob(UploadBlobRequest {
+                client_id: user.clone(),
+                content: blob,
+            })
+            .await?
+            .blob_id;
+
+        let share = HistoryShare {
+            blob_id,
+            nonce,
+            hash,
+            epoch,
+            recipients,
+        };
+        let sent_epoch = self
+            .send_content(
+                &user,
+                group_uuid,
+                ContentType::HistoryShare,
+                Uuid::new_v4(),
+                share.encode_to_vec(),
+            )
+            .await?;
+        ensure!(
+            sent_epoch == epoch,
+            "Group changed while sharing the history, share it again"
+        );
+        Ok(())
+    }
+
+    /// Imports a history share of `sender` if it is meant for the user.
+    /// Returns the number of imported messages.
+    pub(crate) async fn handle_history_share(
+        &mut self,
+        user: &str,
+        group: &MlsGroup,
+        sender: &str,
+        body: &[u8],
+    ) -> anyhow::Result<u64> {
+        let share = HistoryShare::decode(body)?;
+        let group_uuid = Uuid::from_slice(group.group_id().as_slice())?;
+        if !share.recipients.iter().any(|recipient| recipient == user) {
+            debug!(%group_uuid, sender, "Ignoring history share for other members");
+            return Ok(0);
+        }
+        ensure!(
+            share.epoch == group.epoch().as_u64(),
+            "History share of epoch {} can't be decrypted in epoch {}",
+            share.epoch,
+            group.epoch().as_u64()
+        );
+        let key = group.export_secret(
+            self.provider().crypto(),
+            HISTORY_SHARE_LABEL,
+            &share.nonce,
+            KEY_LENGTH,
+        )?;
+
+        let blob = self
+            .client
+            .download_blob(DownloadBlobRequest {
+                blob_id: share.blob_id,
+            })
+            .await?
+            .content;
+        let crypto = RustCrypto::default();
+        ensure!(
+            crypto.hash(HashType::Sha2_256, &blob)? == share.hash,
+            "History share hash mismatch"
+        );
+        let content = crypto
+            .aead_decrypt(AeadType::ChaCha20Poly1305, &key, &blob, &share.nonce, &[])
+            .context("Failed to decrypt history share")?;
+        let shared = SharedHistory::decode(content.as_slice())?;
+
+        self.import_messages(user, group_uuid, sender, shared.messages)
+            .await
+    }
+}
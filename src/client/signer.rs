@@ -0,0 +1,36 @@
+use std::{fmt, sync::Arc};
+
+use openmls::prelude::SignatureScheme;
+use openmls_traits::signatures::{Signer, SignerError};
+
+use crate::client::register::SignaturePrivateKey;
+
+/// Signs with the Ed25519 identity key of a user without exposing it, e.g.
+/// a key on a PIV token, in a TPM or behind PKCS#11. See
+/// [`ClientBuilder::with_signer`](crate::client::builder::ClientBuilder::with_signer).
+pub trait ChatSigner: fmt::Debug + Send + Sync {
+    /// Ed25519 public key of the signer.
+    fn public_key(&self) -> Vec<u8>;
+
+    /// Ed25519 signature of `payload`.
+    fn sign(&self, payload: &[u8]) -> Result<Vec<u8>, SignerError>;
+}
+
+/// Identity key of a user, kept by the client or by a [`ChatSigner`].
+pub(crate) enum IdentitySigner {
+    Local(SignaturePrivateKey),
+    External(Arc<dyn ChatSigner>),
+}
+
+impl Signer for IdentitySigner {
+    fn sign(&self, payload: &[u8]) -> Result<Vec<u8>, SignerError> {
+        match self {
+            Self::Local(key) => key.sign(payload),
+            Self::External(signer) => signer.sign(payload),
+        }
+    }
+
+    fn signature_scheme(&self) -> SignatureScheme {
+        SignatureScheme::ED25519
+    }
+}
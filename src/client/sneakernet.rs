@@ -0,0 +1,394 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use futures_util::{StreamExt, stream};
+use openmls::prelude::{
+    DeserializeBytes, KeyPackageIn, MlsMessageBodyIn, MlsMessageIn, MlsMessageOut,
+    tls_codec::Serialize,
+};
+use openmls_rust_crypto::RustCrypto;
+use tonic::Status;
+use tracing::{debug, info, warn};
+
+use crate::{
+    client::{
+        Client, Result,
+        delivery::{DeliveryService, MessageStream},
+        error::bail,
+        ledger::content_key,
+        message::identity,
+    },
+    grpc::{self, *},
+    provider::PROTOCOL_VERSION,
+};
+
+/// Directory of the messages sent since the last export.
+const OUTGOING_DIR: &str = "outgoing";
+/// Directory of the imported key packages, by user and ciphersuite.
+const KEY_PACKAGES_DIR: &str = "key-packages";
+
+/// A delivery service without server for `file://` endpoints, which hands
+/// messages over in files, see [`Client::export_message_file`] and
+/// [`Client::import_message_file`]. Sent messages and own key packages are
+/// spooled in the directory of the endpoint until they are exported, key
+/// packages of other users are added to the groups once imported. Devices,
+/// attachments and the key transparency log need a server.
+#[derive(Debug)]
+pub(crate) struct SneakernetDeliveryService {
+    dir: PathBuf,
+    next_id: AtomicU64,
+}
+
+impl SneakernetDeliveryService {
+    pub(crate) fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            next_id: AtomicU64::new(0),
+        }
+    }
+
+    /// Spools an MLS message for the next export, named so that files sort
+    /// in the order they were written.
+    async fn spool(&self, content: &[u8]) -> Result<(), Status> {
+        let dir = self.dir.join(OUTGOING_DIR);
+        tokio::fs::create_dir_all(&dir).await.map_err(io_error)?;
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let path = dir.join(format!("{timestamp:024}-{id:06}.mls"));
+        tokio::fs::write(&path, content).await.map_err(io_error)?;
+        debug!(path = %path.display(), "Spooled message");
+        Ok(())
+    }
+
+    /// Paths of the spooled messages, oldest first.
+    async fn outgoing(&self) -> io::Result<Vec<PathBuf>> {
+        let mut paths = Vec::new();
+        let mut entries = match tokio::fs::read_dir(self.dir.join(OUTGOING_DIR)).await {
+            Ok(entries) => entries,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(paths),
+            Err(error) => return Err(error),
+        };
+        while let Some(entry) = entries.next_entry().await? {
+            paths.push(entry.path());
+        }
+        paths.sort();
+        Ok(paths)
+    }
+
+    /// Directory of the key packages of `client_id`, named in hex as user
+    /// names come from the imported credentials.
+    fn key_package_dir(&self, client_id: &str) -> PathBuf {
+        let name: String = client_id
+            .bytes()
+            .map(|byte| format!("{byte:02x}"))
+            .collect();
+        self.dir.join(KEY_PACKAGES_DIR).join(name)
+    }
+
+    fn key_package_path(&self, client_id: &str, ciphersuite: u16) -> PathBuf {
+        self.key_package_dir(client_id)
+            .join(format!("{ciphersuite:#06x}.mls"))
+    }
+
+    /// Keeps the key package of `client_id` for adding it to groups,
+    /// replacing an earlier one of the same ciphersuite.
+    async fn store_key_package(
+        &self,
+        client_id: &str,
+        ciphersuite: u16,
+        key_package: &[u8],
+    ) -> io::Result<()> {
+        let path = self.key_package_path(client_id, ciphersuite);
+        if let Some(dir) = path.parent() {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+        tokio::fs::write(path, key_package).await
+    }
+}
+
+fn io_error(error: io::Error) -> Status {
+    Status::internal(format!("Sneakernet directory: {error}"))
+}
+
+fn needs_server(what: &str) -> Status {
+    Status::unimplemented(format!("{what} need a server"))
+}
+
+#[tonic::async_trait]
+impl DeliveryService for SneakernetDeliveryService {
+    async fn get_versions(
+        &self,
+        _request: GetVersionsRequest,
+    ) -> Result<GetVersionsResponse, Status> {
+        Ok(GetVersionsResponse {
+            versions: API_VERSIONS.to_vec(),
+            server_version: String::new(),
+        })
+    }
+
+    async fn send_message(
+        &self,
+        request: SendMessageRequest,
+    ) -> Result<SendMessageResponse, Status> {
+        self.spool(&request.content).await?;
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as i64;
+        Ok(SendMessageResponse {
+            timestamp,
+            ..Default::default()
+        })
+    }
+
+    /// Messages arrive by import only, so the stream ends right away.
+    async fn receive_messages(
+        &self,
+        _request: ReceiveMessagesRequest,
+    ) -> Result<MessageStream, Status> {
+        Ok(stream::empty().boxed())
+    }
+
+    async fn get_queue_status(
+        &self,
+        _request: GetQueueStatusRequest,
+    ) -> Result<GetQueueStatusResponse, Status> {
+        Ok(GetQueueStatusResponse::default())
+    }
+
+    /// Messages arrive by import only, so none are queued.
+    async fn fetch_messages(
+        &self,
+        _request: FetchMessagesRequest,
+    ) -> Result<FetchMessagesResponse, Status> {
+        Ok(FetchMessagesResponse::default())
+    }
+
+    /// Spools the key package as MLS message, so that the other side can
+    /// import it and add the user to groups.
+    async fn upload_key_package(
+        &self,
+        request: UploadKeyPackageRequest,
+    ) -> Result<UploadKeyPackageResponse, Status> {
+        let key_package_bytes = request
+            .key_package
+            .ok_or_else(|| Status::invalid_argument("Missing key package"))?
+            .key_package_bytes;
+        let key_package = KeyPackageIn::tls_deserialize_exact_bytes(&key_package_bytes)
+            .map_err(|error| Status::invalid_argument(error.to_string()))?
+            .validate(&RustCrypto::default(), PROTOCOL_VERSION)
+            .map_err(|error| Status::invalid_argument(error.to_string()))?;
+        let message = MlsMessageOut::from(key_package)
+            .tls_serialize_detached()
+            .map_err(|error| Status::internal(error.to_string()))?;
+        self.spool(&message).await?;
+        Ok(UploadKeyPackageResponse::default())
+    }
+
+    async fn fetch_key_package(
+        &self,
+        request: FetchKeyPackageRequest,
+    ) -> Result<FetchKeyPackageResponse, Status> {
+        let mut paths = Vec::new();
+        if request.ciphersuite != 0 {
+            let ciphersuite = u16::try_from(request.ciphersuite)
+                .map_err(|_| Status::invalid_argument("Invalid ciphersuite"))?;
+            paths.push(self.key_package_path(&request.client_id, ciphersuite));
+        } else if let Ok(mut entries) =
+            tokio::fs::read_dir(self.key_package_dir(&request.client_id)).await
+        {
+            while let Some(entry) = entries.next_entry().await.map_err(io_error)? {
+                paths.push(entry.path());
+            }
+            paths.sort();
+        }
+        for path in paths {
+            match tokio::fs::read(&path).await {
+                Ok(key_package_bytes) => {
+                    return Ok(FetchKeyPackageResponse {
+                        key_package: Some(grpc::KeyPackage { key_package_bytes }),
+                        device_id: String::new(),
+                    });
+                }
+                Err(error) if error.kind() == io::ErrorKind::NotFound => {}
+                Err(error) => return Err(io_error(error)),
+            }
+        }
+        Err(Status::not_found(format!(
+            "No key package of {} imported",
+            request.client_id
+        )))
+    }
+
+    /// Open groups can't be joined without a server, the GroupInfo is dropped.
+    async fn publish_group_info(
+        &self,
+        _request: PublishGroupInfoRequest,
+    ) -> Result<PublishGroupInfoResponse, Status> {
+        Ok(PublishGroupInfoResponse::default())
+    }
+
+    async fn fetch_group_info(
+        &self,
+        _request: FetchGroupInfoRequest,
+    ) -> Result<FetchGroupInfoResponse, Status> {
+        Err(needs_server("External joins"))
+    }
+
+    async fn upload_blob(&self, _request: UploadBlobRequest) -> Result<UploadBlobResponse, Status> {
+        Err(needs_server("Attachments"))
+    }
+
+    async fn download_blob(
+        &self,
+        _request: DownloadBlobRequest,
+    ) -> Result<DownloadBlobResponse, Status> {
+        Err(needs_server("Attachments"))
+    }
+
+    async fn upload_device_certificate(
+        &self,
+        _request: UploadDeviceCertificateRequest,
+    ) -> Result<UploadDeviceCertificateResponse, Status> {
+        Err(needs_server("Linked devices"))
+    }
+
+    /// Only the primary devices of users, without certificates.
+    async fn fetch_device_certificates(
+        &self,
+        _request: FetchDeviceCertificatesRequest,
+    ) -> Result<FetchDeviceCertificatesResponse, Status> {
+        Ok(FetchDeviceCertificatesResponse::default())
+    }
+
+    async fn list_devices(
+        &self,
+        _request: ListDevicesRequest,
+    ) -> Result<ListDevicesResponse, Status> {
+        Ok(ListDevicesResponse::default())
+    }
+
+    async fn revoke_device(
+        &self,
+        _request: RevokeDeviceRequest,
+    ) -> Result<RevokeDeviceResponse, Status> {
+        Err(needs_server("Linked devices"))
+    }
+
+    async fn rotate_device_key(
+        &self,
+        _request: RotateDeviceKeyRequest,
+    ) -> Result<RotateDeviceKeyResponse, Status> {
+        Err(needs_server("Key rotations"))
+    }
+
+    async fn get_key_log_root(
+        &self,
+        _request: GetKeyLogRootRequest,
+    ) -> Result<GetKeyLogRootResponse, Status> {
+        Err(needs_server("Key transparency proofs"))
+    }
+
+    async fn get_inclusion_proof(
+        &self,
+        _request: GetInclusionProofRequest,
+    ) -> Result<GetInclusionProofResponse, Status> {
+        Err(needs_server("Key transparency proofs"))
+    }
+
+    async fn get_consistency_proof(
+        &self,
+        _request: GetConsistencyProofRequest,
+    ) -> Result<GetConsistencyProofResponse, Status> {
+        Err(needs_server("Key transparency proofs"))
+    }
+}
+
+impl Client {
+    /// Writes the messages sent and the key packages uploaded since the last
+    /// export to `path`, one MLS message after the other, for the other
+    /// clients to [import](Client::import_message_file). Returns the number of
+    /// messages, which are removed from the spool. Needs a `file://`
+    /// endpoint.
+    pub async fn export_message_file(&mut self, path: &Path) -> Result<usize> {
+        let sneakernet = self.sneakernet()?;
+        let paths = sneakernet.outgoing().await?;
+        let mut content = Vec::new();
+        for path in &paths {
+            content.extend(tokio::fs::read(path).await?);
+        }
+        tokio::fs::write(path, content)
+            .await
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        for path in &paths {
+            tokio::fs::remove_file(path).await?;
+        }
+        info!(messages = paths.len(), path = %path.display(), "Exported messages");
+        Ok(paths.len())
+    }
+
+    /// Processes the MLS messages of a file of [`Client::export_message_file`] in
+    /// order, as if they were received from the server, and returns the
+    /// number of those that applied to the user. Key packages are kept for
+    /// adding their users to groups. The
+    /// resulting events are left for [`Client::drain_events`]. Needs a
+    /// `file://` endpoint.
+    pub async fn import_message_file(&mut self, user: String, path: &Path) -> Result<usize> {
+        let sneakernet = self.sneakernet()?;
+        let content = tokio::fs::read(path)
+            .await
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let mut rest = content.as_slice();
+        let mut messages = 0;
+        while !rest.is_empty() {
+            let (message, remainder) = MlsMessageIn::tls_deserialize_bytes(rest)?;
+            let message_bytes = &rest[..rest.len() - remainder.len()];
+            rest = remainder;
+            let MlsMessageBodyIn::KeyPackage(key_package) = message.extract() else {
+                // Files go to everyone, with messages of groups the user isn't
+                // a member of and welcomes to other users.
+                let key = content_key(message_bytes);
+                match self.handle_message(&user, Some(&key), message_bytes).await {
+                    Ok(()) => messages += 1,
+                    Err(error) => warn!(%error, "Skipping message"),
+                }
+                continue;
+            };
+            let key_package = key_package.validate(&RustCrypto::default(), PROTOCOL_VERSION)?;
+            let Some(client_id) = identity(key_package.leaf_node().credential()) else {
+                continue;
+            };
+            if client_id != user {
+                sneakernet
+                    .store_key_package(
+                        &client_id,
+                        key_package.ciphersuite().into(),
+                        &key_package.tls_serialize_detached()?,
+                    )
+                    .await?;
+                info!(client_id, "Imported key package");
+                messages += 1;
+            }
+        }
+        self.send_receipts(&user).await?;
+        Ok(messages)
+    }
+
+    fn sneakernet(&self) -> Result<Arc<SneakernetDeliveryService>> {
+        match &self.sneakernet {
+            Some(sneakernet) => Ok(sneakernet.clone()),
+            None => bail!("Exporting and importing messages needs a file:// endpoint"),
+        }
+    }
+}
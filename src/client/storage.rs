@@ -0,0 +1,67 @@
+use openmls_sqlx_storage::Codec;
+use sqlx::{Connection, SqliteConnection, query, query_scalar};
+use tracing::info;
+
+use crate::{
+    client::error::bail,
+    provider::{CborCodec, JsonCodec, StorageCodec},
+};
+
+/// Columns of the OpenMLS tables whose values are encoded with the codec.
+const ENCODED_COLUMNS: &[(&str, &[&str])] = &[
+    ("openmls_group_data", &["group_id", "group_data"]),
+    (
+        "openmls_proposal",
+        &["group_id", "proposal_ref", "proposal"],
+    ),
+    ("openmls_own_leaf_node", &["group_id", "leaf_node"]),
+    ("openmls_signature_key", &["public_key", "signature_key"]),
+    ("openmls_encryption_key", &["public_key", "key_pair"]),
+    (
+        "openmls_epoch_key_pairs",
+        &["group_id", "epoch_id", "key_pairs"],
+    ),
+    ("openmls_key_package", &["key_package_ref", "key_package"]),
+    ("openmls_psk", &["psk_id", "psk_bundle"]),
+];
+
+/// Codec of the OpenMLS state of the database. With `cbor`, a database that
+/// still uses JSON is converted first.
+pub(crate) async fn storage_codec(
+    connection: &mut SqliteConnection,
+    cbor: bool,
+) -> anyhow::Result<StorageCodec> {
+    let codec = query_scalar!("SELECT codec FROM client_storage_codec")
+        .fetch_optional(&mut *connection)
+        .await?;
+    match codec.as_deref() {
+        None if cbor => {}
+        None => return Ok(StorageCodec::Json),
+        Some("cbor") => return Ok(StorageCodec::Cbor),
+        Some(codec) => bail!("Unknown storage codec {codec}"),
+    }
+
+    info!("Converting the MLS state of the database to CBOR");
+    let mut transaction = connection.begin().await?;
+    for (table, columns) in ENCODED_COLUMNS {
+        for column in *columns {
+            let rows: Vec<(i64, Vec<u8>)> =
+                sqlx::query_as(&format!("SELECT rowid, {column} FROM {table}"))
+                    .fetch_all(&mut *transaction)
+                    .await?;
+            for (rowid, json) in rows {
+                let value: serde_json::Value = JsonCodec::from_slice(&json)?;
+                sqlx::query(&format!("UPDATE {table} SET {column} = ? WHERE rowid = ?"))
+                    .bind(CborCodec::to_vec(&value)?)
+                    .bind(rowid)
+                    .execute(&mut *transaction)
+                    .await?;
+            }
+        }
+    }
+    query!("INSERT INTO client_storage_codec (id, codec) VALUES (0, 'cbor')")
+        .execute(&mut *transaction)
+        .await?;
+    transaction.commit().await?;
+    Ok(StorageCodec::Cbor)
+}
@@ -0,0 +1,332 @@
+use std::path::Path;
+
+use anyhow::{Context, anyhow};
+use openmls::group::{GroupId, MlsGroup};
+use openmls_traits::OpenMlsProvider;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::{
+    Connection, query, query_as, query_scalar,
+    types::chrono::{DateTime, Utc},
+};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::client::{
+    Client, Result,
+    backup::{open_with_passphrase, seal_with_passphrase},
+    error::{bail, ensure, not_found},
+    signer::IdentitySigner,
+};
+
+/// Start of a group export, followed by the salt, the nonce and the encrypted
+/// [`GroupExport`] as JSON.
+const MAGIC: &[u8] = b"mls-chat group 1\n";
+
+/// The state of a group on a device, see [`Client::export_group`].
+///
+/// The OpenMLS rows are decoded, so that the storage codecs of the two
+/// databases may differ.
+#[derive(Serialize, Deserialize)]
+struct GroupExport {
+    username: String,
+    group_id: Uuid,
+    creator: String,
+    inviter: Option<String>,
+    /// As stored, in RFC 3339.
+    created_at: String,
+    read_receipts: bool,
+    muted: bool,
+    archived: bool,
+    /// Key pair of the own leaf.
+    signature_key: Vec<u8>,
+    signature_private_key: Vec<u8>,
+    /// Data type and value of each entry of `openmls_group_data`.
+    group_data: Vec<(String, Value)>,
+    /// Reference and proposal of the pending proposals.
+    proposals: Vec<(Value, Value)>,
+    own_leaf_node: Option<Value>,
+    /// Epoch, leaf index and key pairs of the private tree keys.
+    epoch_key_pairs: Vec<(Value, i64, Value)>,
+    /// Public key and key pair of the encryption key of the own leaf.
+    encryption_key: Option<(Value, Value)>,
+}
+
+impl Client {
+    /// Moves a group to another device of the user: writes its MLS state and
+    /// settings, encrypted with a key derived from `passphrase`, to `path`,
+    /// which must not exist, for [`Client::import_group`].
+    ///
+    /// The export includes the private key of the own leaf, and the state is
+    /// deleted on this device once written, as two devices sharing a leaf
+    /// would fork the group. The history of the group stays here.
+    pub async fn export_group(
+        &mut self,
+        user: String,
+        group_uuid: Uuid,
+        path: &Path,
+        passphrase: &str,
+    ) -> Result<()> {
+        ensure!(
+            self.memory_storage.is_none(),
+            "The state of the client is only kept in memory"
+        );
+        ensure!(!path.exists(), "{} already exists", path.display());
+        let settings = query!(
+            "SELECT
+                creator,
+                inviter,
+                created_at,
+                read_receipts AS \"read_receipts: bool\",
+                muted AS \"muted: bool\",
+                archived AS \"archived: bool\"
+            FROM client_group
+            WHERE group_id = ? AND username = ? AND left_at IS NULL",
+            group_uuid,
+            user,
+        )
+        .fetch_optional(&mut self.connection)
+        .await?
+        .ok_or_else(|| not_found("Group"))?;
+
+        let group_id = GroupId::from_slice(group_uuid.as_bytes());
+        let group = MlsGroup::load(self.provider().storage(), &group_id)?
+            .ok_or_else(|| not_found("Group"))?;
+        let leaf = group
+            .own_leaf_node()
+            .context("Not a member of the group")?
+            .clone();
+        let IdentitySigner::Local(signature_private_key) = self.leaf_signer(&user, &group).await?
+        else {
+            bail!("The key of the own leaf is held by an external signer");
+        };
+
+        let codec = self.storage_codec;
+        let key = codec.encode(&group_id)?;
+        let group_data: Vec<(String, Vec<u8>)> =
+            query_as("SELECT data_type, group_data FROM openmls_group_data WHERE group_id = ?")
+                .bind(&key)
+                .fetch_all(&mut self.connection)
+                .await?;
+        let proposals: Vec<(Vec<u8>, Vec<u8>)> =
+            query_as("SELECT proposal_ref, proposal FROM openmls_proposal WHERE group_id = ?")
+                .bind(&key)
+                .fetch_all(&mut self.connection)
+                .await?;
+        let own_leaf_node: Option<Vec<u8>> =
+            query_scalar("SELECT leaf_node FROM openmls_own_leaf_node WHERE group_id = ?")
+                .bind(&key)
+                .fetch_optional(&mut self.connection)
+                .await?;
+        let epoch_key_pairs: Vec<(Vec<u8>, i64, Vec<u8>)> = query_as(
+            "SELECT epoch_id, leaf_index, key_pairs FROM openmls_epoch_key_pairs
+            WHERE group_id = ?",
+        )
+        .bind(&key)
+        .fetch_all(&mut self.connection)
+        .await?;
+        let encryption_key: Option<Vec<u8>> =
+            query_scalar("SELECT key_pair FROM openmls_encryption_key WHERE public_key = ?")
+                .bind(codec.encode(leaf.encryption_key())?)
+                .fetch_optional(&mut self.connection)
+                .await?;
+
+        let export = GroupExport {
+            username: user.clone(),
+            group_id: group_uuid,
+            creator: settings.creator,
+            inviter: settings.inviter,
+            created_at: settings.created_at,
+            read_receipts: settings.read_receipts,
+            muted: settings.muted,
+            archived: settings.archived,
+            signature_key: leaf.signature_key().as_slice().to_vec(),
+            signature_private_key: signature_private_key.key,
+            group_data: group_data
+                .into_iter()
+                .map(|(data_type, data)| Ok((data_type, codec.decode(&data)?)))
+                .collect::<anyhow::Result<_>>()?,
+            proposals: proposals
+                .into_iter()
+                .map(|(proposal_ref, proposal)| {
+                    Ok((codec.decode(&proposal_ref)?, codec.decode(&proposal)?))
+                })
+                .collect::<anyhow::Result<_>>()?,
+            own_leaf_node: own_leaf_node
+                .map(|leaf_node| codec.decode(&leaf_node))
+                .transpose()?,
+            epoch_key_pairs: epoch_key_pairs
+                .into_iter()
+                .map(|(epoch_id, leaf_index, key_pairs)| {
+                    Ok((
+                        codec.decode(&epoch_id)?,
+                        leaf_index,
+                        codec.decode(&key_pairs)?,
+                    ))
+                })
+                .collect::<anyhow::Result<_>>()?,
+            encryption_key: encryption_key
+                .map(|key_pair| {
+                    anyhow::Ok((
+                        serde_json::to_value(leaf.encryption_key())?,
+                        codec.decode(&key_pair)?,
+                    ))
+                })
+                .transpose()?,
+        };
+        let sealed = seal_with_passphrase(MAGIC, passphrase, &serde_json::to_vec(&export)?)?;
+        tokio::fs::write(path, sealed)
+            .await
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+
+        let provider = self.provider();
+        let mut group = group;
+        group.delete(provider.storage())?;
+        self.group_cache.clear();
+        self.drop_pending_messages(&user, group_uuid).await?;
+        self.mark_group_left(&user, group_uuid).await?;
+        info!(%group_uuid, "Exported group");
+        Ok(())
+    }
+
+    /// Imports a group written by [`Client::export_group`] on another device of
+    /// the user. Returns its id.
+    ///
+    /// The own leaf is then moved to the key of this device with a
+    /// self-update, as [`Client::rotate_identity_key`] does. Until that
+    /// succeeds, the key of the exporting device is kept as retired. Members
+    /// that pinned the key of the exporting device have to
+    /// [trust](Client::trust) the new one.
+    pub async fn import_group(
+        &mut self,
+        user: String,
+        path: &Path,
+        passphrase: &str,
+    ) -> Result<Uuid> {
+        ensure!(
+            self.memory_storage.is_none(),
+            "The state of the client is only kept in memory"
+        );
+        let content = tokio::fs::read(path)
+            .await
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let export = open_with_passphrase(MAGIC, passphrase, &content)?
+            .ok_or_else(|| anyhow!("{} is not a group export", path.display()))?;
+        let export: GroupExport =
+            serde_json::from_slice(&export).context("Invalid group export")?;
+        ensure!(
+            export.username == user,
+            "The group was exported by {}, not {user}",
+            export.username
+        );
+        let (_signature_private_key, credential_with_key) = self.credential(&user).await?;
+        let group_uuid = export.group_id;
+        let group_id = GroupId::from_slice(group_uuid.as_bytes());
+        ensure!(
+            MlsGroup::load(self.provider().storage(), &group_id)?.is_none(),
+            "Group {group_uuid} already exists on this device"
+        );
+
+        let codec = self.storage_codec;
+        let key = codec.encode(&group_id)?;
+        let retired_at: DateTime<Utc> = Utc::now();
+        let mut transaction = self.connection.begin().await?;
+        for (data_type, data) in &export.group_data {
+            sqlx::query(
+                "INSERT INTO openmls_group_data (group_id, data_type, group_data)
+                VALUES (?, ?, ?)",
+            )
+            .bind(&key)
+            .bind(data_type)
+            .bind(codec.encode(data)?)
+            .execute(&mut *transaction)
+            .await?;
+        }
+        for (proposal_ref, proposal) in &export.proposals {
+            sqlx::query(
+                "INSERT INTO openmls_proposal (group_id, proposal_ref, proposal)
+                VALUES (?, ?, ?)",
+            )
+            .bind(&key)
+            .bind(codec.encode(proposal_ref)?)
+            .bind(codec.encode(proposal)?)
+            .execute(&mut *transaction)
+            .await?;
+        }
+        if let Some(leaf_node) = &export.own_leaf_node {
+            sqlx::query("INSERT INTO openmls_own_leaf_node (group_id, leaf_node) VALUES (?, ?)")
+                .bind(&key)
+                .bind(codec.encode(leaf_node)?)
+                .execute(&mut *transaction)
+                .await?;
+        }
+        for (epoch_id, leaf_index, key_pairs) in &export.epoch_key_pairs {
+            sqlx::query(
+                "INSERT INTO openmls_epoch_key_pairs (group_id, epoch_id, leaf_index, key_pairs)
+                VALUES (?, ?, ?, ?)",
+            )
+            .bind(&key)
+            .bind(codec.encode(epoch_id)?)
+            .bind(leaf_index)
+            .bind(codec.encode(key_pairs)?)
+            .execute(&mut *transaction)
+            .await?;
+        }
+        if let Some((public_key, key_pair)) = &export.encryption_key {
+            sqlx::query(
+                "INSERT OR REPLACE INTO openmls_encryption_key (public_key, key_pair)
+                VALUES (?, ?)",
+            )
+            .bind(codec.encode(public_key)?)
+            .bind(codec.encode(key_pair)?)
+            .execute(&mut *transaction)
+            .await?;
+        }
+        if export.signature_key != credential_with_key.signature_key.as_slice() {
+            query!(
+                "INSERT OR IGNORE INTO client_retired_key (
+                    username, signature_key, signature_private_key, retired_at
+                ) VALUES (?, ?, ?, ?)",
+                user,
+                export.signature_key,
+                export.signature_private_key,
+                retired_at,
+            )
+            .execute(&mut *transaction)
+            .await?;
+        }
+        query!(
+            "INSERT INTO client_group (
+                group_id, username, creator, inviter, created_at, read_receipts, muted, archived
+            ) VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+            ON CONFLICT (group_id) DO UPDATE SET
+                username = excluded.username,
+                inviter = excluded.inviter,
+                read_receipts = excluded.read_receipts,
+                muted = excluded.muted,
+                archived = excluded.archived,
+                keys_updated_at = NULL,
+                left_at = NULL",
+            group_uuid,
+            user,
+            export.creator,
+            export.inviter,
+            export.created_at,
+            export.read_receipts,
+            export.muted,
+            export.archived,
+        )
+        .execute(&mut *transaction)
+        .await?;
+        transaction.commit().await?;
+        info!(%group_uuid, "Imported group");
+
+        // The group stays usable with the retired key if this fails, the next
+        // update moves it.
+        if let Err(error) = self.update_group(user.clone(), group_uuid).await {
+            warn!(%group_uuid, %error, "Failed to move imported group to the key of this device");
+        }
+        self.drop_retired_keys(&user).await?;
+        Ok(group_uuid)
+    }
+}
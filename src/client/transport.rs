@@ -0,0 +1,227 @@
+use std::{error::Error, future::Future, io, sync::Arc, time::Duration};
+
+use tokio::time::sleep;
+use tonic::{Code, Status};
+use tracing::{debug, warn};
+
+use crate::{
+    client::{
+        daemon::jitter,
+        delivery::{DeliveryService, MessageStream},
+    },
+    grpc::*,
+};
+
+/// How requests that failed as the server was unreachable are retried. Only
+/// requests that can be repeated without effect are retried.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Number of retries after the first attempt.
+    pub max_retries: u32,
+    /// Delay before the first retry, doubled with each further one.
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(200),
+            max_backoff: Duration::from_secs(5),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Fails on the first error.
+    pub fn never() -> Self {
+        Self {
+            max_retries: 0,
+            ..Self::default()
+        }
+    }
+
+    /// Delay before the retry following `retries` retries, or `None` if
+    /// there are no retries left.
+    pub(crate) fn backoff(&self, retries: u32) -> Option<Duration> {
+        if retries >= self.max_retries {
+            return None;
+        }
+        let delay = self
+            .initial_backoff
+            .saturating_mul(2u32.saturating_pow(retries))
+            .min(self.max_backoff);
+        Some(jitter(delay))
+    }
+}
+
+/// Whether a request failed as the server couldn't be reached or the
+/// connection broke, rather than being rejected.
+pub(crate) fn is_retryable(status: &Status) -> bool {
+    if status.code() == Code::Unavailable {
+        return true;
+    }
+    let mut source = status.source();
+    while let Some(error) = source {
+        if let Some(error) = error.downcast_ref::<io::Error>() {
+            return matches!(
+                error.kind(),
+                io::ErrorKind::ConnectionReset
+                    | io::ErrorKind::ConnectionAborted
+                    | io::ErrorKind::BrokenPipe
+            );
+        }
+        source = error.source();
+    }
+    false
+}
+
+/// The delivery service, retrying idempotent requests according to the
+/// [`RetryPolicy`].
+#[derive(Debug, Clone)]
+pub(crate) struct Transport {
+    service: Arc<dyn DeliveryService>,
+    pub(crate) retry_policy: RetryPolicy,
+    /// Version of the API spoken with the server, once negotiated.
+    version: Option<u32>,
+}
+
+/// Requests that may be repeated, as they only read or overwrite state.
+macro_rules! idempotent {
+    ($($name:ident($request:ty) -> $response:ty;)*) => {
+        $(
+            pub(crate) async fn $name(
+                &mut self,
+                request: $request,
+            ) -> Result<$response, Status> {
+                let service = self.service.clone();
+                self.retry(stringify!($name), service, request, |service, request| async move {
+                    service.$name(request).await
+                })
+                .await
+            }
+        )*
+    };
+}
+
+/// Requests that are sent once, as repeating them could apply them twice.
+macro_rules! once {
+    ($($name:ident($request:ty) -> $response:ty;)*) => {
+        $(
+            pub(crate) async fn $name(
+                &mut self,
+                request: $request,
+            ) -> Result<$response, Status> {
+                self.service.$name(request).await
+            }
+        )*
+    };
+}
+
+impl Transport {
+    pub(crate) fn new(service: Arc<dyn DeliveryService>, retry_policy: RetryPolicy) -> Self {
+        Self {
+            service,
+            retry_policy,
+            version: None,
+        }
+    }
+
+    /// Latest version of the API that both the server and this client speak,
+    /// asked once per client. Fails with `FailedPrecondition` if there is none.
+    pub(crate) async fn negotiate(&mut self) -> Result<u32, Status> {
+        if let Some(version) = self.version {
+            return Ok(version);
+        }
+        let service = self.service.clone();
+        let response = self
+            .retry(
+                "get_versions",
+                service,
+                GetVersionsRequest {},
+                |service, request| async move { service.get_versions(request).await },
+            )
+            .await;
+        let response = match response {
+            Ok(response) => response,
+            Err(status) if status.code() == Code::Unimplemented => {
+                return Err(Status::failed_precondition(
+                    "The server predates the versioned API",
+                ));
+            }
+            Err(status) => return Err(status),
+        };
+        let version = API_VERSIONS
+            .iter()
+            .rev()
+            .find(|version| response.versions.contains(version))
+            .copied()
+            .ok_or_else(|| {
+                Status::failed_precondition(format!(
+                    "The server serves the API versions {:?}, this client {API_VERSIONS:?}",
+                    response.versions
+                ))
+            })?;
+        debug!(
+            version,
+            server_version = response.server_version,
+            "Negotiated API version"
+        );
+        self.version = Some(version);
+        Ok(version)
+    }
+
+    async fn retry<C, Req, Res, F, Fut>(
+        &mut self,
+        rpc: &str,
+        client: C,
+        request: Req,
+        mut call: F,
+    ) -> Result<Res, Status>
+    where
+        C: Clone,
+        Req: Clone,
+        F: FnMut(C, Req) -> Fut,
+        Fut: Future<Output = Result<Res, Status>>,
+    {
+        let mut retries = 0;
+        loop {
+            match call(client.clone(), request.clone()).await {
+                Err(status) if is_retryable(&status) => {
+                    let Some(wait) = self.retry_policy.backoff(retries) else {
+                        return Err(status);
+                    };
+                    warn!(rpc, %status, ?wait, "Request failed, retrying");
+                    sleep(wait).await;
+                    retries += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    idempotent! {
+        download_blob(DownloadBlobRequest) -> DownloadBlobResponse;
+        fetch_device_certificates(FetchDeviceCertificatesRequest) -> FetchDeviceCertificatesResponse;
+        fetch_group_info(FetchGroupInfoRequest) -> FetchGroupInfoResponse;
+        fetch_key_package(FetchKeyPackageRequest) -> FetchKeyPackageResponse;
+        get_consistency_proof(GetConsistencyProofRequest) -> GetConsistencyProofResponse;
+        get_inclusion_proof(GetInclusionProofRequest) -> GetInclusionProofResponse;
+        get_key_log_root(GetKeyLogRootRequest) -> GetKeyLogRootResponse;
+        get_queue_status(GetQueueStatusRequest) -> GetQueueStatusResponse;
+        list_devices(ListDevicesRequest) -> ListDevicesResponse;
+        publish_group_info(PublishGroupInfoRequest) -> PublishGroupInfoResponse;
+        receive_messages(ReceiveMessagesRequest) -> MessageStream;
+    }
+
+    once! {
+        fetch_messages(FetchMessagesRequest) -> FetchMessagesResponse;
+        revoke_device(RevokeDeviceRequest) -> RevokeDeviceResponse;
+        rotate_device_key(RotateDeviceKeyRequest) -> RotateDeviceKeyResponse;
+        send_message(SendMessageRequest) -> SendMessageResponse;
+        upload_blob(UploadBlobRequest) -> UploadBlobResponse;
+        upload_device_certificate(UploadDeviceCertificateRequest) -> UploadDeviceCertificateResponse;
+        upload_key_package(UploadKeyPackageRequest) -> UploadKeyPackageResponse;
+    }
+}
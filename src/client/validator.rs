@@ -0,0 +1,195 @@
+use std::{fmt, path::Path, sync::Arc};
+
+use anyhow::Context;
+use openmls::{
+    group::StagedCommit,
+    prelude::{BasicCredential, KeyPackage, LeafNode},
+};
+use tracing::debug;
+use uuid::Uuid;
+
+use crate::client::{Client, error::bail};
+
+/// Why a credential is validated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CredentialChange {
+    /// The member is added to the group.
+    Added,
+    /// The member replaced its leaf, e.g. to rotate its keys.
+    Updated,
+    /// The member joined the group by itself with an external commit.
+    Joined,
+    /// The member is in a group that the user was invited to.
+    Invited,
+}
+
+/// The credential of a group member to validate, see [`CredentialValidator`].
+#[derive(Debug, Clone)]
+pub struct MemberCredential {
+    pub group_id: Uuid,
+    pub identity: String,
+    pub signature_key: Vec<u8>,
+    pub change: CredentialChange,
+}
+
+/// Checks the credentials of members before the client accepts them, for
+/// membership policies of a deployment, e.g. an allowlist of identities, a
+/// lookup in a directory or in the key transparency log. See
+/// [`ClientBuilder::with_credential_validator`](crate::client::builder::ClientBuilder::with_credential_validator).
+///
+/// Commits and welcomes with a rejected credential are dropped, and the client
+/// refuses to add members with one.
+#[tonic::async_trait]
+pub trait CredentialValidator: fmt::Debug + Send + Sync {
+    /// Accepts the credential, or returns why it is rejected.
+    async fn validate(&self, credential: &MemberCredential) -> anyhow::Result<()>;
+}
+
+/// Accepts the listed identities only. An entry `*@example.com` accepts all
+/// identities of the domain.
+#[derive(Debug, Clone, Default)]
+pub struct Allowlist {
+    entries: Vec<String>,
+}
+
+impl Allowlist {
+    pub fn new(entries: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        Self {
+            entries: entries.into_iter().map(Into::into).collect(),
+        }
+    }
+
+    /// Reads an allowlist with one entry per line. Empty lines and lines
+    /// starting with `#` are skipped.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read allowlist {}", path.display()))?;
+        Ok(Self::new(
+            content
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#')),
+        ))
+    }
+
+    pub fn allows(&self, identity: &str) -> bool {
+        self.entries
+            .iter()
+            .any(|entry| match entry.strip_prefix("*@") {
+                Some(domain) => identity
+                    .rsplit_once('@')
+                    .is_some_and(|(_, identity_domain)| identity_domain == domain),
+                None => entry == identity,
+            })
+    }
+}
+
+#[tonic::async_trait]
+impl CredentialValidator for Allowlist {
+    async fn validate(&self, credential: &MemberCredential) -> anyhow::Result<()> {
+        if !self.allows(&credential.identity) {
+            bail!("{} is not on the allowlist", credential.identity);
+        }
+        Ok(())
+    }
+}
+
+impl Client {
+    /// Runs all credential validators on `credentials`. Fails with the first
+    /// rejection.
+    pub(crate) async fn validate_credentials(
+        &self,
+        credentials: &[MemberCredential],
+    ) -> anyhow::Result<()> {
+        validate(&self.credential_validators, credentials).await
+    }
+}
+
+async fn validate(
+    validators: &[Arc<dyn CredentialValidator>],
+    credentials: &[MemberCredential],
+) -> anyhow::Result<()> {
+    for validator in validators {
+        for credential in credentials {
+            if let Err(error) = validator.validate(credential).await {
+                bail!(
+                    "Credential of {} was rejected: {error}",
+                    credential.identity
+                );
+            }
+        }
+    }
+    if !validators.is_empty() {
+        debug!(credentials = credentials.len(), "Validated credentials");
+    }
+    Ok(())
+}
+
+/// Runs `validators` on the members that `commit` adds or updates. The leaf of
+/// the update path is the committer's, or a new member's if `external`.
+pub(crate) async fn validate_commit(
+    validators: &[Arc<dyn CredentialValidator>],
+    group_id: Uuid,
+    commit: &StagedCommit,
+    external: bool,
+) -> anyhow::Result<()> {
+    if validators.is_empty() {
+        return Ok(());
+    }
+    let credentials = commit_credentials(group_id, commit, external)?;
+    validate(validators, &credentials).await
+}
+
+fn member_credential(
+    group_id: Uuid,
+    leaf_node: &LeafNode,
+    change: CredentialChange,
+) -> anyhow::Result<MemberCredential> {
+    let credential = BasicCredential::try_from(leaf_node.credential().clone())
+        .context("Member with unsupported credential")?;
+    Ok(MemberCredential {
+        group_id,
+        identity: String::from_utf8_lossy(credential.identity()).into_owned(),
+        signature_key: leaf_node.signature_key().as_slice().to_vec(),
+        change,
+    })
+}
+
+/// Credential of a member to add with `key_package`.
+pub(crate) fn key_package_credential(
+    group_id: Uuid,
+    key_package: &KeyPackage,
+) -> anyhow::Result<MemberCredential> {
+    member_credential(group_id, key_package.leaf_node(), CredentialChange::Added)
+}
+
+/// Credentials of the members that `commit` adds or updates.
+fn commit_credentials(
+    group_id: Uuid,
+    commit: &StagedCommit,
+    external: bool,
+) -> anyhow::Result<Vec<MemberCredential>> {
+    let mut credentials = Vec::new();
+    for proposal in commit.add_proposals() {
+        credentials.push(key_package_credential(
+            group_id,
+            proposal.add_proposal().key_package(),
+        )?);
+    }
+    for proposal in commit.update_proposals() {
+        credentials.push(member_credential(
+            group_id,
+            proposal.update_proposal().leaf_node(),
+            CredentialChange::Updated,
+        )?);
+    }
+    if let Some(leaf_node) = commit.update_path_leaf_node() {
+        let change = if external {
+            CredentialChange::Joined
+        } else {
+            CredentialChange::Updated
+        };
+        credentials.push(member_credential(group_id, leaf_node, change)?);
+    }
+    Ok(credentials)
+}
@@ -0,0 +1,229 @@
+use anyhow::Context;
+use openmls::{
+    group::{GroupId, MlsGroup},
+    prelude::{HashType, OpenMlsCrypto, OpenMlsProvider},
+};
+use openmls_rust_crypto::RustCrypto;
+use sqlx::{
+    query, query_scalar,
+    types::chrono::{DateTime, Utc},
+};
+use uuid::Uuid;
+
+use crate::client::{Client, Result, error::ensure, member::fingerprint};
+
+const SAFETY_NUMBER_LABEL: &[u8] = b"mls-chat safety number";
+const GROUP_CODE_LABEL: &[u8] = b"mls-chat group code";
+const QR_PREFIX: &str = "mls-chat:verify";
+
+/// Code that two users compare out of band to verify each other's keys.
+#[derive(Debug)]
+pub struct SafetyNumber {
+    pub code: String,
+    /// Fingerprints of the current signature keys of the contact.
+    pub fingerprints: Vec<String>,
+    /// Whether all of these keys were verified.
+    pub verified: bool,
+}
+
+impl Client {
+    /// Safety number of the user and `contact`, derived from the signature
+    /// keys of all devices of both. Both users get the same number unless
+    /// someone substituted a key.
+    ///
+    /// The keys are taken from this device only, never from the server: the
+    /// own ones from the local credential and the own leaves in the groups,
+    /// those of `contact` from its leaves in the groups or, without a shared
+    /// group, from its pinned and trusted keys. Devices that only one side
+    /// sees in its groups make the numbers differ.
+    pub async fn safety_number(&mut self, user: String, contact: String) -> Result<SafetyNumber> {
+        ensure!(user != contact, "Can't verify yourself");
+        let (safety_number, _contact_keys) = self.compute_safety_number(&user, &contact).await?;
+        Ok(safety_number)
+    }
+
+    /// Marks the signature keys of `contact` as verified, and so also trusted,
+    /// after the safety number `code` was compared with the one of the
+    /// contact. Fails if the keys changed since, so that exactly the keys that
+    /// went into the compared number are marked.
+    pub async fn verify_contact(
+        &mut self,
+        user: String,
+        contact: String,
+        code: &str,
+    ) -> Result<()> {
+        ensure!(user != contact, "Can't verify yourself");
+        let (safety_number, contact_keys) = self.compute_safety_number(&user, &contact).await?;
+        ensure!(
+            same_code(code, &safety_number.code),
+            "Safety number of {contact} does not match, keys may have been substituted"
+        );
+        let verified_at: DateTime<Utc> = Utc::now();
+        for signature_key in contact_keys {
+            self.pin_key(&user, &contact, &signature_key).await?;
+            query!(
+                "UPDATE client_contact SET trusted = TRUE, verified_at = ?
+                WHERE username = ? AND contact = ? AND signature_key = ?",
+                verified_at,
+                user,
+                contact,
+                signature_key,
+            )
+            .execute(&mut self.connection)
+            .await?;
+        }
+        Ok(())
+    }
+
+    /// Payload of a QR code that lets `contact` verify the user by scanning
+    /// it, see [`Client::verify_qr`].
+    pub async fn verification_payload(&mut self, user: String, contact: String) -> Result<String> {
+        let safety_number = self.safety_number(user.clone(), contact.clone()).await?;
+        let code: String = safety_number.code.split_whitespace().collect();
+        Ok(format!("{QR_PREFIX}:{user}:{contact}:{code}"))
+    }
+
+    /// Verifies the user who showed the scanned QR code `payload`, if it
+    /// carries the same safety number. Returns the verified user.
+    pub async fn verify_qr(&mut self, user: String, payload: &str) -> Result<String> {
+        let (contact, scanned_for, code) = payload
+            .trim()
+            .strip_prefix(QR_PREFIX)
+            .and_then(|rest| rest.strip_prefix(':'))
+            .and_then(|rest| {
+                let mut parts = rest.split(':');
+                match (parts.next(), parts.next(), parts.next(), parts.next()) {
+                    (Some(contact), Some(scanned_for), Some(code), None) => {
+                        Some((contact, scanned_for, code))
+                    }
+                    _ => None,
+                }
+            })
+            .context("Invalid verification code")?;
+        ensure!(
+            scanned_for == user,
+            "Verification code is meant for {scanned_for}"
+        );
+
+        self.verify_contact(user, contact.to_string(), code).await?;
+        Ok(contact.to_string())
+    }
+
+    /// Code of the current epoch of the group, derived from the epoch
+    /// authenticator. Members in the same epoch see the same code only if
+    /// they share the same group state.
+    pub async fn group_code(&mut self, user: String, group_uuid: Uuid) -> Result<(u64, String)> {
+        let group = self.load_member_group(&user, group_uuid).await?;
+        let payload = [GROUP_CODE_LABEL, group.epoch_authenticator().as_slice()].concat();
+        Ok((group.epoch().as_u64(), decimal_code(&payload)))
+    }
+
+    /// The safety number of the user and `contact`, and the keys of
+    /// `contact` it was derived from.
+    async fn compute_safety_number(
+        &mut self,
+        user: &str,
+        contact: &str,
+    ) -> anyhow::Result<(SafetyNumber, Vec<Vec<u8>>)> {
+        let (_signer, credential_with_key) = self.credential(user).await?;
+        let mut own_keys = self.leaf_keys(user, user).await?;
+        own_keys.push(credential_with_key.signature_key.as_slice().to_vec());
+        own_keys.sort();
+        own_keys.dedup();
+
+        let mut contact_keys = self.leaf_keys(user, contact).await?;
+        if contact_keys.is_empty() {
+            contact_keys = query_scalar!(
+                "SELECT signature_key FROM client_contact
+                WHERE username = ? AND contact = ? AND trusted",
+                user,
+                contact,
+            )
+            .fetch_all(&mut self.connection)
+            .await?;
+            contact_keys.sort();
+        }
+        ensure!(
+            !contact_keys.is_empty(),
+            "No keys of {contact} are known yet; share a group first"
+        );
+
+        let mut verified = true;
+        for signature_key in &contact_keys {
+            self.pin_key(user, contact, signature_key).await?;
+            let verified_at = query_scalar!(
+                "SELECT verified_at FROM client_contact
+                WHERE username = ? AND contact = ? AND signature_key = ?",
+                user,
+                contact,
+                signature_key,
+            )
+            .fetch_one(&mut self.connection)
+            .await?;
+            verified &= verified_at.is_some();
+        }
+
+        let mut identities = [(user, &own_keys), (contact, &contact_keys)];
+        identities.sort();
+        let mut payload = SAFETY_NUMBER_LABEL.to_vec();
+        for (identity, keys) in identities {
+            payload.extend((identity.len() as u32).to_be_bytes());
+            payload.extend(identity.as_bytes());
+            payload.extend((keys.len() as u32).to_be_bytes());
+            for key in keys {
+                payload.extend((key.len() as u32).to_be_bytes());
+                payload.extend(key);
+            }
+        }
+
+        let safety_number = SafetyNumber {
+            code: decimal_code(&payload),
+            fingerprints: contact_keys.iter().map(|key| fingerprint(key)).collect(),
+            verified,
+        };
+        Ok((safety_number, contact_keys))
+    }
+
+    /// Signature keys of the leaves of `identity` in the groups of the user,
+    /// sorted.
+    async fn leaf_keys(&mut self, user: &str, identity: &str) -> anyhow::Result<Vec<Vec<u8>>> {
+        let mut keys = Vec::new();
+        for group_uuid in self.group_ids(user).await? {
+            let group_id = GroupId::from_slice(group_uuid.as_bytes());
+            let Some(group) = MlsGroup::load(self.provider().storage(), &group_id)? else {
+                continue;
+            };
+            keys.extend(
+                group
+                    .members()
+                    .filter(|member| member.credential.serialized_content() == identity.as_bytes())
+                    .map(|member| member.signature_key),
+            );
+        }
+        keys.sort();
+        keys.dedup();
+        Ok(keys)
+    }
+}
+
+/// Whether two safety numbers are the same, ignoring how they are grouped.
+fn same_code(a: &str, b: &str) -> bool {
+    a.split_whitespace().collect::<String>() == b.split_whitespace().collect::<String>()
+}
+
+/// Six groups of five decimal digits, derived from the SHA-256 hash of
+/// `payload`.
+fn decimal_code(payload: &[u8]) -> String {
+    let hash = RustCrypto::default()
+        .hash(HashType::Sha2_256, payload)
+        .expect("SHA-256 is supported");
+    hash.chunks_exact(5)
+        .map(|chunk| {
+            let value = chunk
+                .iter()
+                .fold(0u64, |value, byte| (value << 8) | u64::from(*byte));
+            format!("{:05}", value % 100_000)
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
@@ -0,0 +1,304 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use futures_util::{SinkExt, StreamExt, stream};
+use tokio::{
+    net::TcpStream,
+    sync::{Mutex, mpsc},
+    time::{interval, timeout},
+};
+use tokio_tungstenite::{
+    MaybeTlsStream, WebSocketStream,
+    tungstenite::{
+        Message,
+        client::IntoClientRequest,
+        http::{HeaderValue, Uri, header},
+    },
+};
+use tonic::Status;
+use tracing::{debug, warn};
+
+use crate::{
+    client::{
+        delivery::MessageStream,
+        frames::{FrameTransport, unexpected_response},
+    },
+    grpc::{web_socket_request::Request, web_socket_response::Response, *},
+    websocket::{Frame, WEBSOCKET_PATH, WebSocketEncoding},
+};
+
+/// Responses of the requests in flight, by id.
+type Pending = Arc<std::sync::Mutex<HashMap<u64, mpsc::UnboundedSender<Response>>>>;
+
+/// Connection options of a [`WebSocketDeliveryService`], see
+/// [`ClientBuilder`](crate::client::builder::ClientBuilder).
+#[derive(Debug, Default)]
+pub(crate) struct WebSocketOptions {
+    pub(crate) encoding: WebSocketEncoding,
+    pub(crate) connect_timeout: Option<Duration>,
+    pub(crate) timeout: Option<Duration>,
+    pub(crate) keepalive: Option<Duration>,
+    pub(crate) user_agent: Option<String>,
+    pub(crate) auth_token: Option<String>,
+}
+
+/// The delivery service of a server reached over a WebSocket, for `ws://`
+/// and `wss://` endpoints, see `proto/mlschat/v1/websocket.proto`. Connects on
+/// the first request and again after the connection broke.
+#[derive(Debug)]
+pub(crate) struct WebSocketDeliveryService {
+    url: String,
+    options: WebSocketOptions,
+    connection: Mutex<Option<Connection>>,
+    next_id: AtomicU64,
+}
+
+#[derive(Debug, Clone)]
+struct Connection {
+    requests: mpsc::UnboundedSender<WebSocketRequest>,
+    pending: Pending,
+}
+
+impl WebSocketDeliveryService {
+    /// Connects to the WebSocket endpoint of the server at `endpoint`, at
+    /// [`WEBSOCKET_PATH`] unless the URL has a path of its own.
+    pub(crate) fn new(endpoint: &str, options: WebSocketOptions) -> Result<Self, Status> {
+        let uri: Uri = endpoint
+            .parse()
+            .map_err(|error| Status::invalid_argument(format!("Invalid endpoint: {error}")))?;
+        let url = if uri.path() == "/" {
+            format!("{}{WEBSOCKET_PATH}", endpoint.trim_end_matches('/'))
+        } else {
+            endpoint.to_string()
+        };
+        Ok(Self {
+            url,
+            options,
+            connection: Mutex::new(None),
+            next_id: AtomicU64::new(0),
+        })
+    }
+
+    async fn connection(&self) -> Result<Connection, Status> {
+        let mut connection = self.connection.lock().await;
+        if let Some(connection) = &*connection
+            && !connection.requests.is_closed()
+        {
+            return Ok(connection.clone());
+        }
+        let connected = self.connect().await?;
+        *connection = Some(connected.clone());
+        Ok(connected)
+    }
+
+    async fn connect(&self) -> Result<Connection, Status> {
+        let mut request = self
+            .url
+            .as_str()
+            .into_client_request()
+            .map_err(|error| Status::invalid_argument(format!("Invalid endpoint: {error}")))?;
+        let headers = request.headers_mut();
+        headers.insert(
+            header::SEC_WEBSOCKET_PROTOCOL,
+            HeaderValue::from_static(self.options.encoding.protocol()),
+        );
+        if let Some(token) = &self.options.auth_token {
+            let token = HeaderValue::try_from(format!("Bearer {token}"))
+                .map_err(|_| Status::invalid_argument("Invalid auth token"))?;
+            headers.insert(header::AUTHORIZATION, token);
+        }
+        if let Some(user_agent) = &self.options.user_agent {
+            let user_agent = HeaderValue::try_from(user_agent)
+                .map_err(|_| Status::invalid_argument("Invalid user agent"))?;
+            headers.insert(header::USER_AGENT, user_agent);
+        }
+
+        debug!(url = self.url, "Connecting WebSocket");
+        let connect = tokio_tungstenite::connect_async(request);
+        let connected = match self.options.connect_timeout {
+            Some(connect_timeout) => timeout(connect_timeout, connect)
+                .await
+                .map_err(|_| Status::unavailable("Timed out connecting to the server"))?,
+            None => connect.await,
+        };
+        let (socket, _) = connected.map_err(|error| Status::unavailable(error.to_string()))?;
+
+        let (requests, outgoing) = mpsc::unbounded_channel();
+        let pending = Pending::default();
+        tokio::spawn(drive(
+            socket,
+            outgoing,
+            pending.clone(),
+            self.options.encoding,
+            self.options.keepalive,
+        ));
+        Ok(Connection { requests, pending })
+    }
+
+    /// Sends `request`, returning its id and the receiver of its responses.
+    async fn start(
+        &self,
+        request: Request,
+    ) -> Result<(Connection, u64, mpsc::UnboundedReceiver<Response>), Status> {
+        let connection = self.connection().await?;
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (responses, receiver) = mpsc::unbounded_channel();
+        connection.pending.lock().unwrap().insert(id, responses);
+        connection
+            .requests
+            .send(WebSocketRequest {
+                id,
+                request: Some(request),
+            })
+            .map_err(|_| connection_closed())?;
+        Ok((connection, id, receiver))
+    }
+}
+
+/// Sends the requests of the connection and passes the responses on, until
+/// the socket closes or the requests are dropped.
+async fn drive(
+    socket: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    mut requests: mpsc::UnboundedReceiver<WebSocketRequest>,
+    pending: Pending,
+    encoding: WebSocketEncoding,
+    keepalive: Option<Duration>,
+) {
+    let (mut sink, mut stream) = socket.split();
+    let mut pings = keepalive.map(interval);
+    loop {
+        tokio::select! {
+            request = requests.recv() => {
+                let Some(request) = request else {
+                    break;
+                };
+                let message = match encoding.encode(&request) {
+                    Frame::Binary(bytes) => Message::Binary(bytes.into()),
+                    Frame::Text(text) => Message::Text(text.into()),
+                };
+                if let Err(error) = sink.send(message).await {
+                    warn!(%error, "WebSocket failed");
+                    break;
+                }
+            }
+            message = stream.next() => {
+                let frame = match message {
+                    Some(Ok(Message::Binary(bytes))) => Frame::Binary(bytes.into()),
+                    Some(Ok(Message::Text(text))) => Frame::Text(text.as_str().to_string()),
+                    Some(Ok(Message::Close(_))) | None => break,
+                    Some(Ok(_)) => continue,
+                    Some(Err(error)) => {
+                        warn!(%error, "WebSocket failed");
+                        break;
+                    }
+                };
+                let response: WebSocketResponse = match encoding.decode(frame) {
+                    Ok(response) => response,
+                    Err(status) => {
+                        warn!(%status, "Closing WebSocket");
+                        break;
+                    }
+                };
+                let Some(body) = response.response else {
+                    continue;
+                };
+                // Only the messages of a stream are followed by further responses.
+                let last = !matches!(body, Response::ReceiveMessages(_));
+                let mut pending = pending.lock().unwrap();
+                if let Some(responses) = pending.get(&response.id) {
+                    let _ = responses.send(body);
+                }
+                if last {
+                    pending.remove(&response.id);
+                }
+            }
+            _ = async {
+                match &mut pings {
+                    Some(pings) => pings.tick().await,
+                    None => std::future::pending().await,
+                }
+            } => {
+                if let Err(error) = sink.send(Message::Ping(Default::default())).await {
+                    warn!(%error, "WebSocket failed");
+                    break;
+                }
+            }
+        }
+    }
+    // Closed first, so that requests started from now on fail right away
+    // instead of waiting for responses that won't come.
+    requests.close();
+    pending.lock().unwrap().clear();
+}
+
+fn connection_closed() -> Status {
+    Status::unavailable("WebSocket closed")
+}
+
+/// Responses of a `receive_messages` request, which is cancelled when they
+/// are dropped before the stream ended.
+struct Subscription {
+    id: u64,
+    responses: mpsc::UnboundedReceiver<Response>,
+    requests: mpsc::UnboundedSender<WebSocketRequest>,
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        if !self.responses.is_closed() {
+            let _ = self.requests.send(WebSocketRequest {
+                id: self.id,
+                request: Some(Request::Cancel(CancelRequest {})),
+            });
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl FrameTransport for WebSocketDeliveryService {
+    async fn unary(&self, request: Request) -> Result<Response, Status> {
+        let (_, _, mut responses) = self.start(request).await?;
+        let response = responses.recv();
+        let response = match self.options.timeout {
+            Some(request_timeout) => timeout(request_timeout, response)
+                .await
+                .map_err(|_| Status::deadline_exceeded("Request timed out"))?,
+            None => response.await,
+        };
+        match response.ok_or_else(connection_closed)? {
+            Response::Status(status) => Err(status.into()),
+            response => Ok(response),
+        }
+    }
+
+    async fn receive_messages(
+        &self,
+        request: ReceiveMessagesRequest,
+    ) -> Result<MessageStream, Status> {
+        let (connection, id, responses) = self.start(Request::ReceiveMessages(request)).await?;
+        let subscription = Subscription {
+            id,
+            responses,
+            requests: connection.requests,
+        };
+        let messages = stream::unfold(Some(subscription), |subscription| async move {
+            let mut subscription = subscription?;
+            let message = match subscription.responses.recv().await {
+                Some(Response::ReceiveMessages(message)) => Ok(message),
+                Some(Response::Status(status)) if status.code == 0 => return None,
+                Some(Response::Status(status)) => Err(status.into()),
+                Some(_) => Err(unexpected_response()),
+                None => Err(connection_closed()),
+            };
+            let subscription = message.is_ok().then_some(subscription);
+            Some((message, subscription))
+        });
+        Ok(messages.boxed())
+    }
+}
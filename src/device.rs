@@ -0,0 +1,57 @@
+use anyhow::{Context, ensure};
+use openmls::prelude::{OpenMlsCrypto, SignatureScheme};
+use openmls_rust_crypto::RustCrypto;
+use prost::Message;
+
+use crate::grpc::{DeviceCertificate, RevokeDeviceRequest};
+
+const CERTIFICATE_LABEL: &[u8] = b"mls-chat device certificate";
+const REVOCATION_LABEL: &[u8] = b"mls-chat device revocation";
+
+/// Bytes covered by the signature of a device certificate.
+pub(crate) fn certificate_payload(certificate: &DeviceCertificate) -> Vec<u8> {
+    let unsigned = DeviceCertificate {
+        signature: Vec::new(),
+        ..certificate.clone()
+    };
+    [CERTIFICATE_LABEL, &unsigned.encode_to_vec()].concat()
+}
+
+/// Bytes covered by the signature of a device revocation.
+pub(crate) fn revocation_payload(request: &RevokeDeviceRequest) -> Vec<u8> {
+    let unsigned = RevokeDeviceRequest {
+        signature: Vec::new(),
+        ..request.clone()
+    };
+    [REVOCATION_LABEL, &unsigned.encode_to_vec()].concat()
+}
+
+pub(crate) fn verify(payload: &[u8], signature_key: &[u8], signature: &[u8]) -> bool {
+    RustCrypto::default()
+        .verify_signature(SignatureScheme::ED25519, payload, signature_key, signature)
+        .is_ok()
+}
+
+/// Link code transferred from a new device to an existing one: the device id and
+/// the hex encoded signature public key of the new device.
+pub(crate) fn encode_link_code(device_id: &str, signature_key: &[u8]) -> String {
+    let key: String = signature_key
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+    format!("{device_id}:{key}")
+}
+
+pub(crate) fn decode_link_code(code: &str) -> anyhow::Result<(String, Vec<u8>)> {
+    let (device_id, key) = code.trim().split_once(':').context("Invalid link code")?;
+    ensure!(
+        !device_id.is_empty() && key.len() % 2 == 0,
+        "Invalid link code"
+    );
+    let signature_key = (0..key.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&key[i..i + 2], 16))
+        .collect::<Result<_, _>>()
+        .context("Invalid link code")?;
+    Ok((device_id.to_string(), signature_key))
+}
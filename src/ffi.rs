@@ -0,0 +1,226 @@
+use std::{sync::Arc, time::SystemTime};
+
+use tokio::sync::{
+    Mutex,
+    broadcast::{self, error::RecvError},
+};
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::{
+    client::{
+        Client, Result,
+        daemon::ClientHandle,
+        events::ChatEvent,
+        group::GroupSummary,
+        history::{Direction, HistoryMessage},
+        invite,
+        policy::RequiredCapabilities,
+    },
+    grpc::GroupMetadata,
+    provider::DEFAULT_CIPHERSUITE,
+};
+
+uniffi::custom_type!(Uuid, String);
+
+impl crate::UniffiCustomTypeConverter for Uuid {
+    type Builtin = String;
+
+    fn into_custom(value: String) -> uniffi::Result<Self> {
+        Ok(value.parse()?)
+    }
+
+    fn from_custom(uuid: Self) -> String {
+        uuid.to_string()
+    }
+}
+
+/// [`Client`] for apps in Kotlin and Swift. It follows the messages of a
+/// single user on a task of its own, see [`Client::spawn`].
+#[derive(uniffi::Object)]
+pub struct ChatClient {
+    handle: ClientHandle,
+    user: String,
+    events: Mutex<broadcast::Receiver<ChatEvent>>,
+}
+
+#[uniffi::export(async_runtime = "tokio")]
+impl ChatClient {
+    /// Opens the database at `db_path` and follows the messages of `user`
+    /// from the server at `endpoint`. Registers the user first if it isn't
+    /// registered on this device yet.
+    #[uniffi::constructor]
+    pub async fn connect(endpoint: String, db_path: String, user: String) -> Result<Arc<Self>> {
+        let mut client = Client::builder(endpoint, db_path).build().await?;
+        if !client.profiles().await?.contains(&user) {
+            client.register(user.clone(), DEFAULT_CIPHERSUITE).await?;
+        }
+        let (handle, events) = client.spawn(user.clone());
+        Ok(Arc::new(Self {
+            handle,
+            user,
+            events: Mutex::new(events),
+        }))
+    }
+
+    pub async fn groups(&self) -> Result<Vec<Group>> {
+        let user = self.user.clone();
+        let groups = self
+            .handle
+            .call(move |client| Box::pin(client.list_groups(user)))
+            .await?;
+        Ok(groups.into_iter().map(Into::into).collect())
+    }
+
+    /// Creates a group named `name` and returns its id.
+    pub async fn create_group(&self, name: String) -> Result<Uuid> {
+        let user = self.user.clone();
+        let metadata = GroupMetadata {
+            name,
+            ..Default::default()
+        };
+        self.handle
+            .call(move |client| {
+                Box::pin(client.create_group(
+                    user,
+                    Some(metadata),
+                    None,
+                    RequiredCapabilities::default(),
+                ))
+            })
+            .await
+    }
+
+    pub async fn add_member(&self, group_id: Uuid, member: String) -> Result<()> {
+        let user = self.user.clone();
+        self.handle
+            .call(move |client| Box::pin(client.add_members(user, group_id, vec![member])))
+            .await
+    }
+
+    pub async fn invites(&self) -> Result<Vec<Invite>> {
+        let user = self.user.clone();
+        let invites = self
+            .handle
+            .call(move |client| Box::pin(client.invites(user)))
+            .await?;
+        Ok(invites.into_iter().map(Into::into).collect())
+    }
+
+    pub async fn accept_invite(&self, group_id: Uuid) -> Result<()> {
+        let user = self.user.clone();
+        self.handle
+            .call(move |client| Box::pin(client.accept_invite(user, group_id)))
+            .await
+    }
+
+    pub async fn send(&self, group_id: Uuid, text: String) -> Result<()> {
+        let user = self.user.clone();
+        self.handle
+            .call(move |client| Box::pin(client.send(user, group_id, text)))
+            .await?;
+        Ok(())
+    }
+
+    /// The latest `limit` messages of the group, oldest first.
+    pub async fn history(&self, group_id: Uuid, limit: u32) -> Result<Vec<ChatMessage>> {
+        let user = self.user.clone();
+        let messages = self
+            .handle
+            .call(move |client| Box::pin(client.history(user, group_id, limit, None)))
+            .await?;
+        Ok(messages.into_iter().map(Into::into).collect())
+    }
+
+    /// Waits for the next event, or returns `None` once the client stopped.
+    pub async fn next_event(&self) -> Option<ChatEvent> {
+        let mut events = self.events.lock().await;
+        loop {
+            match events.recv().await {
+                Ok(event) => return Some(event),
+                Err(RecvError::Lagged(missed)) => warn!(missed, "Missed events"),
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }
+}
+
+/// A group the user is a member of, see [`GroupSummary`].
+#[derive(uniffi::Record)]
+pub struct Group {
+    pub group_id: Uuid,
+    pub name: String,
+    pub topic: String,
+    pub last_activity_at: Option<SystemTime>,
+    pub unread: u64,
+    pub muted: bool,
+    pub archived: bool,
+}
+
+impl From<GroupSummary> for Group {
+    fn from(group: GroupSummary) -> Self {
+        Self {
+            group_id: group.group_id,
+            name: group.name,
+            topic: group.topic,
+            last_activity_at: group.last_activity_at.map(Into::into),
+            unread: group.unread,
+            muted: group.muted,
+            archived: group.archived,
+        }
+    }
+}
+
+/// An invite to a group, see [`invite::Invite`].
+#[derive(uniffi::Record)]
+pub struct Invite {
+    pub group_id: Uuid,
+    pub name: String,
+    pub inviter: String,
+    pub members: Vec<String>,
+    pub received_at: SystemTime,
+}
+
+impl From<invite::Invite> for Invite {
+    fn from(invite: invite::Invite) -> Self {
+        Self {
+            group_id: invite.group_id,
+            name: invite.name,
+            inviter: invite.inviter,
+            members: invite.members,
+            received_at: invite.received_at.into(),
+        }
+    }
+}
+
+/// A message of the history, see [`HistoryMessage`].
+#[derive(uniffi::Record)]
+pub struct ChatMessage {
+    pub message_id: i64,
+    pub sender: String,
+    pub outgoing: bool,
+    pub body: String,
+    pub reply_to: Option<i64>,
+    pub created_at: SystemTime,
+    pub edited_at: Option<SystemTime>,
+    pub deleted_at: Option<SystemTime>,
+    pub read_by: Vec<String>,
+    pub shared_by: Option<String>,
+}
+
+impl From<HistoryMessage> for ChatMessage {
+    fn from(message: HistoryMessage) -> Self {
+        Self {
+            message_id: message.message_id,
+            sender: message.sender,
+            outgoing: message.direction == Direction::Outgoing,
+            body: message.body,
+            reply_to: message.reply_to,
+            created_at: message.created_at.into(),
+            edited_at: message.edited_at.map(Into::into),
+            deleted_at: message.deleted_at.map(Into::into),
+            read_by: message.read_by,
+            shared_by: message.shared_by,
+        }
+    }
+}
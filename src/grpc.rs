@@ -1 +1,16 @@
-tonic::include_proto!("chat");
+// Like `tonic::include_proto!`, which isn't available with `proto-only`.
+include!(concat!(env!("OUT_DIR"), "/mlschat.rs"));
+
+pub mod v1 {
+    include!(concat!(env!("OUT_DIR"), "/mlschat.v1.rs"));
+}
+
+/// The latest version of the API.
+pub use v1::*;
+
+/// Versions of the API that this build serves and speaks, see
+/// `proto/mlschat/v1/chat.proto` for what may change within a version.
+pub const API_VERSIONS: &[u32] = &[1];
+
+/// Largest accepted blob, below the default message size limit of gRPC.
+pub const MAX_BLOB_SIZE: usize = 3 * 1024 * 1024;
@@ -1,4 +1,28 @@
+#[cfg(feature = "client")]
 pub mod client;
+// Each of client and server only uses part of it.
+#[cfg(any(feature = "client", feature = "server"))]
+#[cfg_attr(not(all(feature = "client", feature = "server")), allow(dead_code))]
+mod device;
+#[cfg(feature = "uniffi")]
+pub mod ffi;
+#[cfg(feature = "proto-only")]
 pub mod grpc;
+#[cfg(any(feature = "client", feature = "server"))]
+mod migrations;
+#[cfg(feature = "client")]
 pub mod provider;
+#[cfg(feature = "pyo3")]
+pub mod python;
+#[cfg(all(feature = "quic", any(feature = "client", feature = "server")))]
+pub mod quic;
+#[cfg(feature = "server")]
 pub mod server;
+#[cfg(any(feature = "client", feature = "server"))]
+#[cfg_attr(not(all(feature = "client", feature = "server")), allow(dead_code))]
+mod transparency;
+#[cfg(all(feature = "websocket", any(feature = "client", feature = "server")))]
+pub mod websocket;
+
+#[cfg(feature = "uniffi")]
+uniffi::setup_scaffolding!();
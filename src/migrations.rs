@@ -0,0 +1,38 @@
+use sqlx::{
+    SqliteConnection,
+    migrate::{Migrate, MigrateError, Migrator},
+};
+
+/// Versions of the migrations that changed tables of both the client and the
+/// server, from before each got a directory of migrations. Each was split into
+/// a migration of the client and one of the server with the same version.
+const SPLIT_MIGRATIONS: [i64; 4] = [
+    20260214152314,
+    20261016090100,
+    20261016090400,
+    20261016091000,
+];
+
+/// Prepares `migrator` for a database that may have been migrated before the
+/// migrations of the client and the server were separated, and so applied the
+/// migrations of both: the migrations of the other side are ignored, and the
+/// split migrations are recorded with the checksums of their part, whose
+/// changes the database already has.
+pub(crate) async fn adopt_split_migrations(
+    connection: &mut SqliteConnection,
+    migrator: &mut Migrator,
+) -> Result<(), MigrateError> {
+    migrator.set_ignore_missing(true);
+    connection.ensure_migrations_table().await?;
+    for migration in migrator
+        .iter()
+        .filter(|migration| SPLIT_MIGRATIONS.contains(&migration.version))
+    {
+        sqlx::query("UPDATE _sqlx_migrations SET checksum = ? WHERE version = ?")
+            .bind(&*migration.checksum)
+            .bind(migration.version)
+            .execute(&mut *connection)
+            .await?;
+    }
+    Ok(())
+}
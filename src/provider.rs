@@ -1,29 +1,90 @@
-use openmls::prelude::{Ciphersuite, OpenMlsProvider, ProtocolVersion};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use anyhow::ensure;
+use openmls::prelude::{
+    Capabilities, Ciphersuite, ExtensionType, OpenMlsProvider, ProtocolVersion,
+};
+use openmls_memory_storage::{MemoryStorage, MemoryStorageError};
 use openmls_rust_crypto::RustCrypto;
 use openmls_sqlx_storage::{Codec, SqliteStorageProvider};
+use openmls_traits::storage::{CURRENT_VERSION, StorageProvider, traits};
 use serde::{Serialize, de::DeserializeOwned};
+use sqlx::SqliteConnection;
 
 use crate::client::Client;
 
 pub const PROTOCOL_VERSION: ProtocolVersion = ProtocolVersion::Mls10;
 
-pub(crate) const CIPHERSUITE: Ciphersuite =
+pub const DEFAULT_CIPHERSUITE: Ciphersuite =
     Ciphersuite::MLS_128_DHKEMX25519_CHACHA20POLY1305_SHA256_Ed25519;
 
+/// Ciphersuites that groups can use. Limited to Ed25519, as every user has a
+/// single signature key.
+pub const SUPPORTED_CIPHERSUITES: &[Ciphersuite] = &[
+    DEFAULT_CIPHERSUITE,
+    Ciphersuite::MLS_128_DHKEMX25519_AES128GCM_SHA256_Ed25519,
+];
+
+pub(crate) fn check_ciphersuite(ciphersuite: Ciphersuite) -> anyhow::Result<()> {
+    ensure!(
+        SUPPORTED_CIPHERSUITES.contains(&ciphersuite),
+        "Unsupported ciphersuite {ciphersuite:?}"
+    );
+    Ok(())
+}
+
+/// Group context extension carrying the encoded [`crate::grpc::GroupMetadata`].
+pub(crate) const GROUP_METADATA_EXTENSION: u16 = 0xff01;
+
+/// Group context extension carrying the encoded [`crate::grpc::GroupRoles`].
+pub(crate) const GROUP_ROLES_EXTENSION: u16 = 0xff02;
+
+/// Capabilities of the leaf nodes of this client.
+pub(crate) fn capabilities() -> Capabilities {
+    Capabilities::builder()
+        .ciphersuites(SUPPORTED_CIPHERSUITES.to_vec())
+        .extensions(vec![
+            ExtensionType::LastResort,
+            ExtensionType::Unknown(GROUP_METADATA_EXTENSION),
+            ExtensionType::Unknown(GROUP_ROLES_EXTENSION),
+        ])
+        .build()
+}
+
 impl Client {
     pub(crate) fn provider(&mut self) -> Provider<'_> {
-        let storage = SqliteStorageProvider::<JsonCodec>::new(&mut self.connection);
-        Provider::new(storage)
+        Provider::new(Storage {
+            backend: Backend::new(
+                &mut self.connection,
+                self.memory_storage.as_ref(),
+                self.storage_codec,
+            ),
+            writes: Some(&self.group_cache.writes),
+        })
+    }
+
+    /// Provider for changing a group taken with
+    /// [`Client::take_group`](crate::client::Client::take_group). Its writes
+    /// don't invalidate the group cache.
+    pub(crate) fn cached_provider(&mut self) -> Provider<'_> {
+        Provider::new(Storage {
+            backend: Backend::new(
+                &mut self.connection,
+                self.memory_storage.as_ref(),
+                self.storage_codec,
+            ),
+            writes: None,
+        })
     }
 }
 
 pub(crate) struct Provider<'a> {
-    storage: SqliteStorageProvider<'a, JsonCodec>,
+    storage: Storage<'a>,
     crypto: RustCrypto,
 }
 
 impl<'a> Provider<'a> {
-    pub(crate) fn new(storage: SqliteStorageProvider<'a, JsonCodec>) -> Self {
+    pub(crate) fn new(storage: Storage<'a>) -> Self {
         Self {
             storage,
             crypto: Default::default(),
@@ -36,7 +97,7 @@ impl<'a> OpenMlsProvider for Provider<'a> {
 
     type RandProvider = RustCrypto;
 
-    type StorageProvider = SqliteStorageProvider<'a, JsonCodec>;
+    type StorageProvider = Storage<'a>;
 
     fn storage(&self) -> &Self::StorageProvider {
         &self.storage
@@ -51,6 +112,240 @@ impl<'a> OpenMlsProvider for Provider<'a> {
     }
 }
 
+/// MLS state of the client.
+pub(crate) struct Storage<'a> {
+    backend: Backend<'a>,
+    /// Counts the writes that may leave groups in the
+    /// [`GroupCache`](crate::client::cache::GroupCache) stale.
+    writes: Option<&'a AtomicU64>,
+}
+
+/// Where the MLS state of the client is kept, see
+/// [`ClientBuilder::with_memory_storage`](crate::client::builder::ClientBuilder::with_memory_storage).
+enum Backend<'a> {
+    Sqlite(SqliteStorageProvider<'a, JsonCodec>),
+    SqliteCbor(SqliteStorageProvider<'a, CborCodec>),
+    Memory(&'a MemoryStorage),
+}
+
+impl<'a> Backend<'a> {
+    fn new(
+        connection: &'a mut SqliteConnection,
+        memory_storage: Option<&'a MemoryStorage>,
+        codec: StorageCodec,
+    ) -> Self {
+        match (memory_storage, codec) {
+            (Some(storage), _) => Self::Memory(storage),
+            (None, StorageCodec::Json) => Self::Sqlite(SqliteStorageProvider::new(connection)),
+            (None, StorageCodec::Cbor) => Self::SqliteCbor(SqliteStorageProvider::new(connection)),
+        }
+    }
+}
+
+/// Errors of the in-memory storage, which only fails to encode values, are
+/// reported like database errors.
+fn memory_error(error: MemoryStorageError) -> sqlx::Error {
+    sqlx::Error::Encode(Box::new(error))
+}
+
+/// Implements [`StorageProvider`] for [`Storage`] by forwarding the methods
+/// to the storage in use, counting the writes.
+macro_rules! forward_storage {
+    (
+        writes {$(
+            fn $write:ident<$($write_param:ident: $write_bound:ident),* $(,)?>(
+                &self $(, $write_arg:ident: $write_arg_type:ty)*
+            ) -> ();
+        )*}
+        reads {$(
+            fn $read:ident<$($read_param:ident: $read_bound:ident),* $(,)?>(
+                &self $(, $read_arg:ident: $read_arg_type:ty)*
+            ) -> $read_output:ty;
+        )*}
+    ) => {
+        impl StorageProvider<CURRENT_VERSION> for Storage<'_> {
+            type Error = sqlx::Error;
+
+            $(
+                fn $write<$($write_param: traits::$write_bound<CURRENT_VERSION>),*>(
+                    &self,
+                    $($write_arg: $write_arg_type),*
+                ) -> Result<(), Self::Error> {
+                    if let Some(writes) = self.writes {
+                        writes.fetch_add(1, Ordering::Relaxed);
+                    }
+                    forward_storage!(self.$write::<$($write_param),*>($($write_arg),*))
+                }
+            )*
+
+            $(
+                fn $read<$($read_param: traits::$read_bound<CURRENT_VERSION>),*>(
+                    &self,
+                    $($read_arg: $read_arg_type),*
+                ) -> Result<$read_output, Self::Error> {
+                    forward_storage!(self.$read::<$($read_param),*>($($read_arg),*))
+                }
+            )*
+        }
+    };
+    ($self:ident.$method:ident::<$($param:ident),*>($($arg:ident),*)) => {
+        match &$self.backend {
+            Backend::Sqlite(storage) => storage.$method::<$($param),*>($($arg),*),
+            Backend::SqliteCbor(storage) => storage.$method::<$($param),*>($($arg),*),
+            Backend::Memory(storage) => storage
+                .$method::<$($param),*>($($arg),*)
+                .map_err(memory_error),
+        }
+    };
+}
+
+forward_storage! {
+    writes {
+        fn write_mls_join_config<GroupId: GroupId, MlsGroupJoinConfig: MlsGroupJoinConfig>(
+            &self, group_id: &GroupId, config: &MlsGroupJoinConfig
+        ) -> ();
+        fn append_own_leaf_node<GroupId: GroupId, LeafNode: LeafNode>(
+            &self, group_id: &GroupId, leaf_node: &LeafNode
+        ) -> ();
+        fn queue_proposal<GroupId: GroupId, ProposalRef: ProposalRef, QueuedProposal: QueuedProposal>(
+            &self, group_id: &GroupId, proposal_ref: &ProposalRef, proposal: &QueuedProposal
+        ) -> ();
+        fn write_tree<GroupId: GroupId, TreeSync: TreeSync>(
+            &self, group_id: &GroupId, tree: &TreeSync
+        ) -> ();
+        fn write_interim_transcript_hash<
+            GroupId: GroupId,
+            InterimTranscriptHash: InterimTranscriptHash,
+        >(
+            &self, group_id: &GroupId, interim_transcript_hash: &InterimTranscriptHash
+        ) -> ();
+        fn write_context<GroupId: GroupId, GroupContext: GroupContext>(
+            &self, group_id: &GroupId, group_context: &GroupContext
+        ) -> ();
+        fn write_confirmation_tag<GroupId: GroupId, ConfirmationTag: ConfirmationTag>(
+            &self, group_id: &GroupId, confirmation_tag: &ConfirmationTag
+        ) -> ();
+        fn write_group_state<GroupState: GroupState, GroupId: GroupId>(
+            &self, group_id: &GroupId, group_state: &GroupState
+        ) -> ();
+        fn write_message_secrets<GroupId: GroupId, MessageSecrets: MessageSecrets>(
+            &self, group_id: &GroupId, message_secrets: &MessageSecrets
+        ) -> ();
+        fn write_resumption_psk_store<GroupId: GroupId, ResumptionPskStore: ResumptionPskStore>(
+            &self, group_id: &GroupId, resumption_psk_store: &ResumptionPskStore
+        ) -> ();
+        fn write_own_leaf_index<GroupId: GroupId, LeafNodeIndex: LeafNodeIndex>(
+            &self, group_id: &GroupId, own_leaf_index: &LeafNodeIndex
+        ) -> ();
+        fn write_group_epoch_secrets<GroupId: GroupId, GroupEpochSecrets: GroupEpochSecrets>(
+            &self, group_id: &GroupId, group_epoch_secrets: &GroupEpochSecrets
+        ) -> ();
+        fn write_signature_key_pair<
+            SignaturePublicKey: SignaturePublicKey,
+            SignatureKeyPair: SignatureKeyPair,
+        >(
+            &self, public_key: &SignaturePublicKey, signature_key_pair: &SignatureKeyPair
+        ) -> ();
+        fn write_encryption_key_pair<EncryptionKey: EncryptionKey, HpkeKeyPair: HpkeKeyPair>(
+            &self, public_key: &EncryptionKey, key_pair: &HpkeKeyPair
+        ) -> ();
+        fn write_encryption_epoch_key_pairs<
+            GroupId: GroupId,
+            EpochKey: EpochKey,
+            HpkeKeyPair: HpkeKeyPair,
+        >(
+            &self, group_id: &GroupId, epoch: &EpochKey, leaf_index: u32, key_pairs: &[HpkeKeyPair]
+        ) -> ();
+        fn write_key_package<HashReference: HashReference, KeyPackage: KeyPackage>(
+            &self, hash_ref: &HashReference, key_package: &KeyPackage
+        ) -> ();
+        fn write_psk<PskId: PskId, PskBundle: PskBundle>(&self, psk_id: &PskId, psk: &PskBundle) -> ();
+        fn remove_proposal<GroupId: GroupId, ProposalRef: ProposalRef>(
+            &self, group_id: &GroupId, proposal_ref: &ProposalRef
+        ) -> ();
+        fn delete_own_leaf_nodes<GroupId: GroupId>(&self, group_id: &GroupId) -> ();
+        fn delete_group_config<GroupId: GroupId>(&self, group_id: &GroupId) -> ();
+        fn delete_tree<GroupId: GroupId>(&self, group_id: &GroupId) -> ();
+        fn delete_confirmation_tag<GroupId: GroupId>(&self, group_id: &GroupId) -> ();
+        fn delete_group_state<GroupId: GroupId>(&self, group_id: &GroupId) -> ();
+        fn delete_context<GroupId: GroupId>(&self, group_id: &GroupId) -> ();
+        fn delete_interim_transcript_hash<GroupId: GroupId>(&self, group_id: &GroupId) -> ();
+        fn delete_message_secrets<GroupId: GroupId>(&self, group_id: &GroupId) -> ();
+        fn delete_all_resumption_psk_secrets<GroupId: GroupId>(&self, group_id: &GroupId) -> ();
+        fn delete_own_leaf_index<GroupId: GroupId>(&self, group_id: &GroupId) -> ();
+        fn delete_group_epoch_secrets<GroupId: GroupId>(&self, group_id: &GroupId) -> ();
+        fn clear_proposal_queue<GroupId: GroupId, ProposalRef: ProposalRef>(
+            &self, group_id: &GroupId
+        ) -> ();
+        fn delete_signature_key_pair<SignaturePublicKey: SignaturePublicKey>(
+            &self, public_key: &SignaturePublicKey
+        ) -> ();
+        fn delete_encryption_key_pair<EncryptionKey: EncryptionKey>(
+            &self, public_key: &EncryptionKey
+        ) -> ();
+        fn delete_encryption_epoch_key_pairs<GroupId: GroupId, EpochKey: EpochKey>(
+            &self, group_id: &GroupId, epoch: &EpochKey, leaf_index: u32
+        ) -> ();
+        fn delete_key_package<KeyPackageRef: HashReference>(&self, hash_ref: &KeyPackageRef) -> ();
+        fn delete_psk<PskKey: PskId>(&self, psk_id: &PskKey) -> ();
+    }
+    reads {
+        fn mls_group_join_config<GroupId: GroupId, MlsGroupJoinConfig: MlsGroupJoinConfig>(
+            &self, group_id: &GroupId
+        ) -> Option<MlsGroupJoinConfig>;
+        fn own_leaf_nodes<GroupId: GroupId, LeafNode: LeafNode>(
+            &self, group_id: &GroupId
+        ) -> Vec<LeafNode>;
+        fn queued_proposal_refs<GroupId: GroupId, ProposalRef: ProposalRef>(
+            &self, group_id: &GroupId
+        ) -> Vec<ProposalRef>;
+        fn queued_proposals<GroupId: GroupId, ProposalRef: ProposalRef, QueuedProposal: QueuedProposal>(
+            &self, group_id: &GroupId
+        ) -> Vec<(ProposalRef, QueuedProposal)>;
+        fn tree<GroupId: GroupId, TreeSync: TreeSync>(&self, group_id: &GroupId) -> Option<TreeSync>;
+        fn group_context<GroupId: GroupId, GroupContext: GroupContext>(
+            &self, group_id: &GroupId
+        ) -> Option<GroupContext>;
+        fn interim_transcript_hash<GroupId: GroupId, InterimTranscriptHash: InterimTranscriptHash>(
+            &self, group_id: &GroupId
+        ) -> Option<InterimTranscriptHash>;
+        fn confirmation_tag<GroupId: GroupId, ConfirmationTag: ConfirmationTag>(
+            &self, group_id: &GroupId
+        ) -> Option<ConfirmationTag>;
+        fn group_state<GroupState: GroupState, GroupId: GroupId>(
+            &self, group_id: &GroupId
+        ) -> Option<GroupState>;
+        fn message_secrets<GroupId: GroupId, MessageSecrets: MessageSecrets>(
+            &self, group_id: &GroupId
+        ) -> Option<MessageSecrets>;
+        fn resumption_psk_store<GroupId: GroupId, ResumptionPskStore: ResumptionPskStore>(
+            &self, group_id: &GroupId
+        ) -> Option<ResumptionPskStore>;
+        fn own_leaf_index<GroupId: GroupId, LeafNodeIndex: LeafNodeIndex>(
+            &self, group_id: &GroupId
+        ) -> Option<LeafNodeIndex>;
+        fn group_epoch_secrets<GroupId: GroupId, GroupEpochSecrets: GroupEpochSecrets>(
+            &self, group_id: &GroupId
+        ) -> Option<GroupEpochSecrets>;
+        fn signature_key_pair<
+            SignaturePublicKey: SignaturePublicKey,
+            SignatureKeyPair: SignatureKeyPair,
+        >(
+            &self, public_key: &SignaturePublicKey
+        ) -> Option<SignatureKeyPair>;
+        fn encryption_key_pair<HpkeKeyPair: HpkeKeyPair, EncryptionKey: EncryptionKey>(
+            &self, public_key: &EncryptionKey
+        ) -> Option<HpkeKeyPair>;
+        fn encryption_epoch_key_pairs<GroupId: GroupId, EpochKey: EpochKey, HpkeKeyPair: HpkeKeyPair>(
+            &self, group_id: &GroupId, epoch: &EpochKey, leaf_index: u32
+        ) -> Vec<HpkeKeyPair>;
+        fn key_package<KeyPackageRef: HashReference, KeyPackage: KeyPackage>(
+            &self, hash_ref: &KeyPackageRef
+        ) -> Option<KeyPackage>;
+        fn psk<PskBundle: PskBundle, PskId: PskId>(&self, psk_id: &PskId) -> Option<PskBundle>;
+    }
+}
+
 #[derive(Default)]
 pub(crate) struct JsonCodec;
 
@@ -65,3 +360,59 @@ impl Codec for JsonCodec {
         serde_json::from_slice(slice)
     }
 }
+
+/// Codec of the OpenMLS state in the database.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum StorageCodec {
+    Json,
+    Cbor,
+}
+
+impl StorageCodec {
+    /// Encodes `value` like the storage does its keys and values.
+    pub(crate) fn encode<T: Serialize + ?Sized>(self, value: &T) -> anyhow::Result<Vec<u8>> {
+        Ok(match self {
+            Self::Json => JsonCodec::to_vec(value)?,
+            Self::Cbor => CborCodec::to_vec(value)?,
+        })
+    }
+
+    pub(crate) fn decode<T: DeserializeOwned>(self, slice: &[u8]) -> anyhow::Result<T> {
+        Ok(match self {
+            Self::Json => JsonCodec::from_slice(slice)?,
+            Self::Cbor => CborCodec::from_slice(slice)?,
+        })
+    }
+}
+
+/// Encodes the same values as [`JsonCodec`] in CBOR, which is more compact
+/// and faster to parse. Going through [`serde_json::Value`] keeps the data
+/// model of JSON, so that rows stored with [`JsonCodec`] can be converted
+/// without knowing their types.
+#[derive(Default)]
+pub(crate) struct CborCodec;
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum CborError {
+    #[error(transparent)]
+    Value(#[from] serde_json::Error),
+    #[error(transparent)]
+    Encode(#[from] ciborium::ser::Error<std::io::Error>),
+    #[error(transparent)]
+    Decode(#[from] ciborium::de::Error<std::io::Error>),
+}
+
+impl Codec for CborCodec {
+    type Error = CborError;
+
+    fn to_vec<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, Self::Error> {
+        let mut bytes = Vec::new();
+        ciborium::into_writer(&serde_json::to_value(value)?, &mut bytes)?;
+        Ok(bytes)
+    }
+
+    fn from_slice<T: DeserializeOwned>(slice: &[u8]) -> Result<T, Self::Error> {
+        let value: serde_json::Value = ciborium::from_reader(slice)?;
+        Ok(serde_json::from_value(value)?)
+    }
+}
@@ -0,0 +1,265 @@
+use std::sync::{Arc, Mutex};
+
+use pyo3::{
+    create_exception,
+    exceptions::{PyException, PyStopAsyncIteration},
+    prelude::*,
+};
+use pyo3_async_runtimes::tokio::future_into_py;
+use sqlx::types::chrono::{DateTime, Utc};
+use tokio::sync::{
+    self,
+    broadcast::{self, error::RecvError},
+};
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::{
+    client::{
+        self, Client as ChatClient, daemon::ClientHandle, events::ChatEvent, group::GroupSummary,
+        invite, policy::RequiredCapabilities,
+    },
+    grpc::GroupMetadata,
+    provider::DEFAULT_CIPHERSUITE,
+};
+
+create_exception!(mls_chat, ClientError, PyException, "Error of the client.");
+
+impl From<client::ClientError> for PyErr {
+    fn from(error: client::ClientError) -> Self {
+        ClientError::new_err(error.to_string())
+    }
+}
+
+/// Client for Python, e.g. for bots:
+///
+/// ```python
+/// client = await Client.connect("http://localhost:50051", "bot.db", "bot")
+/// async for event in client.events():
+///     if isinstance(event, ChatEvent.Message) and event.mentioned:
+///         await client.send(event.group_id, f"Hello {event.sender}")
+/// ```
+///
+/// It follows the messages of a single user on a task of its own, see
+/// [`Client::spawn`](crate::client::Client::spawn).
+#[pyclass(frozen)]
+pub struct Client {
+    handle: ClientHandle,
+    user: String,
+    /// Events from the start, for the first call of [`Client::events`].
+    first_events: Mutex<Option<broadcast::Receiver<ChatEvent>>>,
+}
+
+#[pymethods]
+impl Client {
+    /// Opens the database at `db_path` and follows the messages of `user`
+    /// from the server at `endpoint`. Registers the user first if it isn't
+    /// registered on this device yet.
+    #[staticmethod]
+    fn connect(
+        py: Python<'_>,
+        endpoint: String,
+        db_path: String,
+        user: String,
+    ) -> PyResult<Bound<'_, PyAny>> {
+        future_into_py(py, async move {
+            let mut client = ChatClient::builder(endpoint, db_path).build().await?;
+            if !client.profiles().await?.contains(&user) {
+                client.register(user.clone(), DEFAULT_CIPHERSUITE).await?;
+            }
+            let (handle, events) = client.spawn(user.clone());
+            Ok(Client {
+                handle,
+                user,
+                first_events: Mutex::new(Some(events)),
+            })
+        })
+    }
+
+    #[getter]
+    fn user(&self) -> &str {
+        &self.user
+    }
+
+    fn send<'py>(
+        &self,
+        py: Python<'py>,
+        group_id: Uuid,
+        text: String,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        let (handle, user) = (self.handle.clone(), self.user.clone());
+        future_into_py(py, async move {
+            handle
+                .call(move |client| Box::pin(client.send(user, group_id, text)))
+                .await?;
+            Ok(())
+        })
+    }
+
+    fn groups<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        let (handle, user) = (self.handle.clone(), self.user.clone());
+        future_into_py(py, async move {
+            let groups = handle
+                .call(move |client| Box::pin(client.list_groups(user)))
+                .await?;
+            Ok(groups.into_iter().map(Group::from).collect::<Vec<_>>())
+        })
+    }
+
+    /// Creates a group named `name` and returns its id.
+    fn create_group<'py>(&self, py: Python<'py>, name: String) -> PyResult<Bound<'py, PyAny>> {
+        let (handle, user) = (self.handle.clone(), self.user.clone());
+        let metadata = GroupMetadata {
+            name,
+            ..Default::default()
+        };
+        future_into_py(py, async move {
+            let group_id = handle
+                .call(move |client| {
+                    Box::pin(client.create_group(
+                        user,
+                        Some(metadata),
+                        None,
+                        RequiredCapabilities::default(),
+                    ))
+                })
+                .await?;
+            Ok(group_id)
+        })
+    }
+
+    fn add_member<'py>(
+        &self,
+        py: Python<'py>,
+        group_id: Uuid,
+        member: String,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        let (handle, user) = (self.handle.clone(), self.user.clone());
+        future_into_py(py, async move {
+            handle
+                .call(move |client| Box::pin(client.add_members(user, group_id, vec![member])))
+                .await?;
+            Ok(())
+        })
+    }
+
+    fn invites<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        let (handle, user) = (self.handle.clone(), self.user.clone());
+        future_into_py(py, async move {
+            let invites = handle
+                .call(move |client| Box::pin(client.invites(user)))
+                .await?;
+            Ok(invites.into_iter().map(Invite::from).collect::<Vec<_>>())
+        })
+    }
+
+    fn accept_invite<'py>(&self, py: Python<'py>, group_id: Uuid) -> PyResult<Bound<'py, PyAny>> {
+        let (handle, user) = (self.handle.clone(), self.user.clone());
+        future_into_py(py, async move {
+            handle
+                .call(move |client| Box::pin(client.accept_invite(user, group_id)))
+                .await?;
+            Ok(())
+        })
+    }
+
+    /// Async iterator of the events. The first one gets all events since
+    /// connecting, later ones the events from then on.
+    fn events(&self) -> Events {
+        let receiver = self
+            .first_events
+            .lock()
+            .unwrap_or_else(|error| error.into_inner())
+            .take()
+            .unwrap_or_else(|| self.handle.events());
+        Events {
+            receiver: Arc::new(sync::Mutex::new(receiver)),
+        }
+    }
+}
+
+/// Events of a [`Client`], ending once the client stopped.
+#[pyclass(frozen)]
+pub struct Events {
+    receiver: Arc<sync::Mutex<broadcast::Receiver<ChatEvent>>>,
+}
+
+#[pymethods]
+impl Events {
+    fn __aiter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __anext__<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        let receiver = self.receiver.clone();
+        future_into_py(py, async move {
+            let mut receiver = receiver.lock().await;
+            loop {
+                match receiver.recv().await {
+                    Ok(event) => return Ok(event),
+                    Err(RecvError::Lagged(missed)) => warn!(missed, "Missed events"),
+                    Err(RecvError::Closed) => return Err(PyStopAsyncIteration::new_err(())),
+                }
+            }
+        })
+    }
+}
+
+/// A group the user is a member of, see [`GroupSummary`].
+#[pyclass(frozen, get_all)]
+pub struct Group {
+    group_id: Uuid,
+    name: String,
+    topic: String,
+    last_activity_at: Option<DateTime<Utc>>,
+    unread: u64,
+    muted: bool,
+    archived: bool,
+}
+
+impl From<GroupSummary> for Group {
+    fn from(group: GroupSummary) -> Self {
+        Self {
+            group_id: group.group_id,
+            name: group.name,
+            topic: group.topic,
+            last_activity_at: group.last_activity_at,
+            unread: group.unread,
+            muted: group.muted,
+            archived: group.archived,
+        }
+    }
+}
+
+/// An invite to a group, see [`invite::Invite`].
+#[pyclass(frozen, get_all)]
+pub struct Invite {
+    group_id: Uuid,
+    name: String,
+    inviter: String,
+    members: Vec<String>,
+    received_at: DateTime<Utc>,
+}
+
+impl From<invite::Invite> for Invite {
+    fn from(invite: invite::Invite) -> Self {
+        Self {
+            group_id: invite.group_id,
+            name: invite.name,
+            inviter: invite.inviter,
+            members: invite.members,
+            received_at: invite.received_at,
+        }
+    }
+}
+
+#[pymodule]
+fn mls_chat(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<Client>()?;
+    module.add_class::<Events>()?;
+    module.add_class::<ChatEvent>()?;
+    module.add_class::<Group>()?;
+    module.add_class::<Invite>()?;
+    module.add("ClientError", module.py().get_type::<ClientError>())?;
+    Ok(())
+}
@@ -0,0 +1,58 @@
+use prost::Message;
+use quinn::{ReadExactError, RecvStream, SendStream};
+use tonic::Status;
+
+use crate::grpc::MAX_BLOB_SIZE;
+
+/// ALPN protocol of the QUIC endpoint of the server. Each request goes on a
+/// bidirectional stream of its own as a `WebSocketRequest` of
+/// `proto/mlschat/v1/websocket.proto`, answered with `WebSocketResponse`s on
+/// the same stream. Each frame is prefixed with its length as big-endian
+/// `u32`.
+pub const ALPN: &[u8] = b"mlschat.v1";
+
+/// Largest frame accepted, enough for a blob of [`MAX_BLOB_SIZE`].
+const MAX_FRAME_SIZE: usize = MAX_BLOB_SIZE + 64 * 1024;
+
+/// Reads the next frame, `None` at the end of the stream.
+pub(crate) async fn read_frame<M: Message + Default>(
+    stream: &mut RecvStream,
+) -> Result<Option<M>, Status> {
+    let mut length = [0; 4];
+    match stream.read_exact(&mut length).await {
+        Ok(()) => {}
+        Err(ReadExactError::FinishedEarly(0)) => return Ok(None),
+        Err(error) => return Err(Status::unavailable(error.to_string())),
+    }
+    let length = u32::from_be_bytes(length) as usize;
+    if length > MAX_FRAME_SIZE {
+        return Err(Status::resource_exhausted(format!(
+            "Frame of {length} bytes exceeds the limit of {MAX_FRAME_SIZE} bytes"
+        )));
+    }
+    let mut frame = vec![0; length];
+    stream
+        .read_exact(&mut frame)
+        .await
+        .map_err(|error| Status::unavailable(error.to_string()))?;
+    M::decode(frame.as_slice())
+        .map(Some)
+        .map_err(|error| Status::invalid_argument(format!("Invalid frame: {error}")))
+}
+
+pub(crate) async fn write_frame<M: Message>(
+    stream: &mut SendStream,
+    frame: &M,
+) -> Result<(), Status> {
+    let frame = frame.encode_to_vec();
+    let length =
+        u32::try_from(frame.len()).map_err(|_| Status::resource_exhausted("Frame too large"))?;
+    stream
+        .write_all(&length.to_be_bytes())
+        .await
+        .map_err(|error| Status::unavailable(error.to_string()))?;
+    stream
+        .write_all(&frame)
+        .await
+        .map_err(|error| Status::unavailable(error.to_string()))
+}
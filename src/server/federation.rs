@@ -0,0 +1,355 @@
+use std::{collections::HashMap, str::FromStr, sync::Arc, time::Duration};
+
+use prost::Message;
+use sqlx::{
+    query,
+    types::chrono::{DateTime, Utc},
+};
+use tokio::task::JoinHandle;
+use tonic::{
+    Request, Response, Status,
+    metadata::MetadataValue,
+    transport::{Channel, Endpoint},
+};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::{
+    grpc::{
+        FetchKeyPackageRequest, FetchKeyPackageResponse, ListDevicesRequest, ListDevicesResponse,
+        RelayRequest, RelayResponse, SendMessageRequest, chat_service_client::ChatServiceClient,
+        federation_service_client::FederationServiceClient,
+        federation_service_server::FederationService,
+    },
+    server::ChatServiceImpl,
+};
+
+/// Metadata key carrying the domain of the relaying server.
+const ORIGIN_METADATA: &str = "x-mls-chat-origin";
+
+const RELAY_POLL_INTERVAL: Duration = Duration::from_secs(5);
+const MAX_RELAY_BACKOFF: Duration = Duration::from_secs(60 * 60);
+const MAX_RELAY_ATTEMPTS: i64 = 20;
+
+/// Federation settings of a server.
+///
+/// Identities of the form `user@domain` whose domain differs from the own
+/// `domain` are relayed to the configured peer of that domain. Without a domain
+/// federation is disabled and all identities are local.
+#[derive(Debug, Clone, Default)]
+pub struct Federation {
+    pub domain: Option<String>,
+    pub peers: HashMap<String, Peer>,
+}
+
+/// A remote server. The token authenticates both directions of the link.
+#[derive(Debug, Clone)]
+pub struct Peer {
+    pub endpoint: String,
+    pub token: String,
+}
+
+impl Federation {
+    /// Returns the domain of `client_id` if it belongs to another server.
+    pub(crate) fn remote_domain<'a>(&self, client_id: &'a str) -> Option<&'a str> {
+        let local = self.domain.as_deref()?;
+        let (_, domain) = client_id.rsplit_once('@')?;
+        (domain != local).then_some(domain)
+    }
+
+    fn peer(&self, domain: &str) -> Result<&Peer, Status> {
+        self.peers
+            .get(domain)
+            .ok_or_else(|| Status::not_found(format!("Unknown domain {domain}")))
+    }
+
+    fn peer_channel(&self, domain: &str) -> Result<Channel, Status> {
+        let peer = self.peer(domain)?;
+        let endpoint = Endpoint::from_str(&peer.endpoint)
+            .map_err(|error| Status::internal(format!("Invalid peer endpoint: {error}")))?;
+        Ok(endpoint.connect_lazy())
+    }
+}
+
+impl ChatServiceImpl {
+    pub(crate) fn ensure_local(&self, client_id: &str) -> Result<(), Status> {
+        match self.federation.remote_domain(client_id) {
+            Some(domain) => Err(Status::invalid_argument(format!(
+                "Client {client_id} belongs to domain {domain}"
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    /// Splits off the recipients belonging to remote domains and queues one relay
+    /// per domain. Returns the request with only local recipients left.
+    pub(crate) async fn relay_remote(
+        &self,
+        mut request: SendMessageRequest,
+        created_at: DateTime<Utc>,
+    ) -> Result<SendMessageRequest, Status> {
+        let mut relays: HashMap<String, RelayRequest> = HashMap::new();
+
+        let (remote, local): (Vec<_>, Vec<_>) = request
+            .recipients
+            .into_iter()
+            .partition(|recipient| self.federation.remote_domain(recipient).is_some());
+        request.recipients = local;
+        for recipient in remote {
+            let domain = self
+                .federation
+                .remote_domain(&recipient)
+                .unwrap_or_default();
+            relays
+                .entry(domain.to_string())
+                .or_insert_with(|| new_relay(&request, created_at))
+                .recipients
+                .push(recipient);
+        }
+
+        let (remote, local): (Vec<_>, Vec<_>) = request
+            .device_recipients
+            .into_iter()
+            .partition(|address| self.federation.remote_domain(&address.client_id).is_some());
+        request.device_recipients = local;
+        for address in remote {
+            let domain = self
+                .federation
+                .remote_domain(&address.client_id)
+                .unwrap_or_default();
+            relays
+                .entry(domain.to_string())
+                .or_insert_with(|| new_relay(&request, created_at))
+                .device_recipients
+                .push(address);
+        }
+
+        for (domain, relay) in relays {
+            self.federation.peer(&domain)?;
+            self.enqueue_relay(&domain, &relay, created_at)
+                .await
+                .map_err(|error| Status::internal(format!("Database error: {error}")))?;
+        }
+
+        Ok(request)
+    }
+
+    async fn enqueue_relay(
+        &self,
+        domain: &str,
+        relay: &RelayRequest,
+        created_at: DateTime<Utc>,
+    ) -> sqlx::Result<()> {
+        let relay_id = Uuid::new_v4();
+        let request = relay.encode_to_vec();
+        query!(
+            "INSERT INTO server_relay (
+                relay_id, domain, request, attempts, next_attempt_at, created_at
+            ) VALUES (?, ?, ?, 0, ?, ?)",
+            relay_id,
+            domain,
+            request,
+            created_at,
+            created_at,
+        )
+        .execute(&self.pool)
+        .await?;
+        self.relay_notify.notify_one();
+        Ok(())
+    }
+
+    /// Spawns the task forwarding queued relays to remote servers, retrying
+    /// failed attempts with exponential backoff.
+    pub fn spawn_relay_worker(self: &Arc<Self>) -> JoinHandle<()> {
+        let service = self.clone();
+        tokio::spawn(async move {
+            loop {
+                if let Err(error) = service.forward_due_relays().await {
+                    warn!(%error, "Failed to forward relays");
+                }
+                tokio::select! {
+                    _ = service.relay_notify.notified() => {}
+                    _ = tokio::time::sleep(RELAY_POLL_INTERVAL) => {}
+                }
+            }
+        })
+    }
+
+    async fn forward_due_relays(&self) -> sqlx::Result<()> {
+        let now = Utc::now();
+        let relays = query!(
+            "SELECT
+                relay_id AS \"relay_id: Uuid\",
+                domain,
+                request,
+                attempts
+            FROM server_relay
+            WHERE next_attempt_at <= ?
+            ORDER BY created_at",
+            now,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        for relay in relays {
+            let (relay_id, domain, attempts) = (relay.relay_id, relay.domain, relay.attempts);
+            match self.forward_relay(&domain, &relay.request).await {
+                Ok(()) => {
+                    query!("DELETE FROM server_relay WHERE relay_id = ?", relay_id)
+                        .execute(&self.pool)
+                        .await?;
+                }
+                Err(status) if attempts + 1 >= MAX_RELAY_ATTEMPTS => {
+                    warn!(%relay_id, %domain, %status, "Giving up relay");
+                    query!("DELETE FROM server_relay WHERE relay_id = ?", relay_id)
+                        .execute(&self.pool)
+                        .await?;
+                }
+                Err(status) => {
+                    let backoff = Duration::from_secs(1 << attempts.min(12)).min(MAX_RELAY_BACKOFF);
+                    warn!(%relay_id, %domain, %status, ?backoff, "Relay failed, retrying");
+                    let next_attempt_at = Utc::now() + backoff;
+                    let attempts = attempts + 1;
+                    query!(
+                        "UPDATE server_relay
+                        SET attempts = ?, next_attempt_at = ?
+                        WHERE relay_id = ?",
+                        attempts,
+                        next_attempt_at,
+                        relay_id,
+                    )
+                    .execute(&self.pool)
+                    .await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn forward_relay(&self, domain: &str, request: &[u8]) -> Result<(), Status> {
+        let relay = RelayRequest::decode(request)
+            .map_err(|error| Status::internal(format!("Invalid stored relay: {error}")))?;
+        let peer = self.federation.peer(domain)?;
+        let local_domain = self.federation.domain.as_deref().unwrap_or_default();
+
+        let mut request = Request::new(relay);
+        let metadata = request.metadata_mut();
+        metadata.insert(
+            ORIGIN_METADATA,
+            MetadataValue::try_from(local_domain)
+                .map_err(|_| Status::internal("Invalid local domain"))?,
+        );
+        metadata.insert(
+            "authorization",
+            MetadataValue::try_from(format!("Bearer {}", peer.token))
+                .map_err(|_| Status::internal("Invalid peer token"))?,
+        );
+
+        FederationServiceClient::new(self.federation.peer_channel(domain)?)
+            .relay(request)
+            .await?;
+        info!(domain, "Relayed message");
+        Ok(())
+    }
+
+    pub(crate) async fn fetch_remote_key_package(
+        &self,
+        domain: &str,
+        request: FetchKeyPackageRequest,
+    ) -> Result<Response<FetchKeyPackageResponse>, Status> {
+        ChatServiceClient::new(self.federation.peer_channel(domain)?)
+            .fetch_key_package(request)
+            .await
+    }
+
+    pub(crate) async fn list_remote_devices(
+        &self,
+        domain: &str,
+        request: ListDevicesRequest,
+    ) -> Result<Response<ListDevicesResponse>, Status> {
+        ChatServiceClient::new(self.federation.peer_channel(domain)?)
+            .list_devices(request)
+            .await
+    }
+
+    /// Checks the origin and token of an inbound relay and returns the origin
+    /// domain.
+    fn authenticate_peer<T>(&self, request: &Request<T>) -> Result<String, Status> {
+        let metadata = request.metadata();
+        let origin = metadata
+            .get(ORIGIN_METADATA)
+            .and_then(|origin| origin.to_str().ok())
+            .ok_or_else(|| Status::unauthenticated("Missing origin"))?;
+        let token = metadata
+            .get("authorization")
+            .and_then(|token| token.to_str().ok())
+            .and_then(|token| token.strip_prefix("Bearer "))
+            .ok_or_else(|| Status::unauthenticated("Missing token"))?;
+
+        let peer = self
+            .federation
+            .peers
+            .get(origin)
+            .ok_or_else(|| Status::unauthenticated("Unknown peer"))?;
+        if !constant_time_eq(token.as_bytes(), peer.token.as_bytes()) {
+            return Err(Status::unauthenticated("Invalid token"));
+        }
+        Ok(origin.to_string())
+    }
+}
+
+#[tonic::async_trait]
+impl FederationService for ChatServiceImpl {
+    async fn relay(
+        &self,
+        request: Request<RelayRequest>,
+    ) -> Result<Response<RelayResponse>, Status> {
+        let origin = self.authenticate_peer(&request)?;
+        let relay = request.into_inner();
+
+        if self.federation.remote_domain(&relay.sender) != Some(origin.as_str()) {
+            return Err(Status::permission_denied(
+                "Sender does not belong to the relaying domain",
+            ));
+        }
+        for recipient in relay.recipients.iter().chain(
+            relay
+                .device_recipients
+                .iter()
+                .map(|address| &address.client_id),
+        ) {
+            self.ensure_local(recipient)?;
+        }
+
+        info!(origin, sender = relay.sender, "Received relay");
+
+        let created_at = DateTime::from_timestamp_millis(relay.timestamp).unwrap_or_else(Utc::now);
+        self.deliver(
+            SendMessageRequest {
+                sender: relay.sender,
+                recipients: relay.recipients,
+                content: relay.content,
+                device_recipients: relay.device_recipients,
+                sender_device_id: String::new(),
+            },
+            created_at,
+        )
+        .await?;
+
+        Ok(Response::new(RelayResponse {}))
+    }
+}
+
+fn new_relay(request: &SendMessageRequest, created_at: DateTime<Utc>) -> RelayRequest {
+    RelayRequest {
+        sender: request.sender.clone(),
+        recipients: Vec::new(),
+        device_recipients: Vec::new(),
+        content: request.content.clone(),
+        timestamp: created_at.timestamp_millis(),
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
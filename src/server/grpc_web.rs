@@ -0,0 +1,42 @@
+//! The delivery service over gRPC-Web, for clients in browsers.
+//!
+//! Browsers can't control HTTP/2 framing or read trailers, so they can't call
+//! gRPC services directly. gRPC-Web carries the same requests over HTTP/1.1
+//! with the trailers in the body. This endpoint translates them to the
+//! services of [`ChatServiceImpl`], including the stream of
+//! `ReceiveMessages`; client streams aren't part of gRPC-Web.
+
+use std::sync::Arc;
+
+use tokio::net::TcpListener;
+use tonic::transport::server::TcpIncoming;
+use tonic_web::GrpcWebLayer;
+use tower_http::cors::CorsLayer;
+
+use crate::{
+    grpc::{
+        chat_service_server::ChatServiceServer, federation_service_server::FederationServiceServer,
+        version_service_server::VersionServiceServer,
+    },
+    server::ChatServiceImpl,
+};
+
+/// Serves the gRPC-Web endpoint on `listener` until it fails.
+///
+/// Cross-origin requests are allowed from any origin: the service relies on no
+/// cookies or other credentials of the browser, so a page of another origin
+/// can't do anything a client outside the browser couldn't.
+pub async fn serve(
+    service: Arc<ChatServiceImpl>,
+    listener: TcpListener,
+) -> Result<(), tonic::transport::Error> {
+    tonic::transport::Server::builder()
+        .accept_http1(true)
+        .layer(CorsLayer::permissive())
+        .layer(GrpcWebLayer::new())
+        .add_service(ChatServiceServer::from_arc(service.clone()))
+        .add_service(FederationServiceServer::from_arc(service.clone()))
+        .add_service(VersionServiceServer::from_arc(service))
+        .serve_with_incoming(TcpIncoming::from(listener))
+        .await
+}
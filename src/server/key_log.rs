@@ -0,0 +1,63 @@
+use prost::Message;
+use sqlx::{
+    Sqlite, Transaction, query, query_scalar,
+    types::chrono::{DateTime, Utc},
+};
+use tonic::Status;
+
+use crate::{grpc::KeyLogEntry, server::ChatServiceImpl, transparency};
+
+impl ChatServiceImpl {
+    /// Appends the binding of `signature_key` to `client_id` to the key
+    /// transparency log, unless it is already logged.
+    pub(crate) async fn append_key_log(
+        transaction: &mut Transaction<'_, Sqlite>,
+        client_id: &str,
+        device_id: &str,
+        signature_key: &[u8],
+        created_at: DateTime<Utc>,
+    ) -> sqlx::Result<()> {
+        let entry = KeyLogEntry {
+            client_id: client_id.to_string(),
+            device_id: device_id.to_string(),
+            signature_key: signature_key.to_vec(),
+            timestamp: created_at.timestamp_millis(),
+        }
+        .encode_to_vec();
+        let leaf_hash = transparency::leaf_hash(&entry);
+
+        // The index is assigned in the same statement to keep the log dense.
+        query!(
+            "INSERT INTO server_key_log (
+                log_index, client_id, signature_key, entry, leaf_hash, created_at
+            )
+            SELECT COUNT(*), ?, ?, ?, ?, ? FROM server_key_log WHERE true
+            ON CONFLICT (client_id, signature_key) DO NOTHING",
+            client_id,
+            signature_key,
+            entry,
+            leaf_hash,
+            created_at,
+        )
+        .execute(&mut **transaction)
+        .await?;
+        Ok(())
+    }
+
+    /// Leaf hashes of the first `tree_size` log entries, or of the whole log if
+    /// `tree_size` is zero.
+    pub(crate) async fn key_log_leaves(&self, tree_size: u64) -> Result<Vec<Vec<u8>>, Status> {
+        let mut leaves = query_scalar!("SELECT leaf_hash FROM server_key_log ORDER BY log_index")
+            .fetch_all(&self.pool)
+            .await
+            .map_err(|error| Status::internal(format!("Database error: {error}")))?;
+        if tree_size > 0 {
+            let tree_size = usize::try_from(tree_size)
+                .ok()
+                .filter(|&tree_size| tree_size <= leaves.len())
+                .ok_or_else(|| Status::out_of_range("Tree size exceeds the log"))?;
+            leaves.truncate(tree_size);
+        }
+        Ok(leaves)
+    }
+}
@@ -0,0 +1,155 @@
+use std::{path::Path, time::Duration};
+
+use sqlx::{
+    SqlitePool,
+    migrate::Migrate,
+    query, query_scalar,
+    sqlite::{SqliteConnectOptions, SqliteJournalMode, SqliteSynchronous},
+};
+
+use crate::migrations::adopt_split_migrations;
+
+/// SQLite settings of the server database.
+#[derive(Debug, Clone)]
+pub struct DatabaseOptions {
+    /// How long a connection waits for a lock held by another connection.
+    pub busy_timeout: Duration,
+    /// Page cache size of each connection, in KiB.
+    pub cache_size_kib: u64,
+    /// Number of bytes of the database file to memory map; zero disables mmap.
+    pub mmap_size: u64,
+}
+
+impl Default for DatabaseOptions {
+    fn default() -> Self {
+        Self {
+            busy_timeout: Duration::from_secs(5),
+            cache_size_kib: 2000,
+            mmap_size: 0,
+        }
+    }
+}
+
+/// Opens the server database with the settings used by the running server.
+pub async fn connect(
+    db_path: impl AsRef<Path>,
+    options: &DatabaseOptions,
+) -> anyhow::Result<SqlitePool> {
+    let opts: SqliteConnectOptions = SqliteConnectOptions::new()
+        .filename(db_path)
+        .create_if_missing(true)
+        .journal_mode(SqliteJournalMode::Wal)
+        .synchronous(SqliteSynchronous::Extra)
+        .busy_timeout(options.busy_timeout)
+        // A negative cache size is in KiB instead of pages.
+        .pragma("cache_size", format!("-{}", options.cache_size_kib))
+        .pragma("mmap_size", options.mmap_size.to_string());
+    Ok(SqlitePool::connect_with(opts).await?)
+}
+
+/// Applies all pending migrations and returns the descriptions of the ones that
+/// were applied.
+pub async fn migrate(pool: &SqlitePool) -> anyhow::Result<Vec<String>> {
+    let mut migrator = sqlx::migrate!("migrations/server");
+
+    let applied = {
+        let mut connection = pool.acquire().await?;
+        adopt_split_migrations(&mut connection, &mut migrator).await?;
+        connection.list_applied_migrations().await?
+    };
+    let pending = migrator
+        .iter()
+        .filter(|migration| {
+            !applied
+                .iter()
+                .any(|applied| applied.version == migration.version)
+        })
+        .map(|migration| format!("{} {}", migration.version, migration.description))
+        .collect();
+
+    migrator.run(pool).await?;
+    Ok(pending)
+}
+
+/// Copies the write-ahead log into the database and truncates it. Returns
+/// `false` if readers or writers kept the checkpoint from completing.
+pub async fn checkpoint(pool: &SqlitePool) -> anyhow::Result<bool> {
+    let (busy, _log_frames, _checkpointed_frames): (i64, i64, i64) =
+        sqlx::query_as("PRAGMA wal_checkpoint(TRUNCATE)")
+            .fetch_one(pool)
+            .await?;
+    Ok(busy == 0)
+}
+
+/// Checkpoints the write-ahead log and rebuilds the database file.
+pub async fn vacuum(pool: &SqlitePool) -> anyhow::Result<()> {
+    checkpoint(pool).await?;
+    sqlx::query("VACUUM").execute(pool).await?;
+    Ok(())
+}
+
+/// Writes a consistent copy of the database to `path`, which must not exist.
+///
+/// Safe to run while the server is serving requests.
+pub async fn backup(pool: &SqlitePool, path: impl AsRef<Path>) -> anyhow::Result<()> {
+    let path = path.as_ref();
+    anyhow::ensure!(!path.exists(), "{} already exists", path.display());
+    let path = path.to_string_lossy();
+    sqlx::query("VACUUM INTO ?")
+        .bind(path)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+#[derive(Debug)]
+pub struct Stats {
+    pub size_bytes: i64,
+    pub free_bytes: i64,
+    pub devices: i64,
+    pub key_packages: i64,
+    pub pending_messages: i64,
+    pub delivered_messages: i64,
+    pub pending_relays: i64,
+    pub key_log_entries: i64,
+}
+
+pub async fn stats(pool: &SqlitePool) -> anyhow::Result<Stats> {
+    let page_size: i64 = sqlx::query_scalar("PRAGMA page_size")
+        .fetch_one(pool)
+        .await?;
+    let page_count: i64 = sqlx::query_scalar("PRAGMA page_count")
+        .fetch_one(pool)
+        .await?;
+    let freelist_count: i64 = sqlx::query_scalar("PRAGMA freelist_count")
+        .fetch_one(pool)
+        .await?;
+
+    let messages = query!(
+        "SELECT
+            COUNT(*) - COUNT(delivered_at) AS \"pending!: i64\",
+            COUNT(delivered_at) AS \"delivered!: i64\"
+        FROM server_message"
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(Stats {
+        size_bytes: page_size * page_count,
+        free_bytes: page_size * freelist_count,
+        devices: query_scalar!("SELECT COUNT(*) FROM server_device")
+            .fetch_one(pool)
+            .await?,
+        key_packages: query_scalar!("SELECT COUNT(*) FROM server_key_package")
+            .fetch_one(pool)
+            .await?,
+        pending_messages: messages.pending,
+        delivered_messages: messages.delivered,
+        pending_relays: query_scalar!("SELECT COUNT(*) FROM server_relay")
+            .fetch_one(pool)
+            .await?,
+        key_log_entries: query_scalar!("SELECT COUNT(*) FROM server_key_log")
+            .fetch_one(pool)
+            .await?,
+    })
+}
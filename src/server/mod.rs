@@ -0,0 +1,1166 @@
+use std::{
+    path::Path,
+    pin::Pin,
+    result::Result,
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    device,
+    grpc::{
+        self, DownloadBlobRequest, DownloadBlobResponse, FetchDeviceCertificatesRequest,
+        FetchDeviceCertificatesResponse, FetchGroupInfoRequest, FetchGroupInfoResponse,
+        FetchKeyPackageRequest, FetchKeyPackageResponse, FetchMessagesRequest,
+        FetchMessagesResponse, GetConsistencyProofRequest, GetConsistencyProofResponse,
+        GetInclusionProofRequest, GetInclusionProofResponse, GetKeyLogRootRequest,
+        GetKeyLogRootResponse, GetQueueStatusRequest, GetQueueStatusResponse, ListDevicesRequest,
+        ListDevicesResponse, MAX_BLOB_SIZE, PublishGroupInfoRequest, PublishGroupInfoResponse,
+        ReceiveMessagesRequest, RevokeDeviceRequest, RevokeDeviceResponse, RotateDeviceKeyRequest,
+        RotateDeviceKeyResponse, SendMessageRequest, SendMessageResponse, UploadBlobRequest,
+        UploadBlobResponse, UploadDeviceCertificateRequest, UploadDeviceCertificateResponse,
+        UploadKeyPackageRequest, UploadKeyPackageResponse, chat_service_server::ChatService,
+    },
+    server::{federation::Federation, maintenance::DatabaseOptions},
+    transparency,
+};
+use dashmap::DashMap;
+use openmls::prelude::{
+    BasicCredential, ContentType, DeserializeBytes, KeyPackageIn, MlsMessageBodyIn, MlsMessageIn,
+    ProtocolMessage, ProtocolVersion,
+};
+use openmls_rust_crypto::RustCrypto;
+use prost::Message;
+use sqlx::{
+    SqlitePool, query, query_scalar,
+    types::chrono::{DateTime, Utc},
+};
+use tokio::{
+    sync::{Notify, mpsc},
+    task::JoinHandle,
+};
+use tokio_stream::{Stream, StreamExt, wrappers::ReceiverStream};
+use tonic::{Request, Response, Status};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// Delivered messages are kept this long before the sweeper deletes them.
+pub const DEFAULT_RETENTION: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Interval of the WAL checkpoints that keep the log file from growing.
+pub const DEFAULT_CHECKPOINT_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// Most messages returned by one `FetchMessages` request.
+const MAX_FETCHED_MESSAGES: u32 = 1000;
+
+const SWEEP_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+pub mod federation;
+#[cfg(feature = "grpc-web")]
+pub mod grpc_web;
+mod key_log;
+pub mod maintenance;
+#[cfg(feature = "quic")]
+pub mod quic;
+mod version;
+#[cfg(feature = "websocket")]
+pub mod websocket;
+
+pub struct ChatServiceImpl {
+    pool: SqlitePool,
+    connected:
+        DashMap<(String, String), mpsc::Sender<Result<grpc::ReceiveMessagesResponse, Status>>>,
+    federation: Federation,
+    relay_notify: Notify,
+}
+
+impl ChatServiceImpl {
+    pub async fn new(db_path: impl AsRef<Path>, options: &DatabaseOptions) -> anyhow::Result<Self> {
+        let pool = maintenance::connect(db_path, options).await?;
+        maintenance::migrate(&pool).await?;
+        Ok(Self {
+            pool,
+            connected: DashMap::new(),
+            federation: Federation::default(),
+            relay_notify: Notify::new(),
+        })
+    }
+
+    pub fn with_federation(mut self, federation: Federation) -> Self {
+        self.federation = federation;
+        self
+    }
+}
+
+#[tonic::async_trait]
+impl ChatService for ChatServiceImpl {
+    async fn send_message(
+        &self,
+        request: Request<SendMessageRequest>,
+    ) -> Result<Response<SendMessageResponse>, Status> {
+        let request = request.into_inner();
+        let created_at = Utc::now();
+
+        info!(?request.recipients, ?request.device_recipients, "Received message");
+
+        self.sequence_message(&request.content).await?;
+        let message_id = self.deliver(request, created_at).await?;
+
+        let response = SendMessageResponse {
+            timestamp: created_at.timestamp_millis(),
+            message_id: message_id.as_bytes().to_vec(),
+        };
+        Ok(response.into())
+    }
+
+    type ReceiveMessagesStream =
+        Pin<Box<dyn Stream<Item = Result<grpc::ReceiveMessagesResponse, Status>> + Send + 'static>>;
+
+    async fn receive_messages(
+        &self,
+        request: Request<ReceiveMessagesRequest>,
+    ) -> Result<Response<Self::ReceiveMessagesStream>, Status> {
+        let ReceiveMessagesRequest {
+            client_id,
+            device_id,
+        } = request.into_inner();
+        self.ensure_local(&client_id)?;
+        let delivered_at = Utc::now();
+        let mut records = query!(
+            "UPDATE server_message
+            SET delivered_at = ?
+            WHERE recipient = ? AND device_id = ? AND delivered_at IS NULL
+            RETURNING
+                message_id AS \"message_id: Uuid\",
+                content,
+                created_at as \"created_at: DateTime<Utc>\"",
+            delivered_at,
+            client_id,
+            device_id,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|error| Status::internal(format!("Database error: {error}")))?;
+
+        records.sort_by_key(|record| record.created_at);
+
+        let messages = tokio_stream::iter(records.into_iter().map(|record| {
+            let content = record.content;
+            let created_at = record.created_at;
+            Ok(grpc::ReceiveMessagesResponse {
+                content,
+                timestamp: created_at.timestamp_millis(),
+                message_id: record.message_id.as_bytes().to_vec(),
+            })
+        }));
+
+        let (tx, rx) = tokio::sync::mpsc::channel(100);
+
+        self.connected.insert((client_id, device_id), tx);
+
+        let messages = messages.chain(ReceiverStream::new(rx));
+
+        Ok(Response::new(Box::pin(messages)))
+    }
+
+    async fn get_queue_status(
+        &self,
+        request: Request<GetQueueStatusRequest>,
+    ) -> Result<Response<GetQueueStatusResponse>, Status> {
+        let GetQueueStatusRequest {
+            client_id,
+            device_id,
+        } = request.into_inner();
+        self.ensure_local(&client_id)?;
+
+        let record = query!(
+            "SELECT
+                COUNT(*) AS \"pending_messages!: i64\",
+                COALESCE(SUM(LENGTH(content)), 0) AS \"pending_bytes!: i64\",
+                MIN(created_at) AS \"oldest: DateTime<Utc>\"
+            FROM server_message
+            WHERE recipient = ? AND device_id = ? AND delivered_at IS NULL",
+            client_id,
+            device_id,
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|error| Status::internal(format!("Database error: {error}")))?;
+        let key_packages = query_scalar!(
+            "SELECT COUNT(*) FROM server_key_package WHERE client_id = ? AND device_id = ?",
+            client_id,
+            device_id,
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|error| Status::internal(format!("Database error: {error}")))?;
+
+        Ok(Response::new(GetQueueStatusResponse {
+            pending_messages: record.pending_messages as u64,
+            pending_bytes: record.pending_bytes as u64,
+            oldest_timestamp: record
+                .oldest
+                .map(|created_at| created_at.timestamp_millis())
+                .unwrap_or_default(),
+            key_packages: key_packages as u64,
+        }))
+    }
+
+    async fn fetch_messages(
+        &self,
+        request: Request<FetchMessagesRequest>,
+    ) -> Result<Response<FetchMessagesResponse>, Status> {
+        let FetchMessagesRequest {
+            client_id,
+            device_id,
+            limit,
+        } = request.into_inner();
+        self.ensure_local(&client_id)?;
+        let limit = match limit {
+            0 => MAX_FETCHED_MESSAGES,
+            limit => limit.min(MAX_FETCHED_MESSAGES),
+        };
+        let delivered_at = Utc::now();
+        let mut records = query!(
+            "UPDATE server_message
+            SET delivered_at = ?
+            WHERE rowid IN (
+                SELECT rowid FROM server_message
+                WHERE recipient = ? AND device_id = ? AND delivered_at IS NULL
+                ORDER BY created_at, rowid
+                LIMIT ?
+            )
+            RETURNING
+                message_id AS \"message_id: Uuid\",
+                content,
+                created_at as \"created_at: DateTime<Utc>\"",
+            delivered_at,
+            client_id,
+            device_id,
+            limit,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|error| Status::internal(format!("Database error: {error}")))?;
+        records.sort_by_key(|record| record.created_at);
+
+        let more = query_scalar!(
+            "SELECT EXISTS(
+                SELECT 1 FROM server_message
+                WHERE recipient = ? AND device_id = ? AND delivered_at IS NULL
+            ) AS \"more!: bool\"",
+            client_id,
+            device_id,
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|error| Status::internal(format!("Database error: {error}")))?;
+
+        let messages = records
+            .into_iter()
+            .map(|record| grpc::ReceiveMessagesResponse {
+                content: record.content,
+                timestamp: record.created_at.timestamp_millis(),
+                message_id: record.message_id.as_bytes().to_vec(),
+            })
+            .collect();
+        Ok(Response::new(FetchMessagesResponse { messages, more }))
+    }
+
+    async fn upload_key_package(
+        &self,
+        request: Request<UploadKeyPackageRequest>,
+    ) -> Result<Response<UploadKeyPackageResponse>, Status> {
+        let request = request.into_inner();
+        let client_id = request.client_id;
+        let device_id = request.device_id;
+        self.ensure_local(&client_id)?;
+        let key_package_proto = request
+            .key_package
+            .ok_or_else(|| Status::invalid_argument("Key package is required"))?;
+        let key_package =
+            KeyPackageIn::tls_deserialize_exact_bytes(&key_package_proto.key_package_bytes)
+                .map_err(|_| Status::invalid_argument("Invalid key package bytes"))?;
+
+        let key_package = key_package
+            .validate(&RustCrypto::default(), ProtocolVersion::Mls10)
+            .map_err(|error| Status::invalid_argument(format!("Invalid key package: {error}")))?;
+
+        let credential: BasicCredential = key_package
+            .leaf_node()
+            .credential()
+            .clone()
+            .try_into()
+            .map_err(|error| {
+            Status::invalid_argument(format!("Invalid credential: {error}"))
+        })?;
+
+        if !key_package.last_resort() {
+            return Err(Status::invalid_argument("Key package is not last resort"));
+        }
+
+        if credential.identity() != client_id.as_bytes() {
+            return Err(Status::invalid_argument(
+                "Client ID mismatch with credential",
+            ));
+        }
+
+        let signature_key = key_package.leaf_node().signature_key().as_slice().to_vec();
+        self.check_device_key(&client_id, &device_id, &signature_key)
+            .await?;
+
+        let ciphersuite = u16::from(key_package.ciphersuite());
+        let package_id = Uuid::new_v4();
+        let created_at = Utc::now();
+
+        let mut transaction = self
+            .pool
+            .begin()
+            .await
+            .map_err(|error| Status::internal(format!("Database error: {error}")))?;
+
+        sqlx::query!(
+            "INSERT INTO server_device (
+                client_id, device_id, signature_key, created_at
+            ) VALUES (?, ?, ?, ?)
+            ON CONFLICT (client_id, device_id)
+            DO UPDATE SET signature_key = excluded.signature_key",
+            client_id,
+            device_id,
+            signature_key,
+            created_at,
+        )
+        .execute(&mut *transaction)
+        .await
+        .map_err(|error| Status::internal(format!("Database error: {error}")))?;
+
+        Self::append_key_log(
+            &mut transaction,
+            &client_id,
+            &device_id,
+            &signature_key,
+            created_at,
+        )
+        .await
+        .map_err(|error| Status::internal(format!("Database error: {error}")))?;
+
+        // A fresh last resort key package replaces the previous one.
+        sqlx::query!(
+            "DELETE FROM server_key_package
+            WHERE client_id = ? AND device_id = ? AND ciphersuite = ?",
+            client_id,
+            device_id,
+            ciphersuite,
+        )
+        .execute(&mut *transaction)
+        .await
+        .map_err(|error| Status::internal(format!("Database error: {error}")))?;
+
+        sqlx::query!(
+            "INSERT INTO server_key_package (
+                package_id, client_id, device_id, package, ciphersuite, created_at
+            ) VALUES (?, ?, ?, ?, ?, ?)",
+            package_id,
+            client_id,
+            device_id,
+            key_package_proto.key_package_bytes,
+            ciphersuite,
+            created_at,
+        )
+        .execute(&mut *transaction)
+        .await
+        .map_err(|error| Status::internal(format!("Database error: {error}")))?;
+
+        transaction
+            .commit()
+            .await
+            .map_err(|error| Status::internal(format!("Database error: {error}")))?;
+
+        Ok(Response::new(UploadKeyPackageResponse {
+            package_id: package_id.to_string(),
+        }))
+    }
+
+    async fn fetch_key_package(
+        &self,
+        request: Request<FetchKeyPackageRequest>,
+    ) -> Result<Response<FetchKeyPackageResponse>, Status> {
+        let request = request.into_inner();
+        if let Some(domain) = self.federation.remote_domain(&request.client_id) {
+            return self.fetch_remote_key_package(domain, request.clone()).await;
+        }
+        let FetchKeyPackageRequest {
+            client_id,
+            device_id,
+            ciphersuite,
+        } = request;
+
+        let record = query!(
+            "SELECT package, device_id
+            FROM server_key_package
+            WHERE client_id = ?
+                AND (? = '' OR device_id = ?)
+                AND (? = 0 OR ciphersuite = ?)",
+            client_id,
+            device_id,
+            device_id,
+            ciphersuite,
+            ciphersuite,
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|error| Status::internal(format!("Database error: {error}")))?;
+
+        let Some(record) = record else {
+            return Err(Status::not_found(format!(
+                "No key package found for client {}",
+                client_id
+            )));
+        };
+
+        Ok(Response::new(FetchKeyPackageResponse {
+            key_package: Some(grpc::KeyPackage {
+                key_package_bytes: record.package,
+            }),
+            device_id: record.device_id,
+        }))
+    }
+
+    async fn list_devices(
+        &self,
+        request: Request<ListDevicesRequest>,
+    ) -> Result<Response<ListDevicesResponse>, Status> {
+        let request = request.into_inner();
+        if let Some(domain) = self.federation.remote_domain(&request.client_id) {
+            return self.list_remote_devices(domain, request.clone()).await;
+        }
+        let client_id = request.client_id;
+        let device_ids = self
+            .devices(&client_id)
+            .await
+            .map_err(|error| Status::internal(format!("Database error: {error}")))?;
+        Ok(Response::new(ListDevicesResponse { device_ids }))
+    }
+
+    async fn upload_device_certificate(
+        &self,
+        request: Request<UploadDeviceCertificateRequest>,
+    ) -> Result<Response<UploadDeviceCertificateResponse>, Status> {
+        let certificate = request
+            .into_inner()
+            .certificate
+            .ok_or_else(|| Status::invalid_argument("Certificate is required"))?;
+        self.ensure_local(&certificate.client_id)?;
+
+        self.check_issuer(
+            &certificate.client_id,
+            &certificate.device_id,
+            &certificate.issuer_signature_key,
+        )
+        .await?;
+        if !device::verify(
+            &device::certificate_payload(&certificate),
+            &certificate.issuer_signature_key,
+            &certificate.signature,
+        ) {
+            return Err(Status::invalid_argument("Invalid certificate signature"));
+        }
+
+        let certificate_bytes = certificate.encode_to_vec();
+        let created_at = Utc::now();
+        query!(
+            "INSERT OR REPLACE INTO server_device_certificate (
+                client_id, device_id, signature_key, certificate, created_at
+            ) VALUES (?, ?, ?, ?, ?)",
+            certificate.client_id,
+            certificate.device_id,
+            certificate.signature_key,
+            certificate_bytes,
+            created_at,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|error| Status::internal(format!("Database error: {error}")))?;
+
+        info!(
+            client_id = certificate.client_id,
+            device_id = certificate.device_id,
+            "Linked device"
+        );
+
+        Ok(Response::new(UploadDeviceCertificateResponse {}))
+    }
+
+    async fn fetch_device_certificates(
+        &self,
+        request: Request<FetchDeviceCertificatesRequest>,
+    ) -> Result<Response<FetchDeviceCertificatesResponse>, Status> {
+        let client_id = request.into_inner().client_id;
+
+        let certificates = query_scalar!(
+            "SELECT certificate FROM server_device_certificate WHERE client_id = ?",
+            client_id
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|error| Status::internal(format!("Database error: {error}")))?
+        .into_iter()
+        .map(|certificate| grpc::DeviceCertificate::decode(certificate.as_slice()))
+        .collect::<Result<_, _>>()
+        .map_err(|error| Status::internal(format!("Invalid stored certificate: {error}")))?;
+
+        Ok(Response::new(FetchDeviceCertificatesResponse {
+            certificates,
+        }))
+    }
+
+    async fn revoke_device(
+        &self,
+        request: Request<RevokeDeviceRequest>,
+    ) -> Result<Response<RevokeDeviceResponse>, Status> {
+        let request = request.into_inner();
+        self.ensure_local(&request.client_id)?;
+
+        self.check_issuer(
+            &request.client_id,
+            &request.device_id,
+            &request.issuer_signature_key,
+        )
+        .await?;
+        if !device::verify(
+            &device::revocation_payload(&request),
+            &request.issuer_signature_key,
+            &request.signature,
+        ) {
+            return Err(Status::invalid_argument("Invalid revocation signature"));
+        }
+
+        let mut transaction = self
+            .pool
+            .begin()
+            .await
+            .map_err(|error| Status::internal(format!("Database error: {error}")))?;
+        query!(
+            "DELETE FROM server_device WHERE client_id = ? AND device_id = ?",
+            request.client_id,
+            request.device_id,
+        )
+        .execute(&mut *transaction)
+        .await
+        .map_err(|error| Status::internal(format!("Database error: {error}")))?;
+        query!(
+            "DELETE FROM server_device_certificate WHERE client_id = ? AND device_id = ?",
+            request.client_id,
+            request.device_id,
+        )
+        .execute(&mut *transaction)
+        .await
+        .map_err(|error| Status::internal(format!("Database error: {error}")))?;
+        query!(
+            "DELETE FROM server_key_package WHERE client_id = ? AND device_id = ?",
+            request.client_id,
+            request.device_id,
+        )
+        .execute(&mut *transaction)
+        .await
+        .map_err(|error| Status::internal(format!("Database error: {error}")))?;
+        query!(
+            "DELETE FROM server_message WHERE recipient = ? AND device_id = ?",
+            request.client_id,
+            request.device_id,
+        )
+        .execute(&mut *transaction)
+        .await
+        .map_err(|error| Status::internal(format!("Database error: {error}")))?;
+        transaction
+            .commit()
+            .await
+            .map_err(|error| Status::internal(format!("Database error: {error}")))?;
+
+        self.connected
+            .remove(&(request.client_id.clone(), request.device_id.clone()));
+
+        info!(
+            client_id = request.client_id,
+            device_id = request.device_id,
+            "Revoked device"
+        );
+
+        Ok(Response::new(RevokeDeviceResponse {}))
+    }
+
+    async fn rotate_device_key(
+        &self,
+        request: Request<RotateDeviceKeyRequest>,
+    ) -> Result<Response<RotateDeviceKeyResponse>, Status> {
+        let certificate = request
+            .into_inner()
+            .certificate
+            .ok_or_else(|| Status::invalid_argument("Certificate is required"))?;
+        self.ensure_local(&certificate.client_id)?;
+
+        let current_key = query_scalar!(
+            "SELECT signature_key FROM server_device WHERE client_id = ? AND device_id = ?",
+            certificate.client_id,
+            certificate.device_id,
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|error| Status::internal(format!("Database error: {error}")))?
+        .flatten()
+        .ok_or_else(|| Status::not_found("Device not found"))?;
+        if current_key != certificate.issuer_signature_key {
+            return Err(Status::permission_denied(
+                "Certificate is not issued by the current key of the device",
+            ));
+        }
+        if !device::verify(
+            &device::certificate_payload(&certificate),
+            &certificate.issuer_signature_key,
+            &certificate.signature,
+        ) {
+            return Err(Status::invalid_argument("Invalid certificate signature"));
+        }
+
+        let certificate_bytes = certificate.encode_to_vec();
+        let created_at = Utc::now();
+        let mut transaction = self
+            .pool
+            .begin()
+            .await
+            .map_err(|error| Status::internal(format!("Database error: {error}")))?;
+        query!(
+            "UPDATE server_device SET signature_key = ? WHERE client_id = ? AND device_id = ?",
+            certificate.signature_key,
+            certificate.client_id,
+            certificate.device_id,
+        )
+        .execute(&mut *transaction)
+        .await
+        .map_err(|error| Status::internal(format!("Database error: {error}")))?;
+        // The primary device has no certificate to replace.
+        query!(
+            "UPDATE server_device_certificate
+            SET signature_key = ?, certificate = ?
+            WHERE client_id = ? AND device_id = ?",
+            certificate.signature_key,
+            certificate_bytes,
+            certificate.client_id,
+            certificate.device_id,
+        )
+        .execute(&mut *transaction)
+        .await
+        .map_err(|error| Status::internal(format!("Database error: {error}")))?;
+        // Key packages of the old key would add the device with it again.
+        query!(
+            "DELETE FROM server_key_package WHERE client_id = ? AND device_id = ?",
+            certificate.client_id,
+            certificate.device_id,
+        )
+        .execute(&mut *transaction)
+        .await
+        .map_err(|error| Status::internal(format!("Database error: {error}")))?;
+        Self::append_key_log(
+            &mut transaction,
+            &certificate.client_id,
+            &certificate.device_id,
+            &certificate.signature_key,
+            created_at,
+        )
+        .await
+        .map_err(|error| Status::internal(format!("Database error: {error}")))?;
+        transaction
+            .commit()
+            .await
+            .map_err(|error| Status::internal(format!("Database error: {error}")))?;
+
+        info!(
+            client_id = certificate.client_id,
+            device_id = certificate.device_id,
+            "Rotated device key"
+        );
+
+        Ok(Response::new(RotateDeviceKeyResponse {}))
+    }
+
+    async fn get_key_log_root(
+        &self,
+        _request: Request<GetKeyLogRootRequest>,
+    ) -> Result<Response<GetKeyLogRootResponse>, Status> {
+        let leaves = self.key_log_leaves(0).await?;
+        Ok(Response::new(GetKeyLogRootResponse {
+            tree_size: leaves.len() as u64,
+            root_hash: transparency::root(&leaves),
+        }))
+    }
+
+    async fn get_inclusion_proof(
+        &self,
+        request: Request<GetInclusionProofRequest>,
+    ) -> Result<Response<GetInclusionProofResponse>, Status> {
+        let request = request.into_inner();
+        self.ensure_local(&request.client_id)?;
+
+        let logged = query!(
+            "SELECT log_index, entry
+            FROM server_key_log
+            WHERE client_id = ? AND signature_key = ?",
+            request.client_id,
+            request.signature_key,
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|error| Status::internal(format!("Database error: {error}")))?
+        .ok_or_else(|| Status::not_found("Signature key is not logged"))?;
+
+        let leaves = self.key_log_leaves(request.tree_size).await?;
+        let leaf_index = logged.log_index as usize;
+        if leaf_index >= leaves.len() {
+            return Err(Status::out_of_range(
+                "Signature key was logged after the requested tree size",
+            ));
+        }
+
+        Ok(Response::new(GetInclusionProofResponse {
+            leaf_index: leaf_index as u64,
+            entry: logged.entry,
+            proof: transparency::inclusion_proof(leaf_index, &leaves),
+        }))
+    }
+
+    async fn get_consistency_proof(
+        &self,
+        request: Request<GetConsistencyProofRequest>,
+    ) -> Result<Response<GetConsistencyProofResponse>, Status> {
+        let request = request.into_inner();
+        if request.new_size == 0 || request.old_size > request.new_size {
+            return Err(Status::invalid_argument("Invalid tree sizes"));
+        }
+        let leaves = self.key_log_leaves(request.new_size).await?;
+        Ok(Response::new(GetConsistencyProofResponse {
+            proof: transparency::consistency_proof(request.old_size as usize, &leaves),
+        }))
+    }
+
+    async fn publish_group_info(
+        &self,
+        request: Request<PublishGroupInfoRequest>,
+    ) -> Result<Response<PublishGroupInfoResponse>, Status> {
+        let request = request.into_inner();
+        let MlsMessageBodyIn::GroupInfo(group_info) =
+            MlsMessageIn::tls_deserialize_exact_bytes(&request.group_info)
+                .map_err(|error| Status::invalid_argument(format!("Invalid message: {error}")))?
+                .extract()
+        else {
+            return Err(Status::invalid_argument("Message is not a GroupInfo"));
+        };
+
+        // The server can't verify the signature without the group's tree;
+        // joiners do, and a forged GroupInfo only yields commits the members
+        // reject.
+        let group_id = group_info.group_id().as_slice();
+        let epoch = group_info.epoch().as_u64() as i64;
+        let updated_at = Utc::now();
+        query!(
+            "INSERT INTO server_group_info (group_id, epoch, group_info, updated_at)
+            VALUES (?, ?, ?, ?)
+            ON CONFLICT (group_id) DO UPDATE SET
+                epoch = excluded.epoch,
+                group_info = excluded.group_info,
+                updated_at = excluded.updated_at
+            WHERE excluded.epoch >= server_group_info.epoch",
+            group_id,
+            epoch,
+            request.group_info,
+            updated_at,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|error| Status::internal(format!("Database error: {error}")))?;
+
+        info!(epoch, "Published group info");
+
+        Ok(Response::new(PublishGroupInfoResponse {}))
+    }
+
+    async fn fetch_group_info(
+        &self,
+        request: Request<FetchGroupInfoRequest>,
+    ) -> Result<Response<FetchGroupInfoResponse>, Status> {
+        let request = request.into_inner();
+        let group_info = query_scalar!(
+            "SELECT group_info FROM server_group_info WHERE group_id = ?",
+            request.group_id,
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|error| Status::internal(format!("Database error: {error}")))?
+        .ok_or_else(|| Status::not_found("Group info not published"))?;
+
+        Ok(Response::new(FetchGroupInfoResponse { group_info }))
+    }
+
+    async fn upload_blob(
+        &self,
+        request: Request<UploadBlobRequest>,
+    ) -> Result<Response<UploadBlobResponse>, Status> {
+        let request = request.into_inner();
+        self.ensure_local(&request.client_id)?;
+        if request.content.len() > MAX_BLOB_SIZE {
+            return Err(Status::invalid_argument(format!(
+                "Blob exceeds {MAX_BLOB_SIZE} bytes"
+            )));
+        }
+
+        let blob_id = Uuid::new_v4().to_string();
+        let created_at = Utc::now();
+        query!(
+            "INSERT INTO server_blob (blob_id, client_id, content, created_at)
+            VALUES (?, ?, ?, ?)",
+            blob_id,
+            request.client_id,
+            request.content,
+            created_at,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|error| Status::internal(format!("Database error: {error}")))?;
+
+        Ok(Response::new(UploadBlobResponse { blob_id }))
+    }
+
+    async fn download_blob(
+        &self,
+        request: Request<DownloadBlobRequest>,
+    ) -> Result<Response<DownloadBlobResponse>, Status> {
+        let request = request.into_inner();
+        let content = query_scalar!(
+            "SELECT content FROM server_blob WHERE blob_id = ?",
+            request.blob_id,
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|error| Status::internal(format!("Database error: {error}")))?
+        .ok_or_else(|| Status::not_found("Blob not found"))?;
+
+        Ok(Response::new(DownloadBlobResponse { content }))
+    }
+}
+
+impl ChatServiceImpl {
+    /// Delivers a message to the queues of all local recipients and relays it to
+    /// remote ones. Returns the id of the message.
+    pub(crate) async fn deliver(
+        &self,
+        request: SendMessageRequest,
+        created_at: DateTime<Utc>,
+    ) -> Result<Uuid, Status> {
+        let request = self.relay_remote(request, created_at).await?;
+        let message_id = Uuid::new_v4();
+
+        let mut targets = Vec::new();
+        for recipient in request.recipients {
+            let devices = self
+                .devices(&recipient)
+                .await
+                .map_err(|error| Status::internal(format!("Database error: {error}")))?;
+            if devices.is_empty() {
+                targets.push((recipient, String::new()));
+            } else {
+                targets.extend(
+                    devices
+                        .into_iter()
+                        .map(|device| (recipient.clone(), device)),
+                );
+            }
+        }
+        targets.extend(
+            request
+                .device_recipients
+                .into_iter()
+                .map(|address| (address.client_id, address.device_id)),
+        );
+        targets.retain(|(client_id, device_id)| {
+            client_id != &request.sender || device_id != &request.sender_device_id
+        });
+        targets.sort();
+        targets.dedup();
+
+        for (recipient, device_id) in targets {
+            let mut delivered_at = None;
+            if let Some(tx) = self.connected.get(&(recipient.clone(), device_id.clone()))
+                && tx
+                    .send(Ok(grpc::ReceiveMessagesResponse {
+                        content: request.content.clone(),
+                        timestamp: created_at.timestamp_millis(),
+                        message_id: message_id.as_bytes().to_vec(),
+                    }))
+                    .await
+                    .is_ok()
+            {
+                delivered_at = Some(Utc::now());
+            }
+            self.enqueue_message(
+                message_id,
+                recipient,
+                device_id,
+                request.content.clone(),
+                created_at,
+                delivered_at,
+            )
+            .await
+            .map_err(|error| Status::internal(format!("Database error: {error}")))?;
+        }
+
+        Ok(message_id)
+    }
+
+    /// Spawns a task periodically deleting messages that were delivered more than
+    /// `retention` ago. Undelivered messages are never deleted.
+    ///
+    /// Blobs are deleted along with the messages uploaded after them: the
+    /// server can't tell which message points to a blob, but it is sent after
+    /// the upload, so a blob older than all kept messages is no longer needed.
+    /// Blobs of the last `retention` are always kept.
+    pub fn spawn_retention_sweeper(&self, retention: Duration) -> JoinHandle<()> {
+        let pool = self.pool.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+            loop {
+                interval.tick().await;
+                let Some(cutoff) = SystemTime::now().checked_sub(retention) else {
+                    warn!(?retention, "Invalid message retention");
+                    return;
+                };
+                let cutoff = DateTime::<Utc>::from(cutoff);
+                match query!("DELETE FROM server_message WHERE delivered_at < ?", cutoff)
+                    .execute(&pool)
+                    .await
+                {
+                    Ok(result) if result.rows_affected() > 0 => {
+                        info!(deleted = result.rows_affected(), "Swept delivered messages");
+                    }
+                    Ok(_) => {}
+                    Err(error) => warn!(%error, "Failed to sweep delivered messages"),
+                }
+                match query!(
+                    "DELETE FROM server_blob
+                    WHERE created_at < ?
+                        AND created_at < COALESCE((SELECT MIN(created_at) FROM server_message), ?)",
+                    cutoff,
+                    cutoff,
+                )
+                .execute(&pool)
+                .await
+                {
+                    Ok(result) if result.rows_affected() > 0 => {
+                        info!(deleted = result.rows_affected(), "Swept blobs");
+                    }
+                    Ok(_) => {}
+                    Err(error) => warn!(%error, "Failed to sweep blobs"),
+                }
+            }
+        })
+    }
+
+    /// Spawns a task periodically checkpointing and truncating the write-ahead
+    /// log. Under sustained load SQLite's automatic checkpoints rarely find a
+    /// moment without readers and the log keeps growing.
+    pub fn spawn_wal_checkpointer(&self, interval: Duration) -> JoinHandle<()> {
+        let pool = self.pool.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(interval);
+            loop {
+                interval.tick().await;
+                match maintenance::checkpoint(&pool).await {
+                    Ok(true) => {}
+                    Ok(false) => warn!("WAL checkpoint could not complete"),
+                    Err(error) => warn!(%error, "Failed to checkpoint WAL"),
+                }
+            }
+        })
+    }
+
+    /// Orders the messages of each group by epoch. The first commit for an
+    /// epoch wins; later commits for it and messages of earlier epochs are
+    /// rejected as `ABORTED`, so that their senders catch up and retry instead
+    /// of forking the group.
+    async fn sequence_message(&self, content: &[u8]) -> Result<(), Status> {
+        let Ok(message) = MlsMessageIn::tls_deserialize_exact_bytes(content) else {
+            return Ok(());
+        };
+        let message: ProtocolMessage = match message.extract() {
+            MlsMessageBodyIn::PublicMessage(message) => message.into(),
+            MlsMessageBodyIn::PrivateMessage(message) => message.into(),
+            _ => return Ok(()),
+        };
+        let group_id = message.group_id().as_slice();
+        let epoch = message.epoch().as_u64() as i64;
+
+        if message.content_type() == ContentType::Commit {
+            let next_epoch = epoch + 1;
+            let updated_at = Utc::now();
+            let result = query!(
+                "INSERT INTO server_group_epoch (group_id, epoch, updated_at)
+                VALUES (?, ?, ?)
+                ON CONFLICT (group_id) DO UPDATE SET
+                    epoch = excluded.epoch,
+                    updated_at = excluded.updated_at
+                WHERE server_group_epoch.epoch <= ?",
+                group_id,
+                next_epoch,
+                updated_at,
+                epoch,
+            )
+            .execute(&self.pool)
+            .await
+            .map_err(|error| Status::internal(format!("Database error: {error}")))?;
+            if result.rows_affected() == 0 {
+                return Err(Status::aborted(format!(
+                    "Another commit for epoch {epoch} was accepted first"
+                )));
+            }
+        } else {
+            let current = query_scalar!(
+                "SELECT epoch FROM server_group_epoch WHERE group_id = ?",
+                group_id,
+            )
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(|error| Status::internal(format!("Database error: {error}")))?;
+            if current.is_some_and(|current| current > epoch) {
+                return Err(Status::aborted(format!(
+                    "Message of epoch {epoch} is outdated"
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Only the first device of a user is accepted without a certificate. Every
+    /// further device must be linked by an existing one first.
+    async fn check_device_key(
+        &self,
+        client_id: &str,
+        device_id: &str,
+        signature_key: &[u8],
+    ) -> Result<(), Status> {
+        let devices = query!(
+            "SELECT device_id, signature_key FROM server_device WHERE client_id = ?",
+            client_id
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|error| Status::internal(format!("Database error: {error}")))?;
+
+        if let Some(device) = devices.iter().find(|device| device.device_id == device_id) {
+            return match &device.signature_key {
+                Some(key) if key != signature_key => Err(Status::permission_denied(
+                    "Signature key mismatch for device",
+                )),
+                _ => Ok(()),
+            };
+        }
+        if devices.is_empty() {
+            return Ok(());
+        }
+
+        let certified_key = query_scalar!(
+            "SELECT signature_key
+            FROM server_device_certificate
+            WHERE client_id = ? AND device_id = ?",
+            client_id,
+            device_id,
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|error| Status::internal(format!("Database error: {error}")))?;
+
+        match certified_key {
+            Some(key) if key == signature_key => Ok(()),
+            Some(_) => Err(Status::permission_denied(
+                "Signature key does not match device certificate",
+            )),
+            None => Err(Status::permission_denied("Device is not linked")),
+        }
+    }
+
+    /// Checks that `issuer_signature_key` belongs to another device of the user.
+    async fn check_issuer(
+        &self,
+        client_id: &str,
+        device_id: &str,
+        issuer_signature_key: &[u8],
+    ) -> Result<(), Status> {
+        let issuer = query_scalar!(
+            "SELECT device_id
+            FROM server_device
+            WHERE client_id = ? AND device_id != ? AND signature_key = ?",
+            client_id,
+            device_id,
+            issuer_signature_key,
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|error| Status::internal(format!("Database error: {error}")))?;
+
+        match issuer {
+            Some(_) => Ok(()),
+            None => Err(Status::permission_denied(
+                "Issuer is not a device of this user",
+            )),
+        }
+    }
+
+    async fn devices(&self, client_id: &str) -> sqlx::Result<Vec<String>> {
+        query_scalar!(
+            "SELECT device_id FROM server_device WHERE client_id = ? ORDER BY created_at",
+            client_id
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn enqueue_message(
+        &self,
+        message_id: Uuid,
+        recipient: String,
+        device_id: String,
+        content: Vec<u8>,
+        created_at: DateTime<Utc>,
+        delivered_at: Option<DateTime<Utc>>,
+    ) -> sqlx::Result<()> {
+        sqlx::query!(
+            "INSERT INTO server_message (
+                message_id, recipient, device_id, content, created_at, delivered_at
+            ) VALUES (?, ?, ?, ?, ?, ?)",
+            message_id,
+            recipient,
+            device_id,
+            content,
+            created_at,
+            delivered_at,
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    // async fn dequeue_messages<'a>(
+    //     &'a self,
+    //     client_id: &'a str,
+    // ) -> impl Stream<Item = sqlx::Result<Vec<u8>>> + 'a {
+    //     query_scalar!(
+    //         "WITH target_messages AS (
+    //             SELECT message_id
+    //             FROM server_message
+    //             WHERE recipient = ?
+    //             ORDER BY created_at ASC
+    //         )
+    //         DELETE FROM server_message
+    //         WHERE message_id IN (SELECT message_id FROM target_messages)
+    //         RETURNING content",
+    //         client_id,
+    //     )
+    //     .fetch(&self.pool)
+    // }
+}
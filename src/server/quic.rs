@@ -0,0 +1,115 @@
+use std::{net::SocketAddr, path::Path, sync::Arc, time::Duration};
+
+use anyhow::Context;
+use quinn::{
+    Endpoint, IdleTimeout, RecvStream, SendStream, ServerConfig, TransportConfig,
+    crypto::rustls::QuicServerConfig,
+};
+use rustls_pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject};
+use tokio::sync::mpsc;
+use tonic::Status;
+use tracing::{info, warn};
+
+use crate::{
+    grpc::{WebSocketRequest, WebSocketResponse, web_socket_request, web_socket_response},
+    quic::{ALPN, read_frame, write_frame},
+    server::{ChatServiceImpl, websocket::handle_request},
+};
+
+/// How long a connection may be silent before it's closed. Messages are
+/// passed on to the receive stream of a client that went away without closing
+/// the connection until then, and so lost, like on a broken TCP connection.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Binds the QUIC endpoint to `listen`, with the certificate chain and
+/// private key of the PEM files.
+pub fn endpoint(listen: SocketAddr, cert_path: &Path, key_path: &Path) -> anyhow::Result<Endpoint> {
+    let certs = CertificateDer::pem_file_iter(cert_path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .with_context(|| format!("Failed to read {}", cert_path.display()))?;
+    let key = PrivateKeyDer::from_pem_file(key_path)
+        .with_context(|| format!("Failed to read {}", key_path.display()))?;
+    let mut crypto = rustls::ServerConfig::builder_with_provider(Arc::new(
+        rustls::crypto::ring::default_provider(),
+    ))
+    .with_protocol_versions(&[&rustls::version::TLS13])?
+    .with_no_client_auth()
+    .with_single_cert(certs, key)?;
+    crypto.alpn_protocols = vec![ALPN.to_vec()];
+    let mut transport = TransportConfig::default();
+    transport.max_idle_timeout(Some(IdleTimeout::try_from(IDLE_TIMEOUT)?));
+    let mut config = ServerConfig::with_crypto(Arc::new(QuicServerConfig::try_from(crypto)?));
+    config.transport_config(Arc::new(transport));
+    Ok(Endpoint::server(config, listen)?)
+}
+
+/// Serves the requests of the connections to `endpoint` until it's closed.
+pub async fn serve(service: Arc<ChatServiceImpl>, endpoint: Endpoint) {
+    while let Some(incoming) = endpoint.accept().await {
+        let service = service.clone();
+        tokio::spawn(async move {
+            let connection = match incoming.await {
+                Ok(connection) => connection,
+                Err(error) => {
+                    warn!(%error, "QUIC handshake failed");
+                    return;
+                }
+            };
+            let remote = connection.remote_address();
+            info!(%remote, "QUIC connected");
+            loop {
+                match connection.accept_bi().await {
+                    Ok((send, recv)) => {
+                        tokio::spawn(handle_stream(service.clone(), send, recv));
+                    }
+                    Err(error) => {
+                        info!(%remote, %error, "QUIC closed");
+                        break;
+                    }
+                }
+            }
+        });
+    }
+}
+
+/// Answers the request of a stream, until the client stops reading the
+/// responses, which cancels the request.
+async fn handle_stream(service: Arc<ChatServiceImpl>, mut send: SendStream, mut recv: RecvStream) {
+    let request: WebSocketRequest = match read_frame(&mut recv).await {
+        Ok(Some(request)) => request,
+        Ok(None) => return,
+        Err(status) => {
+            warn!(%status, "Invalid QUIC request");
+            return;
+        }
+    };
+    let (responses, mut outgoing) = mpsc::channel(100);
+    let task = match request.request {
+        Some(web_socket_request::Request::Cancel(_)) | None => {
+            let status = Status::invalid_argument("Expected a request");
+            let response = WebSocketResponse {
+                id: request.id,
+                response: Some(web_socket_response::Response::Status(status.into())),
+            };
+            let _ = write_frame(&mut send, &response).await;
+            let _ = send.finish();
+            return;
+        }
+        Some(body) => tokio::spawn(handle_request(service, request.id, body, responses)),
+    };
+    loop {
+        tokio::select! {
+            response = outgoing.recv() => {
+                let Some(response) = response else {
+                    let _ = send.finish();
+                    break;
+                };
+                if write_frame(&mut send, &response).await.is_err() {
+                    break;
+                }
+            }
+            _ = send.stopped() => break,
+        }
+    }
+    task.abort();
+}
@@ -0,0 +1,22 @@
+use tonic::{Request, Response, Status};
+
+use crate::{
+    grpc::{
+        API_VERSIONS, GetVersionsRequest, GetVersionsResponse,
+        version_service_server::VersionService,
+    },
+    server::ChatServiceImpl,
+};
+
+#[tonic::async_trait]
+impl VersionService for ChatServiceImpl {
+    async fn get_versions(
+        &self,
+        _request: Request<GetVersionsRequest>,
+    ) -> Result<Response<GetVersionsResponse>, Status> {
+        Ok(Response::new(GetVersionsResponse {
+            versions: API_VERSIONS.to_vec(),
+            server_version: env!("CARGO_PKG_VERSION").to_string(),
+        }))
+    }
+}
@@ -0,0 +1,210 @@
+use std::{collections::HashMap, io, sync::Arc};
+
+use axum::{
+    Router,
+    extract::{
+        State, WebSocketUpgrade,
+        ws::{Message, WebSocket},
+    },
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use futures_util::{SinkExt, StreamExt};
+use tokio::{net::TcpListener, sync::mpsc, task::JoinHandle};
+use tonic::Status;
+use tracing::{info, warn};
+
+use crate::{
+    grpc::{
+        ReceiveMessagesRequest, WebSocketRequest, WebSocketResponse,
+        chat_service_server::ChatService, version_service_server::VersionService,
+        web_socket_request, web_socket_response,
+    },
+    server::ChatServiceImpl,
+    websocket::{Frame, WEBSOCKET_PATH, WebSocketEncoding},
+};
+
+/// The WebSocket endpoint of the service, see
+/// `proto/mlschat/v1/websocket.proto`.
+pub fn router(service: Arc<ChatServiceImpl>) -> Router {
+    Router::new()
+        .route(WEBSOCKET_PATH, get(upgrade))
+        .with_state(service)
+}
+
+/// Serves the WebSocket endpoint on `listener` until it fails.
+pub async fn serve(service: Arc<ChatServiceImpl>, listener: TcpListener) -> io::Result<()> {
+    axum::serve(listener, router(service)).await
+}
+
+async fn upgrade(
+    State(service): State<Arc<ChatServiceImpl>>,
+    upgrade: WebSocketUpgrade,
+) -> Response {
+    let upgrade = upgrade.protocols(WebSocketEncoding::ALL.map(WebSocketEncoding::protocol));
+    let encoding = upgrade
+        .selected_protocol()
+        .and_then(|protocol| protocol.to_str().ok())
+        .and_then(WebSocketEncoding::from_protocol);
+    let Some(encoding) = encoding else {
+        return (StatusCode::BAD_REQUEST, "Unsupported WebSocket subprotocol").into_response();
+    };
+    upgrade.on_upgrade(move |socket| handle_socket(service, socket, encoding))
+}
+
+/// Handles the requests of a socket concurrently until it closes, which
+/// cancels the requests still running.
+async fn handle_socket(
+    service: Arc<ChatServiceImpl>,
+    socket: WebSocket,
+    encoding: WebSocketEncoding,
+) {
+    info!(?encoding, "WebSocket connected");
+    let (mut sink, mut stream) = socket.split();
+    let (responses, mut outgoing) = mpsc::channel::<WebSocketResponse>(100);
+    let writer = tokio::spawn(async move {
+        while let Some(response) = outgoing.recv().await {
+            let message = match encoding.encode(&response) {
+                Frame::Binary(bytes) => Message::binary(bytes),
+                Frame::Text(text) => Message::text(text),
+            };
+            if sink.send(message).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut requests = HashMap::new();
+    while let Some(Ok(message)) = stream.next().await {
+        let frame = match message {
+            Message::Binary(bytes) => Frame::Binary(bytes.into()),
+            Message::Text(text) => Frame::Text(text.as_str().to_string()),
+            Message::Close(_) => break,
+            Message::Ping(_) | Message::Pong(_) => continue,
+        };
+        let request: WebSocketRequest = match encoding.decode(frame) {
+            Ok(request) => request,
+            Err(status) => {
+                warn!(%status, "Closing WebSocket");
+                break;
+            }
+        };
+        requests.retain(|_, task: &mut JoinHandle<()>| !task.is_finished());
+        match request.request {
+            Some(web_socket_request::Request::Cancel(_)) => {
+                if let Some(task) = requests.remove(&request.id) {
+                    task.abort();
+                }
+            }
+            Some(request_body) => {
+                let task = tokio::spawn(handle_request(
+                    service.clone(),
+                    request.id,
+                    request_body,
+                    responses.clone(),
+                ));
+                requests.insert(request.id, task);
+            }
+            None => {
+                let status = Status::invalid_argument("Empty request");
+                let response = WebSocketResponse {
+                    id: request.id,
+                    response: Some(web_socket_response::Response::Status(status.into())),
+                };
+                if responses.send(response).await.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+
+    for task in requests.values() {
+        task.abort();
+    }
+    writer.abort();
+    info!("WebSocket closed");
+}
+
+/// Calls the service like gRPC would, `$method` for requests `$variant`.
+macro_rules! unary {
+    ($service:expr, $request:expr, $($variant:ident => $method:ident,)*) => {
+        match $request {
+            $(
+                web_socket_request::Request::$variant(request) => $service
+                    .$method(tonic::Request::new(request))
+                    .await
+                    .map(|response| web_socket_response::Response::$variant(response.into_inner())),
+            )*
+            web_socket_request::Request::ReceiveMessages(_)
+            | web_socket_request::Request::Cancel(_) => unreachable!(),
+        }
+    };
+}
+
+/// Answers `request` with responses of the id, like gRPC would. Doesn't take
+/// cancellations, which are up to the transport.
+pub(crate) async fn handle_request(
+    service: Arc<ChatServiceImpl>,
+    id: u64,
+    request: web_socket_request::Request,
+    responses: mpsc::Sender<WebSocketResponse>,
+) {
+    let response = match request {
+        web_socket_request::Request::ReceiveMessages(request) => {
+            receive_messages(&service, id, request, &responses).await
+        }
+        request => unary!(service, request,
+            GetVersions => get_versions,
+            SendMessage => send_message,
+            GetQueueStatus => get_queue_status,
+            FetchMessages => fetch_messages,
+            UploadKeyPackage => upload_key_package,
+            FetchKeyPackage => fetch_key_package,
+            PublishGroupInfo => publish_group_info,
+            FetchGroupInfo => fetch_group_info,
+            UploadBlob => upload_blob,
+            DownloadBlob => download_blob,
+            UploadDeviceCertificate => upload_device_certificate,
+            FetchDeviceCertificates => fetch_device_certificates,
+            ListDevices => list_devices,
+            RevokeDevice => revoke_device,
+            RotateDeviceKey => rotate_device_key,
+            GetKeyLogRoot => get_key_log_root,
+            GetInclusionProof => get_inclusion_proof,
+            GetConsistencyProof => get_consistency_proof,
+        ),
+    };
+    let response = WebSocketResponse {
+        id,
+        response: Some(
+            response.unwrap_or_else(|status| web_socket_response::Response::Status(status.into())),
+        ),
+    };
+    // The socket closed if this fails.
+    let _ = responses.send(response).await;
+}
+
+/// Passes the messages of the stream on as responses to the request `id`,
+/// and returns the status ending the stream.
+async fn receive_messages(
+    service: &ChatServiceImpl,
+    id: u64,
+    request: ReceiveMessagesRequest,
+    responses: &mpsc::Sender<WebSocketResponse>,
+) -> Result<web_socket_response::Response, Status> {
+    let mut messages = service
+        .receive_messages(tonic::Request::new(request))
+        .await?
+        .into_inner();
+    while let Some(message) = messages.next().await {
+        let response = WebSocketResponse {
+            id,
+            response: Some(web_socket_response::Response::ReceiveMessages(message?)),
+        };
+        if responses.send(response).await.is_err() {
+            break;
+        }
+    }
+    Ok(web_socket_response::Response::Status(Status::ok("").into()))
+}
@@ -0,0 +1,428 @@
+use openmls::prelude::{HashType, OpenMlsCrypto};
+use openmls_rust_crypto::RustCrypto;
+
+fn hash(parts: &[&[u8]]) -> Vec<u8> {
+    RustCrypto::default()
+        .hash(HashType::Sha2_256, &parts.concat())
+        .expect("SHA-256 is supported")
+}
+
+fn node_hash(left: &[u8], right: &[u8]) -> Vec<u8> {
+    hash(&[&[0x01], left, right])
+}
+
+/// Hash of a log entry as stored in the tree.
+pub(crate) fn leaf_hash(entry: &[u8]) -> Vec<u8> {
+    hash(&[&[0x00], entry])
+}
+
+/// Largest power of two smaller than `n`, for `n > 1`.
+fn split(n: usize) -> usize {
+    1 << (usize::BITS - (n - 1).leading_zeros() - 1)
+}
+
+/// Root hash of the tree with the given leaf hashes.
+pub(crate) fn root(leaves: &[Vec<u8>]) -> Vec<u8> {
+    match leaves.len() {
+        0 => hash(&[]),
+        1 => leaves[0].clone(),
+        n => {
+            let k = split(n);
+            node_hash(&root(&leaves[..k]), &root(&leaves[k..]))
+        }
+    }
+}
+
+/// Audit path of the leaf at `index`.
+pub(crate) fn inclusion_proof(index: usize, leaves: &[Vec<u8>]) -> Vec<Vec<u8>> {
+    let n = leaves.len();
+    if n <= 1 {
+        return Vec::new();
+    }
+    let k = split(n);
+    let (mut path, sibling) = if index < k {
+        (inclusion_proof(index, &leaves[..k]), root(&leaves[k..]))
+    } else {
+        (inclusion_proof(index - k, &leaves[k..]), root(&leaves[..k]))
+    };
+    path.push(sibling);
+    path
+}
+
+/// Proof that the tree of the first `old_size` leaves is a prefix of the tree
+/// of all `leaves`.
+pub(crate) fn consistency_proof(old_size: usize, leaves: &[Vec<u8>]) -> Vec<Vec<u8>> {
+    if old_size == 0 || old_size >= leaves.len() {
+        return Vec::new();
+    }
+    subproof(old_size, leaves, true)
+}
+
+fn subproof(m: usize, leaves: &[Vec<u8>], complete: bool) -> Vec<Vec<u8>> {
+    let n = leaves.len();
+    if m == n {
+        return if complete {
+            Vec::new()
+        } else {
+            vec![root(leaves)]
+        };
+    }
+    let k = split(n);
+    let (mut proof, sibling) = if m <= k {
+        (subproof(m, &leaves[..k], complete), root(&leaves[k..]))
+    } else {
+        (subproof(m - k, &leaves[k..], false), root(&leaves[..k]))
+    };
+    proof.push(sibling);
+    proof
+}
+
+pub(crate) fn verify_inclusion(
+    index: u64,
+    tree_size: u64,
+    leaf_hash: &[u8],
+    proof: &[Vec<u8>],
+    root: &[u8],
+) -> bool {
+    if index >= tree_size {
+        return false;
+    }
+    let (mut fn_, mut sn) = (index, tree_size - 1);
+    let mut r = leaf_hash.to_vec();
+    for p in proof {
+        if sn == 0 {
+            return false;
+        }
+        if fn_ & 1 == 1 || fn_ == sn {
+            r = node_hash(p, &r);
+            while fn_ & 1 == 0 && fn_ != 0 {
+                fn_ >>= 1;
+                sn >>= 1;
+            }
+        } else {
+            r = node_hash(&r, p);
+        }
+        fn_ >>= 1;
+        sn >>= 1;
+    }
+    sn == 0 && r == root
+}
+
+pub(crate) fn verify_consistency(
+    old_size: u64,
+    new_size: u64,
+    old_root: &[u8],
+    new_root: &[u8],
+    proof: &[Vec<u8>],
+) -> bool {
+    if old_size > new_size {
+        return false;
+    }
+    if old_size == new_size {
+        return proof.is_empty() && old_root == new_root;
+    }
+    if old_size == 0 {
+        return proof.is_empty();
+    }
+
+    let mut path: Vec<&[u8]> = proof.iter().map(Vec::as_slice).collect();
+    if old_size.is_power_of_two() {
+        path.insert(0, old_root);
+    }
+    let Some((first, rest)) = path.split_first() else {
+        return false;
+    };
+
+    let (mut fn_, mut sn) = (old_size - 1, new_size - 1);
+    while fn_ & 1 == 1 {
+        fn_ >>= 1;
+        sn >>= 1;
+    }
+    let (mut fr, mut sr) = (first.to_vec(), first.to_vec());
+    for c in rest {
+        if sn == 0 {
+            return false;
+        }
+        if fn_ & 1 == 1 || fn_ == sn {
+            fr = node_hash(c, &fr);
+            sr = node_hash(c, &sr);
+            while fn_ & 1 == 0 && fn_ != 0 {
+                fn_ >>= 1;
+                sn >>= 1;
+            }
+        } else {
+            sr = node_hash(&sr, c);
+        }
+        fn_ >>= 1;
+        sn >>= 1;
+    }
+    sn == 0 && fr == old_root && sr == new_root
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Leaves of the test vectors of the Certificate Transparency reference
+    /// implementation, whose trees are those of RFC 9162.
+    const LEAVES: [&str; 8] = [
+        "",
+        "00",
+        "10",
+        "2021",
+        "3031",
+        "40414243",
+        "5051525354555657",
+        "606162636465666768696a6b6c6d6e6f",
+    ];
+
+    /// Root hashes of the trees of the first 1 to 8 leaves.
+    const ROOTS: [&str; 8] = [
+        "6e340b9cffb37a989ca544e6bb780a2c78901d3fb33738768511a30617afa01d",
+        "fac54203e7cc696cf0dfcb42c92a1d9dbaf70ad9e621f4bd8d98662f00e3c125",
+        "aeb6bcfe274b70a14fb067a5e5578264db0fa9b51af5e0ba159158f329e06e77",
+        "d37ee418976dd95753c1c73862b9398fa2a2cf9b4ff0fdfe8b30cd95209614b7",
+        "4e3bbb1f7b478dcfe71fb631631519a3bca12c9aefca1612bfce4c13a86264d4",
+        "76e67dadbcdf1e10e1b74ddc608abd2f98dfb16fbce75277b5232a127f2087ef",
+        "ddb89be403809e325750d3d263cd78929c2942b7942a34b77e122c9594a74c8c",
+        "5dc9da79a70659a9ad559cb701ded9a2ab9d823aad2f4960cfe370eff4604328",
+    ];
+
+    /// Index, tree size and audit path.
+    const INCLUSION_PROOFS: [(usize, usize, &[&str]); 5] = [
+        (0, 1, &[]),
+        (
+            0,
+            8,
+            &[
+                "96a296d224f285c67bee93c30f8a309157f0daa35dc5b87e410b78630a09cfc7",
+                "5f083f0a1a33ca076a95279832580db3e0ef4584bdff1f54c8a360f50de3031e",
+                "6b47aaf29ee3c2af9af889bc1fb9254dabd31177f16232dd6aab035ca39bf6e4",
+            ],
+        ),
+        (
+            5,
+            8,
+            &[
+                "bc1a0643b12e4d2d7c77918f44e0f4f79a838b6cf9ec5b5c283e1f4d88599e6b",
+                "ca854ea128ed050b41b35ffc1b87b8eb2bde461e9e3b5596ece6b9d5975a0ae0",
+                "d37ee418976dd95753c1c73862b9398fa2a2cf9b4ff0fdfe8b30cd95209614b7",
+            ],
+        ),
+        (
+            2,
+            3,
+            &["fac54203e7cc696cf0dfcb42c92a1d9dbaf70ad9e621f4bd8d98662f00e3c125"],
+        ),
+        (
+            1,
+            5,
+            &[
+                "6e340b9cffb37a989ca544e6bb780a2c78901d3fb33738768511a30617afa01d",
+                "5f083f0a1a33ca076a95279832580db3e0ef4584bdff1f54c8a360f50de3031e",
+                "bc1a0643b12e4d2d7c77918f44e0f4f79a838b6cf9ec5b5c283e1f4d88599e6b",
+            ],
+        ),
+    ];
+
+    /// Old size, new size and proof.
+    const CONSISTENCY_PROOFS: [(usize, usize, &[&str]); 4] = [
+        (1, 1, &[]),
+        (
+            1,
+            8,
+            &[
+                "96a296d224f285c67bee93c30f8a309157f0daa35dc5b87e410b78630a09cfc7",
+                "5f083f0a1a33ca076a95279832580db3e0ef4584bdff1f54c8a360f50de3031e",
+                "6b47aaf29ee3c2af9af889bc1fb9254dabd31177f16232dd6aab035ca39bf6e4",
+            ],
+        ),
+        (
+            6,
+            8,
+            &[
+                "0ebc5d3437fbe2db158b9f126a1d118e308181031d0a949f8dededebc558ef6a",
+                "ca854ea128ed050b41b35ffc1b87b8eb2bde461e9e3b5596ece6b9d5975a0ae0",
+                "d37ee418976dd95753c1c73862b9398fa2a2cf9b4ff0fdfe8b30cd95209614b7",
+            ],
+        ),
+        (
+            2,
+            5,
+            &[
+                "5f083f0a1a33ca076a95279832580db3e0ef4584bdff1f54c8a360f50de3031e",
+                "bc1a0643b12e4d2d7c77918f44e0f4f79a838b6cf9ec5b5c283e1f4d88599e6b",
+            ],
+        ),
+    ];
+
+    fn decode(hex: &str) -> Vec<u8> {
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn decode_all(hashes: &[&str]) -> Vec<Vec<u8>> {
+        hashes.iter().map(|hash| decode(hash)).collect()
+    }
+
+    fn reference_leaves() -> Vec<Vec<u8>> {
+        LEAVES.iter().map(|leaf| leaf_hash(&decode(leaf))).collect()
+    }
+
+    /// Leaf hashes of a tree of `n` distinct entries.
+    fn leaves(n: usize) -> Vec<Vec<u8>> {
+        (0..n)
+            .map(|i| leaf_hash(format!("entry {i}").as_bytes()))
+            .collect()
+    }
+
+    #[test]
+    fn roots_match_reference() {
+        let leaves = reference_leaves();
+        assert_eq!(
+            root(&[]),
+            decode("e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855")
+        );
+        for (size, expected) in (1..).zip(ROOTS) {
+            assert_eq!(root(&leaves[..size]), decode(expected), "size {size}");
+        }
+    }
+
+    #[test]
+    fn inclusion_proofs_match_reference() {
+        let leaves = reference_leaves();
+        for (index, size, path) in INCLUSION_PROOFS {
+            let path = decode_all(path);
+            assert_eq!(inclusion_proof(index, &leaves[..size]), path);
+            assert!(verify_inclusion(
+                index as u64,
+                size as u64,
+                &leaves[index],
+                &path,
+                &decode(ROOTS[size - 1]),
+            ));
+        }
+    }
+
+    #[test]
+    fn consistency_proofs_match_reference() {
+        let leaves = reference_leaves();
+        for (old_size, new_size, proof) in CONSISTENCY_PROOFS {
+            let proof = decode_all(proof);
+            assert_eq!(consistency_proof(old_size, &leaves[..new_size]), proof);
+            assert!(verify_consistency(
+                old_size as u64,
+                new_size as u64,
+                &decode(ROOTS[old_size - 1]),
+                &decode(ROOTS[new_size - 1]),
+                &proof,
+            ));
+        }
+    }
+
+    #[test]
+    fn inclusion_proofs_round_trip() {
+        let leaves = leaves(33);
+        for size in 1..=leaves.len() {
+            let tree = &leaves[..size];
+            let root = root(tree);
+            for index in 0..size {
+                let proof = inclusion_proof(index, tree);
+                assert!(
+                    verify_inclusion(index as u64, size as u64, &tree[index], &proof, &root),
+                    "index {index} of size {size}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn consistency_proofs_round_trip() {
+        let leaves = leaves(33);
+        for new_size in 1..=leaves.len() {
+            let new_root = root(&leaves[..new_size]);
+            for old_size in 1..=new_size {
+                let proof = consistency_proof(old_size, &leaves[..new_size]);
+                assert!(
+                    verify_consistency(
+                        old_size as u64,
+                        new_size as u64,
+                        &root(&leaves[..old_size]),
+                        &new_root,
+                        &proof,
+                    ),
+                    "from size {old_size} to {new_size}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn inclusion_rejects_wrong_proofs() {
+        let leaves = leaves(13);
+        let root = root(&leaves);
+        let proof = inclusion_proof(6, &leaves);
+        assert!(verify_inclusion(6, 13, &leaves[6], &proof, &root));
+
+        assert!(!verify_inclusion(7, 13, &leaves[6], &proof, &root));
+        assert!(!verify_inclusion(6, 8, &leaves[6], &proof, &root));
+        assert!(!verify_inclusion(13, 13, &leaves[6], &proof, &root));
+        assert!(!verify_inclusion(6, 13, &leaves[5], &proof, &root));
+        assert!(!verify_inclusion(
+            6,
+            13,
+            &leaves[6],
+            &proof[..proof.len() - 1],
+            &root
+        ));
+        let mut tampered = proof.clone();
+        tampered[1][0] ^= 1;
+        assert!(!verify_inclusion(6, 13, &leaves[6], &tampered, &root));
+        let mut extended = proof;
+        extended.push(leaves[0].clone());
+        assert!(!verify_inclusion(6, 13, &leaves[6], &extended, &root));
+    }
+
+    #[test]
+    fn consistency_rejects_wrong_proofs() {
+        let leaves = leaves(13);
+        let old_root = root(&leaves[..6]);
+        let new_root = root(&leaves);
+        let proof = consistency_proof(6, &leaves);
+        assert!(verify_consistency(6, 13, &old_root, &new_root, &proof));
+
+        assert!(!verify_consistency(5, 13, &old_root, &new_root, &proof));
+        assert!(!verify_consistency(6, 8, &old_root, &new_root, &proof));
+        assert!(!verify_consistency(13, 6, &new_root, &old_root, &proof));
+        assert!(!verify_consistency(
+            6,
+            13,
+            &root(&leaves[..5]),
+            &new_root,
+            &proof
+        ));
+        assert!(!verify_consistency(
+            6,
+            13,
+            &old_root,
+            &new_root,
+            &proof[..proof.len() - 1]
+        ));
+        let mut tampered = proof;
+        tampered[0][0] ^= 1;
+        assert!(!verify_consistency(6, 13, &old_root, &new_root, &tampered));
+        // A log that rewrote its history can't prove the new tree consistent.
+        let mut rewritten = leaves.clone();
+        rewritten[2] = leaf_hash(b"rewritten");
+        let rewritten_proof = consistency_proof(6, &rewritten);
+        assert!(!verify_consistency(
+            6,
+            13,
+            &old_root,
+            &root(&rewritten),
+            &rewritten_proof
+        ));
+    }
+}
@@ -0,0 +1,82 @@
+use prost::Message;
+use serde::{Serialize, de::DeserializeOwned};
+use tonic::{Code, Status};
+
+use crate::grpc::WebSocketStatus;
+
+/// Path of the WebSocket endpoint of the server.
+pub const WEBSOCKET_PATH: &str = "/v1/ws";
+
+/// How the frames of `proto/mlschat/v1/websocket.proto` are sent, chosen with
+/// the subprotocol of the WebSocket.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum WebSocketEncoding {
+    /// Protobuf in binary messages.
+    #[default]
+    Protobuf,
+    /// JSON in text messages, e.g. for clients in browsers. Larger, as bytes
+    /// are arrays of numbers.
+    Json,
+}
+
+impl WebSocketEncoding {
+    pub const ALL: [Self; 2] = [Self::Protobuf, Self::Json];
+
+    pub fn protocol(self) -> &'static str {
+        match self {
+            Self::Protobuf => "mlschat.v1",
+            Self::Json => "mlschat.v1.json",
+        }
+    }
+
+    pub fn from_protocol(protocol: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|encoding| encoding.protocol() == protocol)
+    }
+
+    pub(crate) fn encode<M: Message + Serialize>(self, frame: &M) -> Frame {
+        match self {
+            Self::Protobuf => Frame::Binary(frame.encode_to_vec()),
+            Self::Json => {
+                Frame::Text(serde_json::to_string(frame).expect("Messages serialize to JSON"))
+            }
+        }
+    }
+
+    pub(crate) fn decode<M: Message + Default + DeserializeOwned>(
+        self,
+        frame: Frame,
+    ) -> Result<M, Status> {
+        match (self, frame) {
+            (Self::Protobuf, Frame::Binary(bytes)) => M::decode(bytes.as_slice())
+                .map_err(|error| Status::invalid_argument(format!("Invalid frame: {error}"))),
+            (Self::Json, Frame::Text(text)) => serde_json::from_str(&text)
+                .map_err(|error| Status::invalid_argument(format!("Invalid frame: {error}"))),
+            _ => Err(Status::invalid_argument(
+                "Frame doesn't match the subprotocol",
+            )),
+        }
+    }
+}
+
+/// Data message of a WebSocket, whichever library carries it.
+pub(crate) enum Frame {
+    Binary(Vec<u8>),
+    Text(String),
+}
+
+impl From<Status> for WebSocketStatus {
+    fn from(status: Status) -> Self {
+        Self {
+            code: status.code().into(),
+            message: status.message().to_string(),
+        }
+    }
+}
+
+impl From<WebSocketStatus> for Status {
+    fn from(status: WebSocketStatus) -> Self {
+        Status::new(Code::from(status.code), status.message)
+    }
+}
@@ -0,0 +1,67 @@
+#![cfg(all(feature = "client", feature = "server"))]
+
+mod common;
+
+use common::{TestServer, receive_queued, shared_group, texts};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn queued_messages_are_handled_in_order_across_batches_and_groups() {
+    let server = TestServer::start().await;
+    let mut alice = server.client("alice").await;
+    let mut bob = server.client("bob").await;
+    let first = shared_group(&mut alice, "alice", &mut bob, "bob").await;
+    let second = shared_group(&mut alice, "alice", &mut bob, "bob").await;
+
+    // More than fit into one batch, interrupted by messages of another group.
+    let mut sent = Vec::new();
+    for i in 0..130 {
+        let group_uuid = if i % 50 == 49 { second } else { first };
+        let text = format!("message {i}");
+        alice
+            .send("alice".to_string(), group_uuid, text.clone())
+            .await
+            .unwrap();
+        sent.push(("alice".to_string(), text));
+    }
+
+    let received = texts(&receive_queued(&mut bob, "bob").await);
+    assert_eq!(received, sent);
+    let history = bob
+        .history("bob".to_string(), first, 200, None)
+        .await
+        .unwrap();
+    assert_eq!(history.len(), 128);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn group_state_of_a_batch_is_stored() {
+    let server = TestServer::start().await;
+    let mut alice = server.client("alice").await;
+    let mut bob = server.client("bob").await;
+    let group_uuid = shared_group(&mut alice, "alice", &mut bob, "bob").await;
+
+    for i in 0..5 {
+        alice
+            .send("alice".to_string(), group_uuid, format!("message {i}"))
+            .await
+            .unwrap();
+    }
+    assert_eq!(texts(&receive_queued(&mut bob, "bob").await).len(), 5);
+    alice
+        .update_group("alice".to_string(), group_uuid)
+        .await
+        .unwrap();
+    receive_queued(&mut bob, "bob").await;
+
+    // The cached group state was written back, so that a reopened client
+    // continues in the current epoch.
+    drop(bob);
+    let mut bob = server.client("bob").await;
+    bob.send("bob".to_string(), group_uuid, "reply".to_string())
+        .await
+        .unwrap();
+    assert_eq!(
+        texts(&receive_queued(&mut alice, "alice").await),
+        [("bob".to_string(), "reply".to_string())]
+    );
+}
@@ -0,0 +1,137 @@
+//! An in-process server and clients of it for the integration tests.
+
+// Each test only uses part of it.
+#![allow(dead_code)]
+
+use std::{
+    path::PathBuf,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
+use mls_chat::{
+    client::{Client, events::ChatEvent, policy::RequiredCapabilities},
+    grpc::{
+        chat_service_server::ChatServiceServer, federation_service_server::FederationServiceServer,
+        version_service_server::VersionServiceServer,
+    },
+    provider::DEFAULT_CIPHERSUITE,
+    server::{ChatServiceImpl, maintenance::DatabaseOptions},
+};
+use tokio::net::TcpListener;
+use tonic::transport::{Server, server::TcpIncoming};
+use uuid::Uuid;
+
+static SERVERS: AtomicUsize = AtomicUsize::new(0);
+
+/// A server listening on a free local port, with the databases of its
+/// clients in a directory of its own.
+pub struct TestServer {
+    endpoint: String,
+    dir: PathBuf,
+}
+
+impl TestServer {
+    pub async fn start() -> Self {
+        let dir = std::env::temp_dir().join(format!(
+            "mls-chat-test-{}-{}",
+            std::process::id(),
+            SERVERS.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let service = Arc::new(
+            ChatServiceImpl::new(dir.join("server.db"), &DatabaseOptions::default())
+                .await
+                .unwrap(),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(
+            Server::builder()
+                .add_service(ChatServiceServer::from_arc(service.clone()))
+                .add_service(FederationServiceServer::from_arc(service.clone()))
+                .add_service(VersionServiceServer::from_arc(service))
+                .serve_with_incoming(TcpIncoming::from(listener)),
+        );
+        Self { endpoint, dir }
+    }
+
+    /// Opens the database of `username`, which is registered the first time.
+    pub async fn client(&self, username: &str) -> Client {
+        let mut client = Client::builder(&self.endpoint, self.dir.join(format!("{username}.db")))
+            .build()
+            .await
+            .unwrap();
+        if !client
+            .profiles()
+            .await
+            .unwrap()
+            .iter()
+            .any(|user| user == username)
+        {
+            client
+                .register(username.to_string(), DEFAULT_CIPHERSUITE)
+                .await
+                .unwrap();
+        }
+        client
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Handles the messages queued for `user` and returns their events.
+pub async fn receive_queued(client: &mut Client, user: &str) -> Vec<ChatEvent> {
+    client.subscribe(user.to_string()).await.unwrap();
+    client.drain_events()
+}
+
+/// Texts of the message events, by sender.
+pub fn texts(events: &[ChatEvent]) -> Vec<(String, String)> {
+    events
+        .iter()
+        .filter_map(|event| match event {
+            ChatEvent::Message { sender, text, .. } => Some((sender.clone(), text.clone())),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Creates a group of `owner` and adds `member`, who accepts the invite.
+pub async fn shared_group(
+    owner: &mut Client,
+    owner_name: &str,
+    member: &mut Client,
+    member_name: &str,
+) -> Uuid {
+    let group_uuid = owner
+        .create_group(
+            owner_name.to_string(),
+            None,
+            None,
+            RequiredCapabilities::default(),
+        )
+        .await
+        .unwrap();
+    owner
+        .add_members(
+            owner_name.to_string(),
+            group_uuid,
+            vec![member_name.to_string()],
+        )
+        .await
+        .unwrap();
+    receive_queued(member, member_name).await;
+    member
+        .accept_invite(member_name.to_string(), group_uuid)
+        .await
+        .unwrap();
+    group_uuid
+}
@@ -0,0 +1,40 @@
+#![cfg(all(feature = "client", feature = "server"))]
+
+mod common;
+
+use common::TestServer;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn keys_are_logged_as_the_log_grows() {
+    let server = TestServer::start().await;
+    let mut alice = server.client("alice").await;
+    let mut bob = server.client("bob").await;
+
+    // Each check proves the log consistent with the root of the one before,
+    // at sizes that aren't powers of two.
+    for others in ["carol", "dave", "erin", "frank", "grace"] {
+        server.client(others).await;
+        let logged = bob
+            .verify_keys("bob".to_string(), "alice".to_string())
+            .await
+            .unwrap();
+        assert_eq!(logged.len(), 1);
+        let own = alice
+            .verify_keys("alice".to_string(), "alice".to_string())
+            .await
+            .unwrap();
+        assert_eq!(own[0].signature_key, logged[0].signature_key);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn users_of_other_domains_are_not_checked() {
+    let server = TestServer::start().await;
+    let mut alice = server.client("alice").await;
+    assert!(
+        alice
+            .verify_keys("alice".to_string(), "bob@other.example".to_string())
+            .await
+            .is_err()
+    );
+}
@@ -0,0 +1,100 @@
+#![cfg(all(feature = "client", feature = "server"))]
+
+mod common;
+
+use common::{TestServer, receive_queued, shared_group};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn both_sides_verify_the_same_number() {
+    let server = TestServer::start().await;
+    let mut alice = server.client("alice").await;
+    let mut bob = server.client("bob").await;
+    shared_group(&mut alice, "alice", &mut bob, "bob").await;
+
+    let of_alice = alice
+        .safety_number("alice".to_string(), "bob".to_string())
+        .await
+        .unwrap();
+    let of_bob = bob
+        .safety_number("bob".to_string(), "alice".to_string())
+        .await
+        .unwrap();
+    assert_eq!(of_alice.code, of_bob.code);
+    assert!(!of_alice.verified);
+
+    let wrong = "00000 00000 00000 00000 00000 00000";
+    assert!(
+        alice
+            .verify_contact("alice".to_string(), "bob".to_string(), wrong)
+            .await
+            .is_err()
+    );
+    // Grouping of the digits doesn't matter.
+    let code: String = of_bob.code.split_whitespace().collect();
+    alice
+        .verify_contact("alice".to_string(), "bob".to_string(), &code)
+        .await
+        .unwrap();
+    assert!(
+        alice
+            .safety_number("alice".to_string(), "bob".to_string())
+            .await
+            .unwrap()
+            .verified
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn scanned_qr_code_verifies_its_owner() {
+    let server = TestServer::start().await;
+    let mut alice = server.client("alice").await;
+    let mut bob = server.client("bob").await;
+    shared_group(&mut alice, "alice", &mut bob, "bob").await;
+
+    let payload = alice
+        .verification_payload("alice".to_string(), "bob".to_string())
+        .await
+        .unwrap();
+    assert_eq!(
+        bob.verify_qr("bob".to_string(), &payload).await.unwrap(),
+        "alice"
+    );
+    assert!(
+        bob.safety_number("bob".to_string(), "alice".to_string())
+            .await
+            .unwrap()
+            .verified
+    );
+
+    let mut carol = server.client("carol").await;
+    shared_group(&mut alice, "alice", &mut carol, "carol").await;
+    assert!(
+        carol
+            .verify_qr("carol".to_string(), &payload)
+            .await
+            .is_err()
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn rotated_identity_key_changes_the_number() {
+    let server = TestServer::start().await;
+    let mut alice = server.client("alice").await;
+    let mut bob = server.client("bob").await;
+    shared_group(&mut alice, "alice", &mut bob, "bob").await;
+    let before = bob
+        .safety_number("bob".to_string(), "alice".to_string())
+        .await
+        .unwrap();
+
+    alice
+        .rotate_identity_key("alice".to_string())
+        .await
+        .unwrap();
+    receive_queued(&mut bob, "bob").await;
+    let after = bob
+        .safety_number("bob".to_string(), "alice".to_string())
+        .await
+        .unwrap();
+    assert_ne!(before.code, after.code);
+}